@@ -0,0 +1,143 @@
+//! Compares the time taken to process events with the matches of the default dispatch, with
+//! `table_dispatch: true` and with `hash_dispatch: true`, in a state machine where every state
+//! handles every event.
+//!
+//! Run with `cargo bench --bench dispatch`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+macro_rules! bench_machine {
+    ($name:ident $(, $dispatch:ident)?) => {
+        mod $name {
+            use smlang::statemachine;
+
+            statemachine! {
+                $($dispatch: true,)?
+                events_attr: #[derive(Clone, Copy)],
+                transitions: {
+                *S0 + E0 / step = S1,
+                S0 + E1 / step = S2,
+                S0 + E2 / step = S3,
+                S0 + E3 / step = S4,
+                S0 + E4 / step = S5,
+                S0 + E5 / step = S6,
+                S0 + E6 / step = S7,
+                S0 + E7 / step = S0,
+                S1 + E0 / step = S2,
+                S1 + E1 / step = S3,
+                S1 + E2 / step = S4,
+                S1 + E3 / step = S5,
+                S1 + E4 / step = S6,
+                S1 + E5 / step = S7,
+                S1 + E6 / step = S0,
+                S1 + E7 / step = S1,
+                S2 + E0 / step = S3,
+                S2 + E1 / step = S4,
+                S2 + E2 / step = S5,
+                S2 + E3 / step = S6,
+                S2 + E4 / step = S7,
+                S2 + E5 / step = S0,
+                S2 + E6 / step = S1,
+                S2 + E7 / step = S2,
+                S3 + E0 / step = S4,
+                S3 + E1 / step = S5,
+                S3 + E2 / step = S6,
+                S3 + E3 / step = S7,
+                S3 + E4 / step = S0,
+                S3 + E5 / step = S1,
+                S3 + E6 / step = S2,
+                S3 + E7 / step = S3,
+                S4 + E0 / step = S5,
+                S4 + E1 / step = S6,
+                S4 + E2 / step = S7,
+                S4 + E3 / step = S0,
+                S4 + E4 / step = S1,
+                S4 + E5 / step = S2,
+                S4 + E6 / step = S3,
+                S4 + E7 / step = S4,
+                S5 + E0 / step = S6,
+                S5 + E1 / step = S7,
+                S5 + E2 / step = S0,
+                S5 + E3 / step = S1,
+                S5 + E4 / step = S2,
+                S5 + E5 / step = S3,
+                S5 + E6 / step = S4,
+                S5 + E7 / step = S5,
+                S6 + E0 / step = S7,
+                S6 + E1 / step = S0,
+                S6 + E2 / step = S1,
+                S6 + E3 / step = S2,
+                S6 + E4 / step = S3,
+                S6 + E5 / step = S4,
+                S6 + E6 / step = S5,
+                S6 + E7 / step = S6,
+                S7 + E0 / step = S0,
+                S7 + E1 / step = S1,
+                S7 + E2 / step = S2,
+                S7 + E3 / step = S3,
+                S7 + E4 / step = S4,
+                S7 + E5 / step = S5,
+                S7 + E6 / step = S6,
+                S7 + E7 / step = S7,
+                },
+            }
+
+            pub struct Context {
+                pub steps: u64,
+            }
+
+            impl StateMachineContext for Context {
+                fn step(&mut self) -> Result<(), ()> {
+                    self.steps += 1;
+                    Ok(())
+                }
+            }
+
+            pub const EVENTS: [Events; 8] = [
+                Events::E0,
+                Events::E1,
+                Events::E2,
+                Events::E3,
+                Events::E4,
+                Events::E5,
+                Events::E6,
+                Events::E7,
+            ];
+
+            pub fn run(events: usize) -> u64 {
+                let mut sm = StateMachine::new(Context { steps: 0 });
+                for i in 0..events {
+                    let event = super::black_box(EVENTS[i % EVENTS.len()]);
+                    let _ = sm.process_event(event);
+                }
+                sm.context().steps
+            }
+        }
+    };
+}
+
+bench_machine!(nested);
+bench_machine!(table, table_dispatch);
+bench_machine!(hash, hash_dispatch);
+
+fn bench(name: &str, run: fn(usize) -> u64) {
+    const EVENTS: usize = 10_000_000;
+    // Warm up
+    black_box(run(EVENTS / 10));
+    let start = Instant::now();
+    let steps = black_box(run(EVENTS));
+    let elapsed = start.elapsed();
+    assert_eq!(steps, EVENTS as u64);
+    println!(
+        "{:>8}: {:.2} ns/event",
+        name,
+        elapsed.as_nanos() as f64 / EVENTS as f64
+    );
+}
+
+fn main() {
+    bench("nested", nested::run);
+    bench("table", table::run);
+    bench("hash", hash::run);
+}
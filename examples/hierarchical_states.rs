@@ -0,0 +1,52 @@
+//! Hierarchical states example
+//!
+//! An example of nesting sub-states inside a composite state. Events which are not handled by the
+//! active sub-state are handled by the transitions of the composite state.
+
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+
+statemachine! {
+    states_attr: #[derive(Debug)],
+    transitions: {
+        *Off + PowerOn = On,
+
+        // Transitions of `On` apply to all of its sub-states
+        On + PowerOff = Off,
+        On {
+            *SelfTest + Passed = Ready,
+            Ready + Start = Busy,
+            Busy + Done = Ready,
+        },
+    }
+}
+
+/// Context
+pub struct Context;
+
+impl StateMachineContext for Context {
+    fn on_entry_on(&mut self) {
+        println!("Powered on");
+    }
+
+    fn on_exit_on(&mut self) {
+        println!("Powered off");
+    }
+}
+
+fn main() {
+    let mut sm = StateMachine::new(Context);
+
+    // Entering `On` enters its initial sub-state
+    sm.process_event(Events::PowerOn).unwrap();
+    assert!(matches!(sm.state(), &States::On(OnStates::SelfTest)));
+
+    sm.process_event(Events::Passed).unwrap();
+    sm.process_event(Events::Start).unwrap();
+    assert!(matches!(sm.state(), &States::On(OnStates::Busy)));
+
+    // `Busy` does not handle `PowerOff`, so the transition of `On` is taken
+    sm.process_event(Events::PowerOff).unwrap();
+    assert!(matches!(sm.state(), &States::Off));
+}
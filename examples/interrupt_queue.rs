@@ -0,0 +1,58 @@
+//! Interrupt queue example
+//!
+//! An example of a bare-metal state machine whose events are pushed to a queue in a `static` by
+//! interrupt handlers, while the main loop drains it. It is built as a library, e.g. with
+//! `cargo build --target thumbv7em-none-eabihf --features critical-section --example interrupt_queue`,
+//! and the final binary provides the critical section, e.g. with the
+//! `critical-section-single-core` feature of `cortex-m`.
+
+#![no_std]
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Released + Pressed / press = Pressed,
+        Pressed + Released = Released,
+    },
+    queue_capacity: 4,
+}
+
+/// The events pushed by the interrupt handlers
+pub static QUEUE: EventQueue = EventQueue::new();
+
+/// Context counting the presses of a button
+pub struct Context {
+    presses: u32,
+}
+
+impl StateMachineContext for Context {
+    fn press(&mut self) -> Result<(), ()> {
+        self.presses += 1;
+        Ok(())
+    }
+}
+
+/// The handler of the interrupt of the falling edge of the button, e.g. `EXTI0`. A full queue
+/// drops the event.
+pub fn on_falling_edge() {
+    QUEUE.push(Events::Pressed).ok();
+}
+
+/// The handler of the interrupt of the rising edge of the button, e.g. `EXTI1`.
+pub fn on_rising_edge() {
+    QUEUE.push(Events::Released).ok();
+}
+
+/// The main loop, processing the events pushed by the interrupt handlers, where a real main loop
+/// would wait for the next interrupt after draining the queue, e.g. with `cortex_m::asm::wfi()`.
+pub fn run(iterations: usize) -> u32 {
+    let mut consumer = QUEUE.take_consumer().unwrap();
+    let mut sm = StateMachine::new(Context { presses: 0 });
+    for _ in 0..iterations {
+        while sm.process_queued(&mut consumer).is_err() {}
+        core::hint::spin_loop();
+    }
+    sm.context().presses
+}
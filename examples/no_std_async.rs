@@ -0,0 +1,85 @@
+//! No-std async example
+//!
+//! An example of an async state machine on a bare-metal target without an allocator, driven by a
+//! minimal executor. It is built as a library, e.g. with
+//! `cargo build --target thumbv7em-none-eabihf --example no_std_async`.
+
+#![no_std]
+#![deny(missing_docs)]
+
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context as TaskContext, Poll, RawWaker, RawWakerVTable, Waker};
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Start(u32) [ async is_ready ] / async start = Running(u32),
+        Running(u32) + Stop / async stop = Idle,
+    }
+}
+
+/// Set by the interrupt handler of the peripheral once it is ready
+pub static READY: AtomicBool = AtomicBool::new(false);
+
+/// Context driving the peripheral
+pub struct Context {
+    speed: u32,
+}
+
+impl StateMachineContext for Context {
+    async fn is_ready(&self, _speed: &u32) -> Result<bool, ()> {
+        // Waits for the interrupt handler without allocating a future
+        poll_fn(|_| match READY.load(Ordering::Acquire) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        })
+        .await;
+        Ok(true)
+    }
+
+    async fn start(&mut self, speed: u32) -> Result<u32, ()> {
+        self.speed = speed;
+        Ok(speed)
+    }
+
+    async fn stop(&mut self, _speed: &u32) -> Result<(), ()> {
+        self.speed = 0;
+        Ok(())
+    }
+}
+
+/// A waker which does nothing, as the executor polls its future in a loop.
+static NOOP_WAKER: RawWakerVTable =
+    RawWakerVTable::new(|_| noop_raw_waker(), |_| {}, |_| {}, |_| {});
+
+fn noop_raw_waker() -> RawWaker {
+    RawWaker::new(core::ptr::null(), &NOOP_WAKER)
+}
+
+/// Polls a future to completion, where a real executor would wait for an interrupt between the
+/// polls, e.g. with `cortex_m::asm::wfi()`.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    // SAFETY: The functions of the vtable do nothing, and never touch the data pointer.
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut cx = TaskContext::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Starts and stops the peripheral, once it is ready.
+pub fn run() -> u32 {
+    let mut sm = StateMachine::new(Context { speed: 0 });
+    block_on(async {
+        sm.process_event(Events::Start(3)).await.unwrap();
+        let speed = sm.context().speed;
+        sm.process_event(Events::Stop).await.unwrap();
+        speed
+    })
+}
@@ -0,0 +1,67 @@
+//! Parallel regions example
+//!
+//! An example of a state made of regions which are active at the same time. Each region has its
+//! own sub-state and every event is handled by all regions.
+
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+
+statemachine! {
+    states_attr: #[derive(Debug)],
+    transitions: {
+        *Idle + Start = Running,
+
+        // The regions of `Running` are left for `Stopped` once both reach a final sub-state
+        Running [
+            Motor {
+                *SpinningUp + AtSpeed = Spinning,
+                Spinning + Stop = MotorOff,
+            },
+            Fan {
+                *Cooling + Stop = FanOff,
+            },
+        ] = Stopped,
+    }
+}
+
+/// Context
+pub struct Context;
+
+impl StateMachineContext for Context {
+    fn on_entry_running(&mut self) {
+        println!("Running");
+    }
+
+    fn on_entry_stopped(&mut self) {
+        println!("Stopped");
+    }
+}
+
+fn main() {
+    let mut sm = StateMachine::new(Context);
+
+    // Entering `Running` enters the initial sub-state of both regions
+    sm.process_event(Events::Start).unwrap();
+    assert!(matches!(
+        sm.state(),
+        &States::Running(RunningStates {
+            motor: MotorStates::SpinningUp,
+            fan: FanStates::Cooling,
+        })
+    ));
+
+    // Only the motor handles `AtSpeed`
+    sm.process_event(Events::AtSpeed).unwrap();
+    assert!(matches!(
+        sm.state(),
+        &States::Running(RunningStates {
+            motor: MotorStates::Spinning,
+            fan: FanStates::Cooling,
+        })
+    ));
+
+    // Both regions handle `Stop` and reach their final sub-state
+    sm.process_event(Events::Stop).unwrap();
+    assert!(matches!(sm.state(), &States::Stopped));
+}
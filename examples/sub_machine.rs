@@ -0,0 +1,70 @@
+//! Sub-machine example
+//!
+//! An example of reusing a handshake state machine as a state of another state machine.
+
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+
+/// The handshake shared by several connections
+pub mod handshake {
+    use smlang::statemachine;
+
+    statemachine! {
+        transitions: {
+            *Hello + Ack(u32) [ is_valid ] = Established,
+        },
+    }
+
+    /// Context of the handshake
+    pub struct Context {
+        /// The key expected in the acknowledgement
+        pub key: u32,
+    }
+
+    impl StateMachineContext for Context {
+        fn is_valid(&self, key: &u32) -> Result<bool, ()> {
+            Ok(*key == self.key)
+        }
+    }
+}
+
+statemachine! {
+    transitions: {
+        *Idle + Connect(u32) / start_handshake = Connecting(handshake::StateMachine<handshake::Context>),
+        Connecting(handshake::StateMachine<handshake::Context>) + Handshake(handshake::Events) delegate,
+        Connecting(handshake::StateMachine<handshake::Context>) = Connected,
+        Connecting(handshake::StateMachine<handshake::Context>) + Abort = Idle,
+        Connected + Disconnect = Idle,
+    },
+}
+
+/// Context
+pub struct Context;
+
+impl StateMachineContext for Context {
+    fn start_handshake(
+        &mut self,
+        key: u32,
+    ) -> Result<handshake::StateMachine<handshake::Context>, ()> {
+        Ok(handshake::StateMachine::new(handshake::Context { key }))
+    }
+}
+
+fn main() {
+    let mut sm = StateMachine::new(Context);
+
+    let r = sm.process_event(Events::Connect(7));
+    assert!(matches!(r, Ok(&States::Connecting(_))));
+
+    // The handshake fails, so the connection is still being established
+    let r = sm.process_event(Events::Handshake(handshake::Events::Ack(1)));
+    assert!(matches!(r, Err(Error::TransitionsFailed)));
+
+    // Once the handshake reaches its final state, the connection is established
+    let r = sm.process_event(Events::Handshake(handshake::Events::Ack(7)));
+    assert!(matches!(r, Ok(&States::Connected)));
+
+    let r = sm.process_event(Events::Disconnect);
+    assert!(matches!(r, Ok(&States::Idle)));
+}
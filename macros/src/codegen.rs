@@ -1,9 +1,17 @@
 // Move guards to return a Result
 
-use crate::parser::transition::visit_guards;
-use crate::parser::{lifetimes::Lifetimes, AsyncIdent, ParsedStateMachine};
+use crate::parser::event::{EventMapping, ANY_EVENT, COMPLETION_EVENT, TIMEOUT_EVENT};
+use crate::parser::transition::{visit_guards, GuardExpression, GuardTimeout, Retry};
+use crate::parser::{
+    composite_state::{History, ParallelState},
+    lifetimes::Lifetimes,
+    state_machine::DisplayMode,
+    type_names::TypeName,
+    AsyncIdent, ParsedStateMachine,
+};
+use crate::perfect_hash::PerfectHash;
 use proc_macro2::{Ident, Span, TokenStream};
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, ToTokens};
 use syn::Type;
 
 pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
@@ -12,34 +20,25 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
         .as_ref()
         .map(|name| (name.to_string(), name.span()))
         .unwrap_or_else(|| (String::new(), Span::call_site()));
-    let states_type_name = format_ident!("{sm_name}States", span = sm_name_span);
-    let events_type_name = format_ident!("{sm_name}Events", span = sm_name_span);
+    let states_type_name = sm.states_type_name();
+    let events_type_name = sm.events_type_name();
     let error_type_name = format_ident!("{sm_name}Error", span = sm_name_span);
-    let state_machine_type_name = format_ident!("{sm_name}StateMachine", span = sm_name_span);
-    let state_machine_context_type_name =
-        format_ident!("{sm_name}StateMachineContext", span = sm_name_span);
-
-    // Get only the unique states
-    let mut state_list: Vec<_> = sm.states.values().collect();
-    state_list.sort_by_key(|state| state.to_string());
+    let guards_type_name = format_ident!("{sm_name}Guards", span = sm_name_span);
+    let state_id_type_name = format_ident!("{sm_name}StateId", span = sm_name_span);
+    let atomic_state_id_type_name = format_ident!("{sm_name}AtomicStateId", span = sm_name_span);
+    let state_machine_type_name = sm.state_machine_type_name();
+    let state_machine_context_type_name = sm.context_type_name();
+    let visibility = |renamed: &Option<TypeName>| match renamed {
+        Some(renamed) => renamed.vis.to_token_stream(),
+        None => quote! { pub },
+    };
+    let states_vis = visibility(&sm.type_names.states);
+    let events_vis = visibility(&sm.type_names.events);
+    let state_machine_vis = visibility(&sm.type_names.state_machine);
+    let context_vis = visibility(&sm.type_names.context);
 
-    let state_list: Vec<_> = state_list
-        .iter()
-        .map(
-            |value| match sm.state_data.data_types.get(&value.to_string()) {
-                None => {
-                    quote! {
-                        #value
-                    }
-                }
-                Some(t) => {
-                    quote! {
-                        #value(#t)
-                    }
-                }
-            },
-        )
-        .collect();
+    // Get only the unique states, sub-states are nested in the enum of their composite state
+    let state_list = state_variants(sm, None, &sm_name, sm_name_span);
 
     // Extract events
     let mut event_list: Vec<_> = sm.events.values().collect();
@@ -48,160 +47,115 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
     // Extract events
     let event_list: Vec<_> = event_list
         .iter()
-        .map(
-            |value| match sm.event_data.data_types.get(&value.to_string()) {
-                None => {
-                    quote! {
-                        #value
-                    }
-                }
-                Some(t) => {
-                    quote! {
-                        #value(#t)
-                    }
-                }
-            },
-        )
-        .collect();
-
-    let transitions = &sm.states_events_mapping;
-
-    let in_states: Vec<_> = transitions
-        .iter()
-        .map(|(name, _)| {
-            let state_name = sm.states.get(name).unwrap();
-
-            match sm.state_data.data_types.get(name) {
+        .map(|value| {
+            let attrs = sm.event_attrs.get(&value.to_string()).into_iter().flatten();
+            match sm.event_data.data_types.get(&value.to_string()) {
                 None => {
                     quote! {
-                        #state_name
-                    }
-                }
-                Some(_) => {
-                    quote! {
-                        #state_name(ref state_data)
+                        #(#attrs)* #value
                     }
                 }
+                Some(t) => quote! {
+                    #(#attrs)* #value(#t)
+                },
             }
         })
         .collect();
 
-    let events: Vec<Vec<_>> = transitions
-        .iter()
-        .map(|(_, value)| {
-            value
-                .iter()
-                .map(|(name, value)| {
-                    let value = &value.event;
+    let transitions = &sm.states_events_mapping;
 
-                    match sm.event_data.data_types.get(name) {
-                        None => {
-                            quote! {
-                                #value
-                            }
-                        }
-                        Some(_) => {
-                            quote! {
-                                #value(event_data)
-                            }
-                        }
-                    }
-                })
-                .collect()
-        })
-        .collect();
+    // The actions of internal transitions which update the data of the state borrow it mutably
+    let action_parameters = |state_name: &str, name: &str, mutable: bool| {
+        let state_data = match sm.state_data.data_types.get(state_name) {
+            Some(Type::Reference(_)) => quote! { state_data },
+            Some(_) if mutable => quote! { &mut *state_data },
+            Some(_) => quote! { &state_data },
+            None => quote! {},
+        };
 
-    // Map guards, actions and output states into code blocks
-    let guards: Vec<Vec<_>> = transitions
-        .values()
-        .map(|event_mappings| {
-            event_mappings
-                .values()
-                .map(|event_mapping| {
-                    event_mapping
-                        .transitions
-                        .iter()
-                        .map(|transition| transition.guard.clone())
-                        .collect::<Vec<_>>()
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
+        let event_data = match sm.event_data.data_types.get(name) {
+            Some(Type::Reference(_)) if sm.events_by_ref => quote! { *event_data },
+            Some(_) => quote! { event_data },
+            None => quote! {},
+        };
 
-    let actions: Vec<Vec<_>> = transitions
-        .values()
-        .map(|event_mappings| {
-            event_mappings
-                .values()
-                .map(|event_mapping| {
-                    event_mapping
-                        .transitions
-                        .iter()
-                        .map(|transition| transition.action.clone())
-                        .collect::<Vec<_>>()
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
+        if state_data.is_empty() || event_data.is_empty() {
+            quote! { #state_data #event_data }
+        } else {
+            quote! { #state_data, #event_data }
+        }
+    };
 
-    let action_parameters: Vec<Vec<_>> = transitions
-        .iter()
-        .map(|(name, value)| {
-            let state_name = &sm.states.get(name).unwrap().to_string();
+    // Retried actions borrow the event data, which is kept for every attempt
+    let retry_action_parameters = |state_name: &str, name: &str, mutable: bool| {
+        let state_data = match sm.state_data.data_types.get(state_name) {
+            Some(Type::Reference(_)) => quote! { state_data },
+            Some(_) if mutable => quote! { &mut *state_data },
+            Some(_) => quote! { &state_data },
+            None => quote! {},
+        };
 
-            value
-                .iter()
-                .map(|(name, _)| {
-                    let state_data = match sm.state_data.data_types.get(state_name) {
-                        Some(Type::Reference(_)) => quote! { state_data },
-                        Some(_) => quote! { &state_data },
-                        None => quote! {},
-                    };
+        // Borrowed events bind their data by reference
+        let event_data = match sm.event_data.data_types.get(name) {
+            Some(Type::Reference(_)) if sm.events_by_ref => quote! { *event_data },
+            Some(_) if sm.events_by_ref => quote! { event_data },
+            Some(Type::Reference(_)) => quote! { event_data },
+            Some(_) => quote! { &event_data },
+            None => quote! {},
+        };
 
-                    let event_data = match sm.event_data.data_types.get(name) {
-                        Some(_) => quote! { event_data },
-                        None => quote! {},
-                    };
+        if state_data.is_empty() || event_data.is_empty() {
+            quote! { #state_data #event_data }
+        } else {
+            quote! { #state_data, #event_data }
+        }
+    };
 
-                    if state_data.is_empty() || event_data.is_empty() {
-                        quote! { #state_data #event_data }
-                    } else {
-                        quote! { #state_data, #event_data }
-                    }
-                })
-                .collect()
-        })
-        .collect();
+    let guard_parameters = |state_name: &str, name: &str| {
+        let state_data = match sm.state_data.data_types.get(state_name) {
+            Some(Type::Reference(_)) => quote! { state_data },
+            Some(_) => quote! { &state_data },
+            None => quote! {},
+        };
 
-    let guard_parameters: Vec<Vec<_>> = transitions
-        .iter()
-        .map(|(name, value)| {
-            let state_name = &sm.states.get(name).unwrap().to_string();
+        // Borrowed events bind their data by reference
+        let event_data = match sm.event_data.data_types.get(name) {
+            Some(Type::Reference(_)) if sm.events_by_ref => quote! { *event_data },
+            Some(_) if sm.events_by_ref => quote! { event_data },
+            Some(Type::Reference(_)) => quote! { event_data },
+            Some(_) => quote! { &event_data },
+            None => quote! {},
+        };
 
-            value
-                .iter()
-                .map(|(name, _)| {
-                    let state_data = match sm.state_data.data_types.get(state_name) {
-                        Some(Type::Reference(_)) => quote! { state_data },
-                        Some(_) => quote! { &state_data },
-                        None => quote! {},
-                    };
+        if state_data.is_empty() || event_data.is_empty() {
+            quote! { #state_data #event_data }
+        } else {
+            quote! { #state_data, #event_data }
+        }
+    };
 
-                    let event_data = match sm.event_data.data_types.get(name) {
-                        Some(Type::Reference(_)) => quote! { event_data },
-                        Some(_) => quote! { &event_data },
-                        None => quote! {},
-                    };
+    // Event data is only borrowed by the regions of a parallel state, as every region gets to
+    // process the event
+    let region_parameters = |state_name: &str, name: &str, mutable: bool| {
+        let state_data = match sm.state_data.data_types.get(state_name) {
+            Some(Type::Reference(_)) => quote! { state_data },
+            Some(_) if mutable => quote! { &mut *state_data },
+            Some(_) => quote! { &state_data },
+            None => quote! {},
+        };
 
-                    if state_data.is_empty() || event_data.is_empty() {
-                        quote! { #state_data #event_data }
-                    } else {
-                        quote! { #state_data, #event_data }
-                    }
-                })
-                .collect()
-        })
-        .collect();
+        let event_data = match sm.event_data.data_types.get(name) {
+            Some(Type::Reference(_)) => quote! { *event_data },
+            Some(_) => quote! { event_data },
+            None => quote! {},
+        };
+
+        if state_data.is_empty() || event_data.is_empty() {
+            quote! { #state_data #event_data }
+        } else {
+            quote! { #state_data, #event_data }
+        }
+    };
 
     let custom_error = if sm.custom_error {
         quote! { Self::Error }
@@ -209,36 +163,6 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
         quote! { () }
     };
 
-    let out_states: Vec<Vec<Vec<TokenStream>>> = transitions
-        .values()
-        .map(|event_mappings| {
-            event_mappings
-                .values()
-                .map(|event_mapping| {
-                    event_mapping
-                        .transitions
-                        .iter()
-                        .map(|transition| transition.out_state.clone())
-                        .map(|out_state| {
-                            match sm.state_data.data_types.get(&out_state.to_string()) {
-                                None => {
-                                    quote! {
-                                        #out_state
-                                    }
-                                }
-                                Some(_) => {
-                                    quote! {
-                                        #out_state(_data)
-                                    }
-                                }
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
-
     let temporary_context = match &sm.temporary_context_type {
         Some(tct) => {
             quote! { temporary_context: #tct, }
@@ -248,6 +172,20 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
         }
     };
 
+    // Guards borrow the context immutably and actions mutably, unless declared otherwise in the
+    // `context_access` block
+    let receiver = |mutable: bool| {
+        if mutable {
+            quote! { &mut self }
+        } else {
+            quote! { &self }
+        }
+    };
+    let guard_receiver =
+        |guard: &Ident| receiver(sm.context_access.is_mutable_guard(&guard.to_string()));
+    let action_receiver =
+        |action: &Ident| receiver(sm.context_access.is_mutable_action(&action.to_string()));
+
     // Keep track of already added actions not to duplicate definitions
     let mut action_set: Vec<syn::Ident> = Vec::new();
     let mut guard_set: Vec<syn::Ident> = Vec::new();
@@ -259,7 +197,7 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
 
     for (state, event_mappings) in transitions.iter() {
         // create the state data token stream
-        let state_data = match sm.state_data.data_types.get(state) {
+        let state_data = match sm.unboxed_data_type(state) {
             Some(st @ Type::Reference(_)) => quote! { state_data: #st, },
             Some(st) => quote! { state_data: &#st, },
             None => quote! {},
@@ -325,11 +263,13 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
                         // Only add the guard if it hasn't been added before
                         if !guard_set.iter().any(|g| g == guard) {
                             guard_set.push(guard.clone());
-                            let is_async = if is_async { quote!{ async } } else { quote!{ } };
+                            let (is_async, output) = method_output(sm, is_async, quote! { Result<bool,#custom_error> });
+                            let body = guard_body(sm, guard, state, event);
+                            let receiver = guard_receiver(guard);
                             guard_list.extend(quote! {
                             #[allow(missing_docs)]
                             #[allow(clippy::result_unit_err)]
-                            #is_async fn #guard <#all_lifetimes> (&self, #temporary_context #state_data #event_data) -> Result<bool,#custom_error>;
+                            #is_async fn #guard <#all_lifetimes> (#receiver, #temporary_context #state_data #event_data) -> #output #body
                         });
                         };
                         Ok(())
@@ -342,23 +282,31 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
                     is_async,
                 }) = &transition.action
                 {
-                    let is_async = if *is_async {
-                        quote! { async }
-                    } else {
-                        quote! {}
+                    let output_data = match &transition.choice {
+                        Some(choice) => choice.data_type.as_ref(),
+                        None => sm
+                            .unboxed_data_type(&transition.out_state.to_string())
+                            .filter(|_| !transition.keep_state),
                     };
-                    let return_type = if let Some(output_data) = sm
-                        .state_data
-                        .data_types
-                        .get(&transition.out_state.to_string())
-                    {
+                    let return_type = if let Some(output_data) = output_data {
                         quote! { Result<#output_data,#custom_error> }
                     } else {
                         // Empty return type
                         quote! { Result<(),#custom_error> }
                     };
+                    let (is_async, return_type) = method_output(sm, *is_async, return_type);
 
                     let event_data = match sm.event_data.data_types.get(event) {
+                        Some(et @ Type::Reference(_)) => {
+                            quote! { event_data: #et }
+                        }
+                        Some(et)
+                            if sm.region_states.contains_key(state)
+                                || transition.retry.is_some()
+                                || sm.events_by_ref =>
+                        {
+                            quote! { event_data: &#et }
+                        }
                         Some(et) => {
                             quote! { event_data: #et }
                         }
@@ -367,325 +315,6796 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
                         }
                     };
 
+                    // Internal transitions can update the data of the state they stay in
+                    let state_data = match sm.unboxed_data_type(state) {
+                        Some(st) if sm.updates_state_data(state, transition) => {
+                            quote! { state_data: &mut #st, }
+                        }
+                        _ => state_data.clone(),
+                    };
+
                     // Only add the action if it hasn't been added before
                     if !action_set.iter().any(|a| a == action) {
                         action_set.push(action.clone());
+                        let body = action_body(sm, action, state, event);
+                        let receiver = action_receiver(action);
                         action_list.extend(quote! {
                             #[allow(missing_docs)]
                             #[allow(clippy::unused_unit)]
-                            #is_async fn #action <#all_lifetimes> (&mut self, #temporary_context #state_data #event_data) -> #return_type;
+                            #is_async fn #action <#all_lifetimes> (#receiver, #temporary_context #state_data #event_data) -> #return_type #body
                         });
                     }
                 }
-            }
-        }
-    }
 
-    let temporary_context_call = match &sm.temporary_context_type {
-        Some(_) => {
-            quote! { temporary_context, }
-        }
-        None => {
-            quote! {}
-        }
-    };
+                // Create the backoff traits of retried transitions, which take the number of the
+                // retry
+                if let Some(AsyncIdent {
+                    ident: backoff,
+                    is_async,
+                }) = transition.retry.as_ref().and_then(|r| r.backoff.as_ref())
+                {
+                    let (is_async, output) = method_output(sm, *is_async, quote! { () });
+                    if !action_set.iter().any(|a| a == backoff) {
+                        action_set.push(backoff.clone());
+                        let receiver = action_receiver(backoff);
+                        action_list.extend(quote! {
+                            #[allow(missing_docs)]
+                            #[allow(clippy::unused_unit)]
+                            #is_async fn #backoff(#receiver, attempt: usize) -> #output;
+                        });
+                    }
+                }
 
-    let mut is_async_state_machine = false;
+                // Create the traits of chained actions, which borrow the event data and provide no
+                // data
+                for AsyncIdent {
+                    ident: action,
+                    is_async,
+                } in &transition.preceding_actions
+                {
+                    let (is_async, output) =
+                        method_output(sm, *is_async, quote! { Result<(),#custom_error> });
+                    let event_data = match sm.event_data.data_types.get(event) {
+                        Some(et @ Type::Reference(_)) => quote! { event_data: #et },
+                        Some(et) => quote! { event_data: &#et },
+                        None => quote! {},
+                    };
+                    let state_data = match sm.unboxed_data_type(state) {
+                        Some(st) if sm.updates_state_data(state, transition) => {
+                            quote! { state_data: &mut #st, }
+                        }
+                        _ => state_data.clone(),
+                    };
+                    let mut lifetimes = in_state_lifetimes.clone();
+                    lifetimes.extend(&event_lifetimes);
+                    if !action_set.iter().any(|a| a == action) {
+                        action_set.push(action.clone());
+                        let body = action_body(sm, action, state, event);
+                        let receiver = action_receiver(action);
+                        action_list.extend(quote! {
+                            #[allow(missing_docs)]
+                            #[allow(clippy::unused_unit)]
+                            #is_async fn #action <#lifetimes> (#receiver, #temporary_context #state_data #event_data) -> #output #body
+                        });
+                    }
+                }
 
-    // Create the code blocks inside the switch cases
-    let code_blocks: Vec<Vec<_>> = guards
-        .iter()
-        .zip(
-            actions
-                .iter()
-                .zip(in_states.iter().zip(out_states.iter().zip(action_parameters.iter().zip(guard_parameters.iter())))),
-        )
-        .map(
-            |(guards, (actions, (in_state, (out_states, (action_parameters, guard_parameters)))))| {
-                guards
-                    .iter()
-                    .zip(
-                        actions
-                            .iter()
-                            .zip(out_states.iter().zip(action_parameters.iter().zip(guard_parameters.iter()))),
-                    )
-                    .map(|(guard, (action, (out_state, (action_params, guard_params))))| {
-                        let streams: Vec<TokenStream> =
-                            guard.iter()
-                            .zip(action.iter().zip(out_state)).map(|(guard, (action,out_state))| {
-                                let binding = out_state.to_string();
-                                let out_state_string = binding.split('(').next().unwrap().trim();
-                                let binding = in_state.to_string();
-                                let in_state_string = binding.split('(').next().unwrap().trim();
-
-                                let entry_ident = format_ident!("on_entry_{}", string_morph::to_snake_case(out_state_string));
-                                let exit_ident = format_ident!("on_exit_{}", string_morph::to_snake_case(in_state_string));
-
-                                let (is_async_action, action_code) = generate_action(action, &temporary_context_call, action_params, &error_type_name);
-                                is_async_state_machine |= is_async_action;
+                // Create the guard and action traits of the branches of a choice, which take the
+                // data of the choice
+                if let Some(choice) = &transition.choice {
+                    let (choice_lifetimes, guard_data, action_data) = match &choice.data_type {
+                        Some(ct @ Type::Reference(_)) => (
+                            Lifetimes::from_type(ct).unwrap_or_default(),
+                            quote! { choice: #ct },
+                            quote! { choice: #ct },
+                        ),
+                        Some(ct) => (
+                            Lifetimes::from_type(ct).unwrap_or_default(),
+                            quote! { choice: &#ct },
+                            quote! { choice: #ct },
+                        ),
+                        None => (Lifetimes::new(), quote! {}, quote! {}),
+                    };
 
-                                let transition = if in_state_string == out_state_string {
-                                    // Stay in the same state => no need to call on_entry/on_exit
-                                    quote!{
-                                            #action_code
-                                            self.state = #states_type_name::#out_state;
-                                            return Ok(&self.state);
-                                        }
-                                } else {
-                                    quote!{
-                                            self.context.#exit_ident();
-                                            #action_code
-                                            let out_state = #states_type_name::#out_state;
-                                            self.context().transition_callback(&self.state, &out_state);
-                                            self.state = out_state;
-                                            self.context.#entry_ident();
-                                            return Ok(&self.state);
-                                        }
-                                };
-                                if let Some(expr) = guard { // Guarded transition
-                                    let guard_expression= expr.to_token_stream(&mut |async_ident: &AsyncIdent| {
-                                        let guard_ident = &async_ident.ident;
-                                        let guard_await = if async_ident.is_async {
-                                            is_async_state_machine = true;
-                                            quote! { .await }
-                                        } else {
-                                            quote! {}
-                                        };
-                                        quote! {
-                                            self.context.#guard_ident(#temporary_context_call #guard_params) #guard_await .map_err(#error_type_name::GuardFailed)?
-                                        }
+                    for branch in &choice.branches {
+                        if let Some(guard_expression) = &branch.guard {
+                            visit_guards(guard_expression, |guard| {
+                                let (is_async, output) = method_output(sm, guard.is_async, quote! { Result<bool,#custom_error> });
+                                let guard = &guard.ident;
+                                if !guard_set.iter().any(|g| g == guard) {
+                                    guard_set.push(guard.clone());
+                                    let receiver = guard_receiver(guard);
+                                    guard_list.extend(quote! {
+                                        #[allow(missing_docs)]
+                                        #[allow(clippy::result_unit_err)]
+                                        #is_async fn #guard <#choice_lifetimes> (#receiver, #temporary_context #guard_data) -> #output;
                                     });
-                                    quote! {
-                                        // This #guard_expression contains a boolean expression of guard functions
-                                        // Each guard function has Result<bool,_> return type.
-                                        // For example, [ f && !g ] will expand into
-                                        //  self.context.f()? && !self.context.g()?
-                                        let guard_passed = #guard_expression;
-                                        self.context.log_guard(stringify!(#guard_expression), guard_passed);
-
-                                        // If the guard passed, we transition immediately.
-                                        // Otherwise, there may be a later transition that passes,
-                                        // so we'll defer to that.
-                                        if guard_passed {
-                                            #transition
-                                        }
-                                    }
-                                } else { // Unguarded transition
-                                   quote!{
-                                        #transition
-                                   }
                                 }
+                                Ok(())
+                            }).unwrap();
+                        }
+
+                        if let Some(AsyncIdent {
+                            ident: action,
+                            is_async,
+                        }) = &branch.action
+                        {
+                            let out_state_name = branch.out_state.ident.to_string();
+                            let return_type = match sm.unboxed_data_type(&out_state_name) {
+                                Some(output_data) => quote! { Result<#output_data,#custom_error> },
+                                None => quote! { Result<(),#custom_error> },
+                            };
+                            let (is_async, return_type) = method_output(sm, *is_async, return_type);
+                            let mut lifetimes = choice_lifetimes.clone();
+                            if let Some(out_lifetimes) =
+                                sm.state_data.lifetimes.get(&out_state_name)
+                            {
+                                lifetimes.extend(out_lifetimes);
+                            }
+                            if !action_set.iter().any(|a| a == action) {
+                                action_set.push(action.clone());
+                                let receiver = action_receiver(action);
+                                action_list.extend(quote! {
+                                    #[allow(missing_docs)]
+                                    #[allow(clippy::unused_unit)]
+                                    #is_async fn #action <#lifetimes> (#receiver, #temporary_context #action_data) -> #return_type;
+                                });
                             }
-                            ).collect();
-                        quote!{
-                            #(#streams)*
                         }
-                    })
-                    .collect()
-            },
-        )
-        .collect();
+                    }
+                }
+            }
+        }
+    }
 
-    let starting_state = &sm.starting_state;
+    // Create the entry and exit handler traits for user implementation
+    let mut handler_list: Vec<_> = sm.state_handlers.iter().collect();
+    handler_list.sort_by_key(|(state, _)| state.as_str());
+    for (state, handlers) in handler_list {
+        let state_data = match sm.unboxed_data_type(state) {
+            Some(st @ Type::Reference(_)) => quote! { state_data: #st },
+            Some(st) => quote! { state_data: &#st },
+            None => quote! {},
+        };
+        let state_lifetimes = sm
+            .state_data
+            .lifetimes
+            .get(state)
+            .cloned()
+            .unwrap_or_default();
 
-    // create a token stream for creating a new machine.  If the starting state contains data, then
-    // add a second argument to pass this initial data
-    let starting_state_name = starting_state.to_string();
-    let new_sm_code = match sm.state_data.data_types.get(&starting_state_name) {
-        Some(st) => quote! {
-            pub const fn new(context: T, state_data: #st ) -> Self {
-                #state_machine_type_name {
-                    state: #states_type_name::#starting_state (state_data),
-                    context
-                }
+        for handler in handlers.entry.iter().chain(handlers.exit.iter()) {
+            let (is_async, output) =
+                method_output(sm, handler.is_async, quote! { Result<(),#custom_error> });
+            let handler = &handler.ident;
+
+            // Only add the handler if it hasn't been added before, handlers are called like
+            // actions without event or output data
+            if !action_set.iter().any(|a| a == handler) {
+                action_set.push(handler.clone());
+                action_list.extend(quote! {
+                    #[allow(missing_docs)]
+                    #[allow(clippy::unused_unit)]
+                    #is_async fn #handler <#state_lifetimes> (&mut self, #temporary_context #state_data) -> #output;
+                });
             }
-        },
-        None => quote! {
-            pub const fn new(context: T ) -> Self {
-                #state_machine_type_name {
-                    state: #states_type_name::#starting_state,
-                    context
-                }
+        }
+
+        // The data of boxed states can be allocated by the context, e.g. in a pool
+        if let (Some(allocate), Some(data_type)) = (&handlers.allocate, sm.boxed_states.get(state))
+        {
+            let pointer = &sm.state_data.data_types[state];
+            if !action_set.iter().any(|a| a == allocate) {
+                action_set.push(allocate.clone());
+                action_list.extend(quote! {
+                    #[allow(missing_docs)]
+                    fn #allocate <#state_lifetimes> (&mut self, data: #data_type) -> Result<#pointer,#custom_error>;
+                });
             }
-        },
-    };
+        }
 
-    let state_lifetimes = &sm.state_data.all_lifetimes;
-    let event_lifetimes = &sm.event_data.all_lifetimes;
+        // Do-activities run until they return the event which is processed next
+        if let Some(activity) = &handlers.activity {
+            let activity = &activity.ident;
+            let (is_async, output) = method_output(sm, true, quote! { #events_type_name });
+            if !action_set.iter().any(|a| a == activity) {
+                action_set.push(activity.clone());
+                action_list.extend(quote! {
+                    #[allow(missing_docs)]
+                    #is_async fn #activity <#state_lifetimes> (&mut self, #state_data) -> #output;
+                });
+            }
+        }
+    }
 
-    // lifetimes that exists in #events_type_name but not in #states_type_name
-    let event_unique_lifetimes = event_lifetimes - state_lifetimes;
+    // Composite states are entered and exited along with their sub-states
+    let mut composite_list: Vec<_> = sm.composite_states.keys().collect();
+    composite_list.sort();
+    for state in composite_list {
+        let entry_ident = format_ident!("on_entry_{}", string_morph::to_snake_case(state));
+        let state_name = format!("[{}::{}]", states_type_name, state);
+        entries_exits.extend(quote! {
+            #[doc = concat!("Called on entry to ", #state_name)]
+            #[inline(always)]
+            fn #entry_ident(&mut self) {}
+        });
+        let exit_ident = format_ident!("on_exit_{}", string_morph::to_snake_case(state));
+        entries_exits.extend(quote! {
+            #[doc = concat!("Called on exit from ", #state_name)]
+            #[inline(always)]
+            fn #exit_ident(&mut self) {}
+        });
+    }
 
-    let custom_error = if sm.custom_error {
-        quote! {
-            /// The error type returned by guard or action functions.
-            type Error: core::fmt::Debug;
+    let temporary_context_call = match &sm.temporary_context_type {
+        Some(_) => {
+            quote! { temporary_context, }
+        }
+        None => {
+            quote! {}
         }
-    } else {
-        quote! {}
     };
 
-    let is_async = if is_async_state_machine {
-        quote! { async }
+    // Async actions can be cancelled by `process_event_cancellable` if there are any
+    let mut is_cancellable_state_machine = false;
+
+    let handler_error = if sm.custom_error {
+        quote! { <T as #state_machine_context_type_name>::Error }
     } else {
-        quote! {}
+        quote! { () }
     };
+    let hooks = Hooks {
+        sm,
+        temporary_context_call: &temporary_context_call,
+        error_type_name: &error_type_name,
+        handler_error: &handler_error,
+        sm_name: &sm_name,
+        sm_name_span,
+    };
+    // Entry and exit handlers are async if any of them is
+    let mut is_async_state_machine = hooks.has_async_handlers();
+
+    // With `transition_coverage`, every transition records its index in the machine description
+    // once its actions have run
+    let transition_indices = sm.transition_indices();
+
+    // With `custom_guard_error`, the error of a guard is returned along with the guard
+    let guard_failed = |guard: &Ident, error: TokenStream| {
+        if sm.custom_guard_error {
+            let variant = format_ident!(
+                "{}",
+                string_morph::to_pascal_case(&guard.to_string()),
+                span = guard.span()
+            );
+            quote! { #error_type_name::GuardFailed(#guards_type_name::#variant, #error) }
+        } else {
+            quote! { #error_type_name::GuardFailed(#error) }
+        }
+    };
+    let map_guard_error = |guard: &Ident| {
+        if sm.custom_guard_error {
+            let guard_failed = guard_failed(guard, quote! { error });
+            quote! { |error| #guard_failed }
+        } else {
+            quote! { #error_type_name::GuardFailed }
+        }
+    };
+
+    // With `poisoning`, the state machine is poisoned while the states are exited and entered, so
+    // a panicking handler, a failed handler or a dropped future leaves it poisoned
+    let poison = if sm.poisoning {
+        quote! { self.poisoned = true; }
+    } else {
+        quote! {}
+    };
+
+    // With `rejected_events`, the events which are rejected are kept in the state machine, where
+    // they are taken by `try_process_event`
+    let reject = |event: &TokenStream| {
+        if sm.rejected_events {
+            quote! { self.rejected = Some(#event); }
+        } else {
+            quote! {}
+        }
+    };
+    let reject_event = reject(&quote! { event });
+
+    // Create the code block handling an event in a state
+    let mut transition_block = |in_state: &String, event: &String, event_mapping: &EventMapping| {
+        // Sub-states of regions are handled in a separate function, which has the context and
+        // the state of the region passed to it
+        let in_region = sm.region_states.contains_key(in_state);
+        // Only the actions of the transitions of the processed event can be cancelled, not those
+        // of timed and completion transitions or of regions
+        let cancellable =
+            !in_region && event.as_str() != TIMEOUT_EVENT && event.as_str() != COMPLETION_EVENT;
+        let context = if in_region {
+            quote! { context }
+        } else {
+            quote! { self.context }
+        };
+        // The event processed in the state machine, rather than in a region, rebuilt from its data
+        let rejected_event = match event.as_str() {
+            _ if in_region => None,
+            TIMEOUT_EVENT | COMPLETION_EVENT => None,
+            ANY_EVENT => Some(quote! { event }),
+            name => {
+                let event_ident = &sm.events[name];
+                Some(if sm.event_data.data_types.contains_key(name) {
+                    quote! { #events_type_name::#event_ident(event_data) }
+                } else {
+                    quote! { #events_type_name::#event_ident }
+                })
+            }
+        };
+        // The transition history names the event, where a catch-all transition takes any event
+        let event_name = match event.as_str() {
+            ANY_EVENT => quote! { Self::event_name(&event) },
+            COMPLETION_EVENT => quote! { "" },
+            name => quote! { #name },
+        };
+        // The outcome of processing an event records whether an action has been run
+        let track_action = |action_code: TokenStream| match (sm.transition_outcome, in_region) {
+            _ if action_code.is_empty() => action_code,
+            (false, _) => action_code,
+            (true, false) => quote! { #action_code self.action_taken = true; },
+            (true, true) => quote! { #action_code *action_taken = true; },
+        };
+        let indices = &transition_indices[&(in_state.clone(), event.clone())];
+        let record =
+            |action_code: TokenStream, index: usize| match (sm.transition_coverage, in_region) {
+                (false, _) => action_code,
+                (true, false) => quote! { #action_code self.coverage.record(#index); },
+                (true, true) => quote! { #action_code coverage.record(#index); },
+            };
+        let (action_params, guard_params) = if in_region {
+            (
+                region_parameters(in_state, event, false),
+                region_parameters(in_state, event, false),
+            )
+        } else {
+            (
+                action_parameters(in_state, event, false),
+                guard_parameters(in_state, event),
+            )
+        };
+
+        // Create the code entering `out_state` once the action has run, where a transition of
+        // `source_composite` to itself exits and enters the composite state again
+        let enter_state = |out_state: &Ident,
+                           out_composite: Option<&Ident>,
+                           source_composite: Option<&Ident>,
+                           keep_state: bool,
+                           action_code: TokenStream| {
+            let external = source_composite.filter(|composite| Some(*composite) == out_composite);
+            let out_state_string = out_state.to_string();
+            let out_state = if sm.parallel_states.contains_key(&out_state_string) {
+                let initial = parallel_initial(sm, &out_state_string, &sm_name, sm_name_span);
+                state_path(sm, out_state, initial, &sm_name, sm_name_span)
+            } else {
+                match sm.state_data.data_types.get(&out_state_string) {
+                    None => state_path(sm, out_state, quote! {}, &sm_name, sm_name_span),
+                    Some(_) => {
+                        state_path(sm, out_state, quote! { (_data) }, &sm_name, sm_name_span)
+                    }
+                }
+            };
+            // The data of boxed states is moved behind its pointer before the state is exited, so
+            // a failed allocation cancels the transition
+            let action_code = match sm.boxed_states.get(&out_state_string) {
+                Some(data_type) if !keep_state => {
+                    let pointer = &sm.state_data.data_types[&out_state_string];
+                    match &sm.state_handlers[&out_state_string].allocate {
+                        Some(allocate) => quote! {
+                            #action_code
+                            let _data = #context.#allocate(_data).map_err(#error_type_name::ActionFailed)?;
+                        },
+                        None => quote! {
+                            #action_code
+                            let _data = <#pointer as core::convert::From<#data_type>>::from(_data);
+                        },
+                    }
+                }
+                _ => action_code,
+            };
+
+            if keep_state {
+                // Stay in the state without exiting it, so its data is kept
+                if in_region {
+                    quote! {
+                        #action_code
+                        return Ok(Some(true));
+                    }
+                } else {
+                    quote! {
+                        #action_code
+                        return Ok(&self.state);
+                    }
+                }
+            } else if *in_state == out_state_string && external.is_none() {
+                // Stay in the same state => no need to call on_entry/on_exit
+                if in_region {
+                    quote! {
+                        #action_code
+                        *next = Some((#out_state, false));
+                        return Ok(Some(true));
+                    }
+                } else {
+                    let timer_reset = hooks.timer_reset();
+                    let leaving = hooks.leaving();
+                    let transitioned = hooks.transitioned(&event_name);
+                    quote! {
+                        #action_code
+                        #leaving
+                        self.state = #out_state;
+                        #timer_reset
+                        #transitioned
+                        return Ok(&self.state);
+                    }
+                }
+            } else if in_region {
+                // The next state of the region is only entered once the actions of all regions
+                // have run, so a failed action in any region cancels the transitions of all of them
+                quote! {
+                    #action_code
+                    *next = Some((#out_state, true));
+                    return Ok(Some(true));
+                }
+            } else {
+                // Exit and enter the composite states which are not shared by
+                // the input and output states, after the actions have run so a failed action
+                // cancels the transition
+                let in_ancestors = sm.ancestors(in_state);
+                let out_ancestors = sm.ancestors(&out_state_string);
+                let mut shared = in_ancestors
+                    .iter()
+                    .zip(out_ancestors.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if let Some(composite) = external {
+                    shared = in_ancestors.iter().position(|a| *a == composite).unwrap();
+                }
+
+                let exits = hooks.exit_calls(in_state, &in_ancestors[shared..]);
+                let entering = hooks.entering();
+                let entries = hooks.entry_calls(&out_state_string, &out_ancestors[shared..]);
+                let set_state = hooks.set_state(&in_ancestors[shared..]);
+                let unpoison = hooks.unpoison();
+                let entry_failed = hooks.entry_failed(&event_name);
+
+                // Entering a composite state with history resumes its remembered sub-state
+                let entered = &out_ancestors[shared..];
+                let history = out_composite.and_then(|target| {
+                    entered.iter().position(|ancestor| {
+                        sm.composite_states[&ancestor.to_string()].history.is_some()
+                            && (*ancestor == target
+                                || sm.is_descendant(&ancestor.to_string(), &target.to_string()))
+                    })
+                });
+                match history {
+                    Some(index) => {
+                        let composite = entered[index];
+                        let restored = hooks.restored_history(composite);
+                        let restored_path = state_path(
+                            sm,
+                            composite,
+                            quote! { (sub_state) },
+                            &sm_name,
+                            sm_name_span,
+                        );
+                        let restored_entries =
+                            hooks.history_entry_calls(composite, &entered[..index]);
+                        let leaving = hooks.leaving();
+                        let transitioned = hooks.transitioned(&event_name);
+                        quote! {
+                            #action_code
+                            #poison
+                            #exits
+                            let (out_state, restored) = match #restored {
+                                Some(sub_state) => (#restored_path, true),
+                                None => (#out_state, false),
+                            };
+                            self.context().transition_callback(&self.state, &out_state);
+                            #leaving
+                            #set_state
+                            #entering
+                            if restored {
+                                #restored_entries
+                            } else {
+                                #entries
+                            }
+                            #unpoison
+                            #transitioned
+                            #entry_failed
+                            return Ok(&self.state);
+                        }
+                    }
+                    None => {
+                        let leaving = hooks.leaving();
+                        let transitioned = hooks.transitioned(&event_name);
+                        quote! {
+                            #action_code
+                            #poison
+                            #exits
+                            let out_state = #out_state;
+                            self.context().transition_callback(&self.state, &out_state);
+                            #leaving
+                            #set_state
+                            #entering
+                            #entries
+                            #unpoison
+                            #transitioned
+                            #entry_failed
+                            return Ok(&self.state);
+                        }
+                    }
+                }
+            }
+        };
+
+        // A failed action enters the fault state, carrying the error as its data, except in
+        // regions where the error is returned
+        let on_error = sm
+            .fault_state
+            .as_ref()
+            .filter(|_| !in_region)
+            .map(|fault_state| {
+                if sm
+                    .state_data
+                    .data_types
+                    .contains_key(&fault_state.to_string())
+                {
+                    let enter_fault = enter_state(
+                        fault_state,
+                        None,
+                        None,
+                        false,
+                        quote! { let _data = error.into(); },
+                    );
+                    quote! { Err(error) => { #enter_fault } }
+                } else {
+                    let enter_fault = enter_state(fault_state, None, None, false, quote! {});
+                    quote! { Err(_) => { #enter_fault } }
+                }
+            });
+
+        // Create the code taking `transition_code` if the guards pass, and whether a guard is async
+        let guarded = |expr: &GuardExpression,
+                       guard_params: &TokenStream,
+                       retry: Option<&Retry>,
+                       transition_code: TokenStream| {
+            let mut is_async = false;
+            let guard_expression = expr.to_token_stream(
+                &mut |async_ident: &AsyncIdent, timeout: Option<&GuardTimeout>| {
+                    is_async |= async_ident.is_async;
+                    let call = guard_call(
+                        sm,
+                        &context,
+                        async_ident,
+                        timeout,
+                        &temporary_context_call,
+                        guard_params,
+                        &error_type_name,
+                    );
+                    let (is_async_backoff, call) = retried(call, retry, &context);
+                    is_async |= is_async_backoff;
+                    match &rejected_event {
+                        // The data of the event hasn't been passed to an action yet
+                        Some(rejected_event) if sm.rejected_events => {
+                            let reject = reject(rejected_event);
+                            let guard_failed = guard_failed(&async_ident.ident, quote! { error });
+                            quote! {
+                                match #call {
+                                    Ok(passed) => passed,
+                                    Err(error) => {
+                                        #reject
+                                        return Err(#guard_failed);
+                                    }
+                                }
+                            }
+                        }
+                        _ => {
+                            let map_guard_error = map_guard_error(&async_ident.ident);
+                            quote! {
+                                #call.map_err(#map_guard_error)?
+                            }
+                        }
+                    }
+                },
+            );
+            let trace_guard = if cfg!(feature = "tracing") {
+                let guard = expr.to_string();
+                quote! {
+                    smlang::tracing::trace!(
+                        guard = #guard,
+                        passed = guard_passed,
+                    );
+                }
+            } else {
+                quote! {}
+            };
+            let reject_guard = if sm.metrics {
+                let guard = expr.to_string();
+                quote! {
+                    if !guard_passed {
+                        #context.guard_rejected(#in_state, #event_name, #guard);
+                    }
+                }
+            } else {
+                quote! {}
+            };
+            let code = quote! {
+                // This #guard_expression contains a boolean expression of guard functions
+                // Each guard function has Result<bool,_> return type.
+                // For example, [ f && !g ] will expand into
+                //  self.context.f()? && !self.context.g()?
+                let guard_passed = #guard_expression;
+                #context.log_guard(stringify!(#guard_expression), guard_passed);
+                #trace_guard
+                #reject_guard
+
+                // If the guard passed, we transition immediately.
+                // Otherwise, there may be a later transition that passes,
+                // so we'll defer to that.
+                if guard_passed {
+                    #transition_code
+                }
+            };
+            (is_async, code)
+        };
+
+        let streams: Vec<TokenStream> = event_mapping
+            .transitions
+            .iter()
+            .zip(indices)
+            .map(|(transition, index)| {
+                let retry = transition.retry.as_ref();
+                let mutable = sm.updates_state_data(in_state, transition);
+                let action_params = match retry {
+                    Some(_) if !in_region => retry_action_parameters(in_state, event, mutable),
+                    _ if mutable && in_region => region_parameters(in_state, event, true),
+                    _ if mutable => action_parameters(in_state, event, true),
+                    _ => action_params.clone(),
+                };
+                let (is_async_action, action_code) = generate_action(
+                    &transition.action,
+                    &context,
+                    &temporary_context_call,
+                    &action_params,
+                    &error_type_name,
+                    on_error.as_ref(),
+                    retry,
+                    cancellable,
+                );
+                let action_code = track_action(action_code);
+                is_async_state_machine |= is_async_action;
+                is_cancellable_state_machine |=
+                    cancellable && transition.action.as_ref().is_some_and(|a| a.is_async);
+
+                // Chained actions run in order before the last action, borrowing the event data
+                let preceding_params = if in_region {
+                    action_params.clone()
+                } else if mutable {
+                    retry_action_parameters(in_state, event, true)
+                } else {
+                    guard_params.clone()
+                };
+                let preceding_actions: Vec<_> = transition
+                    .preceding_actions
+                    .iter()
+                    .map(|action| {
+                        let (is_async_action, action_code) = generate_action(
+                            &Some(action.clone()),
+                            &context,
+                            &temporary_context_call,
+                            &preceding_params,
+                            &error_type_name,
+                            on_error.as_ref(),
+                            retry,
+                            cancellable,
+                        );
+                        is_async_state_machine |= is_async_action;
+                        is_cancellable_state_machine |= cancellable && action.is_async;
+                        track_action(action_code)
+                    })
+                    .collect();
+                let action_code = quote! {
+                    #(#preceding_actions)*
+                    #action_code
+                };
+
+                let transition_code = match &transition.choice {
+                    None => enter_state(
+                        &transition.out_state,
+                        transition.out_composite.as_ref(),
+                        transition.source_composite.as_ref(),
+                        transition.keep_state,
+                        record(action_code, *index),
+                    ),
+                    Some(choice) => {
+                        // The action provides the data of the choice, which is borrowed by the
+                        // guards of the branches and passed to the action of the taken branch
+                        let (bind_choice, guard_params, branch_params) = match &choice.data_type {
+                            Some(Type::Reference(_)) => (
+                                quote! { let choice = _data; },
+                                quote! { choice },
+                                quote! { choice },
+                            ),
+                            Some(_) => (
+                                quote! { let choice = _data; },
+                                quote! { &choice },
+                                quote! { choice },
+                            ),
+                            None => (quote! {}, quote! {}, quote! {}),
+                        };
+                        let branches: Vec<_> = choice
+                            .branches
+                            .iter()
+                            .enumerate()
+                            .map(|(branch_index, branch)| {
+                                let (is_async_action, branch_action) = generate_action(
+                                    &branch.action,
+                                    &context,
+                                    &temporary_context_call,
+                                    &branch_params,
+                                    &error_type_name,
+                                    on_error.as_ref(),
+                                    None,
+                                    cancellable,
+                                );
+                                let branch_action =
+                                    record(track_action(branch_action), index + branch_index);
+                                is_async_state_machine |= is_async_action;
+                                is_cancellable_state_machine |= cancellable
+                                    && branch.action.as_ref().is_some_and(|a| a.is_async);
+                                let branch_code = enter_state(
+                                    &branch.out_state.ident,
+                                    branch.out_state.composite.as_ref(),
+                                    transition.source_composite.as_ref(),
+                                    false,
+                                    branch_action,
+                                );
+                                match &branch.guard {
+                                    Some(expr) => {
+                                        let (is_async_guard, code) =
+                                            guarded(expr, &guard_params, None, branch_code);
+                                        is_async_state_machine |= is_async_guard;
+                                        code
+                                    }
+                                    None => branch_code,
+                                }
+                            })
+                            .collect();
+                        quote! {
+                            #action_code
+                            #bind_choice
+                            #(#branches)*
+                        }
+                    }
+                };
+
+                if let Some(expr) = &transition.guard {
+                    // Guarded transition
+                    let (is_async_guard, code) =
+                        guarded(expr, &guard_params, retry, transition_code);
+                    is_async_state_machine |= is_async_guard;
+                    code
+                } else {
+                    // Unguarded transition
+                    quote! {
+                         #transition_code
+                    }
+                }
+            })
+            .collect();
+        quote! {
+            #(#streams)*
+        }
+    };
+
+    // Create the code blocks inside the switch cases, sorted by state and event
+    let mut state_list_sorted: Vec<_> = transitions.iter().collect();
+    state_list_sorted.sort_by_key(|(state, _)| state.as_str());
+    let mut match_states = Vec::new();
+    let mut region_states = Vec::new();
+    let mut timed_states = Vec::new();
+    let mut completion_states = Vec::new();
+    for (in_state, event_mappings) in state_list_sorted {
+        let mut event_list: Vec<_> = event_mappings.iter().collect();
+        event_list.sort_by_key(|(event, _)| event.as_str());
+        let (timed_list, event_list): (Vec<_>, Vec<_>) = event_list
+            .into_iter()
+            .partition(|(event, _)| event.as_str() == TIMEOUT_EVENT);
+        let timed_blocks: Vec<_> = timed_list
+            .into_iter()
+            .map(|(event, event_mapping)| transition_block(in_state, event, event_mapping))
+            .collect();
+        let (completion_list, event_list): (Vec<_>, Vec<_>) = event_list
+            .into_iter()
+            .partition(|(event, _)| event.as_str() == COMPLETION_EVENT);
+        let completion_blocks: Vec<_> = completion_list
+            .into_iter()
+            .map(|(event, event_mapping)| transition_block(in_state, event, event_mapping))
+            .collect();
+        // Catch-all transitions are matched after the events which are handled explicitly
+        let (any_list, mut event_list): (Vec<_>, Vec<_>) = event_list
+            .into_iter()
+            .partition(|(event, _)| event.as_str() == ANY_EVENT);
+        event_list.extend(any_list);
+        let event_names: Vec<_> = event_list.iter().map(|(event, _)| event.as_str()).collect();
+        let event_blocks: Vec<_> = event_list
+            .into_iter()
+            .map(|(event, event_mapping)| {
+                let event_ident = &event_mapping.event;
+                let event_pattern = match sm.event_data.data_types.get(event) {
+                    None if event == ANY_EVENT => quote! { _ },
+                    None => quote! { #events_type_name::#event_ident },
+                    Some(_) => quote! { #events_type_name::#event_ident(event_data) },
+                };
+                (
+                    event_pattern,
+                    transition_block(in_state, event, event_mapping),
+                )
+            })
+            .collect();
+
+        let state_ident = &event_mappings
+            .values()
+            .next()
+            .map(|mapping| mapping.in_state.clone())
+            .or_else(|| sm.states.get(in_state).cloned())
+            .or_else(|| sm.region_states.get(in_state).cloned())
+            .unwrap();
+        let data = if sm.parallel_states.contains_key(in_state) {
+            quote! { (ref mut regions) }
+        } else if sm.has_updated_state_data(in_state) {
+            quote! { (ref mut state_data) }
+        } else if sm.state_data.data_types.contains_key(in_state) {
+            quote! { (ref state_data) }
+        } else {
+            quote! {}
+        };
+        let state_pattern = state_path(sm, state_ident, data, &sm_name, sm_name_span);
+        let data = if sm.state_data.data_types.contains_key(in_state)
+            || sm.parallel_states.contains_key(in_state)
+        {
+            quote! { (..) }
+        } else {
+            quote! {}
+        };
+        let dispatch_pattern = state_path(sm, state_ident, data, &sm_name, sm_name_span);
+
+        if !timed_blocks.is_empty() {
+            timed_states.push((in_state.clone(), state_pattern.clone(), timed_blocks));
+        }
+        if !completion_blocks.is_empty() {
+            // States with a sub-machine are completed once the sub-machine is finished
+            let completion_blocks = if sm.sub_machines.contains_key(in_state) {
+                vec![quote! {
+                    if state_data.is_finished() {
+                        #(#completion_blocks)*
+                    }
+                }]
+            } else {
+                completion_blocks
+            };
+            completion_states.push((state_pattern.clone(), completion_blocks));
+        }
+        if let Some(region) = sm.region_parents.get(in_state) {
+            region_states.push((region.to_string(), state_pattern, event_blocks));
+        } else {
+            match_states.push((
+                in_state.clone(),
+                state_pattern,
+                dispatch_pattern,
+                event_names,
+                event_blocks,
+            ));
+        }
+    }
+
+    // The queue of deferred events starts empty
+    let empty_queue = core::iter::repeat_n(quote! { None }, sm.defer_capacity);
+    let empty_queue = quote! { [#(#empty_queue),*] };
+    let deferred_init = if sm.deferred_events.is_empty() {
+        quote! {}
+    } else {
+        quote! { deferred: #empty_queue, }
+    };
+
+    // Composite states with history remember their sub-state in a struct with a field per composite
+    // state, which starts empty
+    let mut history_list: Vec<_> = sm
+        .composite_states
+        .values()
+        .filter(|composite| composite.history.is_some())
+        .map(|composite| &composite.ident)
+        .collect();
+    history_list.sort();
+    let history_type_name = format_ident!("{sm_name}History", span = sm_name_span);
+    let history_fields: Vec<_> = history_list.iter().map(|c| region_field_name(c)).collect();
+    let mut history_lifetimes = Lifetimes::new();
+    for composite in &history_list {
+        history_lifetimes.extend(&composite_lifetimes(sm, &composite.to_string()));
+    }
+    let outcome_init = if sm.transition_outcome {
+        quote! { action_taken: false, }
+    } else {
+        quote! {}
+    };
+    let transition_history_init = if sm.transition_history.is_some() {
+        quote! { transition_history: smlang::TransitionHistory::new(), }
+    } else {
+        quote! {}
+    };
+    // The states, events and transitions are described as a constant for diagnostics at runtime
+    let description_name = if sm_name.is_empty() {
+        format_ident!("MACHINE_DESCRIPTION", span = sm_name_span)
+    } else {
+        format_ident!(
+            "{}_MACHINE_DESCRIPTION",
+            string_morph::to_snake_caps_case(&sm_name),
+            span = sm_name_span
+        )
+    };
+    let publish_callback = if sm.publish_state_id {
+        quote! {
+            /// Called with the identifier of the new state after every transition, before
+            /// `on_transition`, e.g. to publish it to another core through an atomic. No-op by
+            /// default but can be overridden in implementations of a state machine's
+            /// `StateMachineContext` trait.
+            fn publish_state_id(&mut self, id: #state_id_type_name) {}
+        }
+    } else {
+        quote! {}
+    };
+    let mirror_callback = if sm.state_id_mirror {
+        quote! {
+            /// Returns the mirror of the identifier of the current state, which is updated after
+            /// every transition, before `publish_state_id` and `on_transition`.
+            fn state_id_mirror(&self) -> &#atomic_state_id_type_name;
+        }
+    } else {
+        quote! {}
+    };
+    let (state_id_field, state_id_init, starting_state_id_init) =
+        if sm.publish_state_id || sm.state_id_mirror {
+            let starting_state = &sm.starting_state;
+            (
+                quote! { state_id: #state_id_type_name, },
+                quote! { state_id: Self::id_of(&initial_state), },
+                quote! { state_id: #state_id_type_name::#starting_state, },
+            )
+        } else {
+            (quote! {}, quote! {}, quote! {})
+        };
+    let coverage_init = if sm.transition_coverage {
+        quote! { coverage: smlang::TransitionCoverage::new(#description_name.transitions), }
+    } else {
+        quote! {}
+    };
+    let rejected_init = if sm.rejected_events {
+        quote! { rejected: None, }
+    } else {
+        quote! {}
+    };
+    let poisoned_init = if sm.poisoning {
+        quote! { poisoned: false, }
+    } else {
+        quote! {}
+    };
+    let statistics_init = if sm.statistics {
+        let names = named_states(sm).into_iter().map(|(name, _)| name);
+        quote! { statistics: [#(smlang::StateStatistics::new(#names)),*], }
+    } else {
+        quote! {}
+    };
+    let history_init = if history_list.is_empty() {
+        quote! {}
+    } else {
+        quote! { history: #history_type_name { #(#history_fields: None),* }, }
+    };
+
+    // The clock can't be read in a const fn, so the timer of the starting state is started once
+    // the state machine processes its first event
+    let (timer_init, start_timer) = if !sm.uses_clock() {
+        (quote! {}, quote! {})
+    } else {
+        (
+            quote! { state_entered: None, },
+            quote! { self.start_timer(); },
+        )
+    };
+
+    let starting_state = &sm.starting_state;
+
+    // create a token stream for creating a new machine.  If the starting state contains data, then
+    // add a second argument to pass this initial data
+    let starting_state_name = starting_state.to_string();
+    let new_sm_code = match sm.state_data.data_types.get(&starting_state_name) {
+        Some(st) => {
+            let starting_state = state_path(
+                sm,
+                starting_state,
+                quote! { (state_data) },
+                &sm_name,
+                sm_name_span,
+            );
+            quote! {
+                pub const fn new(context: T, state_data: #st ) -> Self {
+                    #state_machine_type_name {
+                        state: #starting_state,
+                        #starting_state_id_init
+                        #deferred_init
+                        #timer_init
+                        #history_init
+                        #outcome_init
+                        #transition_history_init
+                        #statistics_init
+                        #coverage_init
+                        #rejected_init
+                        #poisoned_init
+                        context
+                    }
+                }
+            }
+        }
+        None => {
+            let data = if sm.parallel_states.contains_key(&starting_state_name) {
+                parallel_initial(sm, &starting_state_name, &sm_name, sm_name_span)
+            } else {
+                quote! {}
+            };
+            let starting_state = state_path(sm, starting_state, data, &sm_name, sm_name_span);
+            quote! {
+                pub const fn new(context: T ) -> Self {
+                    #state_machine_type_name {
+                        state: #starting_state,
+                        #starting_state_id_init
+                        #deferred_init
+                        #timer_init
+                        #history_init
+                        #outcome_init
+                        #transition_history_init
+                        #statistics_init
+                        #coverage_init
+                        #rejected_init
+                        #poisoned_init
+                        context
+                    }
+                }
+            }
+        }
+    };
+
+    let state_lifetimes = &sm.state_data.all_lifetimes;
+    let event_lifetimes = &sm.event_data.all_lifetimes;
+
+    // lifetimes that exists in #events_type_name but not in #states_type_name
+    let event_unique_lifetimes = event_lifetimes - state_lifetimes;
+
+    let custom_error = if sm.custom_error {
+        // The error of a failed action is converted to the data of the fault state
+        let fault_data = sm
+            .fault_state
+            .as_ref()
+            .and_then(|fault_state| sm.unboxed_data_type(&fault_state.to_string()))
+            .map(|data_type| quote! { + Into<#data_type> });
+        quote! {
+            /// The error type returned by guard or action functions.
+            type Error: core::fmt::Debug #fault_data;
+        }
+    } else {
+        quote! {}
+    };
+
+    let is_async = if is_async_state_machine {
+        quote! { async }
+    } else {
+        quote! {}
+    };
+
+    let error_type = if sm.custom_error {
+        quote! {
+            #error_type_name<<T as #state_machine_context_type_name>::Error>
+        }
+    } else {
+        quote! {#error_type_name}
+    };
+
+    let await_call = if is_async_state_machine {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+    let clock = clock_of(sm, &quote! { self.context });
+
+    // Every region of a parallel state processes the event in its own function
+    let action_taken_param = if sm.transition_outcome {
+        quote! { action_taken: &mut bool, }
+    } else {
+        quote! {}
+    };
+    let coverage_param = if sm.transition_coverage {
+        let count = sm.described_transitions().len();
+        quote! { coverage: &mut smlang::TransitionCoverage<#count>, }
+    } else {
+        quote! {}
+    };
+    let mut parallel_list: Vec<_> = sm.parallel_states.values().collect();
+    parallel_list.sort_by_key(|p| p.ident.to_string());
+    let region_functions: Vec<_> = parallel_list
+        .iter()
+        .flat_map(|parallel| parallel.regions.iter())
+        .map(|region| {
+            let region_name = region.ident.to_string();
+            let region_type_name = composite_type_name(&region_name, &sm_name, sm_name_span);
+            let region_lifetimes = region_lifetimes(sm, &region_name);
+            let function_name = region_function_name(&region_name);
+            let (state_patterns, event_blocks): (Vec<_>, Vec<_>) = region_states
+                .iter()
+                .filter(|(r, _, event_blocks)| *r == region_name && !event_blocks.is_empty())
+                .map(|(_, state_pattern, event_blocks)| (state_pattern, event_blocks))
+                .unzip();
+            let (events, code_blocks): (Vec<Vec<_>>, Vec<Vec<_>>) = event_blocks
+                .into_iter()
+                .map(|event_blocks| event_blocks.iter().cloned().unzip())
+                .unzip();
+
+            // The sub-state of the region is exited and the next one entered, unless the region
+            // stays in the same sub-state
+            let context = quote! { context };
+            let (exit_patterns, exits): (Vec<_>, Vec<_>) = region
+                .children
+                .iter()
+                .map(|child| {
+                    let child_name = child.to_string();
+                    let has_handler = sm
+                        .state_handlers
+                        .get(&child_name)
+                        .is_some_and(|h| h.exit.is_some());
+                    let data = if has_handler && sm.state_data.data_types.contains_key(&child_name)
+                    {
+                        quote! { (ref state_data) }
+                    } else {
+                        quote! { { .. } }
+                    };
+                    (
+                        state_path(sm, child, data, &sm_name, sm_name_span),
+                        hooks.exit(&context, &child_name),
+                    )
+                })
+                .unzip();
+            let (entry_patterns, entries): (Vec<_>, Vec<_>) = region
+                .children
+                .iter()
+                .map(|child| {
+                    (
+                        state_path(sm, child, quote! { { .. } }, &sm_name, sm_name_span),
+                        hooks.entry(&context, &quote! { *state }, &child.to_string()),
+                    )
+                })
+                .unzip();
+            let commit_name = format_ident!("commit_{}", function_name);
+            let entering = hooks.entering();
+            let entered = if hooks.has_entry_handlers() {
+                quote! { entered.map_err(#error_type_name::ActionFailed) }
+            } else {
+                quote! { Ok(()) }
+            };
+
+            quote! {
+                #[allow(unused_variables, unreachable_code, unreachable_patterns, clippy::match_single_binding)]
+                #is_async fn #commit_name (
+                    context: &mut T,
+                    #temporary_context
+                    state: &mut #region_type_name <#region_lifetimes>,
+                    (next, reenter): (#region_type_name <#region_lifetimes>, bool)
+                ) -> Result<(), #error_type> {
+                    if reenter {
+                        match *state {
+                            #(#exit_patterns => { #exits })*
+                        }
+                    }
+                    *state = next;
+                    #entering
+                    if reenter {
+                        match *state {
+                            #(#entry_patterns => { #entries })*
+                        }
+                    }
+                    #entered
+                }
+
+                #[allow(unused_variables, unreachable_code, unreachable_patterns, clippy::match_single_binding)]
+                #is_async fn #function_name <#event_unique_lifetimes> (
+                    context: &mut T,
+                    #action_taken_param
+                    #coverage_param
+                    #temporary_context
+                    state: &mut #region_type_name <#region_lifetimes>,
+                    next: &mut Option<(#region_type_name <#region_lifetimes>, bool)>,
+                    event: &#events_type_name <#event_lifetimes>
+                ) -> Result<Option<bool>, #error_type> {
+                    match *state {
+                        #(#state_patterns => match event {
+                            #(#events => {
+                                #code_blocks
+
+                                // none of the guarded transitions occurred
+                                Ok(Some(false))
+                            }),*
+                            _ => Ok(None),
+                        },)*
+                        _ => Ok(None),
+                    }
+                }
+            }
+        })
+        .collect();
+
+    // A failed entry handler enters the fault state once the transition has been completed
+    let fault_code = match &sm.fault_state {
+        Some(fault_state) if hooks.has_entry_handlers() => {
+            hooks.enter_fault(fault_state, &temporary_context, &error_type)
+        }
+        _ => quote! {},
+    };
+
+    // With `table_dispatch` or `hash_dispatch`, the events of states outside of parallel states are
+    // dispatched through a table of their handlers, while parallel states dispatch them to their
+    // regions
+    let is_table_dispatch = sm.table_dispatch || sm.hash_dispatch;
+    let (table_states, match_states): (Vec<_>, Vec<_>) =
+        match_states.into_iter().partition(|(in_state, ..)| {
+            is_table_dispatch && !sm.parallel_states.contains_key(in_state)
+        });
+    // Every state processes its events in a function of its own, so that the functions of large
+    // state machines are compiled in parallel
+    let state_arms: Vec<_> = match_states
+        .into_iter()
+        .map(
+            |(in_state, state_pattern, dispatch_pattern, _, event_blocks)| {
+                let (events, code_blocks): (Vec<_>, Vec<_>) = event_blocks.into_iter().unzip();
+                let rejects: Vec<_> = events
+                    .iter()
+                    .map(|pattern| reject(&event_expression(pattern)))
+                    .collect();
+                let (regions, invalid_event) = match sm.parallel_states.get(&in_state) {
+                    Some(parallel) => (
+                        parallel_dispatch(&hooks, parallel, &await_call),
+                        // The guards of a region failed
+                        quote! {
+                            {
+                                #reject_event
+                                if guards_failed {
+                                    Err(#error_type_name ::TransitionsFailed)
+                                } else {
+                                    Err(#error_type_name ::InvalidEvent)
+                                }
+                            }
+                        },
+                    ),
+                    None => (
+                        quote! {},
+                        quote! {
+                            {
+                                #reject_event
+                                Err(#error_type_name ::InvalidEvent)
+                            }
+                        },
+                    ),
+                };
+                let arm = quote! {
+                    #[allow(clippy::match_single_binding)]
+                    #state_pattern => {
+                        #regions
+                        match event {
+                            #(#events => {
+                                #code_blocks
+
+                                #[allow(unreachable_code)]
+                                {
+                                    // none of the guarded or non-guarded transitions occurred,
+                                    #rejects
+                                    Err(#error_type_name ::TransitionsFailed)
+                                }
+                            }),*
+                            #[allow(unreachable_patterns)]
+                            _ => #invalid_event,
+                        }
+                    }
+                };
+                let function_name = format_ident!(
+                    "process_event_in_{}",
+                    string_morph::to_snake_case(&in_state)
+                );
+                (dispatch_pattern, function_name, arm)
+            },
+        )
+        .collect();
+
+    // With `events_by_ref`, the events are borrowed by `process_event`, where the events owned by
+    // the state machine, e.g. of a queue, are passed by reference
+    let (event_type, event_arg) = if sm.events_by_ref {
+        (
+            quote! { &#events_type_name <#event_lifetimes> },
+            quote! { &event },
+        )
+    } else {
+        (
+            quote! { #events_type_name <#event_lifetimes> },
+            quote! { event },
+        )
+    };
+    let process_event_signature = quote! {
+        (
+            &mut self,
+            #temporary_context
+            event: #event_type
+        ) -> Result<&#states_type_name <#state_lifetimes>, #error_type>
+    };
+    // Delegated events are processed by the sub-machine of the current state, before the
+    // transitions of the state
+    let mut sub_machine_list: Vec<_> = sm.sub_machines.iter().collect();
+    sub_machine_list.sort_by_key(|(state, _)| state.as_str());
+    let delegations: Vec<_> = sub_machine_list
+        .into_iter()
+        .flat_map(|(state, events)| {
+            let state_pattern = state_path(
+                sm,
+                &sm.states[state],
+                quote! { (sub_machine) },
+                &sm_name,
+                sm_name_span,
+            );
+            let (events_type_name, error_type_name) = (&events_type_name, &error_type_name);
+            events.iter().map(move |event| {
+                quote! {
+                    (#state_pattern, #events_type_name::#event(sub_event)) => {
+                        return match sub_machine.process_event(sub_event) {
+                            Ok(_) => Ok(&self.state),
+                            Err(_) => Err(#error_type_name::TransitionsFailed),
+                        };
+                    }
+                }
+            })
+        })
+        .collect();
+    let delegation = if delegations.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            let event = match (&mut self.state, event) {
+                #(#delegations)*
+                (_, event) => event,
+            };
+        }
+    };
+    // The state machine is finished in a state without transitions, which can no longer be left
+    let mut final_list: Vec<_> = sm
+        .states
+        .iter()
+        .filter(|(name, _)| {
+            sm.states_events_mapping
+                .get(*name)
+                .is_none_or(|mapping| mapping.is_empty())
+                && sm
+                    .parallel_states
+                    .get(*name)
+                    .is_none_or(|parallel| parallel.completion.is_none())
+        })
+        .map(|(name, state)| {
+            let data = if sm.state_data.data_types.contains_key(name)
+                || sm.parallel_states.contains_key(name)
+            {
+                quote! { (..) }
+            } else {
+                quote! {}
+            };
+            (name, state_path(sm, state, data, &sm_name, sm_name_span))
+        })
+        .collect();
+    final_list.sort_by_key(|(name, _)| name.as_str());
+    let final_patterns: Vec<_> = final_list.into_iter().map(|(_, pattern)| pattern).collect();
+    let is_finished = if final_patterns.is_empty() {
+        quote! { false }
+    } else {
+        quote! { matches!(self.state, #(#final_patterns)|*) }
+    };
+
+    // Events are no longer processed once a final state has been entered
+    let mut terminal_list: Vec<_> = sm.terminal_states.iter().collect();
+    terminal_list.sort_by_key(|(name, _)| name.as_str());
+    let terminal_patterns: Vec<_> = terminal_list
+        .into_iter()
+        .map(|(name, state)| {
+            let data = if sm.state_data.data_types.contains_key(name) {
+                quote! { (..) }
+            } else {
+                quote! {}
+            };
+            state_path(sm, state, data, &sm_name, sm_name_span)
+        })
+        .collect();
+    let (finished_error, finished_check, completion_callback) = if terminal_patterns.is_empty() {
+        (quote! {}, quote! {}, quote! {})
+    } else {
+        (
+            quote! {
+                /// When an event is processed after a final state has been entered.
+                MachineFinished,
+            },
+            quote! {
+                if matches!(self.state, #(#terminal_patterns)|*) {
+                    return Err(#error_type_name::MachineFinished);
+                }
+            },
+            quote! {
+                /// Called when a final state is entered, after which events are no longer
+                /// processed. No-op by default but can be overridden in implementations of a state
+                /// machine's `StateMachineContext` trait.
+                fn on_completion(&mut self, final_state: & #states_type_name) {}
+            },
+        )
+    };
+    // Unlike `peek_event`, `process_event` owns the event which is rejected
+    let process_finished_check = if terminal_patterns.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            if matches!(self.state, #(#terminal_patterns)|*) {
+                #reject_event
+                return Err(#error_type_name::MachineFinished);
+            }
+        }
+    };
+    // A poisoned state machine rejects every event until it is recovered
+    let process_finished_check = if sm.poisoning {
+        quote! {
+            if self.poisoned {
+                #reject_event
+                return Err(#error_type_name::Poisoned);
+            }
+            #process_finished_check
+        }
+    } else {
+        process_finished_check
+    };
+
+    // Peeking at an event evaluates the guards of its transitions in order, without running any
+    // actions, and reports the target of the first transition whose guards pass
+    let peek_transitions = |in_state: &str, event: &str, target: Option<&str>| {
+        let context = quote! { self.context };
+        let guard_params = region_parameters(in_state, event, false);
+        let checks = transitions[in_state][event]
+            .transitions
+            .iter()
+            .map(|transition| {
+                let result = match (&transition.choice, target) {
+                    (_, Some(target)) => quote! { smlang::PeekResult::Transition(#target) },
+                    (Some(_), None) => quote! { smlang::PeekResult::Choice },
+                    (None, None) => {
+                        let out_state = transition.out_state.to_string();
+                        quote! { smlang::PeekResult::Transition(#out_state) }
+                    }
+                };
+                match &transition.guard {
+                    Some(expr) => {
+                        let guard_expression = expr.to_token_stream(&mut |guard, timeout| {
+                            let call = guard_call(
+                                sm,
+                                &context,
+                                guard,
+                                timeout,
+                                &temporary_context_call,
+                                &guard_params,
+                                &error_type_name,
+                            );
+                            let map_guard_error = map_guard_error(&guard.ident);
+                            quote! { #call.map_err(#map_guard_error)? }
+                        });
+                        quote! {
+                            if #guard_expression {
+                                return Ok(#result);
+                            }
+                        }
+                    }
+                    None => quote! { return Ok(#result); },
+                }
+            });
+        quote! { #(#checks)* }
+    };
+    let peek_events = |in_state: &str, target: Option<&str>| {
+        let mut event_list: Vec<_> = transitions[in_state]
+            .iter()
+            .filter(|(event, _)| {
+                event.as_str() != TIMEOUT_EVENT && event.as_str() != COMPLETION_EVENT
+            })
+            .collect();
+        // Catch-all transitions are matched after the events which are handled explicitly
+        event_list.sort_by_key(|(event, _)| (event.as_str() == ANY_EVENT, event.as_str()));
+        event_list
+            .into_iter()
+            .map(|(event, event_mapping)| {
+                let event_ident = &event_mapping.event;
+                let event_pattern = match sm.event_data.data_types.get(event) {
+                    None if event == ANY_EVENT => quote! { _ },
+                    None => quote! { #events_type_name::#event_ident },
+                    Some(_) => quote! { #events_type_name::#event_ident(event_data) },
+                };
+                let checks = peek_transitions(in_state, event, target);
+                (event_pattern, checks)
+            })
+            .unzip::<_, _, Vec<_>, Vec<_>>()
+    };
+    let state_data_binding = |state: &str| {
+        if sm.state_data.data_types.contains_key(state) {
+            quote! { (ref state_data) }
+        } else {
+            quote! {}
+        }
+    };
+    let mut peek_list: Vec<_> = transitions
+        .keys()
+        .filter(|state| !sm.region_parents.contains_key(*state))
+        .collect();
+    peek_list.sort();
+    let peek_arms: Vec<_> = peek_list
+        .into_iter()
+        .filter_map(|in_state| {
+            let (events, checks) = peek_events(in_state, None);
+            let parallel = sm.parallel_states.get(in_state);
+            if events.is_empty() && parallel.is_none() {
+                return None;
+            }
+            let state_ident = sm
+                .states
+                .get(in_state)
+                .cloned()
+                .unwrap_or_else(|| format_ident!("{}", in_state));
+            let (data, regions) = match parallel {
+                None => (state_data_binding(in_state), quote! {}),
+                Some(parallel) => {
+                    // The transitions of the current sub-states of the regions are taken first,
+                    // which keep the parallel state
+                    let parallel_name = in_state.as_str();
+                    let regions = parallel.regions.iter().map(|region| {
+                        let field = region_field_name(&region.ident);
+                        let mut sub_states: Vec<_> = transitions
+                            .keys()
+                            .filter(|state| sm.region_parents.get(*state) == Some(&region.ident))
+                            .collect();
+                        sub_states.sort();
+                        let arms = sub_states.into_iter().map(|sub_state| {
+                            let sub_state_ident = format_ident!("{}", sub_state);
+                            let pattern = state_path(
+                                sm,
+                                &sub_state_ident,
+                                state_data_binding(sub_state),
+                                &sm_name,
+                                sm_name_span,
+                            );
+                            let (events, checks) = peek_events(sub_state, Some(parallel_name));
+                            quote! {
+                                #pattern => match event {
+                                    #(#events => {
+                                        #checks
+                                        guards_failed = true;
+                                    })*
+                                    _ => {}
+                                },
+                            }
+                        });
+                        quote! {
+                            match regions.#field {
+                                #(#arms)*
+                                _ => {}
+                            }
+                        }
+                    });
+                    (
+                        quote! { (ref regions) },
+                        quote! {
+                            let mut guards_failed = false;
+                            #(#regions)*
+                        },
+                    )
+                }
+            };
+            let invalid_event = if parallel.is_some() {
+                quote! {
+                    if guards_failed {
+                        Err(#error_type_name::TransitionsFailed)
+                    } else {
+                        Err(#error_type_name::InvalidEvent)
+                    }
+                }
+            } else {
+                quote! { Err(#error_type_name::InvalidEvent) }
+            };
+            let state_pattern = state_path(sm, &state_ident, data, &sm_name, sm_name_span);
+            Some(quote! {
+                #state_pattern => {
+                    #regions
+                    match event {
+                        #(#events => {
+                            #checks
+                            #[allow(unreachable_code)]
+                            Err(#error_type_name::TransitionsFailed)
+                        })*
+                        #[allow(unreachable_patterns)]
+                        _ => #invalid_event,
+                    }
+                }
+            })
+        })
+        .collect();
+    let peek_deferred = if sm.deferred_events.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            if self.is_deferred(event) {
+                return Ok(smlang::PeekResult::Deferred);
+            }
+        }
+    };
+    let mut sub_machine_list: Vec<_> = sm.sub_machines.iter().collect();
+    sub_machine_list.sort_by_key(|(state, _)| state.as_str());
+    let peek_delegations: Vec<_> = sub_machine_list
+        .into_iter()
+        .flat_map(|(state, events)| {
+            let state_pattern = state_path(
+                sm,
+                &sm.states[state],
+                quote! { (_) },
+                &sm_name,
+                sm_name_span,
+            );
+            let events_type_name = &events_type_name;
+            events.iter().map(move |event| {
+                quote! { (#state_pattern, #events_type_name::#event(_)) }
+            })
+        })
+        .collect();
+    let peek_delegation = if peek_delegations.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            if matches!((&self.state, event), #(#peek_delegations)|*) {
+                return Ok(smlang::PeekResult::Delegated);
+            }
+        }
+    };
+    // Guards can't be given the mutably borrowed data of an event which is only peeked at
+    let has_mutable_event_data = sm
+        .event_data
+        .data_types
+        .values()
+        .any(|data_type| matches!(data_type, Type::Reference(r) if r.mutability.is_some()));
+    // Nor can guards borrowing the context mutably be called
+    let has_mutable_guards = guard_set
+        .iter()
+        .any(|guard| sm.context_access.is_mutable_guard(&guard.to_string()));
+    let peek_code = if has_mutable_event_data || has_mutable_guards {
+        quote! {}
+    } else {
+        quote! {
+        /// Reports what processing the event would do in the current state, without running any
+        /// actions or changing the state.
+        ///
+        /// The guards of the transitions are evaluated in the order they would be for
+        /// `process_event`, and the errors it would return for the event are returned.
+        #[allow(unused_variables, unreachable_code, clippy::match_single_binding)]
+        pub #is_async fn peek_event <#event_unique_lifetimes> (
+            &self,
+            #temporary_context
+            event: &#events_type_name <#event_lifetimes>
+        ) -> Result<smlang::PeekResult, #error_type> {
+            #finished_check
+            #peek_delegation
+            #peek_deferred
+            match self.state {
+                #(#peek_arms)*
+                #[allow(unreachable_patterns)]
+                _ => Err(#error_type_name::InvalidEvent),
+            }
+        }
+        }
+    };
+
+    // Processing an event takes the `cancel` future of `process_event_cancellable` when async
+    // actions can be cancelled, which is passed on by the functions processing an event
+    let (inner_signature, bind_cancel, pass_cancel, no_cancel) = if is_cancellable_state_machine {
+        (
+            quote! {
+                (
+                    &mut self,
+                    #temporary_context
+                    event: #event_type,
+                    cancel: Option<core::pin::Pin<&mut (dyn core::future::Future<Output = ()> + Send)>>
+                ) -> Result<&#states_type_name <#state_lifetimes>, #error_type>
+            },
+            quote! { let mut cancel = cancel; },
+            quote! { , cancel },
+            quote! { , None },
+        )
+    } else {
+        (
+            process_event_signature.clone(),
+            quote! {},
+            quote! {},
+            quote! {},
+        )
+    };
+
+    let (match_arms, state_functions): (Vec<_>, Vec<_>) = state_arms
+        .into_iter()
+        .map(|(dispatch_pattern, function_name, arm)| {
+            (
+                quote! {
+                    #dispatch_pattern => self.#function_name(#temporary_context_call event #pass_cancel) #await_call
+                },
+                quote! {
+                    #[allow(unused_variables, unreachable_code, unreachable_patterns, clippy::match_single_binding)]
+                    #is_async fn #function_name <#event_unique_lifetimes> #inner_signature {
+                        #bind_cancel
+                        match self.state {
+                            #arm
+                            _ => {
+                                #reject_event
+                                Err(#error_type_name ::InvalidEvent)
+                            }
+                        }
+                    }
+                },
+            )
+        })
+        .unzip();
+
+    // Every transition block of a state and event is a handler, whose number is looked up in the
+    // table by the indices of the state and event, where 0 stands for no handler. Catch-all
+    // transitions handle the events of their state without handlers of their own.
+    // With `hash_dispatch`, only the handled pairs of states and events are kept in the table, at
+    // the slots of a perfect hash of their indices, and the catch-all handlers in a table of
+    // their own.
+    let (dispatch, dispatch_code) = if !is_table_dispatch {
+        (quote! {}, quote! {})
+    } else {
+        let mut table_events: Vec<_> = sm.events.iter().collect();
+        table_events.sort_by_key(|(name, _)| name.as_str());
+        let event_count = table_events.len();
+        let mut table = vec![0u16; table_states.len() * event_count];
+        let mut fallbacks = vec![0u16; table_states.len()];
+        let mut handlers = Vec::new();
+        let mut handler_names = Vec::new();
+        let mut state_patterns = Vec::new();
+        for (index, (_, state_pattern, dispatch_pattern, event_names, event_blocks)) in
+            table_states.into_iter().enumerate()
+        {
+            let row = &mut table[index * event_count..(index + 1) * event_count];
+            for (event, (event_pattern, code_block)) in event_names.into_iter().zip(event_blocks) {
+                let number = handlers.len() as u16 + 1;
+                if event == ANY_EVENT {
+                    fallbacks[index] = number;
+                } else if let Some(column) = table_events
+                    .iter()
+                    .position(|(name, _)| name.as_str() == event)
+                {
+                    row[column] = number;
+                }
+                let handler_name = format_ident!("dispatch_{}", number);
+                let reject_handled = reject(&event_expression(&event_pattern));
+                handlers.push(quote! {
+                    #[allow(unused_variables, unreachable_code, unreachable_patterns, clippy::match_single_binding)]
+                    #is_async fn #handler_name <#event_unique_lifetimes> #inner_signature {
+                        #bind_cancel
+                        match self.state {
+                            #state_pattern => match event {
+                                #event_pattern => {
+                                    #code_block
+
+                                    #[allow(unreachable_code)]
+                                    {
+                                        // none of the guarded or non-guarded transitions occurred,
+                                        #reject_handled
+                                        Err(#error_type_name ::TransitionsFailed)
+                                    }
+                                }
+                                _ => {
+                                    #reject_event
+                                    Err(#error_type_name ::InvalidEvent)
+                                }
+                            },
+                            _ => {
+                                #reject_event
+                                Err(#error_type_name ::InvalidEvent)
+                            }
+                        }
+                    }
+                });
+                handler_names.push(handler_name);
+            }
+            state_patterns.push(dispatch_pattern);
+        }
+        let state_indices = 0..state_patterns.len();
+        let event_patterns = table_events
+            .iter()
+            .map(|(_, ident)| quote! { #events_type_name::#ident { .. } });
+        let event_indices = 0..event_count;
+        let numbers = 1..=handler_names.len() as u16;
+        let (lookup, lookup_code) = if sm.hash_dispatch {
+            let entries: Vec<_> = table
+                .iter()
+                .enumerate()
+                .filter(|(_, handler)| **handler != 0)
+                .map(|(key, handler)| (key as u32, *handler))
+                .collect();
+            let keys: Vec<_> = entries.iter().map(|(key, _)| *key).collect();
+            let hash = PerfectHash::new(&keys);
+            let slots = hash.slots.iter().map(|slot| match slot {
+                Some(index) => {
+                    let (key, handler) = entries[*index];
+                    quote! { (#key, #handler) }
+                }
+                None => quote! { (u32::MAX, 0) },
+            });
+            let displacements = &hash.displacements;
+            let (bucket_count, slot_count) = (hash.displacements.len(), hash.slots.len());
+            let fallback_count = fallbacks.len();
+            // A single bucket or slot is indexed without hashing
+            let index = |hash: TokenStream, count: usize| {
+                if count == 1 {
+                    quote! { 0 }
+                } else {
+                    quote! { Self::dispatch_hash(#hash) as usize % #count }
+                }
+            };
+            let bucket = index(quote! { key }, bucket_count);
+            let slot = index(quote! { key ^ displacement }, slot_count);
+            (
+                quote! {
+                    let key = (state * #event_count + Self::dispatch_column(&event)) as u32;
+                    let displacement = Self::DISPLACEMENTS[#bucket];
+                    match Self::DISPATCH[#slot] {
+                        (slot_key, handler) if slot_key == key => handler,
+                        _ => Self::FALLBACKS[state],
+                    }
+                },
+                quote! {
+                    /// The handled pairs of states and events along with their handlers, at the
+                    /// slots of a perfect hash of their indices.
+                    const DISPATCH: [(u32, u16); #slot_count] = [#(#slots),*];
+
+                    /// The displacements of the keys of the buckets of the perfect hash.
+                    const DISPLACEMENTS: [u32; #bucket_count] = [#(#displacements),*];
+
+                    /// The handlers of the catch-all transitions of every state.
+                    const FALLBACKS: [u16; #fallback_count] = [#(#fallbacks),*];
+
+                    /// The finalizer of MurmurHash3, hashing the keys of the dispatch table.
+                    const fn dispatch_hash(mut key: u32) -> u32 {
+                        key ^= key >> 16;
+                        key = key.wrapping_mul(0x85eb_ca6b);
+                        key ^= key >> 13;
+                        key = key.wrapping_mul(0xc2b2_ae35);
+                        key ^= key >> 16;
+                        key
+                    }
+                },
+            )
+        } else {
+            // Every event without a handler of its own is handled by the catch-all handler
+            for (row, fallback) in table.chunks_mut(event_count.max(1)).zip(&fallbacks) {
+                row.iter_mut()
+                    .filter(|handler| **handler == 0)
+                    .for_each(|handler| *handler = *fallback);
+            }
+            let table_size = table.len();
+            (
+                quote! { Self::DISPATCH[state * #event_count + Self::dispatch_column(&event)] },
+                quote! {
+                    /// The handlers of the events in every state, indexed by the state and the
+                    /// event.
+                    const DISPATCH: [u16; #table_size] = [#(#table),*];
+                },
+            )
+        };
+        (
+            quote! {
+                let handler = match Self::dispatch_row(&self.state) {
+                    Some(state) => { #lookup }
+                    None => 0,
+                };
+                match handler {
+                    #(#numbers => self.#handler_names(#temporary_context_call event #pass_cancel) #await_call,)*
+                    _ => Err(#error_type_name ::InvalidEvent),
+                }
+            },
+            quote! {
+                #lookup_code
+
+                /// Returns the row of the state in the dispatch table.
+                #[allow(unreachable_patterns)]
+                fn dispatch_row(state: &#states_type_name <#state_lifetimes>) -> Option<usize> {
+                    match *state {
+                        #(#state_patterns => Some(#state_indices),)*
+                        _ => None,
+                    }
+                }
+
+                /// Returns the column of the event in the dispatch table.
+                fn dispatch_column <#event_unique_lifetimes> (event: &#events_type_name <#event_lifetimes>) -> usize {
+                    match *event {
+                        #(#event_patterns => #event_indices,)*
+                    }
+                }
+
+                #(#handlers)*
+            },
+        )
+    };
+
+    let process_event_body = if !is_table_dispatch {
+        quote! {
+            #bind_cancel
+            #start_timer
+            self.context.log_process_event(self.state(), &event);
+            #process_finished_check
+            #delegation
+            match self.state {
+                #(#match_arms),*
+            }
+        }
+    } else if match_arms.is_empty() {
+        quote! {
+            #bind_cancel
+            #start_timer
+            self.context.log_process_event(self.state(), &event);
+            #process_finished_check
+            #delegation
+            #dispatch
+        }
+    } else {
+        quote! {
+            #bind_cancel
+            #start_timer
+            self.context.log_process_event(self.state(), &event);
+            #process_finished_check
+            #delegation
+            match self.state {
+                #(#match_arms,)*
+                _ => { #dispatch }
+            }
+        }
+    };
+    // With tracing, every dispatched event is processed within a span naming the state and event,
+    // which is entered by futures for as long as they are polled
+    let process_event_body = if !cfg!(feature = "tracing") {
+        process_event_body
+    } else {
+        let span = quote! {
+            smlang::tracing::debug_span!(
+                "process_event",
+                state = Self::state_name(&self.state),
+                event = Self::event_name(&event),
+            )
+        };
+        if is_async_state_machine {
+            quote! {
+                let span = #span;
+                smlang::tracing::Instrument::instrument(async move { #process_event_body }, span).await
+            }
+        } else {
+            quote! {
+                let _span = #span.entered();
+                #process_event_body
+            }
+        }
+    };
+
+    // Completion transitions are taken after every transition, for as long as their guards pass
+    let (run_completions, completion_code) = if completion_states.is_empty() {
+        (quote! {}, quote! {})
+    } else {
+        let (state_patterns, code_blocks): (Vec<_>, Vec<_>) = completion_states.into_iter().unzip();
+        (
+            quote! { self.process_completions(#temporary_context_call) #await_call ?; },
+            quote! {
+                /// Takes the completion transitions of the current state until none of them
+                /// passes its guards.
+                #is_async fn process_completions(&mut self, #temporary_context) -> Result<(), #error_type> {
+                    loop {
+                        match self.complete(#temporary_context_call) #await_call {
+                            Ok(_) => {}
+                            Err(#error_type_name::InvalidEvent | #error_type_name::TransitionsFailed) => {
+                                return Ok(());
+                            }
+                            Err(error) => return Err(error),
+                        }
+                    }
+                }
+
+                #[allow(unused_variables, clippy::match_single_binding)]
+                #is_async fn complete(&mut self, #temporary_context) -> Result<&#states_type_name <#state_lifetimes>, #error_type> {
+                    match self.state {
+                        #(#state_patterns => {
+                            #(#code_blocks)*
+
+                            #[allow(unreachable_code)]
+                            {
+                                // none of the guarded or non-guarded transitions occurred,
+                                Err(#error_type_name ::TransitionsFailed)
+                            }
+                        }),*
+                        #[allow(unreachable_patterns)]
+                        _ => Err(#error_type_name ::InvalidEvent),
+                    }
+                }
+            },
+        )
+    };
+
+    // Events posted by actions are queued in the context and processed before `process_event`
+    // returns, after the event which has been processed. A single event is then processed by
+    // `process_single_event`.
+    // With `transition_outcome`, `process_event` returns the outcome of `process_event_state`,
+    // and with `event_log` the events accepted by `process_event_unlogged` are logged
+    let (logged_vis, logged_name) = if sm.transition_outcome {
+        (quote! {}, quote! { process_event_state })
+    } else {
+        (quote! { pub }, quote! { process_event })
+    };
+    let (outer_vis, outer_name) = if sm.event_log {
+        (quote! {}, quote! { process_event_unlogged })
+    } else {
+        (logged_vis.clone(), logged_name.clone())
+    };
+    // The event is moved into the guards and actions, so the log takes a clone of it, which is
+    // the only place where the generated code clones events
+    let (log_clone, log_append) = if sm.event_log {
+        (
+            quote! { let logged = event.clone(); },
+            quote! { smlang::EventLog::append(&mut self.context, &logged); },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    let event_log_code = if sm.event_log {
+        let (replay_params, replay_args) = match sm.state_data.data_types.get(&starting_state_name)
+        {
+            Some(st) => (quote! { state_data: #st, }, quote! { , state_data }),
+            None => (quote! {}, quote! {}),
+        };
+        quote! {
+            /// Process an event, which is appended to the event log of the context once it has
+            /// been accepted.
+            ///
+            /// It will return `Ok(&NextState)` if the transition was successful, or `Err(#error_type_name)`
+            /// if there was an error in the transition.
+            #logged_vis #is_async fn #logged_name <#event_unique_lifetimes> #process_event_signature {
+                #log_clone
+                self.process_event_unlogged(#temporary_context_call event) #await_call ?;
+                #log_append
+                Ok(&self.state)
+            }
+
+            /// Creates a state machine in its starting state and processes the events of an event
+            /// log in order, which are not logged again. Actions are run as when the events were
+            /// first processed, while timed transitions are not taken.
+            pub #is_async fn replay(
+                context: T,
+                #replay_params
+                #temporary_context
+                events: impl IntoIterator<Item = #events_type_name>
+            ) -> Result<Self, #error_type> {
+                let mut sm = Self::new(context #replay_args);
+                for event in events {
+                    sm.process_event_unlogged(#temporary_context_call #event_arg) #await_call ?;
+                }
+                Ok(sm)
+            }
+        }
+    } else {
+        quote! {}
+    };
+    // A simulation processes recorded events, e.g. of an event log, in a new state machine and
+    // collects every step, where the events are not logged again
+    let simulate_code = {
+        let (state_data_param, state_data_arg) =
+            match sm.state_data.data_types.get(&starting_state_name) {
+                Some(st) => (quote! { state_data: #st, }, quote! { , state_data }),
+                None => (quote! {}, quote! {}),
+            };
+        quote! {
+            /// Creates a state machine in its starting state and processes the events in order,
+            /// returning it along with the steps of the simulation collected in `S`, e.g. a
+            /// `Vec<smlang::SimulationStep>`. Events which are rejected don't stop the simulation,
+            /// and with `event_log`, the events are not logged again.
+            pub #is_async fn simulate<#event_unique_lifetimes S>(
+                context: T,
+                #state_data_param
+                #temporary_context
+                events: impl IntoIterator<Item = #events_type_name <#event_lifetimes>>
+            ) -> (Self, S)
+            where
+                S: Default + Extend<smlang::SimulationStep>,
+            {
+                let mut sm = Self::new(context #state_data_arg);
+                let mut steps = S::default();
+                for event in events {
+                    let from = Self::state_name(&sm.state);
+                    let event_name = Self::event_name(&event);
+                    let accepted = sm.#outer_name(#temporary_context_call #event_arg) #await_call .is_ok();
+                    steps.extend(core::iter::once(smlang::SimulationStep {
+                        from,
+                        event: event_name,
+                        to: Self::state_name(&sm.state),
+                        accepted,
+                    }));
+                }
+                (sm, steps)
+            }
+        }
+    };
+    let (process_event_vis, process_event_name, post_code, posted_struct, post_methods) = match sm
+        .post_capacity
+    {
+        None if is_cancellable_state_machine => (
+            quote! {},
+            quote! { process_single_event },
+            quote! {
+                /// Processes an event, where its async actions can't be cancelled.
+                #outer_vis #is_async fn #outer_name <#event_unique_lifetimes> #process_event_signature {
+                    self.process_single_event(#temporary_context_call event, None) #await_call
+                }
+            },
+            quote! {},
+            quote! {},
+        ),
+        None => (
+            outer_vis.clone(),
+            outer_name.clone(),
+            quote! {},
+            quote! {},
+            quote! {},
+        ),
+        Some(capacity) => {
+            let posted_type_name = format_ident!("{sm_name}PostedEvents", span = sm_name_span);
+            let empty_queue = core::iter::repeat_n(quote! { None }, capacity);
+            let empty_queue = quote! { [#(#empty_queue),*] };
+            (
+                quote! {},
+                quote! { process_single_event },
+                quote! {
+                    /// Processes an event, followed by the events posted by actions in the
+                    /// order they were posted. An error of a posted event is returned
+                    /// directly, while the events posted after it stay queued.
+                    #outer_vis #is_async fn #outer_name #process_event_signature {
+                        self.process_single_event(#temporary_context_call event #no_cancel) #await_call ?;
+                        while let Some(event) = self.context.posted_events().pop() {
+                            self.process_single_event(#temporary_context_call #event_arg #no_cancel) #await_call ?;
+                        }
+                        Ok(&self.state)
+                    }
+                },
+                quote! {
+                    /// The bounded queue of the events posted by actions, which are processed
+                    /// before `process_event` returns.
+                    pub struct #posted_type_name {
+                        events: [Option<#events_type_name>; #capacity],
+                    }
+
+                    impl #posted_type_name {
+                        /// Creates an empty queue.
+                        pub const fn new() -> Self {
+                            Self { events: #empty_queue }
+                        }
+
+                        /// Queues an event, which is returned as error when the queue is full.
+                        pub fn post(&mut self, event: #events_type_name) -> Result<(), #events_type_name> {
+                            match self.events.iter_mut().find(|slot| slot.is_none()) {
+                                Some(slot) => {
+                                    *slot = Some(event);
+                                    Ok(())
+                                }
+                                None => Err(event),
+                            }
+                        }
+
+                        /// Returns the number of queued events.
+                        pub fn len(&self) -> usize {
+                            self.events.iter().filter(|event| event.is_some()).count()
+                        }
+
+                        /// Returns `true` if no events are queued.
+                        pub fn is_empty(&self) -> bool {
+                            self.events[0].is_none()
+                        }
+
+                        /// Removes the event which has been queued first.
+                        fn pop(&mut self) -> Option<#events_type_name> {
+                            let event = self.events[0].take();
+                            self.events.rotate_left(1);
+                            event
+                        }
+                    }
+
+                    impl Default for #posted_type_name {
+                        fn default() -> Self {
+                            Self::new()
+                        }
+                    }
+                },
+                quote! {
+                    /// Returns the queue of the events posted by actions, which is held by the
+                    /// context.
+                    fn posted_events(&mut self) -> &mut #posted_type_name;
+
+                    /// Posts an event from an action, which is processed before
+                    /// `process_event` returns. The event is returned as error when the queue
+                    /// is full.
+                    fn post(&mut self, event: #events_type_name) -> Result<(), #events_type_name> {
+                        self.posted_events().post(event)
+                    }
+                },
+            )
+        }
+    };
+
+    // Events pushed to the lock-free event queue, e.g. from interrupt handlers, are processed by
+    // `process_queued`. The queue is split into a producer and a consumer, so a single context
+    // pushes events while another drains them with only atomic loads and stores. Every priority
+    // of events has its own ring buffer, which are drained from the highest priority.
+    let (queued_code, queue_struct) = match sm.queue_capacity {
+        None => (quote! {}, quote! {}),
+        Some(capacity) => {
+            let queue_type_name = format_ident!("{sm_name}EventQueue", span = sm_name_span);
+            let producer_type_name = format_ident!("{sm_name}EventProducer", span = sm_name_span);
+            let consumer_type_name = format_ident!("{sm_name}EventConsumer", span = sm_name_span);
+            let mut priorities: Vec<u8> = sm.event_priorities.values().copied().collect();
+            priorities.push(0);
+            priorities.sort_unstable_by(|a, b| b.cmp(a));
+            priorities.dedup();
+            let levels = priorities.len();
+            let level_of = |priority: u8| priorities.iter().position(|p| *p == priority).unwrap();
+            let mut prioritized: Vec<_> = sm.event_priorities.iter().collect();
+            prioritized.sort();
+            let level_code = if prioritized.is_empty() {
+                quote! {
+                    let _ = event;
+                    0
+                }
+            } else {
+                let events = prioritized
+                    .iter()
+                    .map(|(event, _)| sm.events[event.as_str()].clone());
+                let event_levels = prioritized.iter().map(|(_, priority)| level_of(**priority));
+                let default_level = level_of(0);
+                quote! {
+                    match event {
+                        #(#events_type_name::#events { .. } => #event_levels,)*
+                        #[allow(unreachable_patterns)]
+                        _ => #default_level,
+                    }
+                }
+            };
+            // One slot is kept free to tell a full ring buffer from an empty one
+            let slots = capacity + 1;
+            let empty_slots = core::iter::repeat_n(
+                quote! { core::cell::UnsafeCell::new(core::mem::MaybeUninit::uninit()) },
+                slots,
+            );
+            let empty_slots = quote! { [#(#empty_slots),*] };
+            let empty_slots = core::iter::repeat_n(empty_slots, levels);
+            let indices =
+                core::iter::repeat_n(quote! { core::sync::atomic::AtomicUsize::new(0) }, levels);
+            let indices = quote! { [#(#indices),*] };
+            // With the `critical-section` feature, events are pushed through a shared reference in
+            // a critical section, so any number of interrupt handlers can push to a queue in a
+            // `static` while the main task drains it with the consumer taken from it
+            let (consumer_field, consumer_init, critical_section_code) = if cfg!(
+                feature = "critical-section"
+            ) {
+                (
+                    quote! { consumer_taken: core::sync::atomic::AtomicBool, },
+                    quote! { consumer_taken: core::sync::atomic::AtomicBool::new(false), },
+                    quote! {
+                        /// Queues an event in a critical section, e.g. from interrupt handlers of
+                        /// any priority, and returns it as error when the queue is full for the
+                        /// priority of the event.
+                        pub fn push(&self, event: #events_type_name) -> Result<(), #events_type_name> {
+                            // Only one producer pushes at a time, as the others wait for the
+                            // critical section and `split` borrows the queue mutably
+                            smlang::critical_section::with(|_| unsafe { self.enqueue(event) })
+                        }
+
+                        /// Takes the consumer of the events pushed by `push`, which is returned
+                        /// only once, e.g. to the main task.
+                        pub fn take_consumer(&self) -> Option<#consumer_type_name<'_>> {
+                            use core::sync::atomic::Ordering;
+                            smlang::critical_section::with(|_| {
+                                if self.consumer_taken.load(Ordering::Relaxed) {
+                                    return None;
+                                }
+                                self.consumer_taken.store(true, Ordering::Relaxed);
+                                Some(#consumer_type_name { queue: self })
+                            })
+                        }
+                    },
+                )
+            } else {
+                (quote! {}, quote! {}, quote! {})
+            };
+            (
+                quote! {
+                    /// Processes the events of the event queue until the queue is empty, where
+                    /// events with a higher priority are processed first and events with the same
+                    /// priority in the order they were pushed. An error of an event is returned
+                    /// directly, while the other events stay queued.
+                    pub #is_async fn process_queued(
+                        &mut self,
+                        #temporary_context
+                        events: &mut #consumer_type_name<'_>
+                    ) -> Result<&#states_type_name <#state_lifetimes>, #error_type> {
+                        while let Some(event) = events.pop() {
+                            self.process_event(#temporary_context_call #event_arg) #await_call ?;
+                        }
+                        Ok(&self.state)
+                    }
+                },
+                quote! {
+                    /// A lock-free queue of events, which are pushed by its producer and processed
+                    /// by `process_queued` with its consumer.
+                    pub struct #queue_type_name {
+                        events: [[core::cell::UnsafeCell<core::mem::MaybeUninit<#events_type_name>>; #slots]; #levels],
+                        head: [core::sync::atomic::AtomicUsize; #levels],
+                        tail: [core::sync::atomic::AtomicUsize; #levels],
+                        #consumer_field
+                    }
+
+                    // Slots are only written by the producer before they are published by `tail`,
+                    // and only read by the consumer before they are released by `head`.
+                    unsafe impl Sync for #queue_type_name where #events_type_name: Send {}
+
+                    impl #queue_type_name {
+                        /// Creates an empty queue.
+                        pub const fn new() -> Self {
+                            Self {
+                                events: [#(#empty_slots),*],
+                                head: #indices,
+                                tail: #indices,
+                                #consumer_init
+                            }
+                        }
+
+                        /// Splits the queue into the producer pushing events and the consumer
+                        /// passed to `process_queued`.
+                        pub fn split(&mut self) -> (#producer_type_name<'_>, #consumer_type_name<'_>) {
+                            let queue = &*self;
+                            (#producer_type_name { queue }, #consumer_type_name { queue })
+                        }
+
+                        /// Returns the number of queued events.
+                        pub fn len(&self) -> usize {
+                            (0..#levels).map(|level| self.level_len(level)).sum()
+                        }
+
+                        /// Returns `true` if no events are queued.
+                        pub fn is_empty(&self) -> bool {
+                            self.len() == 0
+                        }
+
+                        /// Returns the number of queued events of a priority.
+                        fn level_len(&self, level: usize) -> usize {
+                            use core::sync::atomic::Ordering;
+                            let head = self.head[level].load(Ordering::Acquire);
+                            let tail = self.tail[level].load(Ordering::Acquire);
+                            (tail + #slots - head) % #slots
+                        }
+
+                        /// Returns the ring buffer of an event, by its priority.
+                        fn level(event: &#events_type_name) -> usize {
+                            #level_code
+                        }
+
+                        /// Queues an event, which is returned as error when the queue is full for
+                        /// the priority of the event.
+                        ///
+                        /// # Safety
+                        ///
+                        /// No other event may be queued at the same time.
+                        unsafe fn enqueue(&self, event: #events_type_name) -> Result<(), #events_type_name> {
+                            use core::sync::atomic::Ordering;
+                            let level = Self::level(&event);
+                            let tail = self.tail[level].load(Ordering::Relaxed);
+                            let next = (tail + 1) % #slots;
+                            if next == self.head[level].load(Ordering::Acquire) {
+                                return Err(event);
+                            }
+                            // The slot is not read by the consumer until `tail` has been moved past it
+                            unsafe { (*self.events[level][tail].get()).write(event) };
+                            self.tail[level].store(next, Ordering::Release);
+                            Ok(())
+                        }
+
+                        #critical_section_code
+                    }
+
+                    impl Default for #queue_type_name {
+                        fn default() -> Self {
+                            Self::new()
+                        }
+                    }
+
+                    impl Drop for #queue_type_name {
+                        fn drop(&mut self) {
+                            let mut events = #consumer_type_name { queue: self };
+                            while events.pop().is_some() {}
+                        }
+                    }
+
+                    /// Pushes events to an event queue, e.g. from an interrupt handler.
+                    pub struct #producer_type_name<'a> {
+                        queue: &'a #queue_type_name,
+                    }
+
+                    impl #producer_type_name<'_> {
+                        /// Queues an event, which is returned as error when the queue is full for
+                        /// the priority of the event.
+                        pub fn push(&mut self, event: #events_type_name) -> Result<(), #events_type_name> {
+                            // The producer is the only one pushing while the queue is split
+                            unsafe { self.queue.enqueue(event) }
+                        }
+
+                        /// Returns `true` if the queue is full for events of every priority.
+                        pub fn is_full(&self) -> bool {
+                            (0..#levels).all(|level| self.queue.level_len(level) == #capacity)
+                        }
+                    }
+
+                    /// Takes the events of an event queue, which are processed by
+                    /// `process_queued`.
+                    pub struct #consumer_type_name<'a> {
+                        queue: &'a #queue_type_name,
+                    }
+
+                    impl #consumer_type_name<'_> {
+                        /// Removes the event with the highest priority which has been queued first.
+                        fn pop(&mut self) -> Option<#events_type_name> {
+                            use core::sync::atomic::Ordering;
+                            for level in 0..#levels {
+                                let head = self.queue.head[level].load(Ordering::Relaxed);
+                                if head == self.queue.tail[level].load(Ordering::Acquire) {
+                                    continue;
+                                }
+                                // The slot has been written by the producer before `tail` was moved past it
+                                let event = unsafe { (*self.queue.events[level][head].get()).assume_init_read() };
+                                self.queue.head[level].store((head + 1) % #slots, Ordering::Release);
+                                return Some(event);
+                            }
+                            None
+                        }
+
+                        /// Returns `true` if no events are queued.
+                        pub fn is_empty(&self) -> bool {
+                            self.queue.is_empty()
+                        }
+                    }
+                },
+            )
+        }
+    };
+
+    // The events sorted by their names, which are chosen by their index with `arbitrary` and
+    // `kani`, so the events generated from the same bytes don't depend on the order of declaration
+    let mut sorted_events: Vec<_> = sm.events.iter().collect();
+    sorted_events.sort_by_key(|(name, _)| *name);
+
+    // With `arbitrary: true`, random events can be generated for property tests and fuzzing,
+    // except for events borrowing their data
+    let (arbitrary_impl, arbitrary_code) = if sm.arbitrary && event_lifetimes.is_empty() {
+        // With `fuzz`, the fuzz target checks the invariants of the state machine after every
+        // event, where only the states found reachable by the validation can be entered
+        let fuzz_code = if cfg!(feature = "fuzz") {
+            let reachable = crate::validation::reachable_states(sm);
+            let reachable_names: Vec<_> = named_states(sm)
+                .into_iter()
+                .map(|(name, _)| name)
+                .filter(|name| {
+                    reachable.contains(*name)
+                        || sm
+                            .parallel_states
+                            .get(*name)
+                            .is_some_and(|parallel| reachable.contains(&parallel.ident.to_string()))
+                })
+                .collect();
+            let invariants = &sm.invariants;
+            let invariant_names = sm.invariants.iter().map(|invariant| invariant.to_string());
+            // The data of a starting state is given along with the context
+            let (state_data_param, state_data_arg) =
+                match sm.state_data.data_types.get(&sm.starting_state.to_string()) {
+                    Some(st) => (quote! { state_data: #st, }, quote! { , state_data }),
+                    None => (quote! {}, quote! {}),
+                };
+            quote! {
+                /// A fuzz target processing the events generated from `data` with `arbitrary`
+                /// in a new state machine, e.g. from `libfuzzer_sys::fuzz_target!`.
+                ///
+                /// Panics when the state machine enters a state which cannot be reached from
+                /// the starting state, when a rejected event changes the state, when a finished
+                /// state machine is no longer finished, or when an invariant doesn't hold.
+                pub #is_async fn fuzz(
+                    context: T,
+                    #state_data_param
+                    #temporary_context
+                    data: &[u8]
+                ) {
+                    const REACHABLE: &[&str] = &[#(#reachable_names),*];
+                    let mut sm = Self::new(context #state_data_arg);
+                    let mut data = smlang::arbitrary::Unstructured::new(data);
+                    let Ok(mut events) = data.arbitrary_iter::<#events_type_name>() else {
+                        return;
+                    };
+                    while let Some(Ok(event)) = events.next() {
+                        let before = Self::state_name(&sm.state);
+                        let finished = sm.is_finished();
+                        let event_name = Self::event_name(&event);
+                        let rejected = matches!(
+                            sm.process_event(#temporary_context_call #event_arg) #await_call,
+                            Err(#error_type_name::InvalidEvent | #error_type_name::TransitionsFailed)
+                        );
+                        let after = Self::state_name(&sm.state);
+                        assert!(
+                            REACHABLE.contains(&after),
+                            "{} + {} entered the unreachable state {}",
+                            before,
+                            event_name,
+                            after
+                        );
+                        assert!(
+                            !rejected || before == after,
+                            "{} + {} was rejected, but changed the state to {}",
+                            before,
+                            event_name,
+                            after
+                        );
+                        assert!(
+                            !finished || sm.is_finished(),
+                            "{} + {} left the finished state machine for {}",
+                            before,
+                            event_name,
+                            after
+                        );
+                        #(
+                            assert!(
+                                sm.context.#invariants(&sm.state),
+                                "{} + {} broke the invariant `{}` in {}",
+                                before,
+                                event_name,
+                                #invariant_names,
+                                after
+                            );
+                        )*
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
+        (
+            arbitrary_impl_code(
+                &events_type_name,
+                sorted_events
+                    .iter()
+                    .map(|(name, ident)| {
+                        let data = sm.event_data.data_types.contains_key(*name).then(|| {
+                            match sm.event_generators.get(*name) {
+                                Some(generator) => quote! { #generator(u)? },
+                                None => quote! { smlang::arbitrary::Arbitrary::arbitrary(u)? },
+                            }
+                        });
+                        (*ident, data)
+                    })
+                    .collect(),
+            ),
+            quote! {
+                /// Processes the events generated from `data` with `arbitrary` in order, e.g.
+                /// in fuzz targets. Events which are not accepted in the current state are
+                /// skipped, and no more events are processed once `data` is exhausted.
+                pub #is_async fn process_arbitrary(
+                    &mut self,
+                    #temporary_context
+                    data: &[u8]
+                ) -> &#states_type_name <#state_lifetimes> {
+                    let mut data = smlang::arbitrary::Unstructured::new(data);
+                    if let Ok(mut events) = data.arbitrary_iter::<#events_type_name>() {
+                        while let Some(Ok(event)) = events.next() {
+                            let _ = self.process_event(#temporary_context_call #event_arg) #await_call;
+                        }
+                    }
+                    &self.state
+                }
+
+                #fuzz_code
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    // The invariants are predicates of the context on the current state, which are proven with
+    // Kani for every sequence of events up to a given length with the `kani` feature
+    let invariant_list = sm.invariants.iter().map(|invariant| {
+        quote! {
+            /// An invariant of the state machine, which has to hold in every state.
+            fn #invariant(&self, state: & #states_type_name) -> bool;
+        }
+    });
+    let invariant_list = quote! { #(#invariant_list)* };
+    let (kani_impl, kani_code) = if cfg!(feature = "kani") && event_lifetimes.is_empty() {
+        let (state_data_param, state_data_arg) =
+            match sm.state_data.data_types.get(&sm.starting_state.to_string()) {
+                Some(st) => (quote! { state_data: #st, }, quote! { , state_data }),
+                None => (quote! {}, quote! {}),
+            };
+        let invariants = &sm.invariants;
+        let invariant_names = sm.invariants.iter().map(|invariant| invariant.to_string());
+        let check_invariants = quote! {
+            #(
+                assert!(
+                    sm.context.#invariants(&sm.state),
+                    "the invariant `{}` doesn't hold in {}",
+                    #invariant_names,
+                    Self::state_name(&sm.state)
+                );
+            )*
+        };
+        (
+            kani_arbitrary_code(
+                &events_type_name,
+                sorted_events
+                    .iter()
+                    .map(|(name, ident)| (*ident, sm.event_data.data_types.contains_key(*name)))
+                    .collect(),
+            ),
+            // The harness is implemented in an anonymous constant like `kani::Arbitrary`
+            quote! {
+                #[allow(unexpected_cfgs)]
+                const _: () = {
+                    #[cfg(kani)]
+                    impl<#state_lifetimes T: #state_machine_context_type_name>
+                        #state_machine_type_name<#state_lifetimes T>
+                    {
+                        /// A Kani proof harness processing `steps` events chosen by `kani::any()`
+                        /// in a new state machine, which proves that the invariants hold in the
+                        /// starting state and after every event. The harness has to be unwound at
+                        /// least `steps + 1` times, e.g. with `#[kani::unwind(5)]` for 4 steps,
+                        /// and the context can be chosen by `kani::any()` as well.
+                        pub #is_async fn verify_invariants(
+                            context: T,
+                            #state_data_param
+                            #temporary_context
+                            steps: usize
+                        ) {
+                            let mut sm = Self::new(context #state_data_arg);
+                            #check_invariants
+                            for _ in 0..steps {
+                                let event: #events_type_name = kani::any();
+                                let _ = sm.process_event(#temporary_context_call #event_arg) #await_call;
+                                #check_invariants
+                            }
+                        }
+                    }
+                };
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    // The do-activity of the current state is raced against the next event, where the activity
+    // is dropped and thereby cancelled once the event is ready or the activity returned an event
+    let mut activity_list: Vec<_> = sm
+        .state_handlers
+        .iter()
+        .filter_map(|(state, handlers)| handlers.activity.as_ref().map(|a| (state, &a.ident)))
+        .collect();
+    activity_list.sort_by_key(|(state, _)| state.as_str());
+    let activity_code = if activity_list.is_empty() {
+        quote! {}
+    } else {
+        let (patterns, calls): (Vec<_>, Vec<_>) = activity_list
+            .into_iter()
+            .map(|(state, activity)| {
+                let state_ident = Ident::new(state, sm_name_span);
+                if sm.state_data.data_types.contains_key(state) {
+                    (
+                        state_path(
+                            sm,
+                            &state_ident,
+                            quote! { (ref state_data) },
+                            &sm_name,
+                            sm_name_span,
+                        ),
+                        quote! { self.context.#activity(state_data) },
+                    )
+                } else {
+                    (
+                        state_path(sm, &state_ident, quote! {}, &sm_name, sm_name_span),
+                        quote! { self.context.#activity() },
+                    )
+                }
+            })
+            .unzip();
+        quote! {
+            /// Runs the do-activity of the current state until it returns an event or `event` is
+            /// ready, whichever comes first, and processes that event. The activity is cancelled
+            /// by dropping it, so it is restarted when this is called again in the same state.
+            pub async fn process_next_event(
+                &mut self,
+                #temporary_context
+                event: impl core::future::Future<Output = #events_type_name>
+            ) -> Result<&#states_type_name <#state_lifetimes>, #error_type> {
+                let event = {
+                    let mut event = core::pin::pin!(event);
+                    let mut activity = core::pin::pin!(self.run_activity());
+                    core::future::poll_fn(|cx| match core::future::Future::poll(event.as_mut(), cx) {
+                        core::task::Poll::Ready(event) => core::task::Poll::Ready(event),
+                        core::task::Poll::Pending => core::future::Future::poll(activity.as_mut(), cx),
+                    })
+                    .await
+                };
+                self.process_event(#temporary_context_call #event_arg) #await_call ?;
+                Ok(&self.state)
+            }
+
+            /// Runs the do-activity of the current state until it returns an event, or never
+            /// completes if the current state has no do-activity.
+            async fn run_activity(&mut self) -> #events_type_name {
+                match self.state {
+                    #(#patterns => #calls.await,)*
+                    #[allow(unreachable_patterns)]
+                    _ => core::future::pending().await,
+                }
+            }
+        }
+    };
+
+    // Async guards with a timeout are raced against the clock of the context
+    let timed_out_error = if sm.guard_timeouts().iter().any(|timeout| timeout.is_error) {
+        quote! {
+            /// When an async guard does not resolve within its timeout.
+            GuardTimedOut,
+        }
+    } else {
+        quote! {}
+    };
+
+    // Async actions are cancelled once the `cancel` future is ready, which fails the transition
+    // before its states are exited
+    let (cancelled_error, cancellable_code) = if is_cancellable_state_machine {
+        let drain_posted = if sm.post_capacity.is_some() {
+            quote! {
+                while let Some(event) = self.context.posted_events().pop() {
+                    self.process_single_event(#temporary_context_call #event_arg, None) #await_call ?;
+                }
+            }
+        } else {
+            quote! {}
+        };
+        (
+            quote! {
+                /// When an async action is cancelled by `process_event_cancellable`.
+                Cancelled,
+            },
+            quote! {
+                /// Processes an event like `process_event`, where the async actions of the
+                /// transition are cancelled once `cancel` is ready, e.g. when a higher priority
+                /// event arrives. A cancelled transition returns `Error::Cancelled` and stays in
+                /// the current state.
+                pub async fn process_event_cancellable <#event_unique_lifetimes> (
+                    &mut self,
+                    #temporary_context
+                    event: #event_type,
+                    cancel: impl core::future::Future<Output = ()> + Send
+                ) -> Result<&#states_type_name <#state_lifetimes>, #error_type> {
+                    let cancel: core::pin::Pin<&mut (dyn core::future::Future<Output = ()> + Send)> =
+                        core::pin::pin!(cancel);
+                    #log_clone
+                    self.process_single_event(#temporary_context_call event, Some(cancel)) #await_call ?;
+                    #drain_posted
+                    #log_append
+                    Ok(&self.state)
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    // Re-deliver the deferred events in the order they were deferred, events which are still
+    // deferred are queued again
+    let redeliver = if run_completions.is_empty() {
+        quote! {
+            let _ = self.dispatch_event(#temporary_context_call event #no_cancel) #await_call;
+        }
+    } else {
+        quote! {
+            if self.dispatch_event(#temporary_context_call event #no_cancel) #await_call .is_ok() {
+                let _ = self.process_completions(#temporary_context_call) #await_call;
+            }
+        }
+    };
+    let redeliver_deferred = if sm.deferred_events.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            let deferred = core::mem::replace(&mut self.deferred, #empty_queue);
+            for event in IntoIterator::into_iter(deferred).flatten() {
+                if self.is_deferred(&event) {
+                    self.defer_event(event)?;
+                } else {
+                    #redeliver
+                }
+            }
+        }
+    };
+
+    // Deferred events are queued in the state machine, and re-delivered once another event has
+    // been processed
+    let (deferred_error, deferred_field, process_event) = if sm.deferred_events.is_empty() {
+        let process_event = if run_completions.is_empty() {
+            quote! {
+                #process_event_vis #is_async fn #process_event_name <#event_unique_lifetimes> #inner_signature {
+                    #process_event_body
+                }
+            }
+        } else {
+            quote! {
+                #process_event_vis #is_async fn #process_event_name <#event_unique_lifetimes> #inner_signature {
+                    self.dispatch_event(#temporary_context_call event #pass_cancel) #await_call ?;
+                    #run_completions
+                    Ok(&self.state)
+                }
+
+                #is_async fn dispatch_event <#event_unique_lifetimes> #inner_signature {
+                    #process_event_body
+                }
+            }
+        };
+        (quote! {}, quote! {}, process_event)
+    } else {
+        let capacity = sm.defer_capacity;
+
+        let mut deferred_list: Vec<_> = sm.deferred_events.iter().collect();
+        deferred_list.sort_by_key(|(state, _)| state.as_str());
+        let events_type_name = &events_type_name;
+        let deferred_patterns = deferred_list.into_iter().flat_map(|(state, events)| {
+            let state = &sm.states[state];
+            let state_pattern = state_path(sm, state, quote! { { .. } }, &sm_name, sm_name_span);
+            events
+                .iter()
+                .map(move |event| quote! { (#state_pattern, #events_type_name::#event { .. }) })
+        });
+
+        (
+            quote! {
+                /// When an event is deferred while the queue of deferred events is full.
+                DeferredEventsFull,
+            },
+            quote! {
+                deferred: [Option<#events_type_name>; #capacity],
+            },
+            quote! {
+                #process_event_vis #is_async fn #process_event_name #inner_signature {
+                    #start_timer
+                    if self.is_deferred(&event) {
+                        self.context.log_process_event(self.state(), &event);
+                        self.defer_event(event)?;
+                        return Ok(&self.state);
+                    }
+                    self.dispatch_event(#temporary_context_call event #pass_cancel) #await_call ?;
+                    #run_completions
+                    #redeliver_deferred
+                    Ok(&self.state)
+                }
+
+                /// Returns the number of deferred events waiting to be re-delivered.
+                #[inline(always)]
+                pub fn deferred_events(&self) -> usize {
+                    self.deferred.iter().filter(|event| event.is_some()).count()
+                }
+
+                #is_async fn dispatch_event #inner_signature {
+                    #process_event_body
+                }
+
+                fn is_deferred(&self, event: &#events_type_name) -> bool {
+                    matches!((&self.state, event), #(#deferred_patterns)|*)
+                }
+
+                fn defer_event(&mut self, event: #events_type_name) -> Result<(), #error_type> {
+                    match self.deferred.iter_mut().find(|slot| slot.is_none()) {
+                        Some(slot) => {
+                            *slot = Some(event);
+                            Ok(())
+                        }
+                        None => Err(#error_type_name::DeferredEventsFull),
+                    }
+                }
+            },
+        )
+    };
+
+    // Timed transitions are taken once their state has been active for the duration given with
+    // `after`, which is measured with the clock of the context
+    let timed_code = if sm.timeouts.is_empty() {
+        quote! {}
+    } else {
+        let mut timeout_list: Vec<_> = sm.timeouts.iter().collect();
+        timeout_list.sort_by_key(|(state, _)| state.as_str());
+        let (deadline_patterns, durations): (Vec<_>, Vec<_>) = timeout_list
+            .into_iter()
+            .map(|(state, duration)| {
+                let state = &sm.states[state];
+                (
+                    state_path(sm, state, quote! { { .. } }, &sm_name, sm_name_span),
+                    duration,
+                )
+            })
+            .unzip();
+
+        let (state_patterns, code_blocks): (Vec<_>, Vec<_>) = timed_states
+            .into_iter()
+            .map(|(_, state_pattern, code_blocks)| (state_pattern, code_blocks))
+            .unzip();
+        let timeout_body = quote! {
+            #start_timer
+            match self.state {
+                #(#state_patterns => {
+                    #(#code_blocks)*
+
+                    #[allow(unreachable_code)]
+                    {
+                        // none of the guarded or non-guarded transitions occurred,
+                        Err(#error_type_name ::TransitionsFailed)
+                    }
+                }),*
+                #[allow(unreachable_patterns)]
+                _ => Err(#error_type_name ::InvalidEvent),
+            }
+        };
+        let timeout_signature = quote! {
+            (&mut self, #temporary_context) -> Result<&#states_type_name <#state_lifetimes>, #error_type>
+        };
+        let process_timeout = if sm.deferred_events.is_empty() && run_completions.is_empty() {
+            quote! {
+                #[allow(unused_variables, clippy::match_single_binding)]
+                pub #is_async fn process_timeout #timeout_signature {
+                    #timeout_body
+                }
+            }
+        } else {
+            quote! {
+                pub #is_async fn process_timeout #timeout_signature {
+                    self.dispatch_timeout(#temporary_context_call) #await_call ?;
+                    #run_completions
+                    #redeliver_deferred
+                    Ok(&self.state)
+                }
+
+                #[allow(unused_variables, clippy::match_single_binding)]
+                #is_async fn dispatch_timeout #timeout_signature {
+                    #timeout_body
+                }
+            }
+        };
+
+        quote! {
+                /// Returns the time at which the timed transition of the current state is due, or
+                /// `None` if the current state has no timed transition.
+                pub fn deadline(&self) -> Option<core::time::Duration> {
+                    match self.state {
+                        #(#deadline_patterns => Some(self.timer_start() + #durations),)*
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    }
+                }
+
+                /// Completes once the timed transition of the current state is due, or never if
+                /// the current state has no timed transition.
+                pub async fn wait_timeout(&self) {
+                    match self.deadline() {
+                        Some(deadline) => smlang::Clock::sleep_until(#clock, deadline).await,
+                        None => core::future::pending().await,
+                    }
+                }
+
+                /// Takes the timed transition of the current state, regardless of whether it is
+                /// already due.
+                ///
+                /// It will return `Ok(&NextState)` if the transition was successful, or
+                /// `Err(#error_type_name)` if there was an error in the transition.
+                #process_timeout
+        }
+    };
+
+    // Everything time-related is measured with the clock of the context, which also tracks the
+    // time the current state has been active for
+    let (clock_bound, timer_field, clock_code) = if sm.uses_clock() {
+        (
+            // An associated clock is declared in the context instead
+            if sm.associated_types {
+                quote! {}
+            } else {
+                quote! { smlang::Clock }
+            },
+            quote! { state_entered: Option<core::time::Duration>, },
+            quote! {
+                /// Restarts the timer of the current state at the current time of the clock. The
+                /// timer of the starting state is started by the first processed event otherwise.
+                #[inline(always)]
+                pub fn restart_timer(&mut self) {
+                    self.state_entered = Some(smlang::Clock::now(#clock));
+                }
+
+                /// Returns the time the current state has been active for, measured with the clock
+                /// of the context since the state was entered or its timer was restarted, which is
+                /// zero until the timer of the starting state has been started.
+                #[inline(always)]
+                pub fn time_in_state(&self) -> core::time::Duration {
+                    smlang::Clock::now(#clock).saturating_sub(self.timer_start())
+                }
+
+                /// Returns the time the timer of the current state has been started at, which is
+                /// the current time until the timer of the starting state has been started.
+                #[inline(always)]
+                fn timer_start(&self) -> core::time::Duration {
+                    match self.state_entered {
+                        Some(entered) => entered,
+                        None => smlang::Clock::now(#clock),
+                    }
+                }
+
+                /// Starts the timer of the starting state when the first event is processed.
+                #[inline(always)]
+                fn start_timer(&mut self) {
+                    if self.state_entered.is_none() {
+                        self.restart_timer();
+                    }
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {}, quote! {})
+    };
+
+    // A state with a timeout is watched for staying active longer than the duration, after which
+    // the event of the timeout is processed
+    let mut watchdog_list: Vec<_> = sm
+        .state_handlers
+        .iter()
+        .filter_map(|(state, handlers)| handlers.watchdog.as_ref().map(|w| (state, w)))
+        .collect();
+    watchdog_list.sort_by_key(|(state, _)| state.as_str());
+    let watchdog_code = if watchdog_list.is_empty() {
+        quote! {}
+    } else {
+        let (patterns, watchdogs): (Vec<_>, Vec<_>) = watchdog_list
+            .into_iter()
+            .map(|(state, watchdog)| {
+                let state = &sm.states[state];
+                (
+                    state_path(sm, state, quote! { { .. } }, &sm_name, sm_name_span),
+                    watchdog,
+                )
+            })
+            .unzip();
+        let durations = watchdogs.iter().map(|watchdog| &watchdog.duration);
+        let events = watchdogs.iter().map(|watchdog| &watchdog.event);
+        quote! {
+            /// Returns the time at which the timeout of the current state expires, or `None` if
+            /// the current state has no timeout.
+            pub fn watchdog_deadline(&self) -> Option<core::time::Duration> {
+                match self.state {
+                    #(#patterns => Some(self.timer_start() + #durations),)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                }
+            }
+
+            /// Completes once the timeout of the current state expires, or never if the current
+            /// state has no timeout.
+            pub async fn wait_watchdog(&self) {
+                match self.watchdog_deadline() {
+                    Some(deadline) => smlang::Clock::sleep_until(#clock, deadline).await,
+                    None => core::future::pending().await,
+                }
+            }
+
+            /// Processes the event of the timeout of the current state, regardless of whether it
+            /// has already expired.
+            ///
+            /// It will return `Err(#error_type_name::InvalidEvent)` if the current state has no
+            /// timeout.
+            pub #is_async fn process_watchdog(
+                &mut self,
+                #temporary_context
+            ) -> Result<&#states_type_name <#state_lifetimes>, #error_type> {
+                let event = match self.state {
+                    #(#patterns => #events_type_name::#events,)*
+                    #[allow(unreachable_patterns)]
+                    _ => return Err(#error_type_name::InvalidEvent),
+                };
+                self.process_event(#temporary_context_call #event_arg) #await_call ?;
+                Ok(&self.state)
+            }
+        }
+    };
+
+    // The schema version of the persisted states is named like the machine description
+    let schema_version_name = if sm_name.is_empty() {
+        format_ident!("SCHEMA_VERSION", span = sm_name_span)
+    } else {
+        format_ident!(
+            "{}_SCHEMA_VERSION",
+            string_morph::to_snake_caps_case(&sm_name),
+            span = sm_name_span
+        )
+    };
+    let (schema_version, migrate_callback) = match sm.schema_version {
+        Some(version) => (
+            quote! {
+                /// The version of the persisted states, which is passed to `restore_versioned`.
+                pub const #schema_version_name: u32 = #version;
+            },
+            quote! {
+                /// Called by `restore_versioned` to migrate a state persisted with an older schema
+                /// `version` to the current states. Fails by default but can be overridden in
+                /// implementations of a state machine's `StateMachineContext` trait.
+                fn migrate<'de, D: serde::Deserializer<'de>>(
+                    &mut self,
+                    version: u32,
+                    state: D
+                ) -> Result<#states_type_name, D::Error> {
+                    let _ = (version, state);
+                    Err(serde::de::Error::custom(
+                        "the state was persisted by an older schema version",
+                    ))
+                }
+            },
+        ),
+        None => (quote! {}, quote! {}),
+    };
+    let mut described_states: Vec<_> = sm
+        .states
+        .keys()
+        .chain(sm.composite_states.keys())
+        .chain(sm.region_states.keys())
+        .collect();
+    described_states.sort();
+    described_states.dedup();
+    let mut described_events: Vec<_> = sm.events.keys().collect();
+    described_events.sort();
+    let described_rows = sm.described_transitions();
+    let described_transitions: Vec<_> = described_rows
+        .iter()
+        .map(|transition| {
+            let (from, event, to) = (&transition.from, &transition.event, &transition.to);
+            let guard = match &transition.guard {
+                Some(guard) => quote! { Some(#guard) },
+                None => quote! { None },
+            };
+            let action = match &transition.action {
+                Some(action) => quote! { Some(#action) },
+                None => quote! { None },
+            };
+            quote! {
+                smlang::TransitionDescription {
+                    from: #from,
+                    event: #event,
+                    guard: #guard,
+                    action: #action,
+                    to: #to,
+                }
+            }
+        })
+        .collect();
+
+    // The transition table and diagram in the rustdoc of the state machine
+    #[cfg(feature = "markdown")]
+    let machine_docs = {
+        let docs = crate::docgen::generate_docs(sm);
+        quote! { #[doc = #docs] }
+    };
+    #[cfg(not(feature = "markdown"))]
+    let machine_docs = quote! {};
+
+    // The same description as JSON, for tooling reading it from the binary
+    let machine_json_name = if sm_name.is_empty() {
+        format_ident!("MACHINE_JSON", span = sm_name_span)
+    } else {
+        format_ident!(
+            "{}_MACHINE_JSON",
+            string_morph::to_snake_caps_case(&sm_name),
+            span = sm_name_span
+        )
+    };
+    let json_string =
+        |value: &str| format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+    let json_option = |value: &Option<String>| match value {
+        Some(value) => json_string(value),
+        None => "null".to_string(),
+    };
+    let json_list = |values: &[&String]| {
+        let values: Vec<_> = values.iter().map(|value| json_string(value)).collect();
+        format!("[{}]", values.join(","))
+    };
+    let json_transitions: Vec<_> = described_rows
+        .iter()
+        .map(|transition| {
+            format!(
+                "{{\"from\":{},\"event\":{},\"guard\":{},\"action\":{},\"to\":{}}}",
+                json_string(&transition.from),
+                json_string(&transition.event),
+                json_option(&transition.guard),
+                json_option(&transition.action),
+                json_string(&transition.to)
+            )
+        })
+        .collect();
+    let machine_json = format!(
+        "{{\"name\":{},\"initial\":{},\"states\":{},\"events\":{},\"transitions\":[{}]}}",
+        json_option(&sm.name.as_ref().map(|name| name.to_string())),
+        json_string(&sm.starting_state.to_string()),
+        json_list(&described_states),
+        json_list(&described_events),
+        json_transitions.join(",")
+    );
+
+    // The events accepted in a state are those with transitions in the state or its composite
+    // states, and its deferred and delegated events, where `None` accepts any event
+    let accepted_events = |state: &str| -> Option<Vec<String>> {
+        let mut scopes: Vec<_> = sm.ancestors(state).iter().map(|a| a.to_string()).collect();
+        scopes.push(state.to_string());
+        let mut events = Vec::new();
+        for scope in &scopes {
+            for event in sm
+                .states_events_mapping
+                .get(scope)
+                .into_iter()
+                .flat_map(|m| m.keys())
+            {
+                match event.as_str() {
+                    ANY_EVENT => return None,
+                    TIMEOUT_EVENT | COMPLETION_EVENT => {}
+                    _ => events.push(event.clone()),
+                }
+            }
+            let deferred = sm.deferred_events.get(scope).into_iter().flatten();
+            let delegated = sm.sub_machines.get(scope).into_iter().flatten();
+            events.extend(deferred.chain(delegated).map(|event| event.to_string()));
+        }
+        events.sort();
+        events.dedup();
+        Some(events)
+    };
+    let accepts = |events: Option<Vec<String>>| match events {
+        None => quote! { true },
+        Some(events) if events.is_empty() => quote! { false },
+        Some(events) => quote! { matches!(event, #(#events)|*) },
+    };
+    let mut valid_states: Vec<_> = sm
+        .states
+        .iter()
+        .filter(|(name, _)| {
+            !sm.composite_states.contains_key(*name) && !sm.region_parents.contains_key(*name)
+        })
+        .collect();
+    valid_states.sort_by_key(|(name, _)| name.as_str());
+    let valid_event_arms: Vec<_> = valid_states
+        .into_iter()
+        .map(|(name, state)| {
+            let accepted = accepts(accepted_events(name));
+            let parallel = match sm.parallel_states.get(name) {
+                Some(parallel) => parallel,
+                None => {
+                    let data = if sm.state_data.data_types.contains_key(name) {
+                        quote! { (..) }
+                    } else {
+                        quote! {}
+                    };
+                    let pattern = state_path(sm, state, data, &sm_name, sm_name_span);
+                    return quote! { #pattern => #accepted, };
+                }
+            };
+            // A parallel state also accepts the events of the current sub-state of every region
+            let regions: Vec<_> = parallel
+                .regions
+                .iter()
+                .map(|region| {
+                    let field = region_field_name(&region.ident);
+                    let mut sub_states: Vec<_> = sm
+                        .region_parents
+                        .iter()
+                        .filter(|(_, parent)| **parent == region.ident)
+                        .map(|(sub_state, _)| sub_state)
+                        .collect();
+                    sub_states.sort();
+                    let arms = sub_states.into_iter().map(|sub_state| {
+                        let data = if sm.state_data.data_types.contains_key(sub_state) {
+                            quote! { (..) }
+                        } else {
+                            quote! {}
+                        };
+                        let sub_state_ident = format_ident!("{}", sub_state);
+                        let pattern =
+                            state_path(sm, &sub_state_ident, data, &sm_name, sm_name_span);
+                        let accepted = accepts(accepted_events(sub_state));
+                        quote! { #pattern => #accepted, }
+                    });
+                    quote! {
+                        match parallel.#field {
+                            #(#arms)*
+                            #[allow(unreachable_patterns)]
+                            _ => false,
+                        }
+                    }
+                })
+                .collect();
+            let pattern = state_path(sm, state, quote! { (ref parallel) }, &sm_name, sm_name_span);
+            quote! { #pattern => #accepted #(|| #regions)*, }
+        })
+        .collect();
+    let mut event_names: Vec<_> = sm.events.keys().collect();
+    event_names.sort();
+
+    // The outcomes, the history and the callbacks of transitions name their states and events,
+    // where the sub-states of regions are named by their parallel state
+    let (names_code, defmt_code, state_id_code) = {
+        let (state_patterns, state_names): (Vec<_>, Vec<_>) = named_states(sm)
+            .into_iter()
+            .map(|(name, state)| {
+                let data = if sm.state_data.data_types.contains_key(name)
+                    || sm.parallel_states.contains_key(name)
+                {
+                    quote! { (..) }
+                } else {
+                    quote! {}
+                };
+                (state_path(sm, state, data, &sm_name, sm_name_span), name)
+            })
+            .unzip();
+        let event_idents: Vec<_> = event_names.iter().map(|event| &sm.events[*event]).collect();
+        // With defmt, the states and events are formatted by their names, where an `events_type`
+        // implements `defmt::Format` itself
+        let events_defmt = if sm.events_type.is_none() {
+            quote! {
+                impl<#event_lifetimes> defmt::Format for #events_type_name <#event_lifetimes> {
+                    fn format(&self, f: defmt::Formatter) {
+                        match *self {
+                            #(#events_type_name::#event_idents { .. } => defmt::write!(f, #event_names),)*
+                        }
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
+        let defmt_code = if cfg!(feature = "defmt") {
+            quote! {
+                impl<#state_lifetimes> defmt::Format for #states_type_name <#state_lifetimes> {
+                    fn format(&self, f: defmt::Formatter) {
+                        match *self {
+                            #(#state_patterns => defmt::write!(f, #state_names),)*
+                        }
+                    }
+                }
+
+                #events_defmt
+            }
+        } else {
+            quote! {}
+        };
+
+        // The identifiers of the states are numbered in the order of their names
+        let id_variants: Vec<_> = named_states(sm)
+            .into_iter()
+            .map(|(_, state)| state)
+            .collect();
+        let ids: Vec<_> = (0..id_variants.len() as u8).collect();
+        let atomic_state_id_code = if sm.state_id_mirror {
+            quote! {
+                /// A mirror of the identifier of the current state, which other threads and
+                /// interrupts read wait-free while the state machine processes events.
+                pub struct #atomic_state_id_type_name(core::sync::atomic::AtomicU8);
+
+                impl #atomic_state_id_type_name {
+                    /// Creates a mirror of `id`, which is the identifier of the starting state.
+                    pub const fn new(id: #state_id_type_name) -> Self {
+                        Self(core::sync::atomic::AtomicU8::new(id as u8))
+                    }
+
+                    /// Returns the identifier of the current state.
+                    pub fn load(&self) -> #state_id_type_name {
+                        match core::convert::TryFrom::try_from(self.0.load(core::sync::atomic::Ordering::Acquire)) {
+                            Ok(id) => id,
+                            Err(_) => unreachable!("only identifiers are stored"),
+                        }
+                    }
+
+                    /// Stores the identifier of the current state, which the state machine does
+                    /// after every transition.
+                    pub fn store(&self, id: #state_id_type_name) {
+                        self.0.store(id as u8, core::sync::atomic::Ordering::Release);
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
+        let state_id_code = quote! {
+            /// The identifiers of the states without their data, numbered in the order of the
+            /// names of the states.
+            #[allow(missing_docs)]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+            #[repr(u8)]
+            pub enum #state_id_type_name { #(#id_variants = #ids),* }
+
+            impl From<#state_id_type_name> for u8 {
+                fn from(id: #state_id_type_name) -> u8 {
+                    id as u8
+                }
+            }
+
+            impl core::convert::TryFrom<u8> for #state_id_type_name {
+                type Error = u8;
+
+                /// Returns the identifier numbered `id`, or `id` when no state is numbered `id`.
+                fn try_from(id: u8) -> Result<Self, u8> {
+                    match id {
+                        #(#ids => Ok(#state_id_type_name::#id_variants),)*
+                        _ => Err(id),
+                    }
+                }
+            }
+
+            #atomic_state_id_code
+        };
+
+        // The identifier is either kept next to the states or matched from them
+        let state_id_fn = if sm.publish_state_id || sm.state_id_mirror {
+            quote! {
+                /// Returns the identifier of the current state, without its data.
+                #[inline(always)]
+                pub fn state_id(&self) -> #state_id_type_name {
+                    self.state_id
+                }
+
+                const fn id_of(state: &#states_type_name <#state_lifetimes>) -> #state_id_type_name {
+                    match *state {
+                        #(#state_patterns => #state_id_type_name::#id_variants,)*
+                    }
+                }
+            }
+        } else {
+            quote! {
+                /// Returns the identifier of the current state, without its data.
+                pub fn state_id(&self) -> #state_id_type_name {
+                    match self.state {
+                        #(#state_patterns => #state_id_type_name::#id_variants,)*
+                    }
+                }
+            }
+        };
+
+        let names_code = quote! {
+            #state_id_fn
+
+            fn state_name(state: &#states_type_name <#state_lifetimes>) -> &'static str {
+                match *state {
+                    #(#state_patterns => #state_names,)*
+                }
+            }
+
+            fn event_name <#event_unique_lifetimes> (event: &#events_type_name <#event_lifetimes>) -> &'static str {
+                match *event {
+                    #(#events_type_name::#event_idents { .. } => #event_names,)*
+                    // Variants of an `events_type` which are not events of the state machine
+                    #[allow(unreachable_patterns)]
+                    _ => "_",
+                }
+            }
+        };
+        (names_code, defmt_code, state_id_code)
+    };
+    // The variants of the states and events are enumerated, where only the variants without data
+    // can be iterated
+    let mut top_states: Vec<_> = sm
+        .states
+        .iter()
+        .chain(
+            sm.composite_states
+                .iter()
+                .map(|(name, composite)| (name, &composite.ident)),
+        )
+        .filter(|(name, _)| {
+            !sm.state_parents.contains_key(*name) && !sm.region_parents.contains_key(*name)
+        })
+        .collect();
+    top_states.sort_by_key(|(name, _)| name.as_str());
+    top_states.dedup_by_key(|(name, _)| name.as_str());
+    let plain_states = top_states.iter().filter(|(name, _)| {
+        !sm.state_data.data_types.contains_key(*name)
+            && !sm.composite_states.contains_key(*name)
+            && !sm.parallel_states.contains_key(*name)
+    });
+    let states_variants = variants_code(
+        &states_type_name,
+        &quote! { #state_lifetimes },
+        top_states.iter().map(|(name, _)| name.as_str()).collect(),
+        plain_states.map(|(_, ident)| *ident).collect(),
+    );
+    // An `events_type` enumerates its variants where it is defined
+    let events_variants = if sm.events_type.is_none() {
+        variants_code(
+            &events_type_name,
+            &quote! { #event_lifetimes },
+            event_names.iter().map(|name| name.as_str()).collect(),
+            event_names
+                .iter()
+                .filter(|name| !sm.event_data.data_types.contains_key(**name))
+                .map(|name| &sm.events[*name])
+                .collect(),
+        )
+    } else {
+        quote! {}
+    };
+
+    // With `display`, the states and events are displayed by their names, or the names given to
+    // the states, and the events without data are parsed from their names
+    let display_code = match sm.display {
+        Some(mode) => {
+            let with_data = mode == DisplayMode::Data;
+            let state_arms = named_states(sm).into_iter().map(|(name, state)| {
+                let display = sm
+                    .state_handlers
+                    .get(name)
+                    .and_then(|handlers| handlers.display.as_ref())
+                    .map(|display| display.value())
+                    .unwrap_or_else(|| name.clone());
+                if sm.parallel_states.contains_key(name) {
+                    let pattern = state_path(sm, state, quote! { (..) }, &sm_name, sm_name_span);
+                    quote! { #pattern => f.write_str(#display) }
+                } else if sm.state_data.data_types.contains_key(name) {
+                    if with_data {
+                        let pattern =
+                            state_path(sm, state, quote! { (ref data) }, &sm_name, sm_name_span);
+                        quote! { #pattern => write!(f, "{}({:?})", #display, data) }
+                    } else {
+                        let pattern =
+                            state_path(sm, state, quote! { (..) }, &sm_name, sm_name_span);
+                        quote! { #pattern => f.write_str(#display) }
+                    }
+                } else {
+                    let pattern = state_path(sm, state, quote! {}, &sm_name, sm_name_span);
+                    quote! { #pattern => f.write_str(#display) }
+                }
+            });
+            // An `events_type` is displayed and parsed where it is defined
+            let events_code = if sm.events_type.is_none() {
+                let event_arms = event_names.iter().map(|name| {
+                    let event = &sm.events[*name];
+                    if with_data && sm.event_data.data_types.contains_key(*name) {
+                        quote! {
+                            #events_type_name::#event(ref data) => write!(f, "{}({:?})", #name, data)
+                        }
+                    } else {
+                        quote! { #events_type_name::#event { .. } => f.write_str(#name) }
+                    }
+                });
+                let (parsed_names, parsed_events): (Vec<_>, Vec<_>) = event_names
+                    .iter()
+                    .filter(|name| !sm.event_data.data_types.contains_key(**name))
+                    .map(|name| (name.as_str(), &sm.events[*name]))
+                    .unzip();
+                quote! {
+                    impl<#event_lifetimes> core::fmt::Display for #events_type_name <#event_lifetimes> {
+                        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                            match *self {
+                                #(#event_arms,)*
+                            }
+                        }
+                    }
+
+                    /// Parses the events without data from their names.
+                    impl<#event_lifetimes> core::str::FromStr for #events_type_name <#event_lifetimes> {
+                        type Err = smlang::ParseEventError;
+
+                        fn from_str(name: &str) -> Result<Self, Self::Err> {
+                            match name {
+                                #(#parsed_names => Ok(#events_type_name::#parsed_events),)*
+                                _ => Err(smlang::ParseEventError),
+                            }
+                        }
+                    }
+                }
+            } else {
+                quote! {}
+            };
+            quote! {
+                impl<#state_lifetimes> core::fmt::Display for #states_type_name <#state_lifetimes> {
+                    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                        match *self {
+                            #(#state_arms,)*
+                        }
+                    }
+                }
+
+                #events_code
+            }
+        }
+        None => quote! {},
+    };
+    // With the `shared` feature, the state machine can be shared between tasks behind an async
+    // mutex
+    let shared_code = if cfg!(feature = "shared") {
+        let shared_type_name = format_ident!("{sm_name}SharedStateMachine", span = sm_name_span);
+        quote! {
+            /// A handle to a state machine shared between tasks, which locks the state machine
+            /// for every call.
+            ///
+            /// The lock is held while an event is processed, including the awaited guards and
+            /// actions, and is released before the methods return, so that several tasks can
+            /// process events without deadlocking. The guard returned by `lock` holds the lock
+            /// until it is dropped, and must not be held while the same task awaits another
+            /// method of the handle. Locks are taken in the order they are requested.
+            ///
+            /// The identifier of the new state is published to the `smlang::StateSink` whenever the
+            /// state changes.
+            pub struct #shared_type_name<#state_lifetimes T: #state_machine_context_type_name, S = ()>(
+                smlang::Arc<(smlang::async_lock::Mutex<#state_machine_type_name<#state_lifetimes T>>, S)>,
+            );
+
+            // Implemented by hand, as a derive would require the context to implement `Clone`
+            impl<#state_lifetimes T: #state_machine_context_type_name, S> Clone for #shared_type_name<#state_lifetimes T, S> {
+                fn clone(&self) -> Self {
+                    Self(self.0.clone())
+                }
+            }
+
+            impl<#state_lifetimes T: #state_machine_context_type_name> #shared_type_name<#state_lifetimes T> {
+                /// Shares the state machine, without publishing its states.
+                pub fn new(sm: #state_machine_type_name<#state_lifetimes T>) -> Self {
+                    Self::with_sink(sm, ())
+                }
+            }
+
+            impl<#state_lifetimes T: #state_machine_context_type_name, S: smlang::StateSink<#state_id_type_name>> #shared_type_name<#state_lifetimes T, S> {
+                /// Shares the state machine, publishing the identifiers of its states to `sink`.
+                pub fn with_sink(sm: #state_machine_type_name<#state_lifetimes T>, sink: S) -> Self {
+                    Self(smlang::Arc::new((smlang::async_lock::Mutex::new(sm), sink)))
+                }
+
+                /// Processes an event, returning the identifier of the new state.
+                pub async fn process_event <#event_unique_lifetimes> (
+                    &self,
+                    #temporary_context
+                    event: #events_type_name <#event_lifetimes>
+                ) -> Result<#state_id_type_name, #error_type> {
+                    let mut sm = self.0.0.lock().await;
+                    let from = sm.state_id();
+                    match sm.process_event(#temporary_context_call #event_arg) #await_call {
+                        Ok(_) => {
+                            let id = sm.state_id();
+                            if id != from {
+                                self.0.1.publish(id);
+                            }
+                            Ok(id)
+                        }
+                        Err(error) => Err(error),
+                    }
+                }
+
+                /// Returns the identifier of the current state.
+                pub async fn state(&self) -> #state_id_type_name {
+                    self.0.0.lock().await.state_id()
+                }
+
+                /// Locks the state machine until the guard is dropped, e.g. to access the data of
+                /// the state or the context.
+                ///
+                /// The states entered by events processed through the guard are not published.
+                pub async fn lock(&self) -> smlang::async_lock::MutexGuard<'_, #state_machine_type_name<#state_lifetimes T>> {
+                    self.0.0.lock().await
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    // With the `tokio` and `embassy` features, the state machine can be run as an actor draining
+    // a channel of events, which broadcasts the identifiers of its states
+    let actor_possible = sm.temporary_context_type.is_none()
+        && sm.state_data.all_lifetimes.is_empty()
+        && sm.event_data.all_lifetimes.is_empty();
+    let tokio_sink = if cfg!(feature = "tokio") {
+        quote! {
+            impl smlang::StateSink<#state_id_type_name> for smlang::tokio::sync::watch::Sender<#state_id_type_name> {
+                fn publish(&self, id: #state_id_type_name) {
+                    self.send_replace(id);
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let tokio_code = if cfg!(feature = "tokio") && actor_possible {
+        let actor_type_name = format_ident!("{sm_name}StateMachineActor", span = sm_name_span);
+        let sender_type_name = format_ident!("{sm_name}StateMachineSender", span = sm_name_span);
+        quote! {
+            /// A state machine run as an actor by `run`, which processes the events sent by its
+            /// `StateMachineSender`s.
+            pub struct #actor_type_name<T: #state_machine_context_type_name> {
+                sm: #state_machine_type_name<T>,
+                events: smlang::tokio::sync::mpsc::Receiver<#events_type_name>,
+                states: smlang::tokio::sync::watch::Sender<#state_id_type_name>,
+            }
+
+            impl<T: #state_machine_context_type_name> #actor_type_name<T> {
+                /// Processes the events in the order they have been sent until every sender has
+                /// been dropped, and returns the state machine.
+                ///
+                /// The identifier of the new state is broadcast once the state changes, while
+                /// rejected events are dropped.
+                pub async fn run(mut self) -> #state_machine_type_name<T> {
+                    while let Some(event) = self.events.recv().await {
+                        if self.sm.process_event(#event_arg) #await_call .is_ok() {
+                            let id = self.sm.state_id();
+                            self.states.send_if_modified(|state| core::mem::replace(state, id) != id);
+                        }
+                    }
+                    self.sm
+                }
+            }
+
+            /// A handle sending events to the actor of a state machine and receiving the
+            /// identifiers of its states.
+            #[derive(Clone)]
+            pub struct #sender_type_name {
+                events: smlang::tokio::sync::mpsc::Sender<#events_type_name>,
+                states: smlang::tokio::sync::watch::Receiver<#state_id_type_name>,
+            }
+
+            impl #sender_type_name {
+                /// Sends an event to the actor, waiting while the mailbox is full. Returns the
+                /// event when the actor has stopped.
+                pub async fn send(&self, event: #events_type_name) -> Result<(), #events_type_name> {
+                    self.events.send(event).await.map_err(|error| error.0)
+                }
+
+                /// Returns the identifier of the current state of the actor.
+                pub fn state(&self) -> #state_id_type_name {
+                    *self.states.borrow()
+                }
+
+                /// Returns a receiver of the identifiers of the states of the actor, which is
+                /// notified whenever the state changes.
+                pub fn subscribe(&self) -> smlang::tokio::sync::watch::Receiver<#state_id_type_name> {
+                    self.states.clone()
+                }
+            }
+
+            impl<T: #state_machine_context_type_name> #state_machine_type_name<T> {
+                /// Turns the state machine into an actor with a mailbox of `capacity` events,
+                /// which needs to be run, e.g. by `tokio::spawn(actor.run())`.
+                pub fn into_actor(self, capacity: usize) -> (#actor_type_name<T>, #sender_type_name) {
+                    let (events_sender, events) = smlang::tokio::sync::mpsc::channel(capacity);
+                    let (states, states_receiver) = smlang::tokio::sync::watch::channel(self.state_id());
+                    (
+                        #actor_type_name { sm: self, events, states },
+                        #sender_type_name { events: events_sender, states: states_receiver },
+                    )
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let embassy_code = if cfg!(feature = "embassy") && actor_possible {
+        // The actor races the next event against the timers of the timed transitions and
+        // timeouts and the do-activity of the current state, without allocating
+        let has_timeouts = !sm.timeouts.is_empty();
+        let has_watchdogs = sm.state_handlers.values().any(|h| h.watchdog.is_some());
+        let has_activities = sm.state_handlers.values().any(|h| h.activity.is_some());
+        let timer = |deadline: Ident, next: TokenStream| {
+            quote! {
+                let #deadline = self.#deadline();
+                let mut #deadline = core::pin::pin!(async move {
+                    match #deadline {
+                        Some(deadline) => {
+                            let deadline = embassy_time::Instant::from_micros(deadline.as_micros() as u64);
+                            embassy_time::Timer::at(deadline).await
+                        }
+                        None => core::future::pending().await,
+                    }
+                    #next
+                });
+            }
+        };
+        let (mut variants, mut futures, mut polls, mut arms) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        if has_timeouts {
+            variants.push(quote! { Timeout });
+            futures.push(timer(format_ident!("deadline"), quote! { Next::Timeout }));
+            polls.push(format_ident!("deadline"));
+            arms.push(quote! { Next::Timeout => self.process_timeout() #await_call });
+        }
+        if has_watchdogs {
+            variants.push(quote! { Watchdog });
+            futures.push(timer(
+                format_ident!("watchdog_deadline"),
+                quote! { Next::Watchdog },
+            ));
+            polls.push(format_ident!("watchdog_deadline"));
+            arms.push(quote! { Next::Watchdog => self.process_watchdog() #await_call });
+        }
+        if has_activities {
+            futures.push(quote! {
+                let mut activity = core::pin::pin!(async { Next::Event(self.run_activity().await) });
+            });
+            polls.push(format_ident!("activity"));
+        }
+        quote! {
+            impl<T: #state_machine_context_type_name> #state_machine_type_name<T> {
+                /// Runs the state machine as an actor, processing the events received from the
+                /// channel in the order they have been sent, e.g. in an embassy task.
+                ///
+                /// The identifier of the current state is sent to the watch once the actor starts
+                /// and whenever the state changes, while rejected events are dropped. Timed
+                /// transitions and timeouts are taken on the timers of `embassy_time`, which
+                /// requires the clock of the context to be `smlang::embassy_clock!()`, and the
+                /// do-activity of the current state runs until the next event is received.
+                pub async fn run_actor<M: smlang::embassy_sync::blocking_mutex::raw::RawMutex, const N: usize, const W: usize>(
+                    &mut self,
+                    events: smlang::embassy_sync::channel::Receiver<'_, M, #events_type_name, N>,
+                    states: smlang::embassy_sync::watch::Sender<'_, M, #state_id_type_name, W>,
+                ) -> ! {
+                    enum Next {
+                        Event(#events_type_name),
+                        #(#variants,)*
+                    }
+
+                    let mut id = self.state_id();
+                    states.send(id);
+                    loop {
+                        let next = {
+                            let mut event = core::pin::pin!(async { Next::Event(events.receive().await) });
+                            #(#futures)*
+                            core::future::poll_fn(|cx| {
+                                if let core::task::Poll::Ready(next) = core::future::Future::poll(event.as_mut(), cx) {
+                                    return core::task::Poll::Ready(next);
+                                }
+                                #(
+                                    if let core::task::Poll::Ready(next) = core::future::Future::poll(#polls.as_mut(), cx) {
+                                        return core::task::Poll::Ready(next);
+                                    }
+                                )*
+                                core::task::Poll::Pending
+                            })
+                            .await
+                        };
+                        let result = match next {
+                            Next::Event(event) => self.process_event(#event_arg) #await_call,
+                            #(#arms,)*
+                        };
+                        if result.is_ok() && self.state_id() != id {
+                            id = self.state_id();
+                            states.send(id);
+                        }
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    // With the `futures` feature, the events of streams and sinks are processed in async pipelines
+    let futures_code = if cfg!(feature = "futures") && actor_possible {
+        let sink_type_name = format_ident!("{sm_name}EventSink", span = sm_name_span);
+        quote! {
+            /// A `futures::Sink` processing the events sent to it, returned by
+            /// `StateMachine::sink`.
+            ///
+            /// An event is processed once the sink is flushed or polled for the next event, which
+            /// returns the error of a rejected event. The processing of the event is stored in the
+            /// sink itself, so nothing is allocated.
+            pub struct #sink_type_name<'a, T: #state_machine_context_type_name, F> {
+                sm: Option<&'a mut #state_machine_type_name<T>>,
+                processing: Option<F>,
+                process: fn(&'a mut #state_machine_type_name<T>, #events_type_name) -> F,
+            }
+
+            impl<'a, T, F> #sink_type_name<'a, T, F>
+            where
+                T: #state_machine_context_type_name,
+                F: core::future::Future<Output = (&'a mut #state_machine_type_name<T>, Result<(), #error_type>)>,
+            {
+                fn poll_processed(
+                    self: core::pin::Pin<&mut Self>,
+                    cx: &mut core::task::Context<'_>,
+                ) -> core::task::Poll<Result<(), #error_type>> {
+                    // The processing is pinned along with the sink, as it is never moved out of it
+                    // but only dropped in place
+                    let this = unsafe { self.get_unchecked_mut() };
+                    if let Some(processing) = this.processing.as_mut() {
+                        let processing = unsafe { core::pin::Pin::new_unchecked(processing) };
+                        let (sm, result) = core::task::ready!(processing.poll(cx));
+                        this.processing = None;
+                        this.sm = Some(sm);
+                        return core::task::Poll::Ready(result);
+                    }
+                    core::task::Poll::Ready(Ok(()))
+                }
+            }
+
+            impl<'a, T, F> smlang::futures::Sink<#events_type_name> for #sink_type_name<'a, T, F>
+            where
+                T: #state_machine_context_type_name,
+                F: core::future::Future<Output = (&'a mut #state_machine_type_name<T>, Result<(), #error_type>)>,
+            {
+                type Error = #error_type;
+
+                fn poll_ready(
+                    self: core::pin::Pin<&mut Self>,
+                    cx: &mut core::task::Context<'_>,
+                ) -> core::task::Poll<Result<(), Self::Error>> {
+                    self.poll_processed(cx)
+                }
+
+                fn start_send(
+                    self: core::pin::Pin<&mut Self>,
+                    event: #events_type_name,
+                ) -> Result<(), Self::Error> {
+                    // The processing is only replaced once it has completed, when it is `None`
+                    let this = unsafe { self.get_unchecked_mut() };
+                    let sm = this
+                        .sm
+                        .take()
+                        .expect("`poll_ready` is called before `start_send`");
+                    this.processing = Some((this.process)(sm, event));
+                    Ok(())
+                }
+
+                fn poll_flush(
+                    self: core::pin::Pin<&mut Self>,
+                    cx: &mut core::task::Context<'_>,
+                ) -> core::task::Poll<Result<(), Self::Error>> {
+                    self.poll_processed(cx)
+                }
+
+                fn poll_close(
+                    self: core::pin::Pin<&mut Self>,
+                    cx: &mut core::task::Context<'_>,
+                ) -> core::task::Poll<Result<(), Self::Error>> {
+                    self.poll_processed(cx)
+                }
+            }
+
+            impl<T: #state_machine_context_type_name> #state_machine_type_name<T> {
+                /// Returns a `futures::Sink` processing the events sent to it, e.g. to forward the
+                /// events decoded from a stream.
+                pub fn sink(
+                    &mut self,
+                ) -> #sink_type_name<
+                    '_,
+                    T,
+                    impl core::future::Future<Output = (&mut Self, Result<(), #error_type>)> + '_,
+                > {
+                    #sink_type_name {
+                        sm: Some(self),
+                        processing: None,
+                        process: Self::process_into_sink,
+                    }
+                }
+
+                /// Processes an event sent to the sink, which hands the state machine back to it.
+                async fn process_into_sink(
+                    &mut self,
+                    event: #events_type_name,
+                ) -> (&mut Self, Result<(), #error_type>) {
+                    let result = self.process_event(#event_arg) #await_call .map(|_| ());
+                    (self, result)
+                }
+
+                /// Processes the events of a `futures::Stream` until it ends, and returns the error
+                /// of the first rejected event, whose following events are not processed.
+                pub async fn process_stream(
+                    &mut self,
+                    events: impl smlang::futures::Stream<Item = #events_type_name>,
+                ) -> Result<(), #error_type> {
+                    let mut events = core::pin::pin!(events);
+                    while let Some(event) =
+                        core::future::poll_fn(|cx| events.as_mut().poll_next(cx)).await
+                    {
+                        self.process_event(#event_arg) #await_call ?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    // The state machine is regenerated whenever its SCXML document changes
+    let scxml_code = sm.scxml_path.as_ref().map(|path| {
+        quote! {
+            const _: &str = include_str!(#path);
+        }
+    });
+    // The guards of the state machine, named by the errors of guards with `custom_guard_error`
+    let (guard_failed_variant, guards_code) = if sm.custom_guard_error {
+        let variants: Vec<_> = guard_set
+            .iter()
+            .map(|guard| {
+                format_ident!(
+                    "{}",
+                    string_morph::to_pascal_case(&guard.to_string()),
+                    span = guard.span()
+                )
+            })
+            .collect();
+        let names = guard_set.iter().map(|guard| guard.to_string());
+        (
+            quote! { GuardFailed(#guards_type_name, T), },
+            quote! {
+                /// The guards of the state machine, which name the guard returning an error in
+                /// `GuardFailed`.
+                #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+                pub enum #guards_type_name {
+                    #(#variants),*
+                }
+
+                impl #guards_type_name {
+                    /// Returns the name of the guard, as it is declared in the transitions.
+                    pub fn name(&self) -> &'static str {
+                        match *self {
+                            #(Self::#variants => #names),*
+                        }
+                    }
+                }
+            },
+        )
+    } else {
+        (quote! { GuardFailed(T), }, quote! {})
+    };
+    let (poisoned_error, poisoned_field, poisoned_code) = if sm.poisoning {
+        let state_id_update = if sm.publish_state_id || sm.state_id_mirror {
+            quote! { self.state_id = Self::id_of(&self.state); }
+        } else {
+            quote! {}
+        };
+        let timer_reset = if sm.uses_clock() {
+            quote! { self.state_entered = Some(smlang::Clock::now(#clock)); }
+        } else {
+            quote! {}
+        };
+        (
+            quote! {
+                /// When an event is processed after a transition has been interrupted while the
+                /// states were exited and entered, until the state machine is recovered.
+                Poisoned,
+            },
+            quote! { poisoned: bool, },
+            quote! {
+                /// Returns `true` if a transition has been interrupted while the states were
+                /// exited and entered, by a panicking or failing handler or a dropped future.
+                #[inline(always)]
+                pub fn is_poisoned(&self) -> bool {
+                    self.poisoned
+                }
+
+                /// Recovers a poisoned state machine in the given state, after which events are
+                /// processed again. Unlike a transition, the entry handlers of the state are not
+                /// called.
+                pub fn recover_with(&mut self, state: #states_type_name <#state_lifetimes>) {
+                    self.state = state;
+                    #state_id_update
+                    #timer_reset
+                    self.poisoned = false;
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {}, quote! {})
+    };
+    // With `typestate`, handles of the state machine typed by a marker of their top-level state
+    // only have the events of the state as methods, where the state is checked after the event
+    let typestate_code = if sm.typestate {
+        let typestate_module = if sm_name.is_empty() {
+            format_ident!("typestate", span = sm_name_span)
+        } else {
+            format_ident!(
+                "{}_typestate",
+                string_morph::to_snake_case(&sm_name),
+                span = sm_name_span
+            )
+        };
+        let handle_type_name = format_ident!("{sm_name}Handle", span = sm_name_span);
+        let top_level = |state: &Ident| -> Option<Ident> {
+            if sm.region_states.contains_key(&state.to_string()) {
+                return None;
+            }
+            Some(
+                sm.ancestors(&state.to_string())
+                    .first()
+                    .map(|ancestor| (*ancestor).clone())
+                    .unwrap_or_else(|| state.clone()),
+            )
+        };
+        let variants = scoped_variants(sm, None, &sm_name, sm_name_span);
+        let markers = variants.iter().map(|(name, value, _)| {
+            let doc = format!("The marker of [{}::{}].", states_type_name, name);
+            quote! {
+                #[doc = #doc]
+                pub struct #value;
+            }
+        });
+        let impls = variants.iter().map(|(name, value, data)| {
+            let data_code = data.as_ref().map(|data| {
+                quote! {
+                    /// Returns the data of the state.
+                    pub fn data(&self) -> &#data {
+                        match &self.machine.state {
+                            #states_type_name::#value(data) => data,
+                            #[allow(unreachable_patterns)]
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+            });
+            // The handle of the starting state is created like the state machine
+            let new_code = (value == &&sm.starting_state).then(|| match data {
+                Some(data) => quote! {
+                    /// Creates a new state machine in the starting state.
+                    pub fn new(context: T, state_data: #data) -> Self {
+                        Self::assume(#state_machine_type_name::new(context, state_data))
+                    }
+                },
+                None => quote! {
+                    /// Creates a new state machine in the starting state.
+                    pub fn new(context: T) -> Self {
+                        Self::assume(#state_machine_type_name::new(context))
+                    }
+                },
+            });
+            // Composite and parallel states handle the events of their sub-states first
+            let events = if sm.composite_states.contains_key(name)
+                || sm.parallel_states.contains_key(name)
+            {
+                None
+            } else {
+                transitions.get(name)
+            };
+            let mut events: Vec<_> = events
+                .into_iter()
+                .flatten()
+                .filter(|(event, _)| {
+                    ![TIMEOUT_EVENT, COMPLETION_EVENT, ANY_EVENT].contains(&event.as_str())
+                })
+                .collect();
+            events.sort_by_key(|(event, _)| *event);
+            let methods = events.into_iter().filter_map(|(event, mapping)| {
+                // Only events whose transitions all lead to the same state have a method
+                let mut targets = mapping.transitions.iter().map(|transition| {
+                    if transition.choice.is_some() {
+                        None
+                    } else if transition.keep_state {
+                        Some((*value).clone())
+                    } else {
+                        top_level(&transition.out_state)
+                    }
+                });
+                let target = targets.next()??;
+                if !targets.all(|other| other.as_ref() == Some(&target)) {
+                    return None;
+                }
+                let event_ident = &mapping.event;
+                let method = format_ident!("{}", string_morph::to_snake_case(event));
+                let (parameter, event_value) = match sm.event_data.data_types.get(event) {
+                    Some(data_type) => (
+                        quote! { data: #data_type },
+                        quote! { #events_type_name::#event_ident(data) },
+                    ),
+                    None => (quote! {}, quote! { #events_type_name::#event_ident }),
+                };
+                let event_value = if sm.events_by_ref {
+                    quote! { &#event_value }
+                } else {
+                    event_value
+                };
+                let doc = format!(
+                    "Processes [{}::{}], leading to [{}::{}].",
+                    events_type_name.to_token_stream(),
+                    event,
+                    states_type_name,
+                    target
+                );
+                Some(quote! {
+                    #[doc = #doc]
+                    pub #is_async fn #method <#event_unique_lifetimes> (
+                        self,
+                        #temporary_context
+                        #parameter
+                    ) -> Result<
+                        #handle_type_name <#state_lifetimes #typestate_module::#target, T>,
+                        smlang::Untyped<#state_machine_type_name<#state_lifetimes T>, #error_type>,
+                    > {
+                        let mut machine = self.machine;
+                        let result = machine
+                            .process_event(#temporary_context_call #event_value)
+                            #await_call
+                            .map(|_| ());
+                        match result {
+                            Ok(()) if matches!(machine.state, #states_type_name::#target { .. }) => {
+                                Ok(#handle_type_name::assume(machine))
+                            }
+                            Ok(()) => Err(smlang::Untyped { machine, error: None }),
+                            Err(error) => Err(smlang::Untyped {
+                                machine,
+                                error: Some(error),
+                            }),
+                        }
+                    }
+                })
+            });
+            let try_doc = format!(
+                "Returns a handle of the state machine if it is in [{}::{}].",
+                states_type_name, name
+            );
+            quote! {
+                impl<#state_lifetimes T: #state_machine_context_type_name>
+                    #handle_type_name <#state_lifetimes #typestate_module::#value, T>
+                {
+                    #new_code
+
+                    #[doc = #try_doc]
+                    pub fn try_from_machine(
+                        machine: #state_machine_type_name<#state_lifetimes T>,
+                    ) -> Result<Self, #state_machine_type_name<#state_lifetimes T>> {
+                        if matches!(machine.state, #states_type_name::#value { .. }) {
+                            Ok(Self::assume(machine))
+                        } else {
+                            Err(machine)
+                        }
+                    }
+
+                    #data_code
+
+                    #(#methods)*
+                }
+            }
+        });
+        let module_doc = format!("The markers of the states of [{}].", handle_type_name);
+        quote! {
+            #[doc = #module_doc]
+            pub mod #typestate_module {
+                #(#markers)*
+            }
+
+            /// A handle of the state machine in the state of the marker `S`, which only has the
+            /// events of the state as methods.
+            #state_machine_vis struct #handle_type_name<#state_lifetimes S, T: #state_machine_context_type_name> {
+                machine: #state_machine_type_name<#state_lifetimes T>,
+                state: core::marker::PhantomData<S>,
+            }
+
+            impl<#state_lifetimes S, T: #state_machine_context_type_name> #handle_type_name<#state_lifetimes S, T> {
+                #[inline(always)]
+                fn assume(machine: #state_machine_type_name<#state_lifetimes T>) -> Self {
+                    #handle_type_name {
+                        machine,
+                        state: core::marker::PhantomData,
+                    }
+                }
+
+                /// Returns the state machine.
+                #[inline(always)]
+                pub fn machine(&self) -> &#state_machine_type_name<#state_lifetimes T> {
+                    &self.machine
+                }
+
+                /// Returns the current context as a mutable reference.
+                #[inline(always)]
+                pub fn context_mut(&mut self) -> &mut T {
+                    self.machine.context_mut()
+                }
+
+                /// Converts the handle into the state machine, whose state is only known at
+                /// runtime.
+                #[inline(always)]
+                pub fn into_machine(self) -> #state_machine_type_name<#state_lifetimes T> {
+                    self.machine
+                }
+            }
+
+            #(#impls)*
+        }
+    } else {
+        quote! {}
+    };
+    let (rejected_field, rejected_code) = if sm.rejected_events {
+        let processed = if sm.transition_outcome {
+            quote! {
+                Ok(outcome) => Ok(outcome),
+            }
+        } else {
+            quote! {
+                Ok(_) => Ok(&self.state),
+            }
+        };
+        let ok_type = if sm.transition_outcome {
+            quote! { smlang::TransitionOutcome }
+        } else {
+            quote! { &#states_type_name <#state_lifetimes> }
+        };
+        (
+            quote! { rejected: Option<#events_type_name>, },
+            quote! {
+                /// Process an event, returning the event along with the error and the current state
+                /// when it is rejected.
+                ///
+                /// The event is returned unless the data of the event has been passed to an action
+                /// before the error.
+                pub #is_async fn try_process_event(
+                    &mut self,
+                    #temporary_context
+                    event: #events_type_name
+                ) -> Result<#ok_type, smlang::Rejected<#events_type_name, #error_type>> {
+                    self.rejected = None;
+                    match self.process_event(#temporary_context_call event) #await_call {
+                        #processed
+                        Err(error) => Err(smlang::Rejected {
+                            event: self.rejected.take(),
+                            state: Self::state_name(&self.state),
+                            error,
+                        }),
+                    }
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    let (outcome_field, outcome_code) = if sm.transition_outcome {
+        (
+            quote! { action_taken: bool, },
+            quote! {
+                /// Process an event, returning the outcome of its transition.
+                ///
+                /// It will return `Ok(TransitionOutcome)` if the transition was successful, or
+                /// `Err(#error_type_name)` if there was an error in the transition.
+                pub #is_async fn process_event <#event_unique_lifetimes> (
+                    &mut self,
+                    #temporary_context
+                    event: #event_type
+                ) -> Result<smlang::TransitionOutcome, #error_type> {
+                    let from = Self::state_name(&self.state);
+                    let event_name = Self::event_name(&event);
+                    self.action_taken = false;
+                    self.process_event_state(#temporary_context_call event) #await_call ?;
+                    Ok(smlang::TransitionOutcome {
+                        from,
+                        to: Self::state_name(&self.state),
+                        event: event_name,
+                        action_taken: self.action_taken,
+                    })
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    // The last transitions are recorded with the time of the clock for post-mortem debugging
+    let (transition_history_field, transition_history_code) = match sm.transition_history {
+        Some(capacity) => (
+            quote! { transition_history: smlang::TransitionHistory<#capacity>, },
+            quote! {
+                /// Returns the last transitions between states, along with the time of the
+                /// clock when they were taken.
+                #[inline(always)]
+                pub fn transition_history(&self) -> &smlang::TransitionHistory<#capacity> {
+                    &self.transition_history
+                }
+
+                fn record_transition(&mut self, from: &'static str, event: &'static str, to: &'static str) {
+                    let timestamp = smlang::Clock::now(#clock);
+                    self.transition_history.push(smlang::TransitionRecord {
+                        from,
+                        event,
+                        to,
+                        timestamp,
+                    });
+                }
+            },
+        ),
+        None => (quote! {}, quote! {}),
+    };
+
+    // The time spent in every state is tracked with the clock, as the time the state has been
+    // active for when it is left
+    // In tests, every transition taken by an event can be walked from a new state machine in its
+    // state, with the contexts and data of the fixtures implemented by the test
+    let walker_code = if sm.temporary_context_type.is_none()
+        && state_lifetimes.is_empty()
+        && event_lifetimes.is_empty()
+    {
+        let fixtures_name = format_ident!("{sm_name}TransitionFixtures", span = sm_name_span);
+        let indices = sm.transition_indices();
+        let mut fixture_methods = Vec::new();
+        let mut data_states: Vec<_> = sm.state_data.data_types.iter().collect();
+        data_states.sort_by_key(|(name, _)| name.as_str());
+        for (name, data_type) in data_states {
+            let method = format_ident!("state_{}", string_morph::to_snake_case(name));
+            let doc = format!(" Returns the data of the state `{name}`.");
+            fixture_methods.push(quote! {
+                #[doc = #doc]
+                fn #method(&mut self) -> #data_type;
+            });
+        }
+        let mut data_events: Vec<_> = sm.event_data.data_types.iter().collect();
+        data_events.sort_by_key(|(name, _)| name.as_str());
+        for (name, data_type) in data_events {
+            let method = format_ident!("event_{}", string_morph::to_snake_case(name));
+            let doc = format!(" Returns the data of the event `{name}`.");
+            fixture_methods.push(quote! {
+                #[doc = #doc]
+                fn #method(&mut self) -> #data_type;
+            });
+        }
+
+        let mut walked_states: Vec<_> = sm
+            .states_events_mapping
+            .iter()
+            .filter(|(state, _)| !sm.region_states.contains_key(*state))
+            .collect();
+        walked_states.sort_by_key(|(state, _)| state.as_str());
+        let event_ref = if sm.events_by_ref {
+            quote! { & }
+        } else {
+            quote! {}
+        };
+        let mut walks = Vec::new();
+        for (state, mappings) in walked_states {
+            let ident = match sm.parallel_states.get(state) {
+                Some(parallel) => &parallel.ident,
+                None => &sm.states[state],
+            };
+            let data = if sm.parallel_states.contains_key(state) {
+                parallel_initial(sm, state, &sm_name, sm_name_span)
+            } else if sm.state_data.data_types.contains_key(state) {
+                let method = format_ident!("state_{}", string_morph::to_snake_case(state));
+                quote! { (fixtures.#method()) }
+            } else {
+                quote! {}
+            };
+            let state_value = state_path(sm, ident, data, &sm_name, sm_name_span);
+
+            let mut events: Vec<_> = mappings.iter().collect();
+            events.sort_by_key(|(event, _)| event.as_str());
+            for (event, mapping) in events {
+                // Catch-all transitions are walked with the first event without a transition
+                let event = match event.as_str() {
+                    TIMEOUT_EVENT | COMPLETION_EVENT => continue,
+                    ANY_EVENT => {
+                        let mut unhandled: Vec<_> = sm
+                            .events
+                            .keys()
+                            .filter(|event| !mappings.contains_key(*event))
+                            .collect();
+                        unhandled.sort();
+                        match unhandled.first() {
+                            Some(event) => *event,
+                            None => continue,
+                        }
+                    }
+                    event => event,
+                };
+                let event_ident = &sm.events[event];
+                let event_value = if sm.event_data.data_types.contains_key(event) {
+                    let method = format_ident!("event_{}", string_morph::to_snake_case(event));
+                    quote! { #events_type_name::#event_ident(fixtures.#method()) }
+                } else {
+                    quote! { #events_type_name::#event_ident }
+                };
+
+                let key = (state.clone(), mapping.event.to_string());
+                for (transition, index) in mapping.transitions.iter().zip(&indices[&key]) {
+                    let targets: Vec<_> = match &transition.choice {
+                        Some(choice) => choice
+                            .branches
+                            .iter()
+                            .enumerate()
+                            .map(|(branch, b)| {
+                                (index + branch, sm.resolve_initial(&b.out_state.ident))
+                            })
+                            .collect(),
+                        None if transition.keep_state => vec![(*index, ident.clone())],
+                        None => vec![(*index, sm.resolve_initial(&transition.out_state))],
+                    };
+                    for (row, target) in targets {
+                        let target = target.to_string();
+                        walks.push(quote! {
+                            let transition = &#description_name.transitions[#row];
+                            let mut sm = Self::new_with_state(fixtures.context(transition), #state_value);
+                            let rejected = sm.process_event(#event_ref #event_value) #await_call .is_err();
+                            let state = Self::state_name(&sm.state);
+                            assert!(
+                                state == #target && !rejected,
+                                "walking `{}` ended in {}{}",
+                                transition,
+                                state,
+                                if rejected { " with an error" } else { "" },
+                            );
+                        });
+                    }
+                }
+            }
+        }
+
+        quote! {
+            /// The contexts and the data of states and events the transitions are walked with by
+            /// `walk_transitions()`.
+            #[cfg(test)]
+            pub trait #fixtures_name<T> {
+                /// Returns the context the transition is taken with, whose guards need to let it
+                /// pass.
+                fn context(&mut self, transition: &smlang::TransitionDescription) -> T;
+
+                #(#fixture_methods)*
+            }
+
+            #[cfg(test)]
+            impl<T: #state_machine_context_type_name> #state_machine_type_name<T> {
+                /// Takes every transition of an event from a new state machine in the state of
+                /// the transition, with the context and data of the fixtures, and panics if it
+                /// doesn't end in the target state of the transition.
+                pub #is_async fn walk_transitions(fixtures: &mut impl #fixtures_name<T>) {
+                    #({ #walks })*
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // The transitions taken in tests are recorded by their index in the machine description
+    let (coverage_field, coverage_code) = if sm.transition_coverage {
+        let count = described_rows.len();
+        (
+            quote! { coverage: smlang::TransitionCoverage<#count>, },
+            quote! {
+                /// Returns the transitions which have been taken since the state machine has been
+                /// created.
+                #[inline(always)]
+                pub fn coverage(&self) -> &smlang::TransitionCoverage<#count> {
+                    &self.coverage
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    let (statistics_field, statistics_code) = if sm.statistics {
+        let count = named_states(sm).len();
+        (
+            quote! { statistics: [smlang::StateStatistics; #count], },
+            quote! {
+                /// Returns the time spent in every state, measured with the clock of the context,
+                /// where the total time of the current state includes its current visit.
+                pub fn statistics(&self) -> [smlang::StateStatistics; #count] {
+                    let mut statistics = self.statistics;
+                    let current = Self::state_name(&self.state);
+                    let time_in_state = self.time_in_state();
+                    for statistic in statistics.iter_mut().filter(|s| s.state == current) {
+                        statistic.total += time_in_state;
+                    }
+                    statistics
+                }
+
+                fn record_visit(&mut self, state: &'static str, time: core::time::Duration) {
+                    if let Some(statistic) = self.statistics.iter_mut().find(|s| s.state == state) {
+                        statistic.visits += 1;
+                        statistic.total += time;
+                        statistic.last = time;
+                    }
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    // The context is the clock of the timers, the log of the accepted events and the metrics
+    let mut supertrait_list = vec![];
+    if !clock_bound.is_empty() {
+        supertrait_list.push(clock_bound);
+    }
+    if sm.event_log {
+        supertrait_list.push(quote! { smlang::EventLog<#events_type_name> });
+    }
+    if sm.metrics {
+        supertrait_list.push(quote! { smlang::Metrics });
+    }
+    let supertraits = if supertrait_list.is_empty() {
+        quote! {}
+    } else {
+        quote! { : #(#supertrait_list)+* }
+    };
+    // With `associated_types`, the clock and the logger are associated types of the context,
+    // which the logging callbacks forward to by default
+    let (associated_types, log_process_event, log_guard, log_action, log_transition) =
+        if sm.associated_types {
+            let clock = if sm.uses_clock() {
+                quote! {
+                    /// The clock of the timers of the state machine.
+                    type Clock: smlang::Clock;
+
+                    /// Returns the clock of the timers of the state machine.
+                    fn clock(&self) -> &Self::Clock;
+                }
+            } else {
+                quote! {}
+            };
+            (
+                quote! {
+                    #clock
+
+                    /// The logger of the events, guards, actions and transitions of the state
+                    /// machine, which can be `()` to log nothing.
+                    type Logger: smlang::Logger<#states_type_name, #events_type_name>;
+
+                    /// Returns the logger of the state machine.
+                    fn logger(&self) -> &Self::Logger;
+                },
+                quote! { smlang::Logger::process_event(self.logger(), current_state, event) },
+                quote! { smlang::Logger::guard(self.logger(), guard, result) },
+                quote! { smlang::Logger::action(self.logger(), action) },
+                quote! { smlang::Logger::transition(self.logger(), old_state, new_state) },
+            )
+        } else {
+            (quote! {}, quote! {}, quote! {}, quote! {}, quote! {})
+        };
+
+    // A derived `Hash` would hash the data of the variants, which are not compared by the
+    // `PartialEq` of the states and events, so it is implemented like `PartialEq` instead
+    let (states_attr_list, hash_states) = without_hash_derive(&sm.states_attr);
+    let (events_attr_list, hash_events) = without_hash_derive(&sm.events_attr);
+    let events_hash = if hash_events {
+        quote! {
+            /// Manually define Hash for #events_type_name based on variant only, like PartialEq
+            impl<#event_lifetimes> core::hash::Hash for #events_type_name <#event_lifetimes> {
+                fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+                    core::hash::Hash::hash(&core::mem::discriminant(self), state);
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    // An enum of events defined outside of the state machine is named through an alias, and
+    // implements its own traits
+    let events_code = match &sm.events_type {
+        Some(events_type) => quote! {
+            /// The events of the state machine, defined outside of it.
+            #events_vis type #events_type_name <#event_lifetimes> = #events_type <#event_lifetimes>;
+        },
+        None => quote! {
+            /// List of auto-generated events.
+            #[allow(missing_docs)]
+            #(#events_attr_list)*
+            #events_vis enum #events_type_name <#event_lifetimes> { #(#event_list),* }
+
+            #arbitrary_impl
+
+            #kani_impl
+
+            /// Manually define PartialEq for #events_type_name based on variant only to address issue-#21
+            impl<#event_lifetimes> PartialEq for #events_type_name <#event_lifetimes> {
+                fn eq(&self, other: &Self) -> bool {
+                    use core::mem::discriminant;
+                    discriminant(self) == discriminant(other)
+                }
+            }
+
+            #events_hash
+        },
+    };
+    let derive_attr_list: Vec<_> = states_attr_list
+        .iter()
+        .filter(|attr| attr.path.is_ident("derive"))
+        .collect();
+
+    // With `serde`, the states and their data can be persisted and restored
+    let serde_derive = if sm.serde {
+        quote! { #[derive(serde::Serialize, serde::Deserialize)] }
+    } else {
+        quote! {}
+    };
+    let versioned_code = match sm.schema_version {
+        Some(_) => quote! {
+            /// Restores a state machine in a state persisted with the schema `version`, where
+            /// states of older versions are migrated by the `migrate` function of the context.
+            ///
+            /// States of newer versions fail to deserialize.
+            pub fn restore_versioned<'de, D: serde::Deserializer<'de>>(
+                mut context: T,
+                version: u32,
+                state: D
+            ) -> Result<Self, D::Error> {
+                let state = match version.cmp(&#schema_version_name) {
+                    core::cmp::Ordering::Equal => {
+                        <#states_type_name as serde::Deserialize>::deserialize(state)?
+                    }
+                    core::cmp::Ordering::Less => context.migrate(version, state)?,
+                    core::cmp::Ordering::Greater => {
+                        return Err(serde::de::Error::custom(
+                            "the state was persisted by a newer schema version",
+                        ))
+                    }
+                };
+                Ok(Self::restore(context, state))
+            }
+        },
+        None => quote! {},
+    };
+    // Every named state has a constructor starting the state machine in it, e.g. after reading
+    // the state at boot
+    let state_constructors = named_states(sm).into_iter().map(|(name, ident)| {
+        let constructor = format_ident!("new_in_{}", string_morph::to_snake_case(name));
+        let doc = format!(
+            "Creates a new state machine in [{}::{}]. Unlike a transition, the entry handlers of \
+             the state are not called.",
+            states_type_name, name
+        );
+        let (parameter, data) = match sm.state_data.data_types.get(name) {
+            Some(data_type) => (quote! { , state_data: #data_type }, quote! { (state_data) }),
+            None if sm.parallel_states.contains_key(name) => (
+                quote! {},
+                parallel_initial(sm, name, &sm_name, sm_name_span),
+            ),
+            None => (quote! {}, quote! {}),
+        };
+        let state = state_path(sm, ident, data, &sm_name, sm_name_span);
+        quote! {
+            #[doc = #doc]
+            #[inline(always)]
+            pub const fn #constructor(context: T #parameter) -> Self {
+                Self::new_with_state(context, #state)
+            }
+        }
+    });
+    let invariants = &sm.invariants;
+    let new_in_state_body = if invariants.is_empty() {
+        quote! { Ok(Self::new_with_state(context, state)) }
+    } else {
+        quote! {
+            if #(context.#invariants(&state))&&* {
+                Ok(Self::new_with_state(context, state))
+            } else {
+                Err((context, state))
+            }
+        }
+    };
+    let constructors_code = quote! {
+        /// Creates a new state machine in `state` if the invariants of the state machine hold in
+        /// it, or returns the context and the state otherwise. Unlike a transition, the entry
+        /// handlers of the state are not called.
+        pub fn new_in_state(
+            context: T,
+            state: #states_type_name <#state_lifetimes>,
+        ) -> Result<Self, (T, #states_type_name <#state_lifetimes>)> {
+            #new_in_state_body
+        }
+
+        #(#state_constructors)*
+    };
+    let restore_code = if sm.serde {
+        quote! {
+            /// Restores a state machine in a persisted state, e.g. after a restart. Unlike a
+            /// transition, the entry handlers of the state are not called.
+            ///
+            /// The state is validated when it is deserialized, where states which aren't declared
+            /// fail to deserialize.
+            #[inline(always)]
+            pub const fn restore(context: T, state: #states_type_name <#state_lifetimes>) -> Self {
+                Self::new_with_state(context, state)
+            }
+
+            #versioned_code
+        }
+    } else {
+        quote! {}
+    };
+
+    let (history_struct, history_field, history_code) = if history_list.is_empty() {
+        (quote! {}, quote! {}, quote! {})
+    } else {
+        let history_types: Vec<_> = history_list
+            .iter()
+            .map(|composite| {
+                let name = composite.to_string();
+                let composite_type_name = composite_type_name(&name, &sm_name, sm_name_span);
+                let composite_lifetimes = composite_lifetimes(sm, &name);
+                quote! { #composite_type_name <#composite_lifetimes> }
+            })
+            .collect();
+        let docs: Vec<_> = history_list
+            .iter()
+            .map(|composite| {
+                format!(
+                    "The sub-state of [{}::{}] when it was last exited.",
+                    states_type_name, composite
+                )
+            })
+            .collect();
+
+        (
+            quote! {
+                /// The remembered sub-states of the composite states with history.
+                #serde_derive
+                #(#derive_attr_list)*
+                pub struct #history_type_name <#history_lifetimes> {
+                    #(#[doc = #docs] pub #history_fields: Option<#history_types>),*
+                }
+            },
+            quote! { history: #history_type_name <#history_lifetimes>, },
+            quote! {
+                /// Returns the remembered sub-states of the composite states with history.
+                #[inline(always)]
+                pub fn history(&self) -> &#history_type_name <#history_lifetimes> {
+                    &self.history
+                }
+
+                /// Replaces the remembered sub-states of the composite states with history, e.g.
+                /// to restore them after a restart.
+                #[inline(always)]
+                pub fn set_history(&mut self, history: #history_type_name <#history_lifetimes>) {
+                    self.history = history;
+                }
+            },
+        )
+    };
+
+    let states_eq = states_partial_eq(sm, None, &states_type_name, state_lifetimes, hash_states);
+    let states_accessors = state_accessors(
+        sm,
+        None,
+        &states_type_name,
+        state_lifetimes,
+        &sm_name,
+        sm_name_span,
+    );
+
+    // Create an enum for the sub-states of each composite state
+    let mut composite_list: Vec<_> = sm.composite_states.keys().collect();
+    composite_list.sort();
+    let composite_enums: Vec<_> = composite_list
+        .into_iter()
+        .map(|composite| {
+            let composite_type_name = composite_type_name(composite, &sm_name, sm_name_span);
+            let composite_lifetimes = composite_lifetimes(sm, composite);
+            let state_list = state_variants(sm, Some(composite), &sm_name, sm_name_span);
+            let doc = format!("Sub-states of [{}::{}].", states_type_name, composite);
+            let partial_eq = states_partial_eq(
+                sm,
+                Some(composite),
+                &composite_type_name,
+                &composite_lifetimes,
+                hash_states,
+            );
+            let accessors = state_accessors(
+                sm,
+                Some(composite),
+                &composite_type_name,
+                &composite_lifetimes,
+                &sm_name,
+                sm_name_span,
+            );
+
+            quote! {
+                #[doc = #doc]
+                #[allow(missing_docs)]
+                #serde_derive
+                #(#states_attr_list)*
+                pub enum #composite_type_name <#composite_lifetimes> { #(#state_list),* }
+
+                #partial_eq
+
+                #accessors
+            }
+        })
+        .collect();
+    let composite_enums = quote! { #(#composite_enums)* };
+
+    // Create a struct holding the regions of each parallel state, and an enum for the sub-states
+    // of each region
+    let parallel_structs: Vec<_> = parallel_list
+        .iter()
+        .map(|parallel| {
+            let parallel_name = parallel.ident.to_string();
+            let parallel_type_name = composite_type_name(&parallel_name, &sm_name, sm_name_span);
+            let parallel_lifetimes = parallel_lifetimes(sm, &parallel_name);
+            let doc = format!("Regions of [{}::{}].", states_type_name, parallel_name);
+            let fields: Vec<_> = parallel
+                .regions
+                .iter()
+                .map(|region| region_field_name(&region.ident))
+                .collect();
+            let region_types: Vec<_> = parallel
+                .regions
+                .iter()
+                .map(|region| {
+                    let region_name = region.ident.to_string();
+                    let region_type_name =
+                        composite_type_name(&region_name, &sm_name, sm_name_span);
+                    let region_lifetimes = region_lifetimes(sm, &region_name);
+                    quote! { #region_type_name <#region_lifetimes> }
+                })
+                .collect();
+
+            let region_enums: Vec<_> = parallel
+                .regions
+                .iter()
+                .map(|region| {
+                    let region_name = region.ident.to_string();
+                    let region_type_name =
+                        composite_type_name(&region_name, &sm_name, sm_name_span);
+                    let region_lifetimes = region_lifetimes(sm, &region_name);
+                    let state_list = state_variants(sm, Some(&region_name), &sm_name, sm_name_span);
+                    let doc = format!(
+                        "Sub-states of the {} region of [{}::{}].",
+                        region_name, states_type_name, parallel_name
+                    );
+                    let partial_eq = states_partial_eq(
+                        sm,
+                        Some(&region_name),
+                        &region_type_name,
+                        &region_lifetimes,
+                        hash_states,
+                    );
+                    let accessors = state_accessors(
+                        sm,
+                        Some(&region_name),
+                        &region_type_name,
+                        &region_lifetimes,
+                        &sm_name,
+                        sm_name_span,
+                    );
+
+                    quote! {
+                        #[doc = #doc]
+                        #[allow(missing_docs)]
+                        #serde_derive
+                        #(#states_attr_list)*
+                        pub enum #region_type_name <#region_lifetimes> { #(#state_list),* }
+
+                        #partial_eq
+
+                        #accessors
+                    }
+                })
+                .collect();
+
+            let parallel_hash = if hash_states {
+                quote! {
+                    /// Manually define Hash to hash the sub-states of all regions
+                    impl<#parallel_lifetimes> core::hash::Hash for #parallel_type_name <#parallel_lifetimes> {
+                        fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+                            #(core::hash::Hash::hash(&self.#fields, state);)*
+                        }
+                    }
+                }
+            } else {
+                quote! {}
+            };
+
+            quote! {
+                #[doc = #doc]
+                #[allow(missing_docs)]
+                #serde_derive
+                #(#derive_attr_list)*
+                pub struct #parallel_type_name <#parallel_lifetimes> { #(pub #fields: #region_types),* }
+
+                /// Manually define PartialEq to compare the sub-states of all regions
+                impl<#parallel_lifetimes> PartialEq for #parallel_type_name <#parallel_lifetimes> {
+                    fn eq(&self, other: &Self) -> bool {
+                        #(self.#fields == other.#fields)&&*
+                    }
+                }
+
+                #parallel_hash
+
+                #(#region_enums)*
+            }
+        })
+        .collect();
+    // Build the states and events output
+    quote! {
+        /// This trait outlines the guards and actions that need to be implemented for the state
+        /// machine.
+        #context_vis trait #state_machine_context_type_name #supertraits {
+            #custom_error
+            #guard_list
+            #action_list
+            #invariant_list
+            #entries_exits
+
+
+            #associated_types
+
+            /// Called at the beginning of a state machine's `process_event()`. No-op by
+            /// default but can be overridden in implementations of a state machine's
+            /// `StateMachineContext` trait.
+            fn log_process_event(&self, current_state: & #states_type_name, event: & #events_type_name) {
+                #log_process_event
+            }
+
+            /// Called after executing a guard during `process_event()`. No-op by
+            /// default but can be overridden in implementations of a state machine's
+            /// `StateMachineContext` trait.
+            fn log_guard(&self, guard: &'static str, result: bool) {
+                #log_guard
+            }
+
+            /// Called after executing an action during `process_event()`. No-op by
+            /// default but can be overridden in implementations of a state machine's
+            /// `StateMachineContext` trait.
+            fn log_action(&self, action: &'static str) {
+                #log_action
+            }
+
+            /// Called when transitioning to a new state as a result of an event passed to
+            /// `process_event()`. No-op by default which can be overridden in implementations
+            /// of a state machine's `StateMachineContext` trait.
+            fn transition_callback(&self, old_state: & #states_type_name, new_state: & #states_type_name) {
+                #log_transition
+            }
+
+            /// Called after every transition between states, including those of posted, timed and
+            /// completion events, once the entry handlers have been called. The old state has been
+            /// dropped, so it is passed by its name along with the name of the event. No-op by
+            /// default but can be overridden in implementations of a state machine's
+            /// `StateMachineContext` trait.
+            fn on_transition(&mut self, old_state: &'static str, event: &'static str, new_state: & #states_type_name) {}
+
+            #completion_callback
+
+            #migrate_callback
+
+            #publish_callback
+
+            #mirror_callback
+
+            #post_methods
+        }
+
+        /// List of auto-generated states.
+        #[allow(missing_docs)]
+        #serde_derive
+        #(#states_attr_list)*
+        #states_vis enum #states_type_name <#state_lifetimes> { #(#state_list),* }
+
+        #states_eq
+
+        #states_accessors
+
+        #composite_enums
+
+        #(#parallel_structs)*
+
+        #history_struct
+
+        #events_code
+
+        #defmt_code
+
+        #display_code
+
+        #states_variants
+
+        #events_variants
+
+        #state_id_code
+
+        #shared_code
+
+        #tokio_code
+
+        #tokio_sink
+
+        #futures_code
+
+        #embassy_code
+
+        #scxml_code
+
+        #posted_struct
+
+        #queue_struct
+
+        #walker_code
+
+        /// The states, events and transitions of the state machine.
+        pub const #description_name: smlang::MachineDescription = smlang::MachineDescription {
+            states: &[#(#described_states),*],
+            events: &[#(#described_events),*],
+            transitions: &[#(#described_transitions),*],
+        };
+
+        /// The states, events and transitions of the state machine as JSON, with the names of
+        /// the state machine and its initial state.
+        pub const #machine_json_name: &str = #machine_json;
+
+        #schema_version
+
+        #guards_code
+
+        /// List of possible errors
+        #[derive(Debug,PartialEq)]
+        pub enum #error_type_name  <T=()> {
+            /// When an event is processed which should not come in the current state.
+            InvalidEvent,
+            /// When an event is processed and none of the transitions happened.
+            TransitionsFailed,
+            /// When guard is failed.
+            #guard_failed_variant
+            /// When action returns Err
+            ActionFailed(T),
+            #deferred_error
+            #finished_error
+            #cancelled_error
+            #timed_out_error
+            #poisoned_error
+        }
+
+        /// State machine structure definition.
+        #machine_docs
+        #state_machine_vis struct #state_machine_type_name<#state_lifetimes T: #state_machine_context_type_name> {
+            state: #states_type_name <#state_lifetimes>,
+            #state_id_field
+            #deferred_field
+            #timer_field
+            #history_field
+            #outcome_field
+            #transition_history_field
+            #statistics_field
+            #coverage_field
+            #rejected_field
+            #poisoned_field
+            context: T
+        }
+
+        impl<#state_lifetimes T: #state_machine_context_type_name> #state_machine_type_name<#state_lifetimes T> {
+            /// Creates a new state machine with the specified starting state.
+            #[inline(always)]
+            #new_sm_code
+
+            /// Creates a new state machine with an initial state.
+            #[inline(always)]
+            pub const fn new_with_state(context: T, initial_state: #states_type_name <#state_lifetimes>) -> Self {
+                #state_machine_type_name {
+                    #state_id_init
+                    state: initial_state,
+                    #deferred_init
+                    #timer_init
+                    #history_init
+                    #outcome_init
+                    #transition_history_init
+                    #statistics_init
+                    #coverage_init
+                    #rejected_init
+                    #poisoned_init
+                    context
+                }
+            }
+
+            #constructors_code
+
+            #restore_code
+
+            /// Returns the current state.
+            #[inline(always)]
+            pub fn state(&self) -> &#states_type_name <#state_lifetimes> {
+                &self.state
+            }
+
+            /// Returns `true` if the state machine is in a final state, which is a state without
+            /// transitions.
+            #[inline(always)]
+            pub fn is_finished(&self) -> bool {
+                #is_finished
+            }
+
+            /// Returns the names of the events accepted in the current state, which are the events
+            /// with transitions in the state, and its deferred and delegated events.
+            ///
+            /// Guards are not evaluated, so processing an accepted event can still fail with
+            /// `TransitionsFailed`.
+            pub fn valid_events(&self) -> impl Iterator<Item = &'static str> + '_ {
+                const EVENTS: &[&str] = &[#(#event_names),*];
+                EVENTS.iter().copied().filter(move |event| self.is_valid_event(event))
+            }
+
+            /// Returns `true` if the event named `event` is accepted in the current state, see
+            /// [Self::valid_events].
+            #[allow(unused_variables, unreachable_patterns)]
+            pub fn is_valid_event(&self, event: &str) -> bool {
+                match self.state {
+                    #(#valid_event_arms)*
+                    _ => false,
+                }
+            }
+
+            /// Returns the current context.
+            #[inline(always)]
+            pub fn context(&self) -> &T {
+                &self.context
+            }
+
+            /// Returns the current context as a mutable reference.
+            #[inline(always)]
+            pub fn context_mut(&mut self) -> &mut T {
+                &mut self.context
+            }
+
+            /// Process an event.
+            ///
+            /// It will return `Ok(&NextState)` if the transition was successful, or `Err(#error_type_name)`
+            /// if there was an error in the transition.
+            #process_event
+
+            #names_code
+
+            #outcome_code
+            #rejected_code
+
+            #poisoned_code
+
+            #transition_history_code
+
+            #statistics_code
+
+            #coverage_code
+
+            #event_log_code
+
+            #simulate_code
+
+            #peek_code
+
+            #post_code
+
+            #queued_code
+
+            #arbitrary_code
+
+            #activity_code
+
+            #cancellable_code
+
+            #timed_code
+
+            #clock_code
+
+            #watchdog_code
+
+            #history_code
+
+            #completion_code
+
+            #(#state_functions)*
+
+            #dispatch_code
+
+            #(#region_functions)*
+
+            #fault_code
+        }
+
+        #kani_code
+
+        #typestate_code
+    }
+}
+
+/// Generates the events shared by the state machines of `statemachines!`, which are the events of
+/// all state machines.
+pub fn generate_shared_events(
+    machines: &[ParsedStateMachine],
+    events_type: &TypeName,
+    events_attr: &[syn::Attribute],
+) -> Result<TokenStream, syn::parse::Error> {
+    let mut events: std::collections::BTreeMap<&String, (&Ident, Option<&Type>)> =
+        std::collections::BTreeMap::new();
+    for sm in machines {
+        // The aliases of the shared events in the state machines only have the lifetimes of their
+        // own events
+        if let Some(lifetime) = sm.event_data.all_lifetimes.as_slice().first() {
+            return Err(syn::parse::Error::new(
+                lifetime.span(),
+                "The data of shared events cannot have lifetimes.",
+            ));
+        }
+        for (name, ident) in &sm.events {
+            let data = sm.event_data.data_types.get(name);
+            match events.get(name) {
+                Some((_, other))
+                    if other.map(|t| t.to_token_stream().to_string())
+                        != data.map(|t| t.to_token_stream().to_string()) =>
+                {
+                    return Err(syn::parse::Error::new(
+                        ident.span(),
+                        format!("Event {} has other data in another state machine.", name),
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    events.insert(name, (ident, data));
+                }
+            }
+        }
+    }
+
+    let TypeName {
+        vis,
+        ident: events_type_name,
+    } = events_type;
+    let event_idents: Vec<_> = events.values().map(|(ident, _)| *ident).collect();
+    let event_names = events.keys();
+    let variants = variants_code(
+        events_type_name,
+        &quote! {},
+        events.keys().map(|name| name.as_str()).collect(),
+        events
+            .values()
+            .filter(|(_, data)| data.is_none())
+            .map(|(ident, _)| *ident)
+            .collect(),
+    );
+    let event_list = events.values().map(|(ident, data)| match data {
+        Some(t) => quote! { #ident(#t) },
+        None => quote! { #ident },
+    });
+    let (events_attr_list, hash_events) = without_hash_derive(events_attr);
+    let events_hash = if hash_events {
+        quote! {
+            /// Manually define Hash for #events_type_name based on variant only, like PartialEq
+            impl core::hash::Hash for #events_type_name {
+                fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+                    core::hash::Hash::hash(&core::mem::discriminant(self), state);
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let arbitrary_impl = if machines.iter().any(|sm| sm.arbitrary) {
+        arbitrary_impl_code(
+            events_type_name,
+            events
+                .values()
+                .map(|(ident, data)| {
+                    let data = data.map(|_| quote! { smlang::arbitrary::Arbitrary::arbitrary(u)? });
+                    (*ident, data)
+                })
+                .collect(),
+        )
+    } else {
+        quote! {}
+    };
+    let kani_impl = if cfg!(feature = "kani") {
+        kani_arbitrary_code(
+            events_type_name,
+            events
+                .values()
+                .map(|(ident, data)| (*ident, data.is_some()))
+                .collect(),
+        )
+    } else {
+        quote! {}
+    };
+    let defmt_code = if cfg!(feature = "defmt") {
+        quote! {
+            impl defmt::Format for #events_type_name {
+                fn format(&self, f: defmt::Formatter) {
+                    match *self {
+                        #(#events_type_name::#event_idents { .. } => defmt::write!(f, #event_names),)*
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    Ok(quote! {
+        /// List of auto-generated events, shared by the state machines.
+        #[allow(missing_docs)]
+        #(#events_attr_list)*
+        #vis enum #events_type_name { #(#event_list),* }
+
+        #arbitrary_impl
+
+        #kani_impl
+
+        /// Manually define PartialEq for #events_type_name based on variant only to address issue-#21
+        impl PartialEq for #events_type_name {
+            fn eq(&self, other: &Self) -> bool {
+                use core::mem::discriminant;
+                discriminant(self) == discriminant(other)
+            }
+        }
+
+        #events_hash
+
+        #defmt_code
+
+        #variants
+    })
+}
+
+/// The number and the names of the variants of an enum, in the order of their declaration, along
+/// with an iterator over its variants without data.
+fn variants_code(
+    type_name: &Ident,
+    lifetimes: &TokenStream,
+    names: Vec<&str>,
+    plain_variants: Vec<&Ident>,
+) -> TokenStream {
+    let count = names.len();
+    quote! {
+        impl<#lifetimes> #type_name <#lifetimes> {
+            /// The number of variants.
+            pub const VARIANT_COUNT: usize = #count;
+
+            /// The names of the variants, in the order of their declaration.
+            pub const NAMES: [&'static str; #count] = [#(#names),*];
+
+            /// Returns an iterator over the variants without data, in the order of their
+            /// declaration.
+            pub fn all() -> impl Iterator<Item = Self> {
+                core::iter::IntoIterator::into_iter([#(Self::#plain_variants),*])
+            }
+        }
+    }
+}
+
+/// The implementation of `arbitrary::Arbitrary` for the events, choosing a variant and generating
+/// its data with the given expressions. It is implemented instead of derived, as the derive refers
+/// to the `arbitrary` crate of the crate of the state machine instead of the one of `smlang`.
+fn arbitrary_impl_code(
+    type_name: &Ident,
+    variants: Vec<(&Ident, Option<TokenStream>)>,
+) -> TokenStream {
+    let count = variants.len();
+    let arms = variants
+        .iter()
+        .enumerate()
+        .map(|(i, (ident, data))| match data {
+            Some(data) => quote! { #i => Self::#ident(#data) },
+            None => quote! { #i => Self::#ident },
+        });
+    quote! {
+        impl<'a> smlang::arbitrary::Arbitrary<'a> for #type_name {
+            fn arbitrary(
+                u: &mut smlang::arbitrary::Unstructured<'a>,
+            ) -> smlang::arbitrary::Result<Self> {
+                Ok(match u.choose_index(#count)? {
+                    #(#arms,)*
+                    _ => return Err(smlang::arbitrary::Error::EmptyChoose),
+                })
+            }
+        }
+    }
+}
+
+/// The implementation of `kani::Arbitrary` for the events, choosing a variant with `kani::any()` and
+/// its data with `kani::any()`. It is implemented instead of derived, as `cfg(kani)` is not a cfg of
+/// the crate of the state machine, and only the `unexpected_cfgs` level of an enclosing item
+/// applies to a `#[cfg(kani)]` item, not the level of the item itself.
+fn kani_arbitrary_code(type_name: &Ident, variants: Vec<(&Ident, bool)>) -> TokenStream {
+    let last = variants.len().saturating_sub(1);
+    let arms = variants.iter().enumerate().map(|(i, (ident, has_data))| {
+        let pattern = if i == last {
+            quote! { _ }
+        } else {
+            quote! { #i }
+        };
+        if *has_data {
+            quote! { #pattern => Self::#ident(kani::any()) }
+        } else {
+            quote! { #pattern => Self::#ident }
+        }
+    });
+    if variants.is_empty() {
+        return quote! {};
+    }
+    quote! {
+        #[allow(unexpected_cfgs)]
+        const _: () = {
+            #[cfg(kani)]
+            impl kani::Arbitrary for #type_name {
+                fn any() -> Self {
+                    match kani::any::<usize>() {
+                        #(#arms,)*
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// The body of the trait method of an action, which is the block of an inline action with its
+/// parameters bound to the context followed by the arguments of the action, or `;` for actions
+/// implemented by the context.
+fn action_body(sm: &ParsedStateMachine, action: &Ident, state: &str, event: &str) -> TokenStream {
+    let inline_action = match sm.inline_actions.get(&action.to_string()) {
+        Some(inline_action) => inline_action,
+        None => return quote! { ; },
+    };
+    let mut args = vec![quote! { self }];
+    if sm.temporary_context_type.is_some() {
+        args.push(quote! { temporary_context });
+    }
+    if sm.state_data.data_types.contains_key(state) {
+        args.push(quote! { state_data });
+    }
+    if sm.event_data.data_types.contains_key(event) {
+        args.push(quote! { event_data });
+    }
+    let inputs = &inline_action.inputs;
+    let statements = &inline_action.body.stmts;
+    quote! {
+        {
+            #(let #inputs = #args;)*
+            #(#statements)*
+        }
+    }
+}
+
+/// The `async` keyword and the return type of a method of the context returning `output`, which
+/// returns a future bounded by `Send` instead of being an `async fn` with `send_futures: true`. The
+/// method can still be implemented as an `async fn`, whose future is then checked to be `Send`.
+fn method_output(
+    sm: &ParsedStateMachine,
+    is_async: bool,
+    output: TokenStream,
+) -> (TokenStream, TokenStream) {
+    match (is_async, sm.send_futures) {
+        (false, _) => (quote! {}, output),
+        (true, false) => (quote! { async }, output),
+        (true, true) => (
+            quote! {},
+            quote! { impl core::future::Future<Output = #output> + Send },
+        ),
+    }
+}
+
+/// The body of the trait method of a guard, which evaluates an expression guard with `state` and
+/// `event` bound to the state and event data, or `;` for guards implemented by the context.
+fn guard_body(sm: &ParsedStateMachine, guard: &Ident, state: &str, event: &str) -> TokenStream {
+    let expr = match sm.expression_guards.get(&guard.to_string()) {
+        Some(expr) => expr,
+        None => return quote! { ; },
+    };
+    let context_binding = if sm.temporary_context_type.is_some() {
+        quote! { let _ = temporary_context; }
+    } else {
+        quote! {}
+    };
+    let state_binding = if sm.state_data.data_types.contains_key(state) {
+        quote! { #[allow(unused_variables)] let state = state_data; }
+    } else {
+        quote! {}
+    };
+    let event_binding = if sm.event_data.data_types.contains_key(event) {
+        quote! { #[allow(unused_variables)] let event = event_data; }
+    } else {
+        quote! {}
+    };
+    quote! {
+        {
+            #context_binding
+            #state_binding
+            #event_binding
+            Ok(#expr)
+        }
+    }
+}
+
+/// The event matched by the pattern of a transition, rebuilt from the bound data of the event.
+fn event_expression(pattern: &TokenStream) -> TokenStream {
+    if pattern.to_string() == "_" {
+        quote! { event }
+    } else {
+        pattern.clone()
+    }
+}
+
+/// The clock of the context `context`, which is the context itself unless the clock is an
+/// associated type of the context.
+fn clock_of(sm: &ParsedStateMachine, context: &TokenStream) -> TokenStream {
+    if sm.associated_types {
+        quote! { #context.clock() }
+    } else {
+        quote! { &#context }
+    }
+}
+
+/// The name of the generated enum holding the sub-states of a composite state.
+fn composite_type_name(composite: &str, sm_name: &str, sm_name_span: Span) -> Ident {
+    format_ident!("{sm_name}{composite}States", span = sm_name_span)
+}
+
+/// The lifetimes used by the data of all sub-states of a composite state.
+fn composite_lifetimes(sm: &ParsedStateMachine, composite: &str) -> Lifetimes {
+    let mut states: Vec<_> = sm
+        .states
+        .keys()
+        .filter(|state| sm.is_descendant(state, composite))
+        .collect();
+    states.sort();
+
+    let mut lifetimes = Lifetimes::new();
+    for state in states {
+        if let Some(state_lifetimes) = sm.state_data.lifetimes.get(state) {
+            lifetimes.extend(state_lifetimes);
+        }
+        if sm.parallel_states.contains_key(state) {
+            lifetimes.extend(&parallel_lifetimes(sm, state));
+        }
+    }
+    lifetimes
+}
+
+/// The lifetimes used by the data of all sub-states of a region.
+fn region_lifetimes(sm: &ParsedStateMachine, region: &str) -> Lifetimes {
+    let mut states: Vec<_> = sm
+        .region_parents
+        .iter()
+        .filter(|(_, parent)| *parent == region)
+        .map(|(state, _)| state)
+        .collect();
+    states.sort();
+
+    let mut lifetimes = Lifetimes::new();
+    for state in states {
+        if let Some(state_lifetimes) = sm.state_data.lifetimes.get(state) {
+            lifetimes.extend(state_lifetimes);
+        }
+    }
+    lifetimes
+}
+
+/// The lifetimes used by the data of the sub-states of all regions of a parallel state.
+fn parallel_lifetimes(sm: &ParsedStateMachine, parallel: &str) -> Lifetimes {
+    let mut lifetimes = Lifetimes::new();
+    for region in &sm.parallel_states[parallel].regions {
+        lifetimes.extend(&region_lifetimes(sm, &region.ident.to_string()));
+    }
+    lifetimes
+}
+
+/// The name of the field holding the sub-state of a region in the struct of its parallel state.
+fn region_field_name(region: &Ident) -> Ident {
+    format_ident!(
+        "{}",
+        string_morph::to_snake_case(&region.to_string()),
+        span = region.span()
+    )
+}
+
+/// The states named by their own name, which are the states outside of regions except for the
+/// composite states, whose sub-states are named instead. Sorted by their names.
+fn named_states(sm: &ParsedStateMachine) -> Vec<(&String, &Ident)> {
+    let mut named_states: Vec<_> = sm
+        .states
+        .iter()
+        .filter(|(name, _)| {
+            !sm.composite_states.contains_key(*name) && !sm.region_parents.contains_key(*name)
+        })
+        .collect();
+    named_states.sort_by_key(|(name, _)| name.as_str());
+    named_states
+}
+
+/// The name of the function processing events in a region.
+fn region_function_name(region: &str) -> Ident {
+    format_ident!("process_event_{}", string_morph::to_snake_case(region))
+}
+
+/// The struct with the initial sub-state of every region of a parallel state, wrapped in
+/// parentheses to be appended to the state variant.
+fn parallel_initial(
+    sm: &ParsedStateMachine,
+    parallel: &str,
+    sm_name: &str,
+    sm_name_span: Span,
+) -> TokenStream {
+    let parallel_type_name = composite_type_name(parallel, sm_name, sm_name_span);
+    let (fields, initials): (Vec<_>, Vec<_>) = sm.parallel_states[parallel]
+        .regions
+        .iter()
+        .map(|region| {
+            let initial = region.initial.as_ref().unwrap();
+            (
+                region_field_name(&region.ident),
+                state_path(sm, initial, quote! {}, sm_name, sm_name_span),
+            )
+        })
+        .unzip();
+
+    quote! { (#parallel_type_name { #(#fields: #initials),* }) }
+}
+
+/// Creates the calls to the functions of the context on entry to and exit from states.
+struct Hooks<'a> {
+    sm: &'a ParsedStateMachine,
+    temporary_context_call: &'a TokenStream,
+    error_type_name: &'a Ident,
+    /// The error type of the functions of the context.
+    handler_error: &'a TokenStream,
+    sm_name: &'a str,
+    sm_name_span: Span,
+}
+
+impl Hooks<'_> {
+    /// Calls an entry or exit handler declared in the `states` block, which takes the data of
+    /// `state` bound to `state_data`. A failed exit handler returns its error before the state is
+    /// replaced, while the error of a failed entry handler is kept in `entered` and the remaining
+    /// entry handlers are skipped, so the transition is completed before the error is handled.
+    fn handler_call(
+        &self,
+        context: &TokenStream,
+        handler: &AsyncIdent,
+        state: &str,
+        entry: bool,
+    ) -> TokenStream {
+        let temporary_context_call = self.temporary_context_call;
+        let error_type_name = self.error_type_name;
+        let handler_ident = &handler.ident;
+        let handler_await = if handler.is_async {
+            quote! { .await }
+        } else {
+            quote! {}
+        };
+        let state_data = if self.sm.state_data.data_types.contains_key(state) {
+            quote! { state_data }
+        } else {
+            quote! {}
+        };
+
+        if entry {
+            quote! {
+                if entered.is_ok() {
+                    entered = #context.#handler_ident(#temporary_context_call #state_data) #handler_await;
+                }
+            }
+        } else {
+            quote! {
+                #context.#handler_ident(#temporary_context_call #state_data) #handler_await .map_err(#error_type_name::ActionFailed)?;
+            }
+        }
+    }
+
+    /// Declares `entered`, which holds the error of the first failed entry handler, before the
+    /// states are entered.
+    fn entering(&self) -> TokenStream {
+        if self.has_entry_handlers() {
+            let handler_error = self.handler_error;
+            quote! {
+                #[allow(unused_mut)]
+                let mut entered: Result<(), #handler_error> = Ok(());
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// Unpoisons the state machine once the states have been entered, unless an entry handler has
+    /// failed.
+    fn unpoison(&self) -> TokenStream {
+        match (self.sm.poisoning, self.has_entry_handlers()) {
+            (false, _) => quote! {},
+            (true, false) => quote! { self.poisoned = false; },
+            (true, true) => quote! {
+                if entered.is_ok() {
+                    self.poisoned = false;
+                }
+            },
+        }
+    }
+
+    /// Handles the error of a failed entry handler once the transition has been completed, by
+    /// entering the fault state or returning the error.
+    fn entry_failed(&self, event: &TokenStream) -> TokenStream {
+        if !self.has_entry_handlers() {
+            return quote! {};
+        }
+        let error_type_name = self.error_type_name;
+        match &self.sm.fault_state {
+            Some(_) => {
+                let temporary_context_call = self.temporary_context_call;
+                let await_call = if self.has_async_handlers() {
+                    quote! { .await }
+                } else {
+                    quote! {}
+                };
+                quote! {
+                    if let Err(error) = entered {
+                        self.enter_fault_state(#temporary_context_call error, #event) #await_call ?;
+                    }
+                }
+            }
+            None => quote! { entered.map_err(#error_type_name::ActionFailed)?; },
+        }
+    }
+
+    /// Defines `enter_fault_state`, which enters the fault state from the current state once the
+    /// transition to it has been completed and one of its entry handlers has failed. The error of
+    /// the handler is converted to the data of the fault state, and a failed handler of the fault
+    /// state itself is returned.
+    fn enter_fault(
+        &self,
+        fault_state: &Ident,
+        temporary_context: &TokenStream,
+        error_type: &TokenStream,
+    ) -> TokenStream {
+        let sm = self.sm;
+        let (sm_name, sm_name_span) = (self.sm_name, self.sm_name_span);
+        let error_type_name = self.error_type_name;
+        let handler_error = self.handler_error;
+        let fault_name = fault_state.to_string();
+
+        // The data of boxed states is moved behind its pointer
+        let (fault_data, data) = match sm.state_data.data_types.get(&fault_name) {
+            _ if sm.parallel_states.contains_key(&fault_name) => (
+                quote! {},
+                parallel_initial(sm, &fault_name, sm_name, sm_name_span),
+            ),
+            None => (quote! {}, quote! {}),
+            Some(pointer) => {
+                let boxed = match sm.boxed_states.get(&fault_name) {
+                    Some(data_type) => match &sm.state_handlers[&fault_name].allocate {
+                        Some(allocate) => quote! {
+                            let _data = self.context.#allocate(_data).map_err(#error_type_name::ActionFailed)?;
+                        },
+                        None => quote! {
+                            let _data = <#pointer as core::convert::From<#data_type>>::from(_data);
+                        },
+                    },
+                    None => quote! {},
+                };
+                (
+                    quote! {
+                        let _data = error.into();
+                        #boxed
+                    },
+                    quote! { (_data) },
+                )
+            }
+        };
+        let out_state = state_path(sm, fault_state, data, sm_name, sm_name_span);
+
+        let mut states: Vec<_> = sm
+            .states
+            .iter()
+            .filter(|(name, _)| **name != fault_name && !sm.composite_states.contains_key(*name))
+            .collect();
+        states.sort_by_key(|(name, _)| name.as_str());
+        let out_ancestors = sm.ancestors(&fault_name);
+        let leaving = self.leaving();
+        let unpoison = self.unpoison();
+        let transitioned = self.transitioned(&quote! { event });
+        let (patterns, transitions): (Vec<_>, Vec<_>) = states
+            .into_iter()
+            .map(|(name, state)| {
+                let in_ancestors = sm.ancestors(name);
+                let shared = in_ancestors
+                    .iter()
+                    .zip(out_ancestors.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let has_exit_handler = sm
+                    .state_handlers
+                    .get(name)
+                    .is_some_and(|h| h.exit.is_some());
+                let data = if sm.parallel_states.contains_key(name) {
+                    quote! { (ref mut regions) }
+                } else if has_exit_handler && sm.state_data.data_types.contains_key(name) {
+                    quote! { (ref state_data) }
+                } else {
+                    quote! { { .. } }
+                };
+                let exits = self.exit_calls(name, &in_ancestors[shared..]);
+                let set_state = self.set_state(&in_ancestors[shared..]);
+                let entries = self.entry_calls(&fault_name, &out_ancestors[shared..]);
+                (
+                    state_path(sm, state, data, sm_name, sm_name_span),
+                    quote! {
+                        #exits
+                        #fault_data
+                        let out_state = #out_state;
+                        self.context().transition_callback(&self.state, &out_state);
+                        #leaving
+                        #set_state
+                        #entries
+                        #unpoison
+                        #transitioned
+                    },
+                )
+            })
+            .unzip();
+        let entering = self.entering();
+        let is_async = if self.has_async_handlers() {
+            quote! { async }
+        } else {
+            quote! {}
+        };
+
+        quote! {
+            #[allow(unused_variables, unreachable_patterns, clippy::match_single_binding)]
+            #is_async fn enter_fault_state(
+                &mut self,
+                #temporary_context
+                error: #handler_error,
+                event: &'static str,
+            ) -> Result<(), #error_type> {
+                #entering
+                match self.state {
+                    #(#patterns => { #transitions })*
+                    _ => return Err(#error_type_name::ActionFailed(error)),
+                }
+                entered.map_err(#error_type_name::ActionFailed)
+            }
+        }
+    }
+
+    /// Returns `true` if any state has an entry handler.
+    fn has_entry_handlers(&self) -> bool {
+        self.sm.state_handlers.values().any(|h| h.entry.is_some())
+    }
+
+    /// Returns `true` if any entry or exit handler is async.
+    fn has_async_handlers(&self) -> bool {
+        self.sm
+            .state_handlers
+            .values()
+            .flat_map(|h| h.entry.iter().chain(h.exit.iter()))
+            .any(|handler| handler.is_async)
+    }
+
+    /// Calls the exit function and exit handler of `state`, whose data is bound to `state_data`.
+    fn exit(&self, context: &TokenStream, state: &str) -> TokenStream {
+        let exit_ident = format_ident!("on_exit_{}", string_morph::to_snake_case(state));
+        let handler = match self
+            .sm
+            .state_handlers
+            .get(state)
+            .and_then(|h| h.exit.as_ref())
+        {
+            Some(handler) => self.handler_call(context, handler, state, false),
+            None => quote! {},
+        };
+
+        quote! {
+            #context.#exit_ident();
+            #handler
+        }
+    }
+
+    /// Calls the entry function and entry handler of `state`, which has been stored in `target`.
+    fn entry(&self, context: &TokenStream, target: &TokenStream, state: &str) -> TokenStream {
+        let sm = self.sm;
+        let entry_ident = format_ident!("on_entry_{}", string_morph::to_snake_case(state));
+        let handler = match sm.state_handlers.get(state).and_then(|h| h.entry.as_ref()) {
+            Some(handler) if sm.state_data.data_types.contains_key(state) => {
+                let handler_call = self.handler_call(context, handler, state, true);
+                let state_ident = Ident::new(state, self.sm_name_span);
+                let pattern = state_path(
+                    sm,
+                    &state_ident,
+                    quote! { (ref state_data) },
+                    self.sm_name,
+                    self.sm_name_span,
+                );
+                quote! {
+                    match #target {
+                        #pattern => { #handler_call }
+                        #[allow(unreachable_patterns)]
+                        _ => {}
+                    }
+                }
+            }
+            Some(handler) => self.handler_call(context, handler, state, true),
+            None => quote! {},
+        };
+
+        quote! {
+            #context.#entry_ident();
+            #handler
+        }
+    }
+
+    /// Exits `state` and the composite states in `ancestors`, from the innermost to the
+    /// outermost. When exiting a parallel state, the sub-states of its regions are exited first,
+    /// which requires its `regions` to be bound.
+    fn exit_calls(&self, state: &str, ancestors: &[&Ident]) -> TokenStream {
+        let sm = self.sm;
+        let context = quote! { self.context };
+        let region_exits: Vec<_> = match sm.parallel_states.get(state) {
+            Some(parallel) => parallel
+                .regions
+                .iter()
+                .map(|region| {
+                    let field = region_field_name(&region.ident);
+                    let (patterns, exits): (Vec<_>, Vec<_>) = region
+                        .children
+                        .iter()
+                        .map(|child| {
+                            let child_name = child.to_string();
+                            let has_handler = sm
+                                .state_handlers
+                                .get(&child_name)
+                                .is_some_and(|h| h.exit.is_some());
+                            let data = if has_handler
+                                && sm.state_data.data_types.contains_key(&child_name)
+                            {
+                                quote! { (ref state_data) }
+                            } else {
+                                quote! { { .. } }
+                            };
+                            (
+                                state_path(sm, child, data, self.sm_name, self.sm_name_span),
+                                self.exit(&context, &child_name),
+                            )
+                        })
+                        .unzip();
+                    quote! {
+                        match regions.#field {
+                            #(#patterns => { #exits })*
+                        }
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let exits = core::iter::once(state.to_string())
+            .chain(ancestors.iter().rev().map(|s| s.to_string()))
+            .map(|s| self.exit(&context, &s));
+
+        quote! {
+            #(#region_exits)*
+            #(#exits)*
+        }
+    }
+
+    /// Enters the composite states in `ancestors`, from the outermost to the innermost, and then
+    /// `state`. When entering a parallel state, the initial sub-states of its regions are entered
+    /// last. The timer of timed transitions is restarted first.
+    fn entry_calls(&self, state: &str, ancestors: &[&Ident]) -> TokenStream {
+        let context = quote! { self.context };
+        let target = quote! { self.state };
+        let region_initials: Vec<_> = match self.sm.parallel_states.get(state) {
+            Some(parallel) => parallel
+                .regions
+                .iter()
+                .map(|region| region.initial.as_ref().unwrap().to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let entries = ancestors
+            .iter()
+            .map(|s| s.to_string())
+            .chain(core::iter::once(state.to_string()))
+            .chain(region_initials)
+            .map(|s| self.entry(&context, &target, &s));
+
+        let timer_reset = self.timer_reset();
+        let completion = if self.sm.terminal_states.contains_key(state) {
+            quote! { self.context.on_completion(&self.state); }
+        } else {
+            quote! {}
+        };
+        quote! {
+            #timer_reset
+            #(#entries)*
+            #completion
+        }
+    }
+
+    /// Stores `out_state` as the current state. When a composite state with history is exited,
+    /// its sub-state is moved out of the previous state and remembered.
+    fn set_state(&self, exited: &[&Ident]) -> TokenStream {
+        let sm = self.sm;
+        let history = exited.iter().find(|composite| {
+            sm.composite_states[&composite.to_string()]
+                .history
+                .is_some()
+        });
+        match history {
+            Some(composite) => {
+                let field = region_field_name(composite);
+                let pattern = state_path(
+                    sm,
+                    composite,
+                    quote! { (sub_state) },
+                    self.sm_name,
+                    self.sm_name_span,
+                );
+                quote! {
+                    let previous = core::mem::replace(&mut self.state, out_state);
+                    match previous {
+                        #pattern => self.history.#field = Some(sub_state),
+                        #[allow(unreachable_patterns)]
+                        _ => {}
+                    }
+                }
+            }
+            None => quote! { self.state = out_state; },
+        }
+    }
+
+    /// Takes the remembered sub-state of a composite state with history. For shallow history,
+    /// nested composite and parallel states are entered anew.
+    fn restored_history(&self, composite: &Ident) -> TokenStream {
+        let sm = self.sm;
+        let field = region_field_name(composite);
+        let name = composite.to_string();
+        if sm.composite_states[&name].history != Some(History::Shallow) {
+            return quote! { self.history.#field.take() };
+        }
+
+        let composite_type_name = composite_type_name(&name, self.sm_name, self.sm_name_span);
+        let (patterns, initials): (Vec<_>, Vec<_>) = sm.composite_states[&name]
+            .children
+            .iter()
+            .filter_map(|child| {
+                let child_name = child.to_string();
+                let initial = if sm.parallel_states.contains_key(&child_name) {
+                    let data = parallel_initial(sm, &child_name, self.sm_name, self.sm_name_span);
+                    scoped_state_path(sm, child, data, composite, self.sm_name, self.sm_name_span)
+                } else if sm.composite_states.contains_key(&child_name) {
+                    let initial = sm.resolve_initial(child);
+                    let data = if sm.parallel_states.contains_key(&initial.to_string()) {
+                        parallel_initial(sm, &initial.to_string(), self.sm_name, self.sm_name_span)
+                    } else {
+                        quote! {}
+                    };
+                    scoped_state_path(
+                        sm,
+                        &initial,
+                        data,
+                        composite,
+                        self.sm_name,
+                        self.sm_name_span,
+                    )
+                } else {
+                    return None;
+                };
+                Some((quote! { #composite_type_name::#child(..) }, initial))
+            })
+            .unzip();
+
+        quote! {
+            self.history.#field.take().map(|sub_state| match sub_state {
+                #(#patterns => #initials,)*
+                #[allow(unreachable_patterns)]
+                sub_state => sub_state,
+            })
+        }
+    }
+
+    /// Enters the composite states in `ancestors` and the composite state `composite`, and then
+    /// the sub-states of `composite` which have been resumed from its history.
+    fn history_entry_calls(&self, composite: &Ident, ancestors: &[&Ident]) -> TokenStream {
+        let sm = self.sm;
+        let entries = self.entry_calls(&composite.to_string(), ancestors);
+
+        let mut leaves: Vec<_> = sm
+            .states
+            .keys()
+            .filter(|state| sm.is_descendant(state, &composite.to_string()))
+            .collect();
+        leaves.sort();
+        let (patterns, leaf_entries): (Vec<_>, Vec<_>) = leaves
+            .into_iter()
+            .map(|leaf| {
+                let ancestors = sm.ancestors(leaf);
+                let index = ancestors.iter().position(|a| *a == composite).unwrap();
+                let state = &sm.states[leaf];
+                (
+                    state_path(
+                        sm,
+                        state,
+                        quote! { { .. } },
+                        self.sm_name,
+                        self.sm_name_span,
+                    ),
+                    self.entry_calls(leaf, &ancestors[index + 1..]),
+                )
+            })
+            .unzip();
+
+        quote! {
+            #entries
+            match self.state {
+                #(#patterns => { #leaf_entries })*
+                #[allow(unreachable_patterns)]
+                _ => {}
+            }
+        }
+    }
+
+    /// Names the current state as `transition_from` before it is left, along with the time it has
+    /// been active for as `visit_time` when the statistics are tracked.
+    fn leaving(&self) -> TokenStream {
+        let visit_time = if self.sm.statistics {
+            quote! { let visit_time = self.time_in_state(); }
+        } else {
+            quote! {}
+        };
+        quote! {
+            let transition_from = Self::state_name(&self.state);
+            #visit_time
+        }
+    }
 
-    let error_type = if sm.custom_error {
+    /// Records a transition from the state named `transition_from` to the current state in the
+    /// transition history and the statistics, if any, and notifies the context and its metrics of
+    /// it.
+    fn transitioned(&self, event: &TokenStream) -> TokenStream {
+        let record = if self.sm.transition_history.is_some() {
+            quote! {
+                self.record_transition(transition_from, #event, Self::state_name(&self.state));
+            }
+        } else {
+            quote! {}
+        };
+        let visit = if self.sm.statistics {
+            quote! { self.record_visit(transition_from, visit_time); }
+        } else {
+            quote! {}
+        };
+        let trace = if cfg!(feature = "tracing") {
+            quote! {
+                smlang::tracing::debug!(
+                    from = transition_from,
+                    event = #event,
+                    to = Self::state_name(&self.state),
+                    "transition",
+                );
+            }
+        } else {
+            quote! {}
+        };
+        let log = if cfg!(feature = "defmt") {
+            quote! {
+                defmt::debug!(
+                    "{=str} + {=str} = {=str}",
+                    transition_from,
+                    #event,
+                    Self::state_name(&self.state),
+                );
+            }
+        } else {
+            quote! {}
+        };
+        let metrics = if self.sm.metrics {
+            quote! {
+                self.context.transition_taken(
+                    transition_from,
+                    #event,
+                    Self::state_name(&self.state),
+                );
+            }
+        } else {
+            quote! {}
+        };
+        let publish = match (self.sm.publish_state_id, self.sm.state_id_mirror) {
+            (false, false) => quote! {},
+            (publish, mirror) => {
+                let publish =
+                    publish.then(|| quote! { self.context.publish_state_id(self.state_id); });
+                let mirror =
+                    mirror.then(|| quote! { self.context.state_id_mirror().store(self.state_id); });
+                quote! {
+                    self.state_id = Self::id_of(&self.state);
+                    #mirror
+                    #publish
+                }
+            }
+        };
         quote! {
-            #error_type_name<<T as #state_machine_context_type_name>::Error>
+            #publish
+            #record
+            #visit
+            #trace
+            #log
+            #metrics
+            self.context.on_transition(transition_from, #event, &self.state);
         }
+    }
+
+    /// Restarts the timer of timed transitions when a state is entered.
+    fn timer_reset(&self) -> TokenStream {
+        if !self.sm.uses_clock() {
+            quote! {}
+        } else {
+            let clock = clock_of(self.sm, &quote! { self.context });
+            quote! { self.state_entered = Some(smlang::Clock::now(#clock)); }
+        }
+    }
+}
+
+/// Passes the event to the regions of a parallel state. Once any region handles the event, the
+/// parallel state is left for its completion state if all regions are in a final sub-state.
+fn parallel_dispatch(
+    hooks: &Hooks,
+    parallel: &ParallelState,
+    await_call: &TokenStream,
+) -> TokenStream {
+    let sm = hooks.sm;
+    let (sm_name, sm_name_span) = (hooks.sm_name, hooks.sm_name_span);
+    let temporary_context_call = hooks.temporary_context_call;
+    let action_taken = if sm.transition_outcome {
+        quote! { &mut self.action_taken, }
     } else {
-        quote! {#error_type_name}
+        quote! {}
+    };
+    let coverage = if sm.transition_coverage {
+        quote! { &mut self.coverage, }
+    } else {
+        quote! {}
+    };
+    let (fields, function_names): (Vec<_>, Vec<_>) = parallel
+        .regions
+        .iter()
+        .map(|region| {
+            (
+                region_field_name(&region.ident),
+                region_function_name(&region.ident.to_string()),
+            )
+        })
+        .unzip();
+    let (poison, unpoison) = if sm.poisoning {
+        (
+            quote! { self.poisoned = true; },
+            quote! { self.poisoned = false; },
+        )
+    } else {
+        (quote! {}, quote! {})
     };
+    let commit_names: Vec<_> = function_names
+        .iter()
+        .map(|function_name| format_ident!("commit_{}", function_name))
+        .collect();
+    let next_names: Vec<_> = fields
+        .iter()
+        .map(|field| format_ident!("next_{}", field))
+        .collect();
 
-    let states_attr_list = &sm.states_attr;
-    let events_attr_list = &sm.events_attr;
-    // Build the states and events output
-    quote! {
-        /// This trait outlines the guards and actions that need to be implemented for the state
-        /// machine.
-        pub trait #state_machine_context_type_name {
-            #custom_error
-            #guard_list
-            #action_list
-            #entries_exits
+    let completion = match &parallel.completion {
+        Some(completion) => {
+            let parallel_name = parallel.ident.to_string();
+            let completion_name = completion.to_string();
+            let final_states: Vec<_> = parallel
+                .regions
+                .iter()
+                .map(|region| {
+                    let field = region_field_name(&region.ident);
+                    let patterns = sm
+                        .final_states(region)
+                        .into_iter()
+                        .map(|state| state_path(sm, state, quote! {}, sm_name, sm_name_span));
+                    quote! { matches!(regions.#field, #(#patterns { .. })|*) }
+                })
+                .collect();
 
+            let in_ancestors = sm.ancestors(&parallel_name);
+            let out_ancestors = sm.ancestors(&completion_name);
+            let shared = in_ancestors
+                .iter()
+                .zip(out_ancestors.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let exits = hooks.exit_calls(&parallel_name, &in_ancestors[shared..]);
+            let entering = hooks.entering();
+            let entries = hooks.entry_calls(&completion_name, &out_ancestors[shared..]);
+            let set_state = hooks.set_state(&in_ancestors[shared..]);
+            let data = if sm.parallel_states.contains_key(&completion_name) {
+                parallel_initial(sm, &completion_name, sm_name, sm_name_span)
+            } else {
+                quote! {}
+            };
+            let out_state = state_path(sm, completion, data, sm_name, sm_name_span);
+            let leaving = hooks.leaving();
+            let event_name = quote! { Self::event_name(&event) };
+            let transitioned = hooks.transitioned(&event_name);
+            let unpoison = hooks.unpoison();
+            let entry_failed = hooks.entry_failed(&event_name);
 
-            /// Called at the beginning of a state machine's `process_event()`. No-op by
-            /// default but can be overridden in implementations of a state machine's
-            /// `StateMachineContext` trait.
-            fn log_process_event(&self, current_state: & #states_type_name, event: & #events_type_name) {}
+            quote! {
+                if #(#final_states)&&* {
+                    #poison
+                    #exits
+                    let out_state = #out_state;
+                    self.context().transition_callback(&self.state, &out_state);
+                    #leaving
+                    #set_state
+                    #entering
+                    #entries
+                    #unpoison
+                    #transitioned
+                    #entry_failed
+                }
+            }
+        }
+        None => quote! {},
+    };
 
-            /// Called after executing a guard during `process_event()`. No-op by
-            /// default but can be overridden in implementations of a state machine's
-            /// `StateMachineContext` trait.
-            fn log_guard(&self, guard: &'static str, result: bool) {}
+    // The actions of every region run before any region leaves its sub-state, which happens once
+    // all of them have succeeded. Every region is committed before the first failed handler is
+    // returned.
+    quote! {
+        let mut handled = false;
+        let mut guards_failed = false;
+        #(let mut #next_names = None;)*
+        #(match Self::#function_names(&mut self.context, #action_taken #coverage #temporary_context_call &mut regions.#fields, &mut #next_names, &event) #await_call ? {
+            Some(true) => handled = true,
+            Some(false) => guards_failed = true,
+            None => {}
+        })*
+        #poison
+        let mut committed = Ok(());
+        #(if let Some(next) = #next_names {
+            let result = Self::#commit_names(&mut self.context, #temporary_context_call &mut regions.#fields, next) #await_call;
+            committed = committed.and(result);
+        })*
+        committed?;
+        #unpoison
+        if handled {
+            #completion
+            return Ok(&self.state);
+        }
+    }
+}
 
-            /// Called after executing an action during `process_event()`. No-op by
-            /// default but can be overridden in implementations of a state machine's
-            /// `StateMachineContext` trait.
-            fn log_action(&self, action: &'static str) {}
+/// The variants of the states enum for the states directly nested in `scope`, or the top level
+/// states if `scope` is `None`.
+fn state_variants(
+    sm: &ParsedStateMachine,
+    scope: Option<&str>,
+    sm_name: &str,
+    sm_name_span: Span,
+) -> Vec<TokenStream> {
+    // The attributes declared in the `states` block are put on the variants
+    scoped_variants(sm, scope, sm_name, sm_name_span)
+        .into_iter()
+        .map(|(name, value, data)| {
+            let attrs = sm.state_handlers.get(&name).map(|h| &h.attrs[..]);
+            let attrs = attrs.unwrap_or_default();
+            match data {
+                Some(data) => quote! { #(#attrs)* #value(#data) },
+                None => quote! { #(#attrs)* #value },
+            }
+        })
+        .collect()
+}
 
-            /// Called when transitioning to a new state as a result of an event passed to
-            /// `process_event()`. No-op by default which can be overridden in implementations
-            /// of a state machine's `StateMachineContext` trait.
-            fn transition_callback(&self, old_state: & #states_type_name, new_state: & #states_type_name) {}
-        }
+/// The names, identifiers and data of the variants of the enum of the states in the scope of the
+/// composite state or region `scope`, sorted by their names, where the data of composite and
+/// parallel states are their sub-states.
+fn scoped_variants<'a>(
+    sm: &'a ParsedStateMachine,
+    scope: Option<&str>,
+    sm_name: &str,
+    sm_name_span: Span,
+) -> Vec<(String, &'a Ident, Option<TokenStream>)> {
+    let in_scope = |state: &str| {
+        sm.state_parents.get(state).map(|parent| parent.to_string()) == scope.map(String::from)
+    };
 
-        /// List of auto-generated states.
-        #[allow(missing_docs)]
-        #(#states_attr_list)*
-        pub enum #states_type_name <#state_lifetimes> { #(#state_list),* }
+    let states = match scope {
+        Some(region) if sm.region_parents.values().any(|r| r == region) => &sm.region_states,
+        _ => &sm.states,
+    };
+    let in_scope = |state: &str| match sm.region_parents.get(state) {
+        Some(region) => Some(region.to_string()) == scope.map(String::from),
+        None => in_scope(state),
+    };
 
-        /// Manually define PartialEq for #states_type_name based on variant only to address issue-#21
-        impl<#state_lifetimes> PartialEq for #states_type_name <#state_lifetimes> {
-            fn eq(&self, other: &Self) -> bool {
-                use core::mem::discriminant;
-                discriminant(self) == discriminant(other)
+    let mut variants: Vec<_> = states
+        .iter()
+        .filter(|(name, _)| in_scope(name))
+        .map(|(name, value)| {
+            if sm.parallel_states.contains_key(name) {
+                let parallel_type_name = composite_type_name(name, sm_name, sm_name_span);
+                let lifetimes = parallel_lifetimes(sm, name);
+                return (
+                    name.clone(),
+                    value,
+                    Some(quote! { #parallel_type_name <#lifetimes> }),
+                );
             }
-        }
+            let data = sm.state_data.data_types.get(name).map(|t| quote! { #t });
+            (name.clone(), value, data)
+        })
+        .chain(
+            sm.composite_states
+                .iter()
+                .filter(|(name, _)| in_scope(name))
+                .map(|(name, composite)| {
+                    let value = &composite.ident;
+                    let composite_type_name = composite_type_name(name, sm_name, sm_name_span);
+                    let lifetimes = composite_lifetimes(sm, name);
+                    (
+                        name.clone(),
+                        value,
+                        Some(quote! { #composite_type_name <#lifetimes> }),
+                    )
+                }),
+        )
+        .collect();
+    variants.sort_by(|a, b| a.0.cmp(&b.0));
+    variants
+}
 
-        /// List of auto-generated events.
-        #[allow(missing_docs)]
-        #(#events_attr_list)*
-        pub enum #events_type_name <#event_lifetimes> { #(#event_list),* }
+/// The `is_<state>()` predicates of the variants of an enum of states, and the `as_<state>()` and
+/// `into_<state>()` accessors of their data.
+fn state_accessors(
+    sm: &ParsedStateMachine,
+    scope: Option<&str>,
+    type_name: &Ident,
+    lifetimes: &Lifetimes,
+    sm_name: &str,
+    sm_name_span: Span,
+) -> TokenStream {
+    let methods = scoped_variants(sm, scope, sm_name, sm_name_span)
+        .into_iter()
+        .map(|(name, value, data)| {
+            let snake_case = string_morph::to_snake_case(&name);
+            let is_state = format_ident!("is_{}", snake_case);
+            let is_doc = format!("Returns whether the state is [{}::{}].", type_name, name);
+            let (pattern, accessors) = match data {
+                Some(data) => {
+                    let as_state = format_ident!("as_{}", snake_case);
+                    let into_state = format_ident!("into_{}", snake_case);
+                    let as_doc = format!(
+                        "Returns the data of [{}::{}], if it is the state.",
+                        type_name, name
+                    );
+                    let into_doc = format!(
+                        "Converts the state into the data of [{}::{}], if it is the state.",
+                        type_name, name
+                    );
+                    (
+                        quote! { Self::#value(..) },
+                        quote! {
+                            #[doc = #as_doc]
+                            pub fn #as_state(&self) -> Option<&#data> {
+                                match self {
+                                    Self::#value(data) => Some(data),
+                                    #[allow(unreachable_patterns)]
+                                    _ => None,
+                                }
+                            }
 
-        /// Manually define PartialEq for #events_type_name based on variant only to address issue-#21
-        impl<#event_lifetimes> PartialEq for #events_type_name <#event_lifetimes> {
-            fn eq(&self, other: &Self) -> bool {
-                use core::mem::discriminant;
-                discriminant(self) == discriminant(other)
+                            #[doc = #into_doc]
+                            pub fn #into_state(self) -> Option<#data> {
+                                match self {
+                                    Self::#value(data) => Some(data),
+                                    #[allow(unreachable_patterns)]
+                                    _ => None,
+                                }
+                            }
+                        },
+                    )
+                }
+                None => (quote! { Self::#value }, quote! {}),
+            };
+            quote! {
+                #[doc = #is_doc]
+                pub fn #is_state(&self) -> bool {
+                    matches!(self, #pattern)
+                }
+
+                #accessors
             }
+        });
+    quote! {
+        impl<#lifetimes> #type_name <#lifetimes> {
+            #(#methods)*
         }
+    }
+}
 
-        /// List of possible errors
-        #[derive(Debug,PartialEq)]
-        pub enum #error_type_name  <T=()> {
-            /// When an event is processed which should not come in the current state.
-            InvalidEvent,
-            /// When an event is processed and none of the transitions happened.
-            TransitionsFailed,
-            /// When guard is failed.
-            GuardFailed(T),
-            /// When action returns Err
-            ActionFailed(T),
+/// The path of a state in the enum of the sub-states of the composite state `scope`, wrapping it
+/// in the enums of the composite states in between.
+fn scoped_state_path(
+    sm: &ParsedStateMachine,
+    state: &Ident,
+    data: TokenStream,
+    scope: &Ident,
+    sm_name: &str,
+    sm_name_span: Span,
+) -> TokenStream {
+    let mut path = quote! { #state #data };
+    for ancestor in sm.ancestors(&state.to_string()).into_iter().rev() {
+        if ancestor == scope {
+            break;
         }
+        let composite_type_name = composite_type_name(&ancestor.to_string(), sm_name, sm_name_span);
+        path = quote! { #ancestor(#composite_type_name::#path) };
+    }
 
-        /// State machine structure definition.
-        pub struct #state_machine_type_name<#state_lifetimes T: #state_machine_context_type_name> {
-            state: #states_type_name <#state_lifetimes>,
-            context: T
-        }
+    let scope_type_name = composite_type_name(&scope.to_string(), sm_name, sm_name_span);
+    quote! { #scope_type_name::#path }
+}
 
-        impl<#state_lifetimes T: #state_machine_context_type_name> #state_machine_type_name<#state_lifetimes T> {
-            /// Creates a new state machine with the specified starting state.
-            #[inline(always)]
-            #new_sm_code
+/// The full path of a state in the states enum, wrapping it in the enums of the composite states
+/// containing it. The `data` tokens are appended to the state variant.
+fn state_path(
+    sm: &ParsedStateMachine,
+    state: &Ident,
+    data: TokenStream,
+    sm_name: &str,
+    sm_name_span: Span,
+) -> TokenStream {
+    if let Some(region) = sm.region_parents.get(&state.to_string()) {
+        let region_type_name = composite_type_name(&region.to_string(), sm_name, sm_name_span);
+        return quote! { #region_type_name::#state #data };
+    }
 
-            /// Creates a new state machine with an initial state.
-            #[inline(always)]
-            pub const fn new_with_state(context: T, initial_state: #states_type_name <#state_lifetimes>) -> Self {
-                #state_machine_type_name {
-                    state: initial_state,
-                    context
-                }
-            }
+    let states_type_name = sm.states_type_name();
 
-            /// Returns the current state.
-            #[inline(always)]
-            pub fn state(&self) -> &#states_type_name <#state_lifetimes> {
-                &self.state
-            }
+    let mut path = quote! { #state #data };
+    for ancestor in sm.ancestors(&state.to_string()).into_iter().rev() {
+        let composite_type_name = composite_type_name(&ancestor.to_string(), sm_name, sm_name_span);
+        path = quote! { #ancestor(#composite_type_name::#path) };
+    }
 
-            /// Returns the current context.
-            #[inline(always)]
-            pub fn context(&self) -> &T {
-                &self.context
-            }
+    quote! { #states_type_name::#path }
+}
 
-            /// Returns the current context as a mutable reference.
-            #[inline(always)]
-            pub fn context_mut(&mut self) -> &mut T {
-                &mut self.context
-            }
+/// `PartialEq` for a states enum, comparing the variants only to address issue-#21. Sub-states of
+/// composite states are compared as well.
+fn states_partial_eq(
+    sm: &ParsedStateMachine,
+    scope: Option<&str>,
+    type_name: &Ident,
+    lifetimes: &Lifetimes,
+    hash: bool,
+) -> TokenStream {
+    let mut composites: Vec<_> = sm
+        .composite_states
+        .values()
+        .filter(|c| c.parent.as_ref().map(|p| p.to_string()) == scope.map(String::from))
+        .map(|c| &c.ident)
+        .chain(
+            sm.parallel_states
+                .values()
+                .filter(|p| p.parent.as_ref().map(|p| p.to_string()) == scope.map(String::from))
+                .map(|p| &p.ident),
+        )
+        .collect();
+    composites.sort_by_key(|c| c.to_string());
 
-            /// Process an event.
-            ///
-            /// It will return `Ok(&NextState)` if the transition was successful, or `Err(#error_type_name)`
-            /// if there was an error in the transition.
-            pub #is_async fn process_event <#event_unique_lifetimes> (
-                &mut self,
-                #temporary_context
-                event: #events_type_name <#event_lifetimes>
-            ) -> Result<&#states_type_name <#state_lifetimes>, #error_type> {
-                self.context.log_process_event(self.state(), &event);
-               match self.state {
-                    #(
-                    #[allow(clippy::match_single_binding)]
-                    #states_type_name::#in_states => match event {
-                        #(#events_type_name::#events => {
-                            #code_blocks
+    let doc = format!(
+        "Manually define PartialEq for {} based on variant only to address issue-#21",
+        type_name
+    );
 
-                            #[allow(unreachable_code)]
-                            {
-                                // none of the guarded or non-guarded transitions occurred,
-                                Err(#error_type_name ::TransitionsFailed)
-                            }
-                        }),*
+    // The sub-states of composite states are hashed along with the variant, as they are compared
+    let hash_doc = format!(
+        "Manually define Hash for {} consistently with PartialEq",
+        type_name
+    );
+    let hash_impl = if hash {
+        quote! {
+            #[doc = #hash_doc]
+            impl<#lifetimes> core::hash::Hash for #type_name <#lifetimes> {
+                fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+                    core::hash::Hash::hash(&core::mem::discriminant(self), state);
+                    match self {
+                        #(#type_name::#composites(sub_states) => core::hash::Hash::hash(sub_states, state),)*
                         #[allow(unreachable_patterns)]
-                        _ => Err(#error_type_name ::InvalidEvent),
-                    }),*
+                        _ => {}
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    if composites.is_empty() {
+        quote! {
+            #[doc = #doc]
+            impl<#lifetimes> PartialEq for #type_name <#lifetimes> {
+                fn eq(&self, other: &Self) -> bool {
+                    use core::mem::discriminant;
+                    discriminant(self) == discriminant(other)
                 }
             }
+
+            #hash_impl
+        }
+    } else {
+        quote! {
+            #[doc = #doc]
+            impl<#lifetimes> PartialEq for #type_name <#lifetimes> {
+                fn eq(&self, other: &Self) -> bool {
+                    use core::mem::discriminant;
+                    match (self, other) {
+                        #((#type_name::#composites(a), #type_name::#composites(b)) => a == b,)*
+                        _ => discriminant(self) == discriminant(other),
+                    }
+                }
+            }
+
+            #hash_impl
         }
     }
 }
+
+/// The attributes without `Hash` in their derives, and whether `Hash` has been derived.
+fn without_hash_derive(attrs: &[syn::Attribute]) -> (Vec<syn::Attribute>, bool) {
+    let mut hash = false;
+    let attrs = attrs
+        .iter()
+        .filter_map(|attr| {
+            let Ok(syn::Meta::List(list)) = attr.parse_meta() else {
+                return Some(attr.clone());
+            };
+            if !list.path.is_ident("derive") {
+                return Some(attr.clone());
+            }
+            let derives: Vec<_> = list
+                .nested
+                .iter()
+                .filter(|derive| {
+                    let is_hash = matches!(derive, syn::NestedMeta::Meta(syn::Meta::Path(path))
+                        if path.segments.last().is_some_and(|segment| segment.ident == "Hash"));
+                    hash |= is_hash;
+                    !is_hash
+                })
+                .collect();
+            if derives.is_empty() {
+                return None;
+            }
+            Some(syn::parse_quote! { #[derive(#(#derives),*)] })
+        })
+        .collect();
+    (attrs, hash)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn generate_action(
     action: &Option<AsyncIdent>,
+    context: &TokenStream,
     temporary_context_call: &TokenStream,
     g_a_param: &TokenStream,
     error_type_name: &Ident,
+    on_error: Option<&TokenStream>,
+    retry: Option<&Retry>,
+    cancellable: bool,
 ) -> (bool, TokenStream) {
     let mut is_async = false;
     let code = if let Some(AsyncIdent {
@@ -699,13 +7118,151 @@ fn generate_action(
         } else {
             quote! {}
         };
+        let call = if *is_a_async && cancellable {
+            // The async action is raced against the `cancel` future, which cancels the
+            // transition by dropping the action once it is ready
+            quote! {
+                {
+                    let mut action = core::pin::pin!(#context.#action_ident(#temporary_context_call #g_a_param));
+                    match cancel.as_mut() {
+                        Some(cancel) => core::future::poll_fn(|cx| {
+                            match core::future::Future::poll(cancel.as_mut(), cx) {
+                                core::task::Poll::Ready(()) => core::task::Poll::Ready(None),
+                                core::task::Poll::Pending => {
+                                    core::future::Future::poll(action.as_mut(), cx).map(Some)
+                                }
+                            }
+                        })
+                        .await
+                        .ok_or(#error_type_name::Cancelled)?,
+                        None => action.await,
+                    }
+                }
+            }
+        } else {
+            quote! { #context.#action_ident(#temporary_context_call #g_a_param) #action_await }
+        };
+        let (is_async_backoff, call) = retried(call, retry, context);
+        is_async |= is_async_backoff;
+        let call = match on_error {
+            // The error of the action is handled by entering the fault state
+            Some(on_error) => quote! {
+                match #call {
+                    Ok(data) => data,
+                    #on_error
+                }
+            },
+            None => quote! { #call.map_err(#error_type_name::ActionFailed)? },
+        };
+        let trace_action = if cfg!(feature = "tracing") {
+            quote! { smlang::tracing::trace!(action = stringify!(#action_ident)); }
+        } else {
+            quote! {}
+        };
         quote! {
             // ACTION
-            let _data = self.context.#action_ident(#temporary_context_call #g_a_param) #action_await .map_err(#error_type_name::ActionFailed)?;
-            self.context.log_action(stringify!(#action_ident));
+            let _data = #call;
+            #context.log_action(stringify!(#action_ident));
+            #trace_action
         }
     } else {
         quote! {}
     };
     (is_async, code)
 }
+
+/// Calls a guard of a guard expression. An async guard with a timeout is raced against the timer
+/// of the context, where a guard which does not resolve in time fails or returns an error.
+fn guard_call(
+    sm: &ParsedStateMachine,
+    context: &TokenStream,
+    guard: &AsyncIdent,
+    timeout: Option<&GuardTimeout>,
+    temporary_context_call: &TokenStream,
+    guard_params: &TokenStream,
+    error_type_name: &Ident,
+) -> TokenStream {
+    let guard_ident = &guard.ident;
+    let guard_await = if guard.is_async {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => {
+            return quote! { #context.#guard_ident(#temporary_context_call #guard_params) #guard_await }
+        }
+    };
+    let (now, sleep_until) = if sm.associated_types {
+        (
+            quote! { smlang::Clock::now(#context.clock()) },
+            quote! { smlang::Clock::sleep_until(#context.clock(), deadline) },
+        )
+    } else {
+        (
+            quote! { <T as smlang::Clock>::now(&#context) },
+            quote! { <T as smlang::Clock>::sleep_until(&#context, deadline) },
+        )
+    };
+    let duration = &timeout.duration;
+    let timed_out = if timeout.is_error {
+        quote! { .ok_or(#error_type_name::GuardTimedOut)? }
+    } else {
+        quote! { .unwrap_or(Ok(false)) }
+    };
+    quote! {
+        {
+            let mut guard = core::pin::pin!(#context.#guard_ident(#temporary_context_call #guard_params));
+            let deadline = #now + #duration;
+            let mut timer = core::pin::pin!(#sleep_until);
+            core::future::poll_fn(|cx| match core::future::Future::poll(guard.as_mut(), cx) {
+                core::task::Poll::Ready(result) => core::task::Poll::Ready(Some(result)),
+                core::task::Poll::Pending => {
+                    core::future::Future::poll(timer.as_mut(), cx).map(|()| None)
+                }
+            })
+            .await
+            #timed_out
+        }
+    }
+}
+
+/// Retries the call of a guard or action while it fails, up to the retries of the transition,
+/// calling the backoff before every retry. Returns whether the backoff is async.
+fn retried(call: TokenStream, retry: Option<&Retry>, context: &TokenStream) -> (bool, TokenStream) {
+    let retry = match retry {
+        Some(retry) => retry,
+        None => return (false, call),
+    };
+    let retries = retry.retries;
+    let (is_async, backoff) = match &retry.backoff {
+        Some(AsyncIdent { ident, is_async }) => {
+            let backoff_await = if *is_async {
+                quote! { .await }
+            } else {
+                quote! {}
+            };
+            (
+                *is_async,
+                quote! { #context.#ident(attempt) #backoff_await; },
+            )
+        }
+        None => (false, quote! {}),
+    };
+    let code = quote! {
+        {
+            let mut attempt = 0;
+            loop {
+                match #call {
+                    Err(_) if attempt < #retries => {
+                        attempt += 1;
+                        #backoff
+                    }
+                    result => break result,
+                }
+            }
+        }
+    };
+    (is_async, code)
+}
@@ -1,85 +1,723 @@
 use crate::parser::*;
 
 /// Generates a string containing 'dot' syntax to generate a statemachine diagram with graphviz.
+/// Composite states are drawn as clusters of their sub-states, and parallel states as clusters of
+/// their regions.
+#[cfg(feature = "graphviz")]
 pub fn generate_diagram(sm: &ParsedStateMachine) -> String {
-    let transitions = &sm.states_events_mapping;
-
-    let mut diagram_states = sm.states.iter().map(|s| s.0).collect::<Vec<&String>>();
-    diagram_states.sort();
-    let diagram_states = diagram_states.into_iter();
-    let mut diagram_events = vec![];
-    let mut diagram_transitions = vec![];
-    for (state, event) in transitions {
-        for eventmapping in event.values() {
-            for transition in &eventmapping.transitions {
-                diagram_events.push((
-                    eventmapping.event.to_string(),
-                    transition
-                        .guard
-                        .as_ref()
-                        .map(|i| i.to_string())
-                        .unwrap_or_else(|| "_".to_string()),
-                    transition
-                        .action
-                        .as_ref()
-                        .map(|i| i.ident.to_string())
-                        .unwrap_or_else(|| "_".to_string()),
-                ));
-                diagram_transitions.push((
-                    state,
-                    transition.out_state.to_string(),
-                    eventmapping.event.to_string(),
+    let diagram = state_diagram(sm);
+    let mut lines = vec![
+        "digraph G {".to_string(),
+        "    rankdir=\"LR\";".to_string(),
+        "    compound=true;".to_string(),
+        "    node [fontname=Arial shape=box style=\"rounded,filled\" fillcolor=\"#ffbb33\"];"
+            .to_string(),
+        "    edge [fontname=Arial color=blue];".to_string(),
+    ];
+    diagram.dot_scope(None, &sm.starting_state.to_string(), 1, &mut lines);
+
+    // Edges to and from composite and parallel states are clipped at their clusters
+    for edge in &diagram.edges {
+        let mut attributes = Vec::new();
+        if !edge.label.is_empty() {
+            attributes.push(format!("label=\"{}\"", edge.label.replace('"', "\\\"")));
+        }
+        if diagram.is_cluster(&edge.from) {
+            attributes.push(format!("ltail=cluster_{}", edge.from));
+        }
+        if diagram.is_cluster(&edge.to) && edge.to != edge.from {
+            attributes.push(format!("lhead=cluster_{}", edge.to));
+        }
+        let attributes = if attributes.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", attributes.join(" "))
+        };
+        lines.push(format!(
+            "    {} -> {}{attributes};",
+            diagram.dot_node(&edge.from),
+            diagram.dot_node(&edge.to),
+        ));
+    }
+    lines.push("}".to_string());
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// A transition of a state diagram.
+#[cfg(any(
+    feature = "graphviz",
+    feature = "mermaid",
+    feature = "plantuml",
+    feature = "markdown"
+))]
+#[derive(Clone, PartialEq)]
+struct DiagramEdge {
+    from: String,
+    to: String,
+    label: String,
+}
+
+/// Generates a string containing Mermaid `stateDiagram-v2` syntax, which renders in markdown
+/// without a Graphviz toolchain.
+#[cfg(any(feature = "mermaid", feature = "markdown"))]
+pub fn generate_mermaid(sm: &ParsedStateMachine) -> String {
+    let mut lines = vec!["stateDiagram-v2".to_string()];
+    state_diagram(sm).scope(None, &sm.starting_state.to_string(), 1, &mut lines);
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Generates a string containing a PlantUML state diagram, with the guards and actions of the
+/// transitions on their edges.
+#[cfg(feature = "plantuml")]
+pub fn generate_plantuml(sm: &ParsedStateMachine) -> String {
+    let mut lines = vec!["@startuml".to_string()];
+    state_diagram(sm).scope(None, &sm.starting_state.to_string(), 0, &mut lines);
+    lines.push("@enduml".to_string());
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Collects the states and transitions of the state diagrams.
+#[cfg(any(
+    feature = "graphviz",
+    feature = "mermaid",
+    feature = "plantuml",
+    feature = "markdown"
+))]
+fn state_diagram(sm: &ParsedStateMachine) -> StateDiagram<'_> {
+    use crate::parser::event::{ANY_EVENT, COMPLETION_EVENT};
+    use std::collections::HashMap;
+
+    // The composite state, parallel state or region each state is nested in
+    let mut parents: HashMap<String, String> = HashMap::new();
+    for (state, parent) in sm.state_parents.iter().chain(sm.region_parents.iter()) {
+        parents.insert(state.clone(), parent.to_string());
+    }
+    for parallel in sm.parallel_states.values() {
+        for region in &parallel.regions {
+            parents.insert(region.ident.to_string(), parallel.ident.to_string());
+        }
+    }
+
+    let mut states: Vec<_> = sm.states_events_mapping.keys().collect();
+    states.sort();
+    let mut choices = Vec::new();
+    let mut edges = Vec::new();
+    for state in states {
+        let mut mappings: Vec<_> = sm.states_events_mapping[state].values().collect();
+        mappings.sort_by_key(|mapping| mapping.event.to_string());
+        for mapping in mappings {
+            let event = match mapping.event.to_string().as_str() {
+                COMPLETION_EVENT => String::new(),
+                ANY_EVENT => "_".to_string(),
+                event => event.to_string(),
+            };
+            for (index, transition) in mapping.transitions.iter().enumerate() {
+                let mut label = event.clone();
+                if let Some(guard) = &transition.guard {
+                    label.push_str(&format!(" [{guard}]"));
+                } else if transition.fallback {
+                    label.push_str(" [else]");
+                }
+                let actions: Vec<_> = transition
+                    .preceding_actions
+                    .iter()
+                    .chain(transition.action.iter())
+                    .map(|action| action.ident.to_string())
+                    .collect();
+                if !actions.is_empty() {
+                    label.push_str(&format!(" / {}", actions.join(", ")));
+                }
+                let label = label.trim().to_string();
+                match &transition.choice {
+                    // Choices are drawn as choice pseudo-states branching to their targets
+                    Some(choice) => {
+                        let pseudo_state = format!("{state}_{}_choice{index}", mapping.event);
+                        if let Some(parent) = parents.get(state).cloned() {
+                            parents.insert(pseudo_state.clone(), parent);
+                        }
+                        choices.push(pseudo_state.clone());
+                        edges.push(DiagramEdge {
+                            from: state.clone(),
+                            to: pseudo_state.clone(),
+                            label,
+                        });
+                        for branch in &choice.branches {
+                            edges.push(DiagramEdge {
+                                from: pseudo_state.clone(),
+                                to: branch.out_state.ident.to_string(),
+                                label: match &branch.guard {
+                                    Some(guard) => format!("[{guard}]"),
+                                    None => String::new(),
+                                },
+                            });
+                        }
+                    }
+                    // Transitions to composite states are drawn to the composite state rather
+                    // than to the sub-state entered
+                    None => edges.push(DiagramEdge {
+                        from: state.clone(),
+                        to: transition
+                            .out_composite
+                            .as_ref()
+                            .unwrap_or(&transition.out_state)
+                            .to_string(),
+                        label,
+                    }),
+                }
+            }
+        }
+    }
+    let mut parallels: Vec<_> = sm.parallel_states.values().collect();
+    parallels.sort_by_key(|parallel| parallel.ident.to_string());
+    for parallel in parallels {
+        if let Some(completion) = &parallel.completion {
+            edges.push(DiagramEdge {
+                from: parallel.ident.to_string(),
+                to: completion.to_string(),
+                label: String::new(),
+            });
+        }
+    }
+
+    // The transitions of composite states have been copied to all of their sub-states, so
+    // transitions shared by all sub-states are drawn from the composite state again, starting
+    // with the innermost composite states
+    let depth = |state: &str| {
+        let mut depth = 0;
+        let mut state = state;
+        while let Some(parent) = parents.get(state) {
+            depth += 1;
+            state = parent;
+        }
+        depth
+    };
+    let mut composites: Vec<_> = sm.composite_states.values().collect();
+    composites.sort_by_key(|composite| {
+        (
+            core::cmp::Reverse(depth(&composite.ident.to_string())),
+            composite.ident.to_string(),
+        )
+    });
+    for composite in composites {
+        let children: Vec<_> = composite
+            .children
+            .iter()
+            .map(|child| child.to_string())
+            .filter(|child| !sm.terminal_states.contains_key(child))
+            .collect();
+        let shared: Vec<_> = match children.first() {
+            Some(first) => edges
+                .iter()
+                .filter(|edge| &edge.from == first)
+                .filter(|edge| {
+                    children.iter().all(|child| {
+                        edges.iter().any(|other| {
+                            &other.from == child && other.to == edge.to && other.label == edge.label
+                        })
+                    })
+                })
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        for edge in shared {
+            edges.retain(|other| {
+                !(children.contains(&other.from)
+                    && other.to == edge.to
+                    && other.label == edge.label)
+            });
+            edges.push(DiagramEdge {
+                from: composite.ident.to_string(),
+                ..edge
+            });
+        }
+    }
+
+    StateDiagram {
+        sm,
+        parents,
+        choices,
+        edges,
+    }
+}
+
+/// The states and transitions of a state diagram, which are drawn in the scopes they are nested
+/// in.
+#[cfg(any(
+    feature = "graphviz",
+    feature = "mermaid",
+    feature = "plantuml",
+    feature = "markdown"
+))]
+struct StateDiagram<'a> {
+    sm: &'a ParsedStateMachine,
+    parents: std::collections::HashMap<String, String>,
+    choices: Vec<String>,
+    edges: Vec<DiagramEdge>,
+}
+
+#[cfg(feature = "graphviz")]
+impl StateDiagram<'_> {
+    /// Whether a state is drawn as a cluster, which are the composite and parallel states.
+    fn is_cluster(&self, state: &str) -> bool {
+        self.sm.composite_states.contains_key(state) || self.sm.parallel_states.contains_key(state)
+    }
+
+    /// The node edges to and from a state are drawn to, which is the initial sub-state of the
+    /// clusters of composite and parallel states.
+    fn dot_node(&self, state: &str) -> String {
+        if let Some(composite) = self.sm.composite_states.get(state) {
+            return self.dot_node(&composite.initial.as_ref().unwrap().to_string());
+        }
+        if let Some(parallel) = self.sm.parallel_states.get(state) {
+            let region = &parallel.regions[0];
+            return self.dot_node(&region.initial.as_ref().unwrap().to_string());
+        }
+        state.to_string()
+    }
+
+    /// Adds the nodes and clusters nested in `scope`, or the top-level ones, to the diagram,
+    /// marking its initial state with a point.
+    fn dot_scope(&self, scope: Option<&str>, initial: &str, depth: usize, lines: &mut Vec<String>) {
+        let sm = self.sm;
+        let indent = "    ".repeat(depth);
+        let start = match scope {
+            Some(scope) => format!("{scope}_start"),
+            None => "start".to_string(),
+        };
+        lines.push(format!(
+            "{indent}{start} [shape=point width=0.2 label=\"\" style=filled fillcolor=black];"
+        ));
+        let lhead = if self.is_cluster(initial) {
+            format!(" [lhead=cluster_{initial}]")
+        } else {
+            String::new()
+        };
+        lines.push(format!(
+            "{indent}{start} -> {}{lhead};",
+            self.dot_node(initial)
+        ));
+
+        let mut states: Vec<_> = sm
+            .states
+            .keys()
+            .chain(sm.region_states.keys())
+            .chain(self.edges.iter().map(|edge| &edge.to))
+            .filter(|state| self.parents.get(*state).map(String::as_str) == scope)
+            .filter(|state| !self.choices.contains(state))
+            .collect();
+        states.sort();
+        states.dedup();
+        for state in states {
+            if let Some(composite) = sm.composite_states.get(state) {
+                lines.push(format!("{indent}subgraph cluster_{state} {{"));
+                lines.push(format!("{indent}    label=\"{state}\";"));
+                let initial = composite.initial.as_ref().unwrap().to_string();
+                self.dot_scope(Some(state), &initial, depth + 1, lines);
+                lines.push(format!("{indent}}}"));
+            } else if let Some(parallel) = sm.parallel_states.get(state) {
+                lines.push(format!("{indent}subgraph cluster_{state} {{"));
+                lines.push(format!("{indent}    label=\"{state}\";"));
+                for region in &parallel.regions {
+                    let region_name = region.ident.to_string();
+                    lines.push(format!("{indent}    subgraph cluster_{region_name} {{"));
+                    lines.push(format!("{indent}        label=\"{region_name}\";"));
+                    lines.push(format!("{indent}        style=dashed;"));
+                    let initial = region.initial.as_ref().unwrap().to_string();
+                    self.dot_scope(Some(&region_name), &initial, depth + 2, lines);
+                    lines.push(format!("{indent}    }}"));
+                }
+                lines.push(format!("{indent}}}"));
+            } else if sm.terminal_states.contains_key(state) {
+                lines.push(format!("{indent}{state} [peripheries=2];"));
+            } else {
+                lines.push(format!("{indent}{state};"));
+            }
+        }
+        for choice in &self.choices {
+            if self.parents.get(choice).map(String::as_str) == scope {
+                lines.push(format!(
+                    "{indent}{choice} [shape=diamond label=\"\" width=0.3 height=0.3];"
                 ));
             }
         }
     }
-    // Sorting is needed to ensure stable (ie not changing between runs of
-    // the same sm code) dot file contents. This is needed to ensure stable
-    // hash sum, which is used to name unnamed diagrams. If done without sorting,
-    // the output is polluted with lots of similar svg files with different names.
-    // This ensures that new files will only occur upon changing the structure of the code.
-    diagram_events.sort();
-    diagram_transitions.sort();
-
-    let state_string = diagram_states
-        .map(|s| {
-            format!(
-                "\t{} [shape=box color=\"red\" fillcolor=\"#ffbb33\" style=filled]",
-                s
-            )
-        })
-        .collect::<Vec<String>>();
-    let event_string = diagram_events
-        .iter()
-        .map(|s| {
-            format!(
-                "\t{0} [shape=box label=\"{0}\\n[{1}] / {2}\"]",
-                s.0, s.1, s.2
-            )
-        })
-        .collect::<Vec<String>>();
-    let transition_string = diagram_transitions
-        .iter()
-        .map(|t| format!("\t{0} -> {1} [color=blue label={2}];", t.0, t.1, t.2))
-        .collect::<Vec<String>>();
-
-    format!(
-        "digraph G {{
+}
+
+#[cfg(any(feature = "mermaid", feature = "plantuml", feature = "markdown"))]
+impl StateDiagram<'_> {
+    /// The composite states, parallel states and regions a state is nested in, innermost first.
+    fn ancestors(&self, state: &str) -> Vec<&str> {
+        let mut ancestors = Vec::new();
+        let mut state = state;
+        while let Some(parent) = self.parents.get(state) {
+            ancestors.push(parent.as_str());
+            state = parent;
+        }
+        ancestors
+    }
+
+    /// The scope a transition is drawn in, which is the innermost scope both of its states are
+    /// nested in. Transitions can't be drawn between the regions of a parallel state, so they are
+    /// drawn around it instead.
+    fn edge_scope(&self, edge: &DiagramEdge) -> Option<&str> {
+        let to = self.ancestors(&edge.to);
+        self.ancestors(&edge.from)
+            .into_iter()
+            .filter(|scope| !self.sm.parallel_states.contains_key(*scope))
+            .find(|scope| to.contains(scope))
+    }
+
+    /// Adds the states and transitions nested in `scope`, or the top-level ones, to the diagram.
+    fn scope(&self, scope: Option<&str>, initial: &str, depth: usize, lines: &mut Vec<String>) {
+        let sm = self.sm;
+        let indent = "    ".repeat(depth);
+        lines.push(format!("{indent}[*] --> {initial}"));
+
+        // Composite and parallel states are drawn with their sub-states nested in them
+        let is_nested = |parent: &Option<proc_macro2::Ident>| {
+            parent.as_ref().map(|p| p.to_string()).as_deref() == scope
+        };
+        let mut composites: Vec<_> = sm
+            .composite_states
+            .values()
+            .filter(|composite| is_nested(&composite.parent))
+            .collect();
+        composites.sort_by_key(|composite| composite.ident.to_string());
+        for composite in composites {
+            let name = composite.ident.to_string();
+            let initial = composite.initial.as_ref().unwrap().to_string();
+            lines.push(format!("{indent}state {name} {{"));
+            self.scope(Some(&name), &initial, depth + 1, lines);
+            lines.push(format!("{indent}}}"));
+        }
+        let mut parallels: Vec<_> = sm
+            .parallel_states
+            .values()
+            .filter(|parallel| is_nested(&parallel.parent))
+            .collect();
+        parallels.sort_by_key(|parallel| parallel.ident.to_string());
+        for parallel in parallels {
+            lines.push(format!("{indent}state {} {{", parallel.ident));
+            for (index, region) in parallel.regions.iter().enumerate() {
+                if index > 0 {
+                    lines.push(format!("{indent}    --"));
+                }
+                let region_name = region.ident.to_string();
+                let initial = region.initial.as_ref().unwrap().to_string();
+                self.scope(Some(&region_name), &initial, depth + 1, lines);
+            }
+            lines.push(format!("{indent}}}"));
+        }
+
+        for choice in &self.choices {
+            if self.parents.get(choice).map(String::as_str) == scope {
+                lines.push(format!("{indent}state {choice} <<choice>>"));
+            }
+        }
+        for edge in &self.edges {
+            if self.edge_scope(edge) == scope {
+                let label = if edge.label.is_empty() {
+                    String::new()
+                } else {
+                    format!(" : {}", edge.label)
+                };
+                lines.push(format!("{indent}{} --> {}{label}", edge.from, edge.to));
+            }
+        }
+        let mut terminal_states: Vec<_> = sm
+            .terminal_states
+            .keys()
+            .filter(|state| self.parents.get(*state).map(String::as_str) == scope)
+            .collect();
+        terminal_states.sort();
+        for state in terminal_states {
+            lines.push(format!("{indent}{state} --> [*]"));
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "graphviz", feature = "mermaid", feature = "plantuml")
+))]
+mod test {
+    use crate::parser::{state_machine::StateMachine, ParsedStateMachine};
+
+    fn parse(sm: &str) -> Result<ParsedStateMachine, syn::Error> {
+        ParsedStateMachine::new(syn::parse_str::<StateMachine>(sm)?)
+    }
+
+    #[cfg(feature = "graphviz")]
+    #[test]
+    fn graphviz_diagram() -> Result<(), syn::Error> {
+        let sm = parse(
+            "transitions: {
+                *Off + PowerOn = On,
+                On + PowerOff = Off,
+                On {
+                    *Idle + Start [ ready ] / start = Busy,
+                    Busy + Done / measure = choice(u32) {
+                        [ in_range ] = Idle,
+                        = Failed,
+                    },
+                },
+                Failed + Discard = Broken @final,
+            }",
+        )?;
+
+        assert_eq!(
+            super::generate_diagram(&sm),
+            "digraph G {
     rankdir=\"LR\";
-    node [fontname=Arial];
-    edge [fontname=Arial];
-    s [shape=circle size=2 color=\"black\" style=filled]
-    
-    s -> {}
-{}
-
-{}
-
-{}
-}}",
-        sm.starting_state,
-        state_string.join("\n"),
-        event_string.join("\n"),
-        transition_string.join("\n")
-    )
+    compound=true;
+    node [fontname=Arial shape=box style=\"rounded,filled\" fillcolor=\"#ffbb33\"];
+    edge [fontname=Arial color=blue];
+    start [shape=point width=0.2 label=\"\" style=filled fillcolor=black];
+    start -> Off;
+    Broken [peripheries=2];
+    Failed;
+    Off;
+    subgraph cluster_On {
+        label=\"On\";
+        On_start [shape=point width=0.2 label=\"\" style=filled fillcolor=black];
+        On_start -> Idle;
+        Busy;
+        Idle;
+        Busy_Done_choice0 [shape=diamond label=\"\" width=0.3 height=0.3];
+    }
+    Busy -> Busy_Done_choice0 [label=\"Done / measure\"];
+    Busy_Done_choice0 -> Idle [label=\"[in_range()]\"];
+    Busy_Done_choice0 -> Failed;
+    Failed -> Broken [label=\"Discard\"];
+    Idle -> Busy [label=\"Start [ready()] / start\"];
+    Off -> Idle [label=\"PowerOn\" lhead=cluster_On];
+    Idle -> Off [label=\"PowerOff\" ltail=cluster_On];
+}
+"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "mermaid")]
+    #[test]
+    fn mermaid_diagram() -> Result<(), syn::Error> {
+        let sm = parse(
+            "transitions: {
+                *Off + PowerOn = On,
+                On + PowerOff = Off,
+                On {
+                    *Idle + Start [ ready ] / start = Busy,
+                    Busy + Done / measure = choice(u32) {
+                        [ in_range ] = Idle,
+                        = Failed,
+                    },
+                },
+                Failed + Discard = Broken @final,
+            }",
+        )?;
+
+        assert_eq!(
+            super::generate_mermaid(&sm),
+            "stateDiagram-v2
+    [*] --> Off
+    state On {
+        [*] --> Idle
+        state Busy_Done_choice0 <<choice>>
+        Busy --> Busy_Done_choice0 : Done / measure
+        Busy_Done_choice0 --> Idle : [in_range()]
+        Idle --> Busy : Start [ready()] / start
+    }
+    Busy_Done_choice0 --> Failed
+    Failed --> Broken : Discard
+    Off --> On : PowerOn
+    On --> Off : PowerOff
+    Broken --> [*]
+"
+        );
+        Ok(())
+    }
+    #[cfg(feature = "plantuml")]
+    #[test]
+    fn plantuml_diagram() -> Result<(), syn::Error> {
+        let sm = parse(
+            "transitions: {
+                *Idle + Start [ ready && !busy ] / start = Running,
+                Running [
+                    Motor {
+                        *SpinningUp + AtSpeed = Spinning,
+                    },
+                    Fan {
+                        *Cooling + Cooled = FanOff,
+                    },
+                ] = Idle,
+            }",
+        )?;
+
+        assert_eq!(
+            super::generate_plantuml(&sm),
+            "@startuml
+[*] --> Idle
+state Running {
+    [*] --> SpinningUp
+    SpinningUp --> Spinning : AtSpeed
+    --
+    [*] --> Cooling
+    Cooling --> FanOff : Cooled
+}
+Idle --> Running : Start [ready() && !busy()] / start
+Running --> Idle
+@enduml
+"
+        );
+        Ok(())
+    }
+
+    /// A state machine with history, timed transitions, parallel regions and a final state.
+    fn device() -> Result<ParsedStateMachine, syn::Error> {
+        parse(
+            "transitions: {
+                *Off + PowerOn = On,
+                Off + after(core::time::Duration::from_secs(2)) = Sleeping,
+                Sleeping + PowerOn = On,
+                On + PowerOff = Off,
+                On(H) {
+                    *Idle + Start [ ready && !busy ] / start = Busy,
+                    Busy + Done = Measuring,
+                },
+                Measuring [
+                    Left {
+                        *LeftBusy + Done = LeftDone,
+                    },
+                    Right {
+                        *RightBusy + Tick = RightDone,
+                    },
+                ] = Off,
+                Measuring + Abort = Broken @final,
+            }",
+        )
+    }
+
+    #[cfg(feature = "graphviz")]
+    #[test]
+    fn graphviz_parallel_states() -> Result<(), syn::Error> {
+        assert_eq!(
+            super::generate_diagram(&device()?),
+            "digraph G {
+    rankdir=\"LR\";
+    compound=true;
+    node [fontname=Arial shape=box style=\"rounded,filled\" fillcolor=\"#ffbb33\"];
+    edge [fontname=Arial color=blue];
+    start [shape=point width=0.2 label=\"\" style=filled fillcolor=black];
+    start -> Off;
+    Broken [peripheries=2];
+    subgraph cluster_Measuring {
+        label=\"Measuring\";
+        subgraph cluster_Left {
+            label=\"Left\";
+            style=dashed;
+            Left_start [shape=point width=0.2 label=\"\" style=filled fillcolor=black];
+            Left_start -> LeftBusy;
+            LeftBusy;
+            LeftDone;
+        }
+        subgraph cluster_Right {
+            label=\"Right\";
+            style=dashed;
+            Right_start [shape=point width=0.2 label=\"\" style=filled fillcolor=black];
+            Right_start -> RightBusy;
+            RightBusy;
+            RightDone;
+        }
+    }
+    Off;
+    subgraph cluster_On {
+        label=\"On\";
+        On_start [shape=point width=0.2 label=\"\" style=filled fillcolor=black];
+        On_start -> Idle;
+        Busy;
+        Idle;
+    }
+    Sleeping;
+    Busy -> LeftBusy [label=\"Done\" lhead=cluster_Measuring];
+    Idle -> Busy [label=\"Start [ready() && !busy()] / start\"];
+    LeftBusy -> LeftDone [label=\"Done\"];
+    LeftBusy -> Broken [label=\"Abort\" ltail=cluster_Measuring];
+    Off -> Idle [label=\"PowerOn\" lhead=cluster_On];
+    Off -> Sleeping [label=\"after\"];
+    RightBusy -> RightDone [label=\"Tick\"];
+    Sleeping -> Idle [label=\"PowerOn\" lhead=cluster_On];
+    LeftBusy -> Off [ltail=cluster_Measuring];
+    Idle -> Off [label=\"PowerOff\" ltail=cluster_On];
+}
+"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "mermaid")]
+    #[test]
+    fn mermaid_parallel_states() -> Result<(), syn::Error> {
+        assert_eq!(
+            super::generate_mermaid(&device()?),
+            "stateDiagram-v2
+    [*] --> Off
+    state On {
+        [*] --> Idle
+        Idle --> Busy : Start [ready() && !busy()] / start
+    }
+    state Measuring {
+        [*] --> LeftBusy
+        LeftBusy --> LeftDone : Done
+        --
+        [*] --> RightBusy
+        RightBusy --> RightDone : Tick
+    }
+    Busy --> Measuring : Done
+    Measuring --> Broken : Abort
+    Off --> On : PowerOn
+    Off --> Sleeping : after
+    Sleeping --> On : PowerOn
+    Measuring --> Off
+    On --> Off : PowerOff
+    Broken --> [*]
+"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "plantuml")]
+    #[test]
+    fn plantuml_composite_states() -> Result<(), syn::Error> {
+        assert_eq!(
+            super::generate_plantuml(&device()?),
+            "@startuml
+[*] --> Off
+state On {
+    [*] --> Idle
+    Idle --> Busy : Start [ready() && !busy()] / start
+}
+state Measuring {
+    [*] --> LeftBusy
+    LeftBusy --> LeftDone : Done
+    --
+    [*] --> RightBusy
+    RightBusy --> RightDone : Tick
+}
+Busy --> Measuring : Done
+Measuring --> Broken : Abort
+Off --> On : PowerOn
+Off --> Sleeping : after
+Sleeping --> On : PowerOn
+Measuring --> Off
+On --> Off : PowerOff
+Broken --> [*]
+@enduml
+"
+        );
+        Ok(())
+    }
 }
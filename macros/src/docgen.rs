@@ -0,0 +1,89 @@
+//! Generates the markdown documentation of a state machine, which is added to the rustdoc of the
+//! generated state machine type.
+
+use crate::parser::ParsedStateMachine;
+
+/// Generates a markdown table of the transitions of the state machine, followed by its Mermaid
+/// state diagram.
+pub fn generate_docs(sm: &ParsedStateMachine) -> String {
+    let mut lines = vec![
+        String::new(),
+        "# Transitions".to_string(),
+        String::new(),
+        format!("The initial state is `{}`.", sm.starting_state),
+        String::new(),
+        "| State | Event | Guard | Action | Next state |".to_string(),
+        "|-------|-------|-------|--------|------------|".to_string(),
+    ];
+    for transition in sm.described_transitions() {
+        let event = match transition.event.as_str() {
+            "" => "*completion*".to_string(),
+            event => format!("`{event}`"),
+        };
+        let code = |value: &Option<String>| match value {
+            Some(value) => format!("`{}`", value.replace('|', "\\|")),
+            None => String::new(),
+        };
+        lines.push(format!(
+            "| `{}` | {} | {} | {} | `{}` |",
+            transition.from,
+            event,
+            code(&transition.guard),
+            code(&transition.action),
+            transition.to
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push("# Diagram".to_string());
+    lines.push(String::new());
+    lines.push("```mermaid".to_string());
+    lines.push(
+        crate::diagramgen::generate_mermaid(sm)
+            .trim_end()
+            .to_string(),
+    );
+    lines.push("```".to_string());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::{state_machine::StateMachine, ParsedStateMachine};
+
+    #[test]
+    fn markdown_docs() -> Result<(), syn::Error> {
+        let sm = ParsedStateMachine::new(syn::parse_str::<StateMachine>(
+            "transitions: {
+                *Closed + Open [ is_unlocked || forced ] / open = Opened,
+                Opened + Close / warn, close = Closed,
+                Opened [ idle ] = Closed,
+            }",
+        )?)?;
+
+        assert_eq!(
+            super::generate_docs(&sm),
+            r#"
+# Transitions
+
+The initial state is `Closed`.
+
+| State | Event | Guard | Action | Next state |
+|-------|-------|-------|--------|------------|
+| `Closed` | `Open` | `is_unlocked() \|\| forced()` | `open` | `Opened` |
+| `Opened` | `Close` |  | `warn, close` | `Closed` |
+| `Opened` | *completion* | `idle()` |  | `Closed` |
+
+# Diagram
+
+```mermaid
+stateDiagram-v2
+    [*] --> Closed
+    Closed --> Opened : Open [is_unlocked() || forced()] / open
+    Opened --> Closed : Close / warn, close
+    Opened --> Closed : [idle()]
+```"#
+        );
+        Ok(())
+    }
+}
@@ -3,10 +3,24 @@
 extern crate proc_macro;
 
 mod codegen;
-#[cfg(feature = "graphviz")]
+#[cfg(any(
+    feature = "graphviz",
+    feature = "mermaid",
+    feature = "plantuml",
+    feature = "markdown"
+))]
 mod diagramgen;
+#[cfg(feature = "markdown")]
+mod docgen;
+#[cfg(feature = "nusmv")]
+mod nusmv;
 mod parser;
+mod perfect_hash;
+#[cfg(feature = "scxml")]
+mod scxml;
 mod validation;
+#[cfg(feature = "xstate")]
+mod xstate;
 
 use syn::parse_macro_input;
 
@@ -20,53 +34,180 @@ pub fn statemachine(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     // Validate syntax
     match parser::ParsedStateMachine::new(input) {
         // Generate code and hand the output tokens back to the compiler
-        Ok(sm) => {
-            #[cfg(feature = "graphviz")]
-            {
-                use std::hash::{Hash, Hasher};
-                use std::io::Write;
-
-                // Generate dot syntax for the statemachine.
-                let diagram = diagramgen::generate_diagram(&sm);
-                let diagram_name = if let Some(name) = &sm.name {
-                    name.to_string()
-                } else {
-                    let mut diagram_hasher = std::collections::hash_map::DefaultHasher::new();
-                    diagram.hash(&mut diagram_hasher);
-                    format!("smlang{:010x}", diagram_hasher.finish())
-                };
-
-                // Start the 'dot' process.
-                let mut process = std::process::Command::new("dot")
-                    .args(["-Tsvg", "-o", &format!("statemachine_{diagram_name}.svg")])
-                    .stdin(std::process::Stdio::piped())
-                    .spawn()
-                    .expect("Failed to execute 'dot'. Are you sure graphviz is installed?");
-
-                // Write the dot syntax string to the 'dot' process stdin.
-                process
-                    .stdin
-                    .as_mut()
-                    .map(|s| s.write_all(diagram.as_bytes()));
-
-                // Check the graphviz return status to see if it was successful.
-                match process.wait() {
-                    Ok(status) => {
-                        if !status.success() {
-                            panic!("'dot' failed to run. Are you sure graphviz is installed?");
-                        }
-                    }
-                    Err(_) => panic!("'dot' failed to run. Are you sure graphviz is installed?"),
+        Ok(sm) => generate(&sm).into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+/// Defines several state machines sharing one enum of events, each of which ignores the events of
+/// the other state machines unless it handles them.
+#[proc_macro]
+pub fn statemachines(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as parser::machines::StateMachines);
+
+    let machines: Result<Vec<_>, _> = input
+        .machines
+        .into_iter()
+        .map(parser::ParsedStateMachine::new)
+        .collect();
+    let machines = match machines {
+        Ok(machines) => machines,
+        Err(error) => return error.to_compile_error().into(),
+    };
+    let mut code =
+        match codegen::generate_shared_events(&machines, &input.events_type, &input.events_attr) {
+            Ok(code) => code,
+            Err(error) => return error.to_compile_error().into(),
+        };
+    for sm in &machines {
+        code.extend(generate(sm));
+    }
+    code.into()
+}
+
+/// Generates the code and the enabled diagrams of a state machine.
+fn generate(sm: &parser::ParsedStateMachine) -> proc_macro2::TokenStream {
+    #[cfg(feature = "graphviz")]
+    {
+        use std::io::Write;
+
+        // Generate dot syntax for the statemachine.
+        let diagram = diagramgen::generate_diagram(sm);
+        let diagram_name = diagram_name(sm, &diagram);
+
+        // Start the 'dot' process.
+        let mut process = std::process::Command::new("dot")
+            .args(["-Tsvg", "-o", &format!("statemachine_{diagram_name}.svg")])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .expect("Failed to execute 'dot'. Are you sure graphviz is installed?");
+
+        // Write the dot syntax string to the 'dot' process stdin.
+        process
+            .stdin
+            .as_mut()
+            .map(|s| s.write_all(diagram.as_bytes()));
+
+        // Check the graphviz return status to see if it was successful.
+        match process.wait() {
+            Ok(status) => {
+                if !status.success() {
+                    panic!("'dot' failed to run. Are you sure graphviz is installed?");
                 }
             }
+            Err(_) => panic!("'dot' failed to run. Are you sure graphviz is installed?"),
+        }
+    }
 
-            // Validate the parsed state machine before generating code.
-            if let Err(e) = validation::validate(&sm) {
-                return e.to_compile_error().into();
-            }
+    #[cfg(feature = "mermaid")]
+    {
+        // Generate Mermaid syntax for the statemachine, which is written as is.
+        let diagram = diagramgen::generate_mermaid(sm);
+        let diagram_name = diagram_name(sm, &diagram);
+        write_to_out_dir(
+            format!("statemachine_{diagram_name}.mmd"),
+            diagram,
+            "Failed to write the Mermaid diagram.",
+        );
+    }
 
-            codegen::generate_code(&sm).into()
-        }
-        Err(error) => error.to_compile_error().into(),
+    #[cfg(feature = "plantuml")]
+    {
+        // Generate PlantUML syntax for the statemachine, which is written like the Mermaid
+        // diagram.
+        let diagram = diagramgen::generate_plantuml(sm);
+        let diagram_name = diagram_name(sm, &diagram);
+        write_to_out_dir(
+            format!("statemachine_{diagram_name}.puml"),
+            diagram,
+            "Failed to write the PlantUML diagram.",
+        );
+    }
+
+    #[cfg(feature = "scxml")]
+    {
+        // Generate an SCXML document of the statemachine, which is written next to the
+        // PlantUML diagram.
+        let document = scxml::generate_scxml(sm);
+        let document_name = diagram_name(sm, &document);
+        write_to_out_dir(
+            format!("statemachine_{document_name}.scxml"),
+            document,
+            "Failed to write the SCXML document.",
+        );
+    }
+
+    #[cfg(feature = "xstate")]
+    {
+        // Generate an xstate machine definition of the statemachine, which is written like
+        // the diagrams.
+        let definition = xstate::generate_xstate(sm);
+        let definition_name = diagram_name(sm, &definition);
+        write_to_out_dir(
+            format!("statemachine_{definition_name}.json"),
+            definition,
+            "Failed to write the xstate machine definition.",
+        );
+    }
+
+    #[cfg(feature = "nusmv")]
+    {
+        // Generate a NuSMV module of the statemachine, which is written next to the xstate
+        // machine definition.
+        let module = nusmv::generate_nusmv(sm);
+        let module_name = diagram_name(sm, &module);
+        write_to_out_dir(
+            format!("statemachine_{module_name}.smv"),
+            module,
+            "Failed to write the NuSMV module.",
+        );
+    }
+
+    // Validate the parsed state machine before generating code.
+    let warnings = match validation::validate(sm) {
+        Ok(warnings) => warnings,
+        Err(e) => return e.to_compile_error(),
+    };
+
+    let mut code = codegen::generate_code(sm);
+    code.extend(warnings);
+    code
+}
+
+/// Writes an exported file of a state machine to the output directory of the build script of the
+/// crate. Crates without a build script have no `OUT_DIR`, and nothing is written for them instead
+/// of littering the current directory.
+#[cfg(any(
+    feature = "mermaid",
+    feature = "plantuml",
+    feature = "scxml",
+    feature = "xstate",
+    feature = "nusmv"
+))]
+fn write_to_out_dir(file_name: String, contents: String, error: &str) {
+    if let Some(directory) = std::env::var_os("OUT_DIR") {
+        std::fs::write(std::path::Path::new(&directory).join(file_name), contents).expect(error);
+    }
+}
+
+/// The name of the diagram files of a state machine, which is the name of the state machine or a
+/// hash of the diagram for unnamed state machines.
+#[cfg(any(
+    feature = "graphviz",
+    feature = "mermaid",
+    feature = "plantuml",
+    feature = "scxml",
+    feature = "xstate",
+    feature = "nusmv"
+))]
+fn diagram_name(sm: &parser::ParsedStateMachine, diagram: &str) -> String {
+    use std::hash::{Hash, Hasher};
+
+    if let Some(name) = &sm.name {
+        name.to_string()
+    } else {
+        let mut diagram_hasher = std::collections::hash_map::DefaultHasher::new();
+        diagram.hash(&mut diagram_hasher);
+        format!("smlang{:010x}", diagram_hasher.finish())
     }
 }
@@ -0,0 +1,465 @@
+//! Exports a state machine as a NuSMV module, whose temporal-logic properties can be checked by
+//! the NuSMV and nuXmv model checkers.
+
+use crate::parser::event::{Transition, ANY_EVENT, COMPLETION_EVENT, TIMEOUT_EVENT};
+use crate::parser::transition::GuardExpression;
+use crate::parser::*;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// The value of the variable of a region when its parallel state is not active.
+const INACTIVE: &str = "inactive";
+
+/// The input value of the event of timed transitions.
+const TIMEOUT: &str = "timeout";
+
+/// Generates a string containing the NuSMV module of the state machine.
+///
+/// The current state is the variable `state`, and the current sub-state of every region a
+/// variable named after the region. The events, the guards and the failure of actions are inputs
+/// which can take any value in every step, whereas the data of states and events is abstracted
+/// away. Composite states are entered at their initial sub-state, and deferred and delegated
+/// events leave the state unchanged.
+pub fn generate_nusmv(sm: &ParsedStateMachine) -> String {
+    let model = Model::new(sm);
+    let mut module = String::new();
+    let name = match &sm.name {
+        Some(name) => name.to_string(),
+        None => "StateMachine".to_string(),
+    };
+    writeln!(module, "-- The state machine {name}, generated by smlang").unwrap();
+    writeln!(module, "MODULE main").unwrap();
+
+    let mut inputs = Vec::new();
+    if !model.events.is_empty() {
+        inputs.push(format!("event : {{{}}}", model.events.join(", ")));
+    }
+    inputs.extend(
+        model
+            .guards
+            .iter()
+            .map(|guard| format!("{guard} : boolean")),
+    );
+    if sm.fault_state.is_some() {
+        inputs.push("action_failed : boolean".to_string());
+    }
+    if !inputs.is_empty() {
+        writeln!(module, "IVAR").unwrap();
+        for input in inputs {
+            writeln!(module, "  {input};").unwrap();
+        }
+    }
+
+    writeln!(module, "VAR").unwrap();
+    writeln!(module, "  state : {{{}}};", model.states.join(", ")).unwrap();
+    for (region, states, _) in &model.regions {
+        let mut values = vec![INACTIVE.to_string()];
+        values.extend(states.iter().cloned());
+        writeln!(module, "  {region} : {{{}}};", values.join(", ")).unwrap();
+    }
+
+    writeln!(module, "DEFINE").unwrap();
+    for (region, _, parallel) in &model.regions {
+        let rows = model.region_rows(region);
+        let handled: Vec<String> = rows
+            .iter()
+            .map(|(condition, _)| condition.clone())
+            .collect();
+        let handled = if handled.is_empty() {
+            "FALSE".to_string()
+        } else {
+            handled.join(" | ")
+        };
+        writeln!(
+            module,
+            "  {region}_handled := state = {parallel} & ({handled});"
+        )
+        .unwrap();
+        write_case(&mut module, &format!("{region}_next"), rows, region);
+    }
+    write_case(&mut module, "state_next", model.state_rows(), "state");
+
+    writeln!(module, "ASSIGN").unwrap();
+    let starting_state = sm.resolve_initial(&sm.starting_state).to_string();
+    writeln!(module, "  init(state) := {starting_state};").unwrap();
+    writeln!(module, "  next(state) := state_next;").unwrap();
+    for (region, _, parallel) in &model.regions {
+        let initial = model.initial(region);
+        let init = if *parallel == starting_state {
+            &initial
+        } else {
+            INACTIVE
+        };
+        writeln!(module, "  init({region}) := {init};").unwrap();
+        writeln!(module, "  next({region}) := case").unwrap();
+        writeln!(module, "    state_next != {parallel} : {INACTIVE};").unwrap();
+        writeln!(module, "    state != {parallel} : {initial};").unwrap();
+        writeln!(module, "    TRUE : {region}_next;").unwrap();
+        writeln!(module, "  esac;").unwrap();
+    }
+    module
+}
+
+/// Writes the definition of `name` as a case expression of `rows`, which is `default` if no
+/// condition holds.
+fn write_case(module: &mut String, name: &str, rows: Vec<(String, String)>, default: &str) {
+    writeln!(module, "  {name} := case").unwrap();
+    for (condition, value) in rows {
+        writeln!(module, "    {condition} : {value};").unwrap();
+    }
+    writeln!(module, "    TRUE : {default};").unwrap();
+    writeln!(module, "  esac;").unwrap();
+}
+
+/// The values of the variables of a state machine.
+struct Model<'a> {
+    sm: &'a ParsedStateMachine,
+    /// The states outside of regions, where composite states are represented by their sub-states.
+    states: Vec<String>,
+    events: Vec<String>,
+    guards: BTreeSet<String>,
+    /// The regions, with their sub-states and their parallel state.
+    regions: Vec<(String, Vec<String>, String)>,
+}
+
+impl<'a> Model<'a> {
+    fn new(sm: &'a ParsedStateMachine) -> Self {
+        let mut states: Vec<String> = sm
+            .states
+            .keys()
+            .chain(sm.parallel_states.keys())
+            .filter(|state| !sm.composite_states.contains_key(*state))
+            .cloned()
+            .collect();
+        states.sort();
+        states.dedup();
+
+        let mut events: Vec<String> = sm.events.keys().cloned().collect();
+        let mappings = || {
+            sm.states_events_mapping
+                .values()
+                .flat_map(|mappings| mappings.values())
+        };
+        if mappings().any(|mapping| mapping.event == TIMEOUT_EVENT) {
+            events.push(TIMEOUT.to_string());
+        }
+        events.sort();
+
+        let mut guards = BTreeSet::new();
+        for transition in mappings().flat_map(|mapping| mapping.transitions.iter()) {
+            let branch_guards = transition
+                .choice
+                .iter()
+                .flat_map(|choice| choice.branches.iter())
+                .filter_map(|branch| branch.guard.as_ref());
+            for guard in transition.guard.iter().chain(branch_guards) {
+                collect_guards(guard, &mut guards);
+            }
+        }
+
+        let mut parallels: Vec<_> = sm.parallel_states.values().collect();
+        parallels.sort_by_key(|parallel| parallel.ident.to_string());
+        let regions = parallels
+            .into_iter()
+            .flat_map(|parallel| {
+                parallel.regions.iter().map(move |region| {
+                    let mut states: Vec<String> = sm
+                        .region_parents
+                        .iter()
+                        .filter(|(_, r)| **r == region.ident)
+                        .map(|(state, _)| state.clone())
+                        .collect();
+                    states.sort();
+                    (region.ident.to_string(), states, parallel.ident.to_string())
+                })
+            })
+            .collect();
+
+        Self {
+            sm,
+            states,
+            events,
+            guards,
+            regions,
+        }
+    }
+
+    /// The initial sub-state of a region.
+    fn initial(&self, region: &str) -> String {
+        self.sm
+            .parallel_states
+            .values()
+            .flat_map(|parallel| parallel.regions.iter())
+            .find(|r| r.ident == region)
+            .and_then(|r| r.initial.as_ref())
+            .map(|initial| self.sm.resolve_initial(initial).to_string())
+            .unwrap()
+    }
+
+    /// The conditions of the transitions of the sub-states of a region, with the sub-states they
+    /// lead to.
+    fn region_rows(&self, region: &str) -> Vec<(String, String)> {
+        let (_, states, _) = self.regions.iter().find(|(r, _, _)| r == region).unwrap();
+        states
+            .iter()
+            .flat_map(|state| self.rows(region, state, false))
+            .collect()
+    }
+
+    /// The conditions of the transitions of the states outside of regions, with the states they
+    /// lead to, where the transitions of the regions of a parallel state are taken first.
+    fn state_rows(&self) -> Vec<(String, String)> {
+        let sm = self.sm;
+        let mut rows = Vec::new();
+        for state in &self.states {
+            if let Some(parallel) = sm.parallel_states.get(state) {
+                let regions: Vec<&str> = self
+                    .regions
+                    .iter()
+                    .filter(|(_, _, p)| p == state)
+                    .map(|(region, _, _)| region.as_str())
+                    .collect();
+                let handled: Vec<String> = regions.iter().map(|r| format!("{r}_handled")).collect();
+                let handled = format!("state = {state} & ({})", handled.join(" | "));
+                if let Some(completion) = &parallel.completion {
+                    let completed: Vec<String> = parallel
+                        .regions
+                        .iter()
+                        .map(|region| {
+                            let finals: Vec<String> = sm
+                                .final_states(region)
+                                .into_iter()
+                                .map(|s| s.to_string())
+                                .collect();
+                            format!("{}_next in {{{}}}", region.ident, finals.join(", "))
+                        })
+                        .collect();
+                    rows.push((
+                        format!("{handled} & {}", completed.join(" & ")),
+                        sm.resolve_initial(completion).to_string(),
+                    ));
+                }
+                rows.push((handled, state.clone()));
+            }
+            rows.extend(self.rows("state", state, sm.fault_state.is_some()));
+        }
+        rows
+    }
+
+    /// The conditions of the transitions of `state`, the value of `variable`, with the states
+    /// they lead to, where transitions running actions enter the fault state if they can fail.
+    fn rows(&self, variable: &str, state: &str, can_fail: bool) -> Vec<(String, String)> {
+        let sm = self.sm;
+        let Some(mappings) = sm.states_events_mapping.get(state) else {
+            return Vec::new();
+        };
+        let mut mappings: Vec<_> = mappings.values().collect();
+        // Completion transitions are taken regardless of the event, and catch-all transitions
+        // only for the events without a transition
+        mappings.sort_by_key(|mapping| {
+            let event = mapping.event.to_string();
+            (event != COMPLETION_EVENT, event == ANY_EVENT, event)
+        });
+        let explicit: Vec<String> = mappings
+            .iter()
+            .map(|mapping| mapping.event.to_string())
+            .filter(|event| event != COMPLETION_EVENT && event != ANY_EVENT)
+            .map(|event| match event.as_str() {
+                TIMEOUT_EVENT => TIMEOUT.to_string(),
+                _ => event,
+            })
+            .collect();
+
+        let mut rows = Vec::new();
+        for mapping in mappings {
+            let mut condition = format!("{variable} = {state}");
+            match mapping.event.to_string().as_str() {
+                COMPLETION_EVENT => {}
+                ANY_EVENT if explicit.is_empty() => {}
+                ANY_EVENT => {
+                    write!(condition, " & !(event in {{{}}})", explicit.join(", ")).unwrap()
+                }
+                TIMEOUT_EVENT => write!(condition, " & event = {TIMEOUT}").unwrap(),
+                event => write!(condition, " & event = {event}").unwrap(),
+            }
+            for transition in &mapping.transitions {
+                let mut condition = condition.clone();
+                if let Some(guard) = &transition.guard {
+                    write!(condition, " & {}", expression(guard)).unwrap();
+                }
+                if let (true, Some(fault)) = (can_fail && has_actions(transition), &sm.fault_state)
+                {
+                    rows.push((
+                        format!("{condition} & action_failed"),
+                        sm.resolve_initial(fault).to_string(),
+                    ));
+                }
+                rows.push((condition, self.target(state, transition)));
+            }
+        }
+        rows
+    }
+
+    /// The state a transition leads to, which is a case expression of the branches of choices.
+    fn target(&self, state: &str, transition: &Transition) -> String {
+        let sm = self.sm;
+        match &transition.choice {
+            Some(choice) => {
+                let mut branches = String::from("case ");
+                for branch in &choice.branches {
+                    let condition = match &branch.guard {
+                        Some(guard) => expression(guard),
+                        None => "TRUE".to_string(),
+                    };
+                    let target = sm.resolve_initial(&branch.out_state.ident);
+                    write!(branches, "{condition} : {target}; ").unwrap();
+                }
+                if choice.branches.iter().all(|branch| branch.guard.is_some()) {
+                    write!(branches, "TRUE : {state}; ").unwrap();
+                }
+                branches.push_str("esac");
+                branches
+            }
+            None if transition.keep_state => state.to_string(),
+            None => sm.resolve_initial(&transition.out_state).to_string(),
+        }
+    }
+}
+
+/// Returns whether a transition runs any action, which may fail.
+fn has_actions(transition: &Transition) -> bool {
+    transition.action.is_some()
+        || !transition.preceding_actions.is_empty()
+        || transition
+            .choice
+            .iter()
+            .any(|choice| choice.branches.iter().any(|branch| branch.action.is_some()))
+}
+
+/// Adds the names of the guards of a guard expression to `guards`.
+fn collect_guards(guard: &GuardExpression, guards: &mut BTreeSet<String>) {
+    match guard {
+        GuardExpression::Guard(guard) | GuardExpression::Timeout(guard, _) => {
+            guards.insert(guard.ident.to_string());
+        }
+        GuardExpression::Not(guard) | GuardExpression::Group(guard) => {
+            collect_guards(guard, guards)
+        }
+        GuardExpression::And(lhs, rhs) | GuardExpression::Or(lhs, rhs) => {
+            collect_guards(lhs, guards);
+            collect_guards(rhs, guards);
+        }
+    }
+}
+
+/// The NuSMV expression of a guard expression, e.g. `ready & !busy`.
+fn expression(guard: &GuardExpression) -> String {
+    match guard {
+        GuardExpression::Guard(guard) | GuardExpression::Timeout(guard, _) => {
+            guard.ident.to_string()
+        }
+        GuardExpression::Not(guard) => format!("!{}", expression(guard)),
+        GuardExpression::Group(guard) => format!("({})", expression(guard)),
+        GuardExpression::And(lhs, rhs) => format!("{} & {}", expression(lhs), expression(rhs)),
+        GuardExpression::Or(lhs, rhs) => format!("{} | {}", expression(lhs), expression(rhs)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::{state_machine::StateMachine, ParsedStateMachine};
+
+    fn parse(sm: &str) -> Result<ParsedStateMachine, syn::Error> {
+        ParsedStateMachine::new(syn::parse_str::<StateMachine>(sm)?)
+    }
+
+    #[test]
+    fn nusmv_module() -> Result<(), syn::Error> {
+        let sm = parse(
+            "name: Device,
+            on_action_error: Faulted,
+            transitions: {
+                *Off + PowerOn = On,
+                Off + after(core::time::Duration::from_secs(2)) = Faulted,
+                On + PowerOff = Off,
+                On {
+                    *Idle + Start [ ready && !busy ] / start = Busy,
+                    Busy + Done = choice {
+                        [ in_range ] = Idle,
+                        = Measuring,
+                    },
+                },
+                Measuring [
+                    Left {
+                        *LeftBusy + Done = LeftDone,
+                    },
+                    Right {
+                        *RightBusy + Tick = RightDone,
+                    },
+                ] = Off,
+                Measuring + Abort = Faulted,
+                Faulted + Reset = Off,
+                Faulted + _ = Faulted,
+            }",
+        )?;
+
+        assert_eq!(
+            super::generate_nusmv(&sm),
+            r"-- The state machine Device, generated by smlang
+MODULE main
+IVAR
+  event : {Abort, Done, PowerOff, PowerOn, Reset, Start, Tick, timeout};
+  busy : boolean;
+  in_range : boolean;
+  ready : boolean;
+  action_failed : boolean;
+VAR
+  state : {Busy, Faulted, Idle, Measuring, Off};
+  Left : {inactive, LeftBusy, LeftDone};
+  Right : {inactive, RightBusy, RightDone};
+DEFINE
+  Left_handled := state = Measuring & (Left = LeftBusy & event = Done);
+  Left_next := case
+    Left = LeftBusy & event = Done : LeftDone;
+    TRUE : Left;
+  esac;
+  Right_handled := state = Measuring & (Right = RightBusy & event = Tick);
+  Right_next := case
+    Right = RightBusy & event = Tick : RightDone;
+    TRUE : Right;
+  esac;
+  state_next := case
+    state = Busy & event = Done : case in_range : Idle; TRUE : Measuring; esac;
+    state = Busy & event = PowerOff : Off;
+    state = Faulted & event = Reset : Off;
+    state = Faulted & !(event in {Reset}) : Faulted;
+    state = Idle & event = PowerOff : Off;
+    state = Idle & event = Start & ready & !busy & action_failed : Faulted;
+    state = Idle & event = Start & ready & !busy : Busy;
+    state = Measuring & (Left_handled | Right_handled) & Left_next in {LeftDone} & Right_next in {RightDone} : Off;
+    state = Measuring & (Left_handled | Right_handled) : Measuring;
+    state = Measuring & event = Abort : Faulted;
+    state = Off & event = PowerOn : Idle;
+    state = Off & event = timeout : Faulted;
+    TRUE : state;
+  esac;
+ASSIGN
+  init(state) := Off;
+  next(state) := state_next;
+  init(Left) := inactive;
+  next(Left) := case
+    state_next != Measuring : inactive;
+    state != Measuring : LeftBusy;
+    TRUE : Left_next;
+  esac;
+  init(Right) := inactive;
+  next(Right) := case
+    state_next != Measuring : inactive;
+    state != Measuring : RightBusy;
+    TRUE : Right_next;
+  esac;
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use syn::{bracketed, parse, punctuated::Punctuated, Ident, Token};
+
+/// A reachability property of the state graph, declared in the `assertions` block as
+/// `Target reachable from Source`, `Target unreachable from Source` or
+/// `Target unreachable from Source without Passed`, where `_` as the source stands for every
+/// state.
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    pub target: Ident,
+    /// Whether the target must be reachable, instead of unreachable.
+    pub reachable: bool,
+    /// The state the paths start at, or `None` for every state.
+    pub source: Option<Ident>,
+    /// The states the paths to an unreachable target are allowed to pass, declared with
+    /// `without State` or `without [State1, State2]`.
+    pub without: Vec<Ident>,
+}
+
+impl parse::Parse for Assertion {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let target: Ident = input.parse()?;
+
+        let property: Ident = input.parse()?;
+        let reachable = match property.to_string().as_str() {
+            "reachable" => true,
+            "unreachable" => false,
+            _ => {
+                return Err(parse::Error::new(
+                    property.span(),
+                    "Expected `reachable` or `unreachable`.",
+                ))
+            }
+        };
+
+        let from: Ident = input.parse()?;
+        if from != "from" {
+            return Err(parse::Error::new(from.span(), "Expected `from`."));
+        }
+        let source = if input.peek(Token![_]) {
+            input.parse::<Token![_]>()?;
+            None
+        } else {
+            Some(input.parse()?)
+        };
+
+        let mut without = Vec::new();
+        if input.peek(Ident) && input.fork().parse::<Ident>()? == "without" {
+            let keyword: Ident = input.parse()?;
+            if reachable {
+                return Err(parse::Error::new(
+                    keyword.span(),
+                    "Only `unreachable` assertions can allow states with `without`.",
+                ));
+            }
+            if input.peek(syn::token::Bracket) {
+                let content;
+                bracketed!(content in input);
+                without.extend(Punctuated::<Ident, Token![,]>::parse_terminated(&content)?);
+            } else {
+                without.push(input.parse()?);
+            }
+        }
+
+        Ok(Assertion {
+            target,
+            reachable,
+            source,
+            without,
+        })
+    }
+}
@@ -0,0 +1,105 @@
+use super::output_state::OutputState;
+use super::transition::GuardExpression;
+use super::AsyncIdent;
+use syn::{braced, bracketed, parenthesized, parse, token, Ident, Token, Type};
+
+/// The keyword used for choice pseudo-states.
+pub const CHOICE: &str = "choice";
+
+/// A choice pseudo-state, declared as the target of a transition with
+/// `choice(Type) { [guard] / action = State, ... }`. The action of the transition provides the
+/// data of the choice, which is passed to the guards and actions of its branches.
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub ident: Ident,
+    pub data_type: Option<Type>,
+    pub branches: Vec<ChoiceBranch>,
+}
+
+/// A branch of a choice, where the last branch has no guard and is taken when no other guard
+/// passes.
+#[derive(Debug, Clone)]
+pub struct ChoiceBranch {
+    pub guard: Option<GuardExpression>,
+    pub action: Option<AsyncIdent>,
+    pub out_state: OutputState,
+}
+
+impl parse::Parse for Choice {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let ident: Ident = input.parse()?;
+
+        // Possible type of the data the branches are chosen on
+        let data_type = if input.peek(token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            Some(content.parse()?)
+        } else {
+            None
+        };
+
+        let content;
+        braced!(content in input);
+
+        let mut branches: Vec<ChoiceBranch> = Vec::new();
+        while !content.is_empty() {
+            if let Some(previous) = branches.last() {
+                if previous.guard.is_none() {
+                    return Err(parse::Error::new(
+                        content.span(),
+                        "Only the last branch of a choice can be without a guard.",
+                    ));
+                }
+            }
+
+            // Possible guard
+            let guard = if content.peek(token::Bracket) {
+                let guard_content;
+                bracketed!(guard_content in content);
+                Some(GuardExpression::parse(&guard_content)?)
+            } else {
+                None
+            };
+
+            // Possible action
+            let action = if content.parse::<Token![/]>().is_ok() {
+                let is_async = content.parse::<token::Async>().is_ok();
+                Some(AsyncIdent {
+                    ident: content.parse()?,
+                    is_async,
+                })
+            } else {
+                None
+            };
+
+            let out_state: OutputState = content.parse()?;
+            if out_state.internal_transition {
+                return Err(parse::Error::new(
+                    out_state.ident.span(),
+                    "The branches of a choice need a target state.",
+                ));
+            }
+            branches.push(ChoiceBranch {
+                guard,
+                action,
+                out_state,
+            });
+
+            if content.parse::<Token![,]>().is_err() {
+                break;
+            }
+        }
+
+        match branches.last() {
+            Some(branch) if branch.guard.is_none() => Ok(Self {
+                ident,
+                data_type,
+                branches,
+            }),
+            _ => Err(parse::Error::new(
+                ident.span(),
+                "The last branch of a choice cannot have a guard, as it is taken when all other guards fail.",
+            )),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use syn::Ident;
+
+/// A state which contains sub-states, declared as a block inside `transitions`.
+#[derive(Debug, Clone)]
+pub struct CompositeState {
+    pub ident: Ident,
+    /// The composite state this one is nested in, if any.
+    pub parent: Option<Ident>,
+    /// Indicates that this composite state is the initial state of its enclosing scope.
+    pub start: bool,
+    /// The sub-state entered when a transition targets this composite state.
+    pub initial: Option<Ident>,
+    /// The direct sub-states, filled in when the state machine is parsed.
+    pub children: Vec<Ident>,
+    /// Re-entering this composite state resumes its previously active sub-state.
+    pub history: Option<History>,
+}
+
+/// The history of a composite state, declared as `Composite(H) { ... }` or `Composite(H*) { ... }`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum History {
+    /// Resumes the previously active direct sub-state, entering nested composite states anew.
+    Shallow,
+    /// Resumes the previously active sub-state at any depth.
+    Deep,
+}
+
+/// A state made of several regions which are active at the same time, declared as a list of
+/// region blocks inside `transitions`.
+#[derive(Debug, Clone)]
+pub struct ParallelState {
+    pub ident: Ident,
+    /// The composite state this one is nested in, if any.
+    pub parent: Option<Ident>,
+    /// Indicates that this parallel state is the initial state of its enclosing scope.
+    pub start: bool,
+    /// The regions, each holding its own sub-states.
+    pub regions: Vec<CompositeState>,
+    /// The state entered once all regions have reached a final sub-state.
+    pub completion: Option<Ident>,
+}
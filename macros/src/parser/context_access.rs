@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use syn::{braced, parse, Ident, Token};
+
+/// Parses how a guard or action borrows the context, declared as `&self` or `&mut self`, returning
+/// whether it is borrowed mutably.
+fn parse_mutability(input: parse::ParseStream) -> syn::Result<bool> {
+    input.parse::<Token![&]>()?;
+    let mutable = input.parse::<Option<Token![mut]>>()?.is_some();
+    input.parse::<Token![self]>()?;
+    Ok(mutable)
+}
+
+/// How the guards and actions borrow the context, declared in the `context_access` block as
+/// `context_access: { guards: &self, actions: &mut self, count_attempt: &mut self }`.
+#[derive(Debug, Clone)]
+pub struct ContextAccess {
+    /// Guards borrow the context mutably, which they don't by default.
+    pub mutable_guards: bool,
+    /// Actions borrow the context mutably, which they do by default.
+    pub mutable_actions: bool,
+    /// Whether single guards and actions borrow the context mutably, overriding `guards` and
+    /// `actions`.
+    pub overrides: HashMap<String, (Ident, bool)>,
+}
+
+impl Default for ContextAccess {
+    fn default() -> Self {
+        Self {
+            mutable_guards: false,
+            mutable_actions: true,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl ContextAccess {
+    /// Returns `true` if the guard named `guard` borrows the context mutably.
+    pub fn is_mutable_guard(&self, guard: &str) -> bool {
+        match self.overrides.get(guard) {
+            Some((_, mutable)) => *mutable,
+            None => self.mutable_guards,
+        }
+    }
+
+    /// Returns `true` if the action named `action` borrows the context mutably.
+    pub fn is_mutable_action(&self, action: &str) -> bool {
+        match self.overrides.get(action) {
+            Some((_, mutable)) => *mutable,
+            None => self.mutable_actions,
+        }
+    }
+
+    /// Parses the braced borrows of the `context_access` block.
+    pub fn parse_borrows(&mut self, input: parse::ParseStream) -> syn::Result<()> {
+        let content;
+        braced!(content in input);
+
+        loop {
+            if content.is_empty() {
+                break;
+            }
+
+            let name: Ident = content.parse()?;
+            content.parse::<Token![:]>()?;
+            let mutable = parse_mutability(&content)?;
+            match name.to_string().as_str() {
+                "guards" => self.mutable_guards = mutable,
+                "actions" => self.mutable_actions = mutable,
+                _ => {
+                    self.overrides.insert(name.to_string(), (name, mutable));
+                }
+            }
+
+            // No comma at end of line, no more borrows
+            if content.is_empty() {
+                break;
+            }
+
+            if content.parse::<Token![,]>().is_err() {
+                break;
+            };
+        }
+
+        Ok(())
+    }
+}
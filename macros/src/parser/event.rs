@@ -1,11 +1,25 @@
-use crate::parser::transition::GuardExpression;
+use crate::parser::choice::Choice;
+use crate::parser::transition::{GuardExpression, Retry};
 use crate::parser::AsyncIdent;
 use syn::{parenthesized, parse, spanned::Spanned, token, Ident, Token, Type};
 
+/// The key of timed transitions in the transitions of a state.
+pub const TIMEOUT_EVENT: &str = "after";
+
+/// The key of completion transitions, which are declared without an event, in the transitions of
+/// a state.
+pub const COMPLETION_EVENT: &str = "_";
+
+/// The key of catch-all transitions, which are declared as `State + _` and taken for any event not
+/// handled explicitly, in the transitions of a state.
+pub const ANY_EVENT: &str = "_any";
+
 #[derive(Debug, Clone)]
 pub struct Event {
     pub ident: Ident,
     pub data_type: Option<Type>,
+    /// The duration of a timed transition, declared as `after(duration)`.
+    pub timeout: Option<syn::Expr>,
 }
 
 #[derive(Debug)]
@@ -18,16 +32,122 @@ pub struct EventMapping {
 #[derive(Debug)]
 pub struct Transition {
     pub guard: Option<GuardExpression>,
+    /// Taken when all guarded transitions fail, which is why it is kept last.
+    pub fallback: bool,
+    /// The explicit priority, where transitions are kept in the order of their priorities.
+    pub priority: Option<i32>,
+    /// The retries of the guards and actions which fail.
+    pub retry: Option<Retry>,
     pub action: Option<AsyncIdent>,
+    /// The actions run in order before `action`, which borrow the event data and provide no data.
+    pub preceding_actions: Vec<AsyncIdent>,
     pub out_state: Ident,
+    /// Stay in the current state without exiting it, keeping its data.
+    pub keep_state: bool,
+    /// Branch to one of several states after the action, whose targets have been resolved.
+    pub choice: Option<Choice>,
+    /// The composite state targeted by the transition, before it is resolved to the sub-state
+    /// entered.
+    pub out_composite: Option<Ident>,
+    /// The span of the event of the declaration, which diagnostics of the transition point at.
+    pub span: proc_macro2::Span,
+    /// The transition is declared for several input states, as `State1 | State2 + Event`.
+    pub pattern: bool,
+    /// The composite state the transition is declared for, before it is expanded to the
+    /// sub-states of the composite state.
+    pub source_composite: Option<Ident>,
 }
 
-impl parse::Parse for Event {
-    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
-        // Event
+impl Event {
+    /// The event of a completion transition, which is taken as soon as its guard passes.
+    pub fn completion(span: proc_macro2::Span) -> Self {
+        Self {
+            ident: Ident::new(COMPLETION_EVENT, span),
+            data_type: None,
+            timeout: None,
+        }
+    }
+
+    /// Returns `true` if this is the event of a completion transition.
+    pub fn is_completion(&self) -> bool {
+        self.ident == COMPLETION_EVENT
+    }
+
+    /// Returns `true` if this is the event of a catch-all transition.
+    pub fn is_any(&self) -> bool {
+        self.ident == ANY_EVENT
+    }
+
+    /// Parses the events of a transition, where several events sharing the transition are
+    /// declared as `+ (Event1 | Event2)`.
+    pub fn parse_alternatives(input: parse::ParseStream) -> syn::Result<Vec<Self>> {
+        if !input.peek2(token::Paren) {
+            return Ok(vec![input.parse()?]);
+        }
         input.parse::<Token![+]>()?;
+        let content;
+        parenthesized!(content in input);
+
+        let mut events: Vec<Self> = Vec::new();
+        loop {
+            let event = Self::parse_event(&content)?;
+            if event.timeout.is_some() || event.is_any() {
+                return Err(parse::Error::new(
+                    event.ident.span(),
+                    "Timed and catch-all transitions cannot be used with several events.",
+                ));
+            }
+
+            // The events share the guards and actions, so they need the same data
+            if let Some(first) = events.first() {
+                if first.data_type != event.data_type {
+                    return Err(parse::Error::new(
+                        event.ident.span(),
+                        format!(
+                            "Event {} needs to have the same data as {} to share a transition.",
+                            event.ident, first.ident
+                        ),
+                    ));
+                }
+            }
+            events.push(event);
+
+            if content.is_empty() {
+                break;
+            }
+            content.parse::<Token![|]>()?;
+        }
+        Ok(events)
+    }
+
+    fn parse_event(input: parse::ParseStream) -> syn::Result<Self> {
+        // Catch-all transitions, which have no data as they are taken for any event
+        if let Ok(underscore) = input.parse::<Token![_]>() {
+            return Ok(Self {
+                ident: Ident::new(ANY_EVENT, underscore.span),
+                data_type: None,
+                timeout: None,
+            });
+        }
         let ident: Ident = input.parse()?;
 
+        // Timed transitions
+        if ident == TIMEOUT_EVENT {
+            if !input.peek(token::Paren) {
+                return Err(parse::Error::new(
+                    ident.span(),
+                    "`after` is reserved for timed transitions, e.g. `after(Duration::from_secs(1))`.",
+                ));
+            }
+            let content;
+            parenthesized!(content in input);
+            return Ok(Self {
+                ident,
+                data_type: None,
+                timeout: Some(content.parse()?),
+            });
+        }
+
         // Possible type on the event
         let data_type = if input.peek(token::Paren) {
             let content;
@@ -55,6 +175,18 @@ impl parse::Parse for Event {
             None
         };
 
-        Ok(Self { ident, data_type })
+        Ok(Self {
+            ident,
+            data_type,
+            timeout: None,
+        })
+    }
+}
+
+impl parse::Parse for Event {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        // Event
+        input.parse::<Token![+]>()?;
+        Self::parse_event(input)
     }
 }
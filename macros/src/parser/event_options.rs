@@ -0,0 +1,99 @@
+use syn::{braced, parse, spanned::Spanned, Attribute, Ident, Token};
+
+/// The options of an event, declared in the `events` block as `Event { priority: 2 }`,
+/// `Event { allow_unused: true }`, `Event { arbitrary: generate }` or
+/// `Event { attrs: #[attribute] }`.
+#[derive(Debug, Clone)]
+pub struct EventOptions {
+    pub event: Ident,
+    /// The priority of the event in the event queue, where events with a higher priority are
+    /// processed first.
+    pub priority: Option<syn::LitInt>,
+    /// The event is not reported when no reachable state handles it.
+    pub allow_unused: bool,
+    /// The function generating the data of the event with the `arbitrary` feature, taking an
+    /// `arbitrary::Unstructured`.
+    pub arbitrary: Option<syn::Path>,
+    /// The attributes of the variant of the event.
+    pub attrs: Vec<Attribute>,
+}
+
+impl parse::Parse for EventOptions {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let event: Ident = input.parse()?;
+
+        let content;
+        braced!(content in input);
+
+        let mut priority = None;
+        let mut allow_unused = false;
+        let mut arbitrary = None;
+        let mut attrs = Vec::new();
+        loop {
+            if content.is_empty() {
+                break;
+            }
+
+            let keyword: Ident = content.parse()?;
+            content.parse::<Token![:]>()?;
+
+            match keyword.to_string().as_str() {
+                "priority" => {
+                    if priority.is_some() {
+                        return Err(parse::Error::new(
+                            keyword.span(),
+                            format!("The priority of {} is defined more than once.", event),
+                        ));
+                    }
+                    let value: syn::LitInt = content.parse()?;
+                    value.base10_parse::<u8>()?;
+                    priority = Some(value);
+                }
+                "allow_unused" => {
+                    let value: syn::LitBool = content.parse()?;
+                    allow_unused = value.value;
+                }
+                "arbitrary" => {
+                    let path: syn::Path = content.parse()?;
+                    if cfg!(not(feature = "arbitrary")) {
+                        return Err(parse::Error::new(
+                            path.span(),
+                            "Generating the data of events requires the `arbitrary` feature of \
+                            smlang.",
+                        ));
+                    }
+                    arbitrary = Some(path);
+                }
+                "attrs" => {
+                    attrs.extend(Attribute::parse_outer(&content)?);
+                }
+                _ => {
+                    return Err(parse::Error::new(
+                        keyword.span(),
+                        format!(
+                        "Unknown keyword {}. Supported keywords: [\"priority\", \"allow_unused\", \"arbitrary\", \"attrs\"]",
+                        keyword
+                    ),
+                    ))
+                }
+            }
+
+            // No comma at end of line, no more options
+            if content.is_empty() {
+                break;
+            }
+
+            if content.parse::<Token![,]>().is_err() {
+                break;
+            };
+        }
+
+        Ok(Self {
+            event,
+            priority,
+            allow_unused,
+            arbitrary,
+            attrs,
+        })
+    }
+}
@@ -0,0 +1,128 @@
+use syn::{braced, parse, Ident, Token};
+
+/// How a lint reports its findings, declared as `deny`, `warn` or `allow`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    /// The state machine fails to compile.
+    Deny,
+    /// The state machine compiles with a warning.
+    Warn,
+    /// The findings are ignored.
+    Allow,
+}
+
+impl parse::Parse for Level {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let level: Ident = input.parse()?;
+        match level.to_string().as_str() {
+            "deny" => Ok(Level::Deny),
+            "warn" => Ok(Level::Warn),
+            "allow" => Ok(Level::Allow),
+            _ => Err(parse::Error::new(
+                level.span(),
+                "Expected one of `deny`, `warn` or `allow`.",
+            )),
+        }
+    }
+}
+
+/// The levels of the lints of the validation, declared in the `lints` block as
+/// `lints: { unreachable_states: warn }`.
+#[derive(Debug, Clone)]
+pub struct Lints {
+    /// States which cannot be reached from the starting state.
+    pub unreachable_states: Level,
+    /// Events which are not handled by any reachable state.
+    pub unused_events: Level,
+    /// States which neither handle nor ignore an event, denied with `strict_events: true`.
+    pub unhandled_events: Level,
+    /// Guards which always pass.
+    pub tautological_guards: Level,
+    /// Guards which never pass.
+    pub contradictory_guards: Level,
+    /// Guards which only pass when a guard tried before them passes.
+    pub shadowed_guards: Level,
+    /// Transitions of several patterns handling the same state and event.
+    pub overlapping_transitions: Level,
+}
+
+impl Default for Lints {
+    fn default() -> Self {
+        Self {
+            unreachable_states: Level::Warn,
+            unused_events: Level::Warn,
+            unhandled_events: Level::Allow,
+            tautological_guards: Level::Warn,
+            contradictory_guards: Level::Warn,
+            shadowed_guards: Level::Warn,
+            overlapping_transitions: Level::Warn,
+        }
+    }
+}
+
+impl Lints {
+    /// Returns the level of the lint named `lint`.
+    pub fn level(&self, lint: &str) -> Level {
+        match lint {
+            "unreachable_states" => self.unreachable_states,
+            "unused_events" => self.unused_events,
+            "unhandled_events" => self.unhandled_events,
+            "tautological_guards" => self.tautological_guards,
+            "contradictory_guards" => self.contradictory_guards,
+            "shadowed_guards" => self.shadowed_guards,
+            "overlapping_transitions" => self.overlapping_transitions,
+            _ => unreachable!("unknown lint {}", lint),
+        }
+    }
+
+    /// Parses the braced levels of the `lints` block, overriding the current levels.
+    pub fn parse_levels(&mut self, input: parse::ParseStream) -> syn::Result<()> {
+        let content;
+        braced!(content in input);
+
+        loop {
+            if content.is_empty() {
+                break;
+            }
+
+            let lint: Ident = content.parse()?;
+            content.parse::<Token![:]>()?;
+            let level: Level = content.parse()?;
+            match lint.to_string().as_str() {
+                "unreachable_states" => self.unreachable_states = level,
+                "unused_events" => self.unused_events = level,
+                "unhandled_events" => self.unhandled_events = level,
+                "tautological_guards" => self.tautological_guards = level,
+                "contradictory_guards" => self.contradictory_guards = level,
+                "shadowed_guards" => self.shadowed_guards = level,
+                "overlapping_transitions" => self.overlapping_transitions = level,
+                _ => {
+                    return Err(parse::Error::new(
+                        lint.span(),
+                        format!(
+                            "Unknown lint {}. Supported lints: [\"unreachable_states\", \
+                                \"unused_events\", \
+                                \"unhandled_events\", \
+                                \"tautological_guards\", \
+                                \"contradictory_guards\", \
+                                \"shadowed_guards\", \
+                                \"overlapping_transitions\"]",
+                            lint
+                        ),
+                    ))
+                }
+            }
+
+            // No comma at end of line, no more lints
+            if content.is_empty() {
+                break;
+            }
+
+            if content.parse::<Token![,]>().is_err() {
+                break;
+            };
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use super::state_machine::StateMachine;
+use super::type_names::TypeName;
+use syn::{braced, parse, spanned::Spanned, token, Attribute, Ident, Token};
+
+/// Several state machines sharing their events, declared in one `statemachines!` invocation as
+/// `Door { transitions: { .. } }, Light { transitions: { .. } }`.
+#[derive(Debug)]
+pub struct StateMachines {
+    /// The name and visibility of the shared events, `events_type: pub Events` by default.
+    pub events_type: TypeName,
+    /// The attributes of the shared events.
+    pub events_attr: Vec<Attribute>,
+    pub machines: Vec<StateMachine>,
+}
+
+impl parse::Parse for StateMachines {
+    fn parse(input: parse::ParseStream) -> parse::Result<Self> {
+        let mut events_type = None;
+        let mut events_attr = Vec::new();
+        let mut machines = Vec::new();
+
+        loop {
+            if input.is_empty() {
+                break;
+            }
+
+            let keyword: Ident = input.parse()?;
+            if input.peek(token::Brace) {
+                // A state machine, named by its identifier
+                let content;
+                braced!(content in input);
+                let mut machine: StateMachine = content.parse()?;
+                if let Some(name) = &machine.name {
+                    return Err(parse::Error::new(
+                        name.span(),
+                        "The state machines of `statemachines!` are named by their identifiers.",
+                    ));
+                }
+                if let Some(path) = &machine.events_type {
+                    return Err(parse::Error::new(
+                        path.span(),
+                        "The state machines of `statemachines!` share their `events_type`.",
+                    ));
+                }
+                if let Some(attr) = machine.events_attr.first() {
+                    return Err(parse::Error::new(
+                        attr.span(),
+                        "The attributes of the shared events are given to `statemachines!`.",
+                    ));
+                }
+                machine.name = Some(keyword);
+                machines.push(machine);
+            } else {
+                input.parse::<Token![:]>()?;
+                match keyword.to_string().as_str() {
+                    "events_type" => events_type = Some(input.parse()?),
+                    "events_attr" => events_attr = Attribute::parse_outer(input)?,
+                    _ => {
+                        return Err(parse::Error::new(
+                            keyword.span(),
+                            format!(
+                                "Unknown keyword {}. Support keywords: [\"events_type\", \
+                                    \"events_attr\"]",
+                                keyword
+                            ),
+                        ))
+                    }
+                }
+            }
+
+            // No comma at end of line, no more state machines
+            if input.is_empty() {
+                break;
+            }
+
+            if input.parse::<Token![,]>().is_err() {
+                break;
+            };
+        }
+
+        if machines.is_empty() {
+            return Err(parse::Error::new(
+                input.span(),
+                "No state machines are defined.",
+            ));
+        }
+        let events_type = events_type.unwrap_or_else(|| TypeName {
+            vis: syn::parse_quote! { pub },
+            ident: Ident::new("Events", proc_macro2::Span::call_site()),
+        });
+        // Every state machine uses the shared events as its `events_type`
+        for machine in &mut machines {
+            let ident = &events_type.ident;
+            machine.events_type = Some(syn::parse_quote! { #ident });
+        }
+
+        Ok(StateMachines {
+            events_type,
+            events_attr,
+            machines,
+        })
+    }
+}
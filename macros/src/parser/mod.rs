@@ -1,23 +1,42 @@
+pub mod assertion;
+pub mod choice;
+pub mod composite_state;
+pub mod context_access;
 pub mod data;
 pub mod event;
+pub mod event_options;
 pub mod input_state;
 pub mod lifetimes;
+pub mod lints;
+pub mod machines;
 pub mod output_state;
+#[cfg(feature = "scxml")]
+pub mod scxml;
+pub mod state_handlers;
 pub mod state_machine;
 pub mod transition;
+pub mod type_names;
 
+use assertion::Assertion;
+use composite_state::{CompositeState, History, ParallelState};
+use context_access::ContextAccess;
 use data::DataDefinitions;
 use event::EventMapping;
-use state_machine::StateMachine;
+use lints::Lints;
+use state_handlers::StateHandlers;
+use state_machine::{DisplayMode, StateMachine};
+use transition::GuardTimeout;
+use type_names::{TypeName, TypeNames};
 
 use input_state::InputState;
-use proc_macro2::{Span, TokenStream};
+use proc_macro2::Span;
+use quote::format_ident;
 
 use crate::parser::event::Transition;
-use std::collections::{hash_map, HashMap};
+use std::collections::{hash_map, HashMap, HashSet};
 use std::fmt;
-use syn::{parse, Attribute, Ident, Type};
-use transition::StateTransition;
+use syn::{parse, spanned::Spanned, Attribute, Ident, Type};
+use transition::{InlineAction, StateTransition};
 pub type TransitionMap = HashMap<String, HashMap<String, EventMapping>>;
 
 #[derive(Debug, Clone)]
@@ -25,14 +44,6 @@ pub struct AsyncIdent {
     pub ident: Ident,
     pub is_async: bool,
 }
-impl AsyncIdent {
-    pub fn to_token_stream<F>(&self, visit: &mut F) -> TokenStream
-    where
-        F: FnMut(&AsyncIdent) -> TokenStream,
-    {
-        visit(self)
-    }
-}
 impl fmt::Display for AsyncIdent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.is_async {
@@ -43,16 +54,112 @@ impl fmt::Display for AsyncIdent {
     }
 }
 
+/// A transition as described by the generated machine description, where choices are described
+/// by a transition for every branch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescribedTransition {
+    pub from: String,
+    /// The event, which is `_` for catch-all transitions, `after` for timed transitions and empty
+    /// for completion transitions.
+    pub event: String,
+    pub guard: Option<String>,
+    /// The actions, separated by commas.
+    pub action: Option<String>,
+    pub to: String,
+}
+
 #[derive(Debug)]
 pub struct ParsedStateMachine {
     pub name: Option<Ident>,
     pub states_attr: Vec<Attribute>,
     pub events_attr: Vec<Attribute>,
+    /// The enum defined outside of the state machine whose variants are the events, instead of
+    /// the generated `Events`.
+    pub events_type: Option<syn::Path>,
+    /// How `Display` is implemented for the states and events, if it is.
+    pub display: Option<DisplayMode>,
     pub temporary_context_type: Option<Type>,
     pub custom_error: bool,
+    pub custom_guard_error: bool,
+    pub transition_outcome: bool,
+    pub serde: bool,
+    pub schema_version: Option<u32>,
+    pub event_log: bool,
+    pub metrics: bool,
+    pub statistics: bool,
+    /// Whether the transitions which have been taken are recorded.
+    pub transition_coverage: bool,
+    /// Whether events are dispatched through a table of their handlers.
+    pub table_dispatch: bool,
+    /// Whether the handlers of events are looked up in a perfect hash table.
+    pub hash_dispatch: bool,
+    /// Whether the identifier of the current state is kept next to the states and published.
+    pub publish_state_id: bool,
+    /// Whether the identifier of the current state is mirrored in an atomic of the context.
+    pub state_id_mirror: bool,
+    /// Whether the futures of the async methods of the context are required to be `Send`.
+    pub send_futures: bool,
+    /// Whether the rejected events are kept, to be returned by `try_process_event`.
+    pub rejected_events: bool,
+    /// `process_event` borrows the event, whose data is borrowed by the guards and actions.
+    pub events_by_ref: bool,
+    /// Whether the state machine is poisoned by transitions interrupted while exiting and
+    /// entering states.
+    pub poisoning: bool,
+    /// Whether `arbitrary::Arbitrary` is implemented for the events, with the `arbitrary` feature.
+    pub arbitrary: bool,
+    /// Whether handles typed by the top-level states are generated.
+    pub typestate: bool,
+    /// Whether the clock and the logger are associated types of the context.
+    pub associated_types: bool,
+    pub transition_history: Option<usize>,
     pub states: HashMap<String, Ident>,
+    pub composite_states: HashMap<String, CompositeState>,
+    pub state_parents: HashMap<String, Ident>,
+    pub parallel_states: HashMap<String, ParallelState>,
+    pub region_states: HashMap<String, Ident>,
+    pub region_parents: HashMap<String, Ident>,
+    pub state_handlers: HashMap<String, StateHandlers>,
+    /// The data types of the states declared with `boxed`, whose data is stored behind the
+    /// pointer type in `state_data`.
+    pub boxed_states: HashMap<String, Type>,
+    pub deferred_events: HashMap<String, Vec<Ident>>,
+    pub defer_capacity: usize,
+    /// The capacity of the queue of events posted by actions, if events can be posted.
+    pub post_capacity: Option<usize>,
+    /// The capacity of the event queue drained by `process_queued`, if events can be queued.
+    pub queue_capacity: Option<usize>,
+    /// The priorities of events in the event queue, where events without one have priority 0.
+    pub event_priorities: HashMap<String, u8>,
+    /// The events declared with `allow_unused: true`, which may not be handled by any state.
+    pub unused_events: HashSet<String>,
+    /// The functions generating the data of events with the `arbitrary` feature.
+    pub event_generators: HashMap<String, syn::Path>,
+    /// The attributes of the variants of events, declared in the `events` block.
+    pub event_attrs: HashMap<String, Vec<syn::Attribute>>,
+    pub timeouts: HashMap<String, syn::Expr>,
+    pub sub_machines: HashMap<String, Vec<Ident>>,
+    /// The states marked with `@final`, which are distinct from the final sub-states of regions.
+    pub terminal_states: HashMap<String, Ident>,
+    /// The state entered when an action fails outside of a region.
+    pub fault_state: Option<Ident>,
+    /// The inline actions, which are hoisted into default methods of the context.
+    pub inline_actions: HashMap<String, InlineAction>,
+    /// The expression guards, which are hoisted into default methods of the context.
+    pub expression_guards: HashMap<String, syn::Expr>,
     pub starting_state: Ident,
     pub state_data: DataDefinitions,
+    /// The SCXML document the state machine has been imported from.
+    pub scxml_path: Option<String>,
+    pub lints: Lints,
+    /// How the guards and actions borrow the context.
+    pub context_access: ContextAccess,
+    /// The names and visibilities of the generated types.
+    pub type_names: TypeNames,
+    /// The reachability properties checked by the validation.
+    pub assertions: Vec<Assertion>,
+    /// The predicates of the context which have to hold in every state.
+    pub invariants: Vec<Ident>,
     pub events: HashMap<String, Ident>,
     pub event_data: DataDefinitions,
     pub states_events_mapping: HashMap<String, HashMap<String, EventMapping>>,
@@ -75,26 +182,61 @@ fn add_transition(
                 event: transition.event.ident.clone(),
                 transitions: vec![Transition {
                     guard: transition.guard.clone(),
+                    fallback: transition.fallback,
+                    priority: transition.priority,
+                    retry: transition.retry.clone(),
                     action: transition.action.clone(),
+                    preceding_actions: transition.preceding_actions.clone(),
                     out_state: transition.out_state.ident.clone(),
+                    keep_state: transition.out_state.keep_state,
+                    choice: transition.out_state.choice.clone(),
+                    out_composite: transition.out_state.composite.clone(),
+                    span: transition.event.ident.span(),
+                    pattern: transition.pattern,
+                    source_composite: transition.source_composite.clone(),
                 }],
             };
             entry.insert(mapping);
         }
         hash_map::Entry::Occupied(mut entry) => {
             let mapping = entry.get_mut();
-            mapping.transitions.push(Transition {
-                guard: transition.guard.clone(),
-                action: transition.action.clone(),
-                out_state: transition.out_state.ident.clone(),
-            });
+            // Transitions are kept in the order of their priorities, and fallback transitions
+            // after all other transitions, as they are only taken when the guards of these fail.
+            let priority = transition.priority.unwrap_or_default();
+            let index = mapping
+                .transitions
+                .iter()
+                .position(|t| {
+                    !transition.fallback
+                        && (t.fallback || t.priority.unwrap_or_default() < priority)
+                })
+                .unwrap_or(mapping.transitions.len());
+            mapping.transitions.insert(
+                index,
+                Transition {
+                    guard: transition.guard.clone(),
+                    fallback: transition.fallback,
+                    priority: transition.priority,
+                    retry: transition.retry.clone(),
+                    action: transition.action.clone(),
+                    preceding_actions: transition.preceding_actions.clone(),
+                    out_state: transition.out_state.ident.clone(),
+                    keep_state: transition.out_state.keep_state,
+                    choice: transition.out_state.choice.clone(),
+                    out_composite: transition.out_state.composite.clone(),
+                    span: transition.event.ident.span(),
+                    pattern: transition.pattern,
+                    source_composite: transition.source_composite.clone(),
+                },
+            );
         }
     }
 
     // Check for actions when states have data a
-    if state_data
-        .data_types
-        .contains_key(&transition.out_state.ident.to_string())
+    if !transition.out_state.keep_state
+        && state_data
+            .data_types
+            .contains_key(&transition.out_state.ident.to_string())
     {
         // This transition goes to a state that has data associated, check so it has an
         // action
@@ -106,14 +248,323 @@ fn add_transition(
             ));
         }
     }
+
+    // The same goes for the branches of a choice
+    if let Some(choice) = &transition.out_state.choice {
+        for branch in &choice.branches {
+            let out_state = &branch.out_state.ident;
+            if branch.action.is_none() && state_data.data_types.contains_key(&out_state.to_string())
+            {
+                return Err(parse::Error::new(
+                    out_state.span(),
+                    "This state has data associated, but not action is define here to provide it.",
+                ));
+            }
+        }
+    }
     Ok(())
 }
 
+// helper function for expanding a wildcard or composite state transition to a set of states
+fn expand_transition(
+    transition: &StateTransition,
+    targets: &[&Ident],
+    transition_map: &mut TransitionMap,
+    state_data: &DataDefinitions,
+    bubble: bool,
+) -> Result<bool, parse::Error> {
+    let mut transition_added = false;
+
+    for in_state in targets {
+        let p = transition_map.get_mut(&in_state.to_string()).unwrap();
+
+        // Skip states that already handle the event. Transitions of a composite state also
+        // apply after the guarded transitions of its sub-states, unless a sub-state handles
+        // the event unconditionally.
+        if let Some(mapping) = p.get(&transition.event.ident.to_string()) {
+            if !bubble || mapping.transitions.iter().any(|t| t.guard.is_none()) {
+                continue;
+            }
+        }
+
+        // create a new input state from the wildcard or composite state
+        let in_state = InputState {
+            start: false,
+            wildcard: false,
+            ident: (*in_state).clone(),
+            data_type: state_data.data_types.get(&in_state.to_string()).cloned(),
+        };
+
+        // create the transition
+        let mut out_state = transition.out_state.clone();
+        if out_state.internal_transition {
+            out_state.ident = in_state.ident.clone();
+            out_state.data_type.clone_from(&in_state.data_type);
+        }
+        let expanded_transition = StateTransition {
+            in_state,
+            event: transition.event.clone(),
+            guard: transition.guard.clone(),
+            fallback: transition.fallback,
+            action: transition.action.clone(),
+            preceding_actions: transition.preceding_actions.clone(),
+            out_state,
+            excluded: Vec::new(),
+            priority: transition.priority,
+            retry: transition.retry.clone(),
+            parent: transition.parent.clone(),
+            pattern: transition.pattern,
+            source_composite: bubble.then(|| transition.in_state.ident.clone()),
+        };
+
+        // add the expanded transition to the transition map
+        // TODO:  Need to work on the span of this error, as it is being caused by the wildcard
+        // but won't show up at that line
+        add_transition(&expanded_transition, transition_map, state_data)?;
+
+        transition_added = true;
+    }
+
+    Ok(transition_added)
+}
+
 impl ParsedStateMachine {
+    /// Returns the composite states containing `state`, from the outermost to the innermost.
+    pub fn ancestors(&self, state: &str) -> Vec<&Ident> {
+        let mut ancestors = Vec::new();
+        let mut current = self.state_parents.get(state);
+        while let Some(parent) = current {
+            ancestors.insert(0, parent);
+            current = self.state_parents.get(&parent.to_string());
+        }
+        ancestors
+    }
+
+    /// Returns the timeouts of the async guards of all transitions.
+    pub fn guard_timeouts(&self) -> Vec<&GuardTimeout> {
+        self.states_events_mapping
+            .values()
+            .flat_map(|mappings| mappings.values())
+            .flat_map(|mapping| mapping.transitions.iter())
+            .flat_map(|transition| {
+                let branch_guards = transition
+                    .choice
+                    .iter()
+                    .flat_map(|choice| choice.branches.iter())
+                    .filter_map(|branch| branch.guard.as_ref());
+                transition.guard.iter().chain(branch_guards)
+            })
+            .flat_map(|guard| guard.timeouts())
+            .collect()
+    }
+
+    /// Returns `true` if the state machine measures time with the clock of the context, for timed
+    /// transitions, timeouts of guards and states, and the timestamps of the transition history.
+    pub fn uses_clock(&self) -> bool {
+        !self.timeouts.is_empty()
+            || !self.guard_timeouts().is_empty()
+            || self.state_handlers.values().any(|h| h.watchdog.is_some())
+            || self.transition_history.is_some()
+            || self.statistics
+    }
+
+    /// Returns the transitions of all states, sorted by their state and event and in the order
+    /// they are tried.
+    pub fn described_transitions(&self) -> Vec<DescribedTransition> {
+        let mut mappings: Vec<_> = self
+            .states_events_mapping
+            .iter()
+            .flat_map(|(state, mappings)| mappings.iter().map(move |(event, m)| (state, event, m)))
+            .collect();
+        mappings.sort_by_key(|(state, event, _)| (state.as_str(), event.as_str()));
+
+        let mut described = Vec::new();
+        for (state, event, mapping) in mappings {
+            let event = match event.as_str() {
+                event::ANY_EVENT => "_".to_string(),
+                event::COMPLETION_EVENT => String::new(),
+                _ => event.clone(),
+            };
+            for transition in &mapping.transitions {
+                let actions: Vec<_> = transition
+                    .preceding_actions
+                    .iter()
+                    .chain(transition.action.iter())
+                    .map(|action| action.ident.to_string())
+                    .collect();
+                let targets: Vec<_> = match &transition.choice {
+                    Some(choice) => choice
+                        .branches
+                        .iter()
+                        .map(|branch| branch.out_state.ident.to_string())
+                        .collect(),
+                    None => vec![transition.out_state.to_string()],
+                };
+                for to in targets {
+                    described.push(DescribedTransition {
+                        from: state.clone(),
+                        event: event.clone(),
+                        guard: transition.guard.as_ref().map(|guard| guard.to_string()),
+                        action: if actions.is_empty() {
+                            None
+                        } else {
+                            Some(actions.join(", "))
+                        },
+                        to,
+                    });
+                }
+            }
+        }
+        described
+    }
+
+    /// Returns the indices of the transitions of every state and event in the described
+    /// transitions, in the order they are tried, where a choice has the index of its first branch
+    /// and the following indices belong to its other branches.
+    pub fn transition_indices(&self) -> HashMap<(String, String), Vec<usize>> {
+        let mut mappings: Vec<_> = self
+            .states_events_mapping
+            .iter()
+            .flat_map(|(state, mappings)| mappings.iter().map(move |(event, m)| (state, event, m)))
+            .collect();
+        mappings.sort_by_key(|(state, event, _)| (state.as_str(), event.as_str()));
+
+        let mut index = 0;
+        let mut indices = HashMap::new();
+        for (state, event, mapping) in mappings {
+            let transitions = mapping
+                .transitions
+                .iter()
+                .map(|transition| {
+                    let first = index;
+                    index += transition
+                        .choice
+                        .as_ref()
+                        .map_or(1, |choice| choice.branches.len());
+                    first
+                })
+                .collect();
+            indices.insert((state.clone(), event.clone()), transitions);
+        }
+        indices
+    }
+
+    /// Returns `true` if `state` is nested (at any depth) in the composite state `composite`.
+    pub fn is_descendant(&self, state: &str, composite: &str) -> bool {
+        self.ancestors(state).iter().any(|a| *a == composite)
+    }
+
+    /// Returns the final sub-states of a region, which are those without any transitions.
+    pub fn final_states<'a>(&'a self, region: &'a CompositeState) -> Vec<&'a Ident> {
+        region
+            .children
+            .iter()
+            .filter(|state| {
+                self.states_events_mapping
+                    .get(&state.to_string())
+                    .is_none_or(|mapping| mapping.is_empty())
+            })
+            .collect()
+    }
+
+    /// Returns the name of a generated type, given in the `types` block or named like the state
+    /// machine.
+    fn type_name(&self, renamed: &Option<TypeName>, suffix: &str) -> Ident {
+        match (renamed, &self.name) {
+            (Some(renamed), _) => renamed.ident.clone(),
+            (None, Some(name)) => format_ident!("{}{}", name, suffix, span = name.span()),
+            (None, None) => format_ident!("{}", suffix),
+        }
+    }
+
+    /// Returns the name of the enum of the states.
+    pub fn states_type_name(&self) -> Ident {
+        self.type_name(&self.type_names.states, "States")
+    }
+
+    /// Returns the name of the enum of the events.
+    pub fn events_type_name(&self) -> Ident {
+        self.type_name(&self.type_names.events, "Events")
+    }
+
+    /// Returns the name of the state machine.
+    pub fn state_machine_type_name(&self) -> Ident {
+        self.type_name(&self.type_names.state_machine, "StateMachine")
+    }
+
+    /// Returns the name of the trait of the context.
+    pub fn context_type_name(&self) -> Ident {
+        self.type_name(&self.type_names.context, "StateMachineContext")
+    }
+
+    /// Returns the type of the data of `state` taken and returned by the functions of the context,
+    /// which is not behind the pointer of boxed states.
+    pub fn unboxed_data_type(&self, state: &str) -> Option<&Type> {
+        self.boxed_states
+            .get(state)
+            .or_else(|| self.state_data.data_types.get(state))
+    }
+
+    /// Returns whether the actions of `transition` from `state` borrow the data of the state
+    /// mutably, which internal transitions do unless the data is a reference or boxed.
+    pub fn updates_state_data(&self, state: &str, transition: &Transition) -> bool {
+        transition.keep_state
+            && !self.boxed_states.contains_key(state)
+            && matches!(
+                self.state_data.data_types.get(state),
+                Some(data_type) if !matches!(data_type, Type::Reference(_))
+            )
+    }
+
+    /// Returns whether an action of an internal transition of `state` updates the data of the
+    /// state, which is then bound mutably when events are dispatched.
+    pub fn has_updated_state_data(&self, state: &str) -> bool {
+        self.states_events_mapping
+            .get(state)
+            .is_some_and(|mapping| {
+                mapping
+                    .values()
+                    .flat_map(|mapping| &mapping.transitions)
+                    .any(|transition| {
+                        (transition.action.is_some() || !transition.preceding_actions.is_empty())
+                            && self.updates_state_data(state, transition)
+                    })
+            })
+    }
+
+    /// Returns the leaf state entered when transitioning to `state`.
+    pub fn resolve_initial(&self, state: &Ident) -> Ident {
+        match self.composite_states.get(&state.to_string()) {
+            Some(composite) => self.resolve_initial(composite.initial.as_ref().unwrap()),
+            None => state.clone(),
+        }
+    }
+
     pub fn new(mut sm: StateMachine) -> parse::Result<Self> {
+        let composite_states: HashMap<String, CompositeState> = sm
+            .composite_states
+            .iter()
+            .map(|c| (c.ident.to_string(), c.clone()))
+            .collect();
+
+        let parallel_states: HashMap<String, ParallelState> = sm
+            .parallel_states
+            .iter()
+            .map(|p| (p.ident.to_string(), p.clone()))
+            .collect();
+        let regions: HashMap<String, &CompositeState> = sm
+            .parallel_states
+            .iter()
+            .flat_map(|p| p.regions.iter())
+            .map(|r| (r.ident.to_string(), r))
+            .collect();
+
         // Derive out_state for internal non-wildcard transitions
         for transition in sm.transitions.iter_mut() {
-            if transition.out_state.internal_transition && !transition.in_state.wildcard {
+            if transition.out_state.internal_transition
+                && !transition.in_state.wildcard
+                && !composite_states.contains_key(&transition.in_state.ident.to_string())
+            {
                 transition.out_state.ident = transition.in_state.ident.clone();
                 transition
                     .out_state
@@ -124,22 +575,38 @@ impl ParsedStateMachine {
         }
 
         // Check the initial state definition
-        let mut starting_transitions_iter = sm.transitions.iter().filter(|sm| sm.in_state.start);
+        let mut starting_transitions_iter = sm
+            .transitions
+            .iter()
+            .filter(|sm| sm.in_state.start && sm.parent.is_none())
+            .map(|sm| &sm.in_state.ident)
+            .chain(
+                sm.composite_states
+                    .iter()
+                    .filter(|c| c.start && c.parent.is_none())
+                    .map(|c| &c.ident),
+            )
+            .chain(
+                sm.parallel_states
+                    .iter()
+                    .filter(|p| p.start && p.parent.is_none())
+                    .map(|p| &p.ident),
+            );
 
-        let starting_transition = starting_transitions_iter.next().ok_or(parse::Error::new(
+        let starting_state = starting_transitions_iter.next().ok_or(parse::Error::new(
             Span::call_site(),
             "No starting state defined, indicate the starting state with a *.",
         ))?;
 
-        if starting_transitions_iter.next().is_some() {
+        if let Some(duplicate) = starting_transitions_iter.next() {
             return Err(parse::Error::new(
-                Span::call_site(),
+                duplicate.span(),
                 "More than one starting state defined (indicated with *), remove duplicates.",
             ));
         }
 
         // Extract the starting state
-        let starting_state = starting_transition.in_state.ident.clone();
+        let starting_state = starting_state.clone();
 
         let mut states = HashMap::new();
         let mut state_data = DataDefinitions::new();
@@ -147,111 +614,1105 @@ impl ParsedStateMachine {
         let mut event_data = DataDefinitions::new();
         let mut states_events_mapping = TransitionMap::new();
 
+        // The composite state a state is nested in is the block where it is used as an input
+        // state, or else the block where it is first used as an output state.
+        let mut state_parents: HashMap<String, Option<Ident>> = HashMap::new();
+        let mut output_state_parents: HashMap<String, Option<Ident>> = HashMap::new();
+
+        // The durations of the timed transitions of each state
+        let mut timeouts = HashMap::new();
+
+        // The states marked as final, where the state machine stops processing events
+        let mut terminal_states = HashMap::new();
+
+        // The region a sub-state of a parallel state is in
+        let mut region_states = HashMap::new();
+        let mut region_parents: HashMap<String, Ident> = HashMap::new();
+
+        for composite in composite_states.values() {
+            if let Some(parent) = &composite.parent {
+                state_parents.insert(composite.ident.to_string(), Some(parent.clone()));
+            }
+        }
+
+        // Parallel states are leaf states holding the sub-states of their regions
+        for parallel in parallel_states.values() {
+            let name = parallel.ident.to_string();
+            states.insert(name.clone(), parallel.ident.clone());
+            states_events_mapping.insert(name.clone(), HashMap::new());
+            if let Some(parent) = &parallel.parent {
+                state_parents.insert(name, Some(parent.clone()));
+            }
+
+            // The completion state is treated as an output state next to the parallel state
+            if let Some(completion) = &parallel.completion {
+                let completion_name = completion.to_string();
+                if !composite_states.contains_key(&completion_name)
+                    && !parallel_states.contains_key(&completion_name)
+                {
+                    states.insert(completion_name.clone(), completion.clone());
+                    states_events_mapping.insert(completion_name.clone(), HashMap::new());
+                    output_state_parents
+                        .entry(completion_name)
+                        .or_insert_with(|| parallel.parent.clone());
+                }
+            }
+        }
+
         for transition in sm.transitions.iter() {
             // Collect states
             let in_state_name = transition.in_state.ident.to_string();
-            if !transition.in_state.wildcard {
+            let region = transition
+                .parent
+                .as_ref()
+                .filter(|parent| regions.contains_key(&parent.to_string()));
+
+            if composite_states.contains_key(&in_state_name)
+                || parallel_states.contains_key(&in_state_name)
+            {
+                if let Some(data_type) = &transition.in_state.data_type {
+                    return Err(parse::Error::new(
+                        data_type.span(),
+                        "Composite states cannot have data associated with it.",
+                    ));
+                }
+            } else if let (Some(region), false) = (region, transition.in_state.wildcard) {
+                region_states.insert(in_state_name.clone(), transition.in_state.ident.clone());
+                state_data.collect(in_state_name.clone(), transition.in_state.data_type.clone())?;
+                states_events_mapping.insert(in_state_name.clone(), HashMap::new());
+
+                match region_parents.get(&in_state_name) {
+                    Some(parent) if parent != region => {
+                        return Err(parse::Error::new(
+                            transition.in_state.ident.span(),
+                            format!(
+                                "State {} is used as an input state in more than one region.",
+                                in_state_name
+                            ),
+                        ));
+                    }
+                    _ => {
+                        region_parents.insert(in_state_name.clone(), region.clone());
+                    }
+                }
+            } else if !transition.in_state.wildcard {
                 states.insert(in_state_name.clone(), transition.in_state.ident.clone());
                 state_data.collect(in_state_name.clone(), transition.in_state.data_type.clone())?;
+
+                match state_parents.get(&in_state_name) {
+                    Some(parent) if parent != &transition.parent => {
+                        return Err(parse::Error::new(
+                            transition.in_state.ident.span(),
+                            format!(
+                                "State {} is used as an input state in more than one composite state.",
+                                in_state_name
+                            ),
+                        ));
+                    }
+                    _ => {
+                        state_parents.insert(in_state_name.clone(), transition.parent.clone());
+                    }
+                }
             }
-            if !transition.out_state.internal_transition {
-                let out_state_name = transition.out_state.ident.to_string();
-                states.insert(out_state_name.clone(), transition.out_state.ident.clone());
-                state_data.collect(
-                    out_state_name.clone(),
-                    transition.out_state.data_type.clone(),
-                )?;
+            if let Some(choice) = &transition.out_state.choice {
+                if region.is_some() {
+                    return Err(parse::Error::new(
+                        choice.ident.span(),
+                        "Choices cannot be used in regions.",
+                    ));
+                }
+                if let (Some(data_type), None) = (&choice.data_type, &transition.action) {
+                    return Err(parse::Error::new(
+                        data_type.span(),
+                        "This choice has data associated, but no action is defined here to provide it.",
+                    ));
+                }
+            }
+            for out_state in transition.out_state.targets() {
+                if out_state.internal_transition {
+                    continue;
+                }
+                let out_state_name = out_state.ident.to_string();
+                if out_state.final_state {
+                    if composite_states.contains_key(&out_state_name)
+                        || parallel_states.contains_key(&out_state_name)
+                        || region.is_some()
+                    {
+                        return Err(parse::Error::new(
+                            out_state.ident.span(),
+                            "Only states which are not composite states or sub-states of regions can be final states.",
+                        ));
+                    }
+                    terminal_states.insert(out_state_name.clone(), out_state.ident.clone());
+                }
+                if composite_states.contains_key(&out_state_name)
+                    || parallel_states.contains_key(&out_state_name)
+                {
+                    if let Some(data_type) = &out_state.data_type {
+                        return Err(parse::Error::new(
+                            data_type.span(),
+                            "Composite states cannot have data associated with it.",
+                        ));
+                    }
+                    if region.is_some() {
+                        return Err(parse::Error::new(
+                            out_state.ident.span(),
+                            "Transitions in a region cannot leave the region.",
+                        ));
+                    }
+                } else if let Some(region) = region {
+                    region_states.insert(out_state_name.clone(), out_state.ident.clone());
+                    state_data.collect(out_state_name.clone(), out_state.data_type.clone())?;
+                    states_events_mapping.insert(out_state_name.clone(), HashMap::new());
+                    region_parents
+                        .entry(out_state_name)
+                        .or_insert_with(|| region.clone());
+                } else {
+                    states.insert(out_state_name.clone(), out_state.ident.clone());
+                    state_data.collect(out_state_name.clone(), out_state.data_type.clone())?;
+                    output_state_parents
+                        .entry(out_state_name)
+                        .or_insert_with(|| transition.parent.clone());
+                }
             }
 
-            // Collect events
-            let event_name = transition.event.ident.to_string();
-            events.insert(event_name.clone(), transition.event.ident.clone());
-            event_data.collect(event_name.clone(), transition.event.data_type.clone())?;
+            // Collect events, timed transitions don't have an event
+            if let Some(timeout) = &transition.event.timeout {
+                let in_state = &transition.in_state;
+                if in_state.wildcard
+                    || region.is_some()
+                    || composite_states.contains_key(&in_state_name)
+                {
+                    return Err(parse::Error::new(
+                        transition.event.ident.span(),
+                        "Timed transitions can only be used on states which are not composite states, wildcards or sub-states of regions.",
+                    ));
+                }
+                match timeouts.get(&in_state_name) {
+                    Some(previous) if previous != timeout => {
+                        return Err(parse::Error::new(
+                            transition.event.ident.span(),
+                            format!(
+                                "The timed transitions of {} must have the same duration.",
+                                in_state_name
+                            ),
+                        ));
+                    }
+                    _ => {
+                        timeouts.insert(in_state_name.clone(), timeout.clone());
+                    }
+                }
+            } else if transition.event.is_completion() {
+                if region.is_some() {
+                    return Err(parse::Error::new(
+                        transition.in_state.ident.span(),
+                        "Completion transitions cannot be used in regions.",
+                    ));
+                }
+            } else if transition.event.is_any() {
+                if region.is_some() || parallel_states.contains_key(&in_state_name) {
+                    return Err(parse::Error::new(
+                        transition.event.ident.span(),
+                        "Catch-all transitions cannot be used in regions or parallel states.",
+                    ));
+                }
+            } else {
+                let event_name = transition.event.ident.to_string();
+                events.insert(event_name.clone(), transition.event.ident.clone());
+                event_data.collect(event_name.clone(), transition.event.data_type.clone())?;
+            }
 
             // add input and output states to the mapping HashMap
-            if !transition.in_state.wildcard {
-                states_events_mapping.insert(transition.in_state.ident.to_string(), HashMap::new());
+            if states.contains_key(&in_state_name) {
+                states_events_mapping.insert(in_state_name, HashMap::new());
             }
-            if !transition.out_state.internal_transition {
-                states_events_mapping
-                    .insert(transition.out_state.ident.to_string(), HashMap::new());
+            for out_state in transition.out_state.targets() {
+                if !out_state.internal_transition
+                    && states.contains_key(&out_state.ident.to_string())
+                {
+                    states_events_mapping.insert(out_state.ident.to_string(), HashMap::new());
+                }
             }
         }
 
+        // Final states are never left
         for transition in sm.transitions.iter() {
-            // if input state is a wildcard, we need to add this transition for all states
-            if transition.in_state.wildcard {
-                let mut transition_added = false;
+            if !transition.in_state.wildcard
+                && terminal_states.contains_key(&transition.in_state.ident.to_string())
+            {
+                return Err(parse::Error::new(
+                    transition.in_state.ident.span(),
+                    "Final states cannot have transitions.",
+                ));
+            }
+        }
 
-                for (name, in_state) in &states {
-                    // skip already set input state
-                    let p = states_events_mapping
-                        .get_mut(&in_state.to_string())
-                        .unwrap();
+        // The fault state is entered from any state when an action fails
+        if let Some((fault_state, data_type)) = &sm.fault_state {
+            let name = fault_state.to_string();
+            if composite_states.contains_key(&name)
+                || parallel_states.contains_key(&name)
+                || region_states.contains_key(&name)
+            {
+                return Err(parse::Error::new(
+                    fault_state.span(),
+                    "The fault state needs to be a state which is not a composite state or a sub-state of a region.",
+                ));
+            }
+            if let (Some(data_type), false) = (data_type, sm.custom_error) {
+                return Err(parse::Error::new(
+                    data_type.span(),
+                    "The fault state can only hold the error of an action when `custom_error` is set.",
+                ));
+            }
+            states.insert(name.clone(), fault_state.clone());
+            state_data.collect(name.clone(), data_type.clone())?;
+            states_events_mapping.entry(name.clone()).or_default();
+            output_state_parents.entry(name).or_insert(None);
+        }
 
-                    if p.contains_key(&transition.event.ident.to_string()) {
-                        continue;
-                    }
+        // Collect delegated events, which hold the events of the sub-machines
+        for delegated in sm.delegated_events.iter() {
+            let event_name = delegated.event.ident.to_string();
+            if delegated.event.data_type.is_none() {
+                return Err(parse::Error::new(
+                    delegated.event.ident.span(),
+                    "Delegated events need the event of the sub-machine as data, e.g. `Event(SubEvents)`.",
+                ));
+            }
+            events.insert(event_name.clone(), delegated.event.ident.clone());
+            event_data.collect(event_name, delegated.event.data_type.clone())?;
+        }
 
-                    // create a new input state from wildcard
-                    let in_state = InputState {
-                        start: false,
-                        wildcard: false,
-                        ident: in_state.clone(),
-                        data_type: state_data.data_types.get(name).cloned(),
-                    };
-
-                    // create the transition
-                    let mut out_state = transition.out_state.clone();
-                    if out_state.internal_transition {
-                        out_state.ident = in_state.ident.clone();
-                        out_state.data_type.clone_from(&in_state.data_type);
-                    }
-                    let wildcard_transition = StateTransition {
-                        in_state,
-                        event: transition.event.clone(),
-                        guard: transition.guard.clone(),
-                        action: transition.action.clone(),
-                        out_state,
-                    };
-
-                    // add the wildcard transition to the transition map
-                    // TODO:  Need to work on the span of this error, as it is being caused by the wildcard
-                    // but won't show up at that line
-                    add_transition(
-                        &wildcard_transition,
-                        &mut states_events_mapping,
-                        &state_data,
-                    )?;
-
-                    transition_added = true;
+        // Collect deferred events. Events are stored in the state machine when deferred, so they
+        // can't hold references.
+        for deferred in sm.deferred_events.iter() {
+            let event_name = deferred.event.ident.to_string();
+            events.insert(event_name.clone(), deferred.event.ident.clone());
+            event_data.collect(event_name, deferred.event.data_type.clone())?;
+        }
+        if let Some(deferred) = sm.deferred_events.first() {
+            if !event_data.all_lifetimes.is_empty() {
+                return Err(parse::Error::new(
+                    deferred.event.ident.span(),
+                    "Events cannot be deferred when the data of events has lifetimes.",
+                ));
+            }
+        }
+        // Posted events are queued in the context, so they can't hold references
+        let post_capacity = match &sm.post_capacity {
+            Some(capacity) if !event_data.all_lifetimes.is_empty() => {
+                return Err(parse::Error::new(
+                    capacity.span(),
+                    "Events cannot be posted when the data of events has lifetimes.",
+                ));
+            }
+            Some(capacity) => Some(capacity.base10_parse()?),
+            None => None,
+        };
+        // Queued events are moved between contexts, so they can't hold references either
+        let queue_capacity = match &sm.queue_capacity {
+            Some(capacity) if !event_data.all_lifetimes.is_empty() => {
+                return Err(parse::Error::new(
+                    capacity.span(),
+                    "Events cannot be queued when the data of events has lifetimes.",
+                ));
+            }
+            Some(capacity) => Some(capacity.base10_parse()?),
+            None => None,
+        };
+        // Logged events are kept by the context, so they can't hold references either
+        if let Some(event_log) = &sm.event_log {
+            if !event_data.all_lifetimes.is_empty() {
+                return Err(parse::Error::new(
+                    event_log.span(),
+                    "Events cannot be logged when the data of events has lifetimes.",
+                ));
+            }
+        }
+        let defer_capacity = match &sm.defer_capacity {
+            Some(capacity) => capacity.base10_parse()?,
+            None => 4,
+        };
+        // Deserialized states own their data, so they can't hold references
+        if let Some(serde) = &sm.serde {
+            if !state_data.all_lifetimes.is_empty() {
+                return Err(parse::Error::new(
+                    serde.span(),
+                    "States cannot be serialized when the data of states has lifetimes.",
+                ));
+            }
+        }
+        // The logger is bound by the states and events, which can't be named without lifetimes
+        let associated_types = match &sm.associated_types {
+            Some(associated_types)
+                if associated_types.value
+                    && !(state_data.all_lifetimes.is_empty()
+                        && event_data.all_lifetimes.is_empty()) =>
+            {
+                return Err(parse::Error::new(
+                    associated_types.span(),
+                    "The types of the context cannot be associated when the data of states or \
+                     events has lifetimes.",
+                ));
+            }
+            Some(associated_types) => associated_types.value,
+            None => false,
+        };
+        let hash_dispatch = match &sm.hash_dispatch {
+            Some(hash_dispatch) if hash_dispatch.value && sm.table_dispatch => {
+                return Err(parse::Error::new(
+                    hash_dispatch.span(),
+                    "`hash_dispatch` cannot be combined with `table_dispatch`.",
+                ));
+            }
+            Some(hash_dispatch) => hash_dispatch.value,
+            None => false,
+        };
+        // The rejected events are kept in the state machine, which doesn't borrow them
+        let rejected_events = match &sm.rejected_events {
+            Some(rejected_events)
+                if rejected_events.value && !event_data.all_lifetimes.is_empty() =>
+            {
+                return Err(parse::Error::new(
+                    rejected_events.span(),
+                    "Rejected events cannot be kept when the data of events has lifetimes.",
+                ));
+            }
+            Some(rejected_events) => rejected_events.value,
+            None => false,
+        };
+        // Borrowed events can't be moved into the queue of deferred events or into a sub-machine
+        let events_by_ref = match &sm.events_by_ref {
+            Some(events_by_ref) if events_by_ref.value => {
+                let owning = if !sm.deferred_events.is_empty() {
+                    Some("Events cannot be deferred when they are processed by reference.")
+                } else if !sm.delegated_events.is_empty() {
+                    Some("Events cannot be delegated when they are processed by reference.")
+                } else if rejected_events {
+                    Some("Events processed by reference are kept by the caller when rejected.")
+                } else if event_data.data_types.values().any(
+                    |data_type| matches!(data_type, Type::Reference(r) if r.mutability.is_some()),
+                ) {
+                    Some("Events holding mutable references cannot be processed by reference.")
+                } else {
+                    None
+                };
+                if let Some(message) = owning {
+                    return Err(parse::Error::new(events_by_ref.span(), message));
                 }
+                true
+            }
+            _ => false,
+        };
+        // The variants of an external enum of events are only known where it is defined
+        if let Some(events_type) = &sm.events_type {
+            if sm.table_dispatch || hash_dispatch {
+                return Err(parse::Error::new(
+                    events_type.span(),
+                    "Events of an `events_type` cannot be dispatched through a table.",
+                ));
+            }
+            if let Some(attr) = sm.events_attr.first() {
+                return Err(parse::Error::new(
+                    attr.span(),
+                    "The attributes of an `events_type` are given where it is defined.",
+                ));
+            }
+        }
+        let schema_version = match (&sm.schema_version, &sm.serde) {
+            (Some(version), None) => {
+                return Err(parse::Error::new(
+                    version.span(),
+                    "The schema version can only be set when `serde` is set.",
+                ));
+            }
+            (Some(version), Some(_)) => Some(version.base10_parse()?),
+            (None, _) => None,
+        };
 
-                // No transitions were added by expanding the wildcard,
-                // so emit an error to the user
-                if !transition_added {
-                    return Err(parse::Error::new(
-                        transition.in_state.ident.span(),
-                        "Wildcard has no effect",
-                    ));
-                }
-            } else {
-                add_transition(transition, &mut states_events_mapping, &state_data)?;
+        for (name, parent) in output_state_parents {
+            state_parents.entry(name).or_insert(parent);
+        }
+
+        // Sub-states of regions are only reachable from within their region
+        for (name, ident) in &region_states {
+            if states.contains_key(name) {
+                return Err(parse::Error::new(
+                    ident.span(),
+                    format!(
+                        "State {} is used both inside and outside of region {}.",
+                        name, region_parents[name]
+                    ),
+                ));
+            }
+        }
+        for region in regions.values() {
+            let initial = region.initial.as_ref().unwrap();
+            if state_data.data_types.contains_key(&initial.to_string()) {
+                return Err(parse::Error::new(
+                    initial.span(),
+                    "The initial sub-state of a region cannot have data associated with it.",
+                ));
             }
         }
 
-        Ok(ParsedStateMachine {
+        let mut sm_parsed = ParsedStateMachine {
             name: sm.name,
             states_attr: sm.states_attr,
             events_attr: sm.events_attr,
+            events_type: sm.events_type,
+            display: sm.display,
+            scxml_path: sm.scxml_path,
+            lints: sm.lints,
+            context_access: sm.context_access,
+            type_names: sm.type_names,
+            assertions: sm.assertions,
+            invariants: sm.invariants,
             temporary_context_type: sm.temporary_context_type,
             custom_error: sm.custom_error,
+            custom_guard_error: sm.custom_guard_error,
+            transition_outcome: sm.transition_outcome,
+            serde: sm.serde.is_some(),
+            schema_version,
+            event_log: sm.event_log.is_some(),
+            metrics: sm.metrics,
+            statistics: sm.statistics,
+            transition_coverage: sm.transition_coverage,
+            table_dispatch: sm.table_dispatch,
+            hash_dispatch,
+            publish_state_id: sm.publish_state_id,
+            state_id_mirror: sm.state_id_mirror,
+            send_futures: sm.send_futures,
+            rejected_events,
+            events_by_ref,
+            poisoning: sm.poisoning,
+            arbitrary: sm.arbitrary,
+            typestate: sm.typestate,
+            associated_types,
+            transition_history: match &sm.transition_history {
+                Some(capacity) => Some(capacity.base10_parse()?),
+                None => None,
+            },
             states,
+            composite_states,
+            state_parents: state_parents
+                .into_iter()
+                .filter_map(|(name, parent)| parent.map(|parent| (name, parent)))
+                .collect(),
+            parallel_states,
+            region_states,
+            region_parents,
+            state_handlers: HashMap::new(),
+            boxed_states: HashMap::new(),
+            deferred_events: HashMap::new(),
+            defer_capacity,
+            post_capacity,
+            queue_capacity,
+            event_priorities: HashMap::new(),
+            event_generators: HashMap::new(),
+            event_attrs: HashMap::new(),
+            unused_events: HashSet::new(),
+            timeouts,
+            sub_machines: HashMap::new(),
+            terminal_states,
+            fault_state: sm.fault_state.map(|(fault_state, _)| fault_state),
+            inline_actions: sm
+                .inline_actions
+                .into_iter()
+                .map(|inline_action| (inline_action.ident.to_string(), inline_action))
+                .collect(),
+            expression_guards: sm
+                .expression_guards
+                .into_iter()
+                .map(|guard| (guard.ident.to_string(), guard.expr))
+                .collect(),
             starting_state,
             state_data,
             events,
             event_data,
-            states_events_mapping,
-        })
+            states_events_mapping: TransitionMap::new(),
+        };
+
+        // Collect the sub-states of each region
+        let mut region_children: Vec<_> = sm_parsed
+            .region_parents
+            .iter()
+            .map(|(name, region)| (region.to_string(), sm_parsed.region_states[name].clone()))
+            .collect();
+        region_children.sort_by_key(|(_, child)| child.to_string());
+        for (region, child) in region_children {
+            let region = sm_parsed
+                .parallel_states
+                .values_mut()
+                .flat_map(|p| p.regions.iter_mut())
+                .find(|r| r.ident == region)
+                .unwrap();
+            region.children.push(child);
+        }
+
+        // Collect the direct sub-states of each composite state
+        let mut children: Vec<(String, Ident)> = sm_parsed
+            .state_parents
+            .iter()
+            .map(|(name, parent)| (parent.to_string(), Ident::new(name, parent.span())))
+            .collect();
+        children.sort_by_key(|(_, child)| child.to_string());
+        for (parent, child) in children {
+            let composite = sm_parsed.composite_states.get_mut(&parent).unwrap();
+            composite.children.push(child);
+        }
+
+        // Composite states with history remember a single sub-state, which must be re-entered
+        // without data for shallow history
+        for composite in sm_parsed.composite_states.values() {
+            let history = match composite.history {
+                Some(history) => history,
+                None => continue,
+            };
+            let name = composite.ident.to_string();
+            if let Some(nested) = sm_parsed.composite_states.values().find(|c| {
+                c.history.is_some() && sm_parsed.is_descendant(&c.ident.to_string(), &name)
+            }) {
+                return Err(parse::Error::new(
+                    nested.ident.span(),
+                    format!(
+                        "{} has history, so it cannot be nested in {} which has history as well.",
+                        nested.ident, name
+                    ),
+                ));
+            }
+            if history == History::Shallow {
+                for child in &composite.children {
+                    let initial = sm_parsed.resolve_initial(child);
+                    if initial != *child
+                        && sm_parsed
+                            .state_data
+                            .data_types
+                            .contains_key(&initial.to_string())
+                    {
+                        return Err(parse::Error::new(
+                            initial.span(),
+                            format!(
+                                "This state has data associated, but is entered by the shallow history of {}.",
+                                name
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        // Transitions to a composite state enter its initial sub-state
+        sm_parsed.starting_state = sm_parsed.resolve_initial(&sm_parsed.starting_state);
+        for transition in sm.transitions.iter_mut() {
+            let out_states = match &mut transition.out_state.choice {
+                Some(choice) => choice
+                    .branches
+                    .iter_mut()
+                    .map(|b| &mut b.out_state)
+                    .collect(),
+                None => vec![&mut transition.out_state],
+            };
+            for out_state in out_states {
+                if !out_state.internal_transition
+                    && sm_parsed
+                        .composite_states
+                        .contains_key(&out_state.ident.to_string())
+                {
+                    let initial = sm_parsed.resolve_initial(&out_state.ident);
+                    out_state.data_type = sm_parsed
+                        .state_data
+                        .data_types
+                        .get(&initial.to_string())
+                        .cloned();
+                    out_state.composite = Some(out_state.ident.clone());
+                    out_state.ident = initial;
+                }
+            }
+        }
+
+        let state_data = &sm_parsed.state_data;
+
+        for transition in sm.transitions.iter() {
+            // if input state is a wildcard, we need to add this transition for all states
+            if transition.in_state.wildcard {
+                let in_region = |name: &String| {
+                    sm_parsed.region_parents.get(name) == transition.parent.as_ref()
+                };
+                let mut targets: Vec<_> = match &transition.parent {
+                    Some(parent) if regions.contains_key(&parent.to_string()) => sm_parsed
+                        .region_states
+                        .iter()
+                        .filter(|(name, _)| in_region(name))
+                        .map(|(_, state)| state)
+                        .collect(),
+                    _ => sm_parsed
+                        .states
+                        .iter()
+                        .filter(|(name, _)| match &transition.parent {
+                            Some(parent) => sm_parsed.is_descendant(name, &parent.to_string()),
+                            None => true,
+                        })
+                        .filter(|(name, _)| {
+                            !transition.event.is_any()
+                                || !sm_parsed.parallel_states.contains_key(*name)
+                        })
+                        .filter(|(name, _)| !sm_parsed.terminal_states.contains_key(*name))
+                        .map(|(_, state)| state)
+                        .collect(),
+                };
+                targets.sort_by_key(|state| state.to_string());
+
+                // Excluded states, where excluding a composite state excludes all of its sub-states
+                for excluded in &transition.excluded {
+                    let name = excluded.to_string();
+                    if !sm_parsed.states.contains_key(&name)
+                        && !sm_parsed.region_states.contains_key(&name)
+                        && !sm_parsed.composite_states.contains_key(&name)
+                    {
+                        return Err(parse::Error::new(
+                            excluded.span(),
+                            format!(
+                                "Unknown state {}, which is excluded from the wildcard.",
+                                name
+                            ),
+                        ));
+                    }
+                }
+                targets.retain(|state| {
+                    let name = state.to_string();
+                    !transition.excluded.iter().any(|excluded| {
+                        *excluded == name || sm_parsed.is_descendant(&name, &excluded.to_string())
+                    })
+                });
+
+                // No transitions were added by expanding the wildcard,
+                // so emit an error to the user
+                if !expand_transition(
+                    transition,
+                    &targets,
+                    &mut states_events_mapping,
+                    state_data,
+                    false,
+                )? {
+                    return Err(parse::Error::new(
+                        transition.in_state.ident.span(),
+                        "Wildcard has no effect",
+                    ));
+                }
+            } else if !sm_parsed
+                .composite_states
+                .contains_key(&transition.in_state.ident.to_string())
+            {
+                add_transition(transition, &mut states_events_mapping, state_data)?;
+            }
+        }
+
+        // Transitions of composite states apply to all of their sub-states, with the innermost
+        // composite states taking precedence
+        let mut composite_transitions: Vec<_> = sm
+            .transitions
+            .iter()
+            .filter(|t| {
+                sm_parsed
+                    .composite_states
+                    .contains_key(&t.in_state.ident.to_string())
+            })
+            .collect();
+        composite_transitions.sort_by_key(|t| {
+            core::cmp::Reverse(sm_parsed.ancestors(&t.in_state.ident.to_string()).len())
+        });
+
+        for transition in composite_transitions {
+            let composite = transition.in_state.ident.to_string();
+            let mut targets: Vec<_> = sm_parsed
+                .states
+                .iter()
+                .filter(|(name, _)| sm_parsed.is_descendant(name, &composite))
+                .filter(|(name, _)| !sm_parsed.terminal_states.contains_key(*name))
+                .filter(|(name, _)| {
+                    !transition.event.is_any() || !sm_parsed.parallel_states.contains_key(*name)
+                })
+                .map(|(_, state)| state)
+                .collect();
+            targets.sort_by_key(|state| state.to_string());
+
+            if !expand_transition(
+                transition,
+                &targets,
+                &mut states_events_mapping,
+                state_data,
+                true,
+            )? {
+                return Err(parse::Error::new(
+                    transition.in_state.ident.span(),
+                    format!(
+                        "Transition has no effect, all sub-states of {} already handle this event",
+                        composite
+                    ),
+                ));
+            }
+        }
+
+        sm_parsed.states_events_mapping = states_events_mapping;
+
+        // Parallel states are left once all of their regions are in a final sub-state
+        let mut completions = Vec::new();
+        for parallel in sm_parsed.parallel_states.values() {
+            if let Some(completion) = &parallel.completion {
+                let completion = sm_parsed.resolve_initial(completion);
+                if sm_parsed
+                    .state_data
+                    .data_types
+                    .contains_key(&completion.to_string())
+                {
+                    return Err(parse::Error::new(
+                        completion.span(),
+                        "This state has data associated, but is entered on completion of a parallel state.",
+                    ));
+                }
+
+                for region in &parallel.regions {
+                    if sm_parsed.final_states(region).is_empty() {
+                        return Err(parse::Error::new(
+                            region.ident.span(),
+                            format!(
+                                "Region {} has no final sub-state, which is a sub-state without transitions.",
+                                region.ident
+                            ),
+                        ));
+                    }
+                }
+                completions.push((parallel.ident.to_string(), completion));
+            }
+        }
+        for (parallel, completion) in completions {
+            sm_parsed
+                .parallel_states
+                .get_mut(&parallel)
+                .unwrap()
+                .completion = Some(completion);
+        }
+
+        // Deferrals of composite states and wildcards apply to all sub-states in their scope which
+        // neither handle nor defer the event themselves
+        let mut deferred_events = sm.deferred_events.clone();
+        deferred_events.sort_by_key(|deferred| {
+            let name = deferred.in_state.ident.to_string();
+            (
+                deferred.in_state.wildcard,
+                core::cmp::Reverse(sm_parsed.ancestors(&name).len()),
+            )
+        });
+        for deferred in deferred_events {
+            let name = deferred.in_state.ident.to_string();
+            let event_name = deferred.event.ident.to_string();
+            let targets: Vec<Ident> = if deferred.in_state.wildcard {
+                sm_parsed
+                    .states
+                    .iter()
+                    .filter(|(state, _)| match &deferred.parent {
+                        Some(parent) => sm_parsed.is_descendant(state, &parent.to_string()),
+                        None => true,
+                    })
+                    .filter(|(state, _)| !sm_parsed.terminal_states.contains_key(*state))
+                    .map(|(_, state)| state.clone())
+                    .collect()
+            } else if sm_parsed.composite_states.contains_key(&name) {
+                sm_parsed
+                    .states
+                    .iter()
+                    .filter(|(state, _)| sm_parsed.is_descendant(state, &name))
+                    .filter(|(state, _)| !sm_parsed.terminal_states.contains_key(*state))
+                    .map(|(_, state)| state.clone())
+                    .collect()
+            } else if sm_parsed.states.contains_key(&name) {
+                if sm_parsed.terminal_states.contains_key(&name) {
+                    return Err(parse::Error::new(
+                        deferred.in_state.ident.span(),
+                        "Final states cannot defer events.",
+                    ));
+                }
+                if sm_parsed.states_events_mapping[&name].contains_key(&event_name) {
+                    return Err(parse::Error::new(
+                        deferred.event.ident.span(),
+                        format!("State {} both handles and defers {}.", name, event_name),
+                    ));
+                }
+                vec![deferred.in_state.ident.clone()]
+            } else if sm_parsed.region_states.contains_key(&name) {
+                return Err(parse::Error::new(
+                    deferred.in_state.ident.span(),
+                    "Events cannot be deferred in a region.",
+                ));
+            } else {
+                return Err(parse::Error::new(
+                    deferred.in_state.ident.span(),
+                    format!("Unknown state {}.", name),
+                ));
+            };
+
+            let mut deferred_added = false;
+            for target in targets {
+                let target_name = target.to_string();
+                let handled =
+                    sm_parsed.states_events_mapping[&target_name].contains_key(&event_name);
+                let events = sm_parsed.deferred_events.entry(target_name).or_default();
+                if !handled && !events.contains(&deferred.event.ident) {
+                    events.push(deferred.event.ident.clone());
+                    deferred_added = true;
+                }
+            }
+            if !deferred_added {
+                return Err(parse::Error::new(
+                    deferred.in_state.ident.span(),
+                    "Deferral has no effect",
+                ));
+            }
+        }
+        sm_parsed
+            .deferred_events
+            .retain(|_, events| !events.is_empty());
+
+        // Delegated events are processed by the sub-machine held as the data of a state
+        for delegated in sm.delegated_events {
+            let name = delegated.in_state.ident.to_string();
+            let event_name = delegated.event.ident.to_string();
+            if delegated.in_state.wildcard
+                || !sm_parsed.states.contains_key(&name)
+                || sm_parsed.parallel_states.contains_key(&name)
+            {
+                return Err(parse::Error::new(
+                    delegated.in_state.ident.span(),
+                    "Events can only be delegated in states which are not composite states, wildcards or sub-states of regions.",
+                ));
+            }
+            if !sm_parsed.state_data.data_types.contains_key(&name) {
+                return Err(parse::Error::new(
+                    delegated.in_state.ident.span(),
+                    "Events are delegated to the data of the state, so it needs a sub-machine as data.",
+                ));
+            }
+            if sm_parsed.states_events_mapping[&name].contains_key(&event_name) {
+                return Err(parse::Error::new(
+                    delegated.event.ident.span(),
+                    format!("State {} both handles and delegates {}.", name, event_name),
+                ));
+            }
+            if sm_parsed
+                .deferred_events
+                .get(&name)
+                .is_some_and(|events| events.contains(&delegated.event.ident))
+            {
+                return Err(parse::Error::new(
+                    delegated.event.ident.span(),
+                    format!("State {} both defers and delegates {}.", name, event_name),
+                ));
+            }
+            let events = sm_parsed.sub_machines.entry(name).or_default();
+            if !events.contains(&delegated.event.ident) {
+                events.push(delegated.event.ident);
+            }
+        }
+
+        // Entry and exit handlers can be declared for any known state
+        for handlers in sm.state_handlers {
+            let name = handlers.state.to_string();
+            if !sm_parsed.states.contains_key(&name)
+                && !sm_parsed.region_states.contains_key(&name)
+                && !sm_parsed.composite_states.contains_key(&name)
+            {
+                return Err(parse::Error::new(
+                    handlers.state.span(),
+                    format!("Unknown state {}.", name),
+                ));
+            }
+            if let Some(activity) = &handlers.activity {
+                if sm_parsed.composite_states.contains_key(&name)
+                    || sm_parsed.parallel_states.contains_key(&name)
+                    || sm_parsed.region_states.contains_key(&name)
+                {
+                    return Err(parse::Error::new(
+                        activity.ident.span(),
+                        "Do-activities can only be declared for states which are not composite states or sub-states of regions.",
+                    ));
+                }
+                if !sm_parsed.event_data.all_lifetimes.is_empty() {
+                    return Err(parse::Error::new(
+                        activity.ident.span(),
+                        "Do-activities cannot be declared when the data of events has lifetimes.",
+                    ));
+                }
+            }
+            if let Some(display) = &handlers.display {
+                if sm_parsed.display.is_none() {
+                    return Err(parse::Error::new(
+                        display.span(),
+                        "States can only be displayed with their own names when `display` is set.",
+                    ));
+                }
+                if sm_parsed.composite_states.contains_key(&name)
+                    || sm_parsed.region_states.contains_key(&name)
+                {
+                    return Err(parse::Error::new(
+                        display.span(),
+                        "Only states which are not composite states or sub-states of regions can be displayed with their own names.",
+                    ));
+                }
+            }
+            if let Some(watchdog) = &handlers.watchdog {
+                if sm_parsed.composite_states.contains_key(&name)
+                    || sm_parsed.parallel_states.contains_key(&name)
+                    || sm_parsed.region_states.contains_key(&name)
+                {
+                    return Err(parse::Error::new(
+                        watchdog.event.span(),
+                        "Timeouts can only be declared for states which are not composite states or sub-states of regions.",
+                    ));
+                }
+                let event = watchdog.event.to_string();
+                if !sm_parsed.events.contains_key(&event) {
+                    return Err(parse::Error::new(
+                        watchdog.event.span(),
+                        format!("Unknown event {}.", event),
+                    ));
+                }
+                if sm_parsed.event_data.data_types.contains_key(&event) {
+                    return Err(parse::Error::new(
+                        watchdog.event.span(),
+                        "The event of a timeout cannot have data.",
+                    ));
+                }
+            }
+            if sm_parsed.state_handlers.contains_key(&name) {
+                return Err(parse::Error::new(
+                    handlers.state.span(),
+                    format!("The handlers of {} are defined more than once.", name),
+                ));
+            }
+            if let Some(allocate) = &handlers.allocate {
+                if handlers.boxed.is_none() {
+                    return Err(parse::Error::new(
+                        allocate.span(),
+                        "Only states declared with `boxed` can allocate their data.",
+                    ));
+                }
+            }
+            if let Some(pointer) = &handlers.boxed {
+                let data_type = match sm_parsed.state_data.data_types.get(&name) {
+                    None => {
+                        return Err(parse::Error::new(
+                            handlers.state.span(),
+                            format!("State {} has no data to box.", name),
+                        ))
+                    }
+                    Some(Type::Reference(_)) => {
+                        return Err(parse::Error::new(
+                            handlers.state.span(),
+                            "The data of a boxed state cannot be a reference.",
+                        ))
+                    }
+                    Some(data_type) => data_type.clone(),
+                };
+                sm_parsed
+                    .state_data
+                    .data_types
+                    .insert(name.clone(), syn::parse_quote! { #pointer<#data_type> });
+                sm_parsed.boxed_states.insert(name.clone(), data_type);
+            }
+            sm_parsed.state_handlers.insert(name, handlers);
+        }
+
+        // Priorities of events order the event queue, so they need one
+        for options in sm.event_options {
+            let name = options.event.to_string();
+            if !sm_parsed.events.contains_key(&name) {
+                return Err(parse::Error::new(
+                    options.event.span(),
+                    format!("Unknown event {}.", name),
+                ));
+            }
+            if options.allow_unused {
+                sm_parsed.unused_events.insert(name.clone());
+            }
+            if let (Some(attr), Some(_)) = (options.attrs.first(), &sm_parsed.events_type) {
+                return Err(parse::Error::new(
+                    attr.span(),
+                    "The attributes of an `events_type` are given where it is defined.",
+                ));
+            }
+            if !options.attrs.is_empty() {
+                sm_parsed
+                    .event_attrs
+                    .entry(name.clone())
+                    .or_default()
+                    .extend(options.attrs);
+            }
+            if let Some(generator) = options.arbitrary {
+                if !sm_parsed.arbitrary {
+                    return Err(parse::Error::new(
+                        generator.span(),
+                        "Generating the data of events requires `arbitrary: true`.",
+                    ));
+                }
+                if sm_parsed.events_type.is_some() {
+                    return Err(parse::Error::new(
+                        generator.span(),
+                        "The data of an `events_type` is generated by its own \
+                        `arbitrary::Arbitrary`.",
+                    ));
+                }
+                if !sm_parsed.event_data.data_types.contains_key(&name) {
+                    return Err(parse::Error::new(
+                        generator.span(),
+                        format!("Event {} has no data to generate.", name),
+                    ));
+                }
+                sm_parsed.event_generators.insert(name.clone(), generator);
+            }
+            if let Some(priority) = options.priority {
+                if sm_parsed.queue_capacity.is_none() {
+                    return Err(parse::Error::new(
+                        priority.span(),
+                        "Events can only have priorities when `queue_capacity` is set.",
+                    ));
+                }
+                if sm_parsed
+                    .event_priorities
+                    .insert(name, priority.base10_parse()?)
+                    .is_some()
+                {
+                    return Err(parse::Error::new(
+                        options.event.span(),
+                        format!(
+                            "The priority of {} is defined more than once.",
+                            options.event
+                        ),
+                    ));
+                }
+            }
+        }
+
+        // The states are identified by a `u8`
+        let state_ids = sm_parsed
+            .states
+            .keys()
+            .filter(|name| {
+                !sm_parsed.composite_states.contains_key(*name)
+                    && !sm_parsed.region_parents.contains_key(*name)
+            })
+            .count();
+        if state_ids > u8::MAX as usize + 1 {
+            return Err(parse::Error::new(
+                sm_parsed.starting_state.span(),
+                format!(
+                    "The state machine has {} states, while at most 256 states can be identified by a `u8`.",
+                    state_ids
+                ),
+            ));
+        }
+
+        Ok(sm_parsed)
     }
 }
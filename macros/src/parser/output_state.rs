@@ -1,17 +1,75 @@
+use super::choice::{Choice, CHOICE};
 use proc_macro2::Span;
 use syn::{parenthesized, parse, spanned::Spanned, token, Ident, Token, Type};
 
+/// The target of transitions which consume the event without changing the state, declared as
+/// `State + Event = ignore`.
+pub const IGNORE: &str = "ignore";
+
 #[derive(Debug, Clone)]
 pub struct OutputState {
     pub ident: Ident,
     pub internal_transition: bool,
+    /// The target state is omitted, so the state machine stays in the current state without
+    /// exiting it and keeps its data.
+    pub keep_state: bool,
     pub data_type: Option<Type>,
+    /// The target is a final state, declared as `State @final`.
+    pub final_state: bool,
+    /// The target is a choice pseudo-state branching to one of several states.
+    pub choice: Option<Choice>,
+    /// The composite state targeted by the transition, before it is resolved to the sub-state
+    /// entered.
+    pub composite: Option<Ident>,
+}
+
+impl OutputState {
+    /// Returns the states this transition can go to, which are the targets of the branches for
+    /// a choice.
+    pub fn targets(&self) -> Vec<&OutputState> {
+        match &self.choice {
+            Some(choice) => choice.branches.iter().map(|b| &b.out_state).collect(),
+            None => vec![self],
+        }
+    }
 }
 
 impl parse::Parse for OutputState {
     fn parse(input: parse::ParseStream) -> syn::Result<Self> {
         if input.peek(Token![=]) {
             input.parse::<Token![=]>()?;
+
+            // Choice pseudo-state
+            if input.peek(Ident)
+                && (input.peek2(token::Paren) || input.peek2(token::Brace))
+                && input.fork().parse::<Ident>()? == CHOICE
+            {
+                let choice: Choice = input.parse()?;
+                return Ok(Self {
+                    ident: choice.ident.clone(),
+                    internal_transition: false,
+                    keep_state: false,
+                    data_type: None,
+                    final_state: false,
+                    choice: Some(choice),
+                    composite: None,
+                });
+            }
+
+            // Ignored events keep the state like internal transitions without a target
+            if input.peek(Ident) && input.fork().parse::<Ident>()? == IGNORE {
+                let ignore: Ident = input.parse()?;
+                return Ok(Self {
+                    ident: Ident::new("_", ignore.span()),
+                    internal_transition: true,
+                    keep_state: true,
+                    data_type: None,
+                    final_state: false,
+                    choice: None,
+                    composite: None,
+                });
+            }
+
             let (internal_transition, ident) = if input.peek(Token![_]) {
                 // Underscore ident here is used to represent an internal transition
                 let underscore = input.parse::<Token![_]>()?;
@@ -47,17 +105,34 @@ impl parse::Parse for OutputState {
                 None
             };
 
+            // Possible marker of a final state
+            let final_state = if !internal_transition && input.peek(Token![@]) {
+                input.parse::<Token![@]>()?;
+                input.parse::<Token![final]>()?;
+                true
+            } else {
+                false
+            };
+
             Ok(Self {
                 ident,
                 internal_transition,
+                keep_state: false,
                 data_type,
+                final_state,
+                choice: None,
+                composite: None,
             })
         } else {
             // Internal transition
             Ok(Self {
                 ident: Ident::new("_", Span::call_site()),
                 internal_transition: true,
+                keep_state: true,
                 data_type: None,
+                final_state: false,
+                choice: None,
+                composite: None,
             })
         }
     }
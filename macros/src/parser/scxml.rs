@@ -0,0 +1,559 @@
+//! Translates SCXML documents, given with `from_scxml: "machine.scxml"`, into the transitions and
+//! state handlers of the DSL.
+
+use super::state_handlers::StateHandlers;
+use super::state_machine::StateMachine;
+use roxmltree::Node;
+use std::collections::{HashMap, HashSet};
+use syn::{parse, parse::Parser, punctuated::Punctuated, LitStr, Token};
+
+/// The transitions and state handlers of an SCXML document, in the syntax of the DSL.
+#[derive(Debug, PartialEq)]
+pub struct Translation {
+    /// The `name` of the `<scxml>` element, used if the state machine has no name.
+    pub name: Option<String>,
+    pub transitions: String,
+    pub states: String,
+}
+
+impl StateMachine {
+    /// Adds the transitions and state handlers of the SCXML document at `path`, relative to the
+    /// manifest directory of the crate.
+    pub fn import_scxml(&mut self, path: &LitStr) -> parse::Result<()> {
+        let directory = std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default();
+        let file = std::path::Path::new(&directory).join(path.value());
+        let error = |message: String| parse::Error::new(path.span(), message);
+
+        let document = std::fs::read_to_string(&file)
+            .map_err(|e| error(format!("Failed to read {}: {}", file.display(), e)))?;
+        let translation =
+            translate(&document).map_err(|e| error(format!("{}: {}", file.display(), e)))?;
+
+        let transitions: proc_macro2::TokenStream = translation
+            .transitions
+            .parse()
+            .map_err(|e| error(format!("{}: {}", file.display(), e)))?;
+        (|input: parse::ParseStream| self.parse_transitions(input, None))
+            .parse2(transitions)
+            .map_err(|e| error(format!("{}: {}", file.display(), e)))?;
+
+        let states: proc_macro2::TokenStream = translation
+            .states
+            .parse()
+            .map_err(|e| error(format!("{}: {}", file.display(), e)))?;
+        let handlers = Punctuated::<StateHandlers, Token![,]>::parse_terminated
+            .parse2(states)
+            .map_err(|e| error(format!("{}: {}", file.display(), e)))?;
+        self.state_handlers.extend(handlers);
+
+        if self.name.is_none() {
+            if let Some(name) = translation.name {
+                self.name = Some(syn::Ident::new(&identifier(&name), path.span()));
+            }
+        }
+        self.scxml_path = Some(file.display().to_string());
+        Ok(())
+    }
+}
+
+/// Translates an SCXML document into the syntax of the DSL.
+///
+/// States and events are named in UpperCamelCase, guards are translated from their `cond` and
+/// actions and entry and exit handlers from `<script>` elements calling a function. Delayed events
+/// sent when a state is entered become timed transitions if the state has a transition for the
+/// event, and timeouts otherwise. Other executable content and the data model are ignored.
+pub fn translate(document: &str) -> Result<Translation, String> {
+    let document = roxmltree::Document::parse(document).map_err(|e| e.to_string())?;
+    let root = document.root_element();
+    if root.tag_name().name() != "scxml" {
+        return Err(format!(
+            "The root element is <{}> instead of <scxml>.",
+            root.tag_name().name()
+        ));
+    }
+
+    // Transitions to a history pseudo-state resume the history of its composite state
+    let mut histories = HashMap::new();
+    let mut finals = HashSet::new();
+    for node in root.descendants().filter(Node::is_element) {
+        let parent = node
+            .parent_element()
+            .and_then(|parent| parent.attribute("id"));
+        match (node.tag_name().name(), node.attribute("id"), parent) {
+            ("history", Some(id), Some(parent)) => {
+                histories.insert(id, parent);
+            }
+            ("final", Some(id), _) => {
+                finals.insert(id);
+            }
+            _ => (),
+        }
+    }
+
+    let translator = Translator { histories, finals };
+    let mut transitions = Vec::new();
+    let mut states = Vec::new();
+    translator.scope(root, false, &mut transitions, &mut states)?;
+    Ok(Translation {
+        name: root.attribute("name").map(str::to_string),
+        transitions: transitions.join(",\n"),
+        states: states.join(",\n"),
+    })
+}
+
+struct Translator<'a> {
+    histories: HashMap<&'a str, &'a str>,
+    finals: HashSet<&'a str>,
+}
+
+impl<'a> Translator<'a> {
+    /// Translates the states nested in `scope`, a state, region or the document itself, where
+    /// `region` is set for the regions of parallel states.
+    fn scope(
+        &self,
+        scope: Node<'a, '_>,
+        region: bool,
+        transitions: &mut Vec<String>,
+        states: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let initial = initial(scope)?;
+        for node in children(scope) {
+            let id = element_id(node)?;
+            let start = if Some(id) == initial { "*" } else { "" };
+            match node.tag_name().name() {
+                "state" if children(node).next().is_some() => {
+                    let mut block = Vec::new();
+                    self.scope(node, false, &mut block, states)?;
+                    let history = node
+                        .children()
+                        .find(|child| child.has_tag_name("history"))
+                        .map(|history| match history.attribute("type") {
+                            Some("deep") => "(H*)",
+                            _ => "(H)",
+                        })
+                        .unwrap_or_default();
+                    transitions.push(format!(
+                        "{start}{}{history} {{\n{}\n}}",
+                        identifier(id),
+                        block.join(",\n")
+                    ));
+                    self.handlers(node, states)?;
+                    self.transitions(node, "", region, transitions)?;
+                }
+                "state" => {
+                    self.handlers(node, states)?;
+                    let before = transitions.len();
+                    self.transitions(node, start, region, transitions)?;
+                    if !start.is_empty() && transitions.len() == before {
+                        return Err(format!(
+                            "The initial state {} has no transitions, so it cannot be declared.",
+                            id
+                        ));
+                    }
+                }
+                "parallel" => {
+                    let mut regions = Vec::new();
+                    for child in children(node) {
+                        let mut block = Vec::new();
+                        self.scope(child, true, &mut block, states)?;
+                        regions.push(format!(
+                            "{} {{\n{}\n}}",
+                            identifier(element_id(child)?),
+                            block.join(",\n")
+                        ));
+                    }
+                    // Parallel states are left once all of their regions are done
+                    let done = format!("done.state.{id}");
+                    let completion = node
+                        .children()
+                        .filter(|child| child.has_tag_name("transition"))
+                        .find(|transition| transition.attribute("event") == Some(done.as_str()))
+                        .map(|transition| self.target(transition, false))
+                        .transpose()?
+                        .flatten()
+                        .unwrap_or_default();
+                    transitions.push(format!(
+                        "{start}{} [\n{}\n]{completion}",
+                        identifier(id),
+                        regions.join(",\n")
+                    ));
+                    self.handlers(node, states)?;
+                    self.transitions(node, "", region, transitions)?;
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Translates the transitions of a state, where the first transition of the initial state
+    /// is marked with `start`.
+    fn transitions(
+        &self,
+        state: Node<'a, '_>,
+        start: &str,
+        region: bool,
+        transitions: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let id = element_id(state)?;
+        let delay = delayed_events(state)?;
+        let done = format!("done.state.{id}");
+        let mut start = start;
+        for transition in state.children().filter(|c| c.has_tag_name("transition")) {
+            let events: Vec<_> = transition
+                .attribute("event")
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect();
+            if state.has_tag_name("parallel") && events == [done.as_str()] {
+                continue;
+            }
+            let event = match events[..] {
+                [] => String::new(),
+                ["*"] => " + _".to_string(),
+                [event] => match delay.iter().find(|(delayed, _)| *delayed == event) {
+                    Some((_, duration)) => format!(" + after({duration})"),
+                    None => format!(" + {}", identifier(event)),
+                },
+                _ => {
+                    let events: Vec<_> = events.iter().map(|event| identifier(event)).collect();
+                    format!(" + ({})", events.join(" | "))
+                }
+            };
+            let guard = match transition.attribute("cond") {
+                Some(cond) => format!(" [ {} ]", guard(cond)),
+                None => String::new(),
+            };
+            let actions = transition
+                .children()
+                .filter(|child| child.has_tag_name("script"))
+                .map(script)
+                .collect::<Result<Vec<_>, _>>()?;
+            let actions = if actions.is_empty() {
+                String::new()
+            } else {
+                format!(" / {}", actions.join(", "))
+            };
+            let target = self.target(transition, region)?.unwrap_or_default();
+            transitions.push(format!(
+                "{start}{}{event}{guard}{actions}{target}",
+                identifier(id)
+            ));
+            start = "";
+        }
+        Ok(())
+    }
+
+    /// The target of a transition as ` = Target`, or `None` for transitions without a target
+    /// which keep the state.
+    fn target(&self, transition: Node, region: bool) -> Result<Option<String>, String> {
+        let target = match transition.attribute("target") {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        let mut targets = target.split_whitespace();
+        let target = targets.next().unwrap_or_default();
+        if targets.next().is_some() {
+            return Err(format!(
+                "The transition to {} has several targets, which cannot be translated.",
+                target
+            ));
+        }
+        let target = self.histories.get(target).copied().unwrap_or(target);
+        // The final sub-states of regions are those without transitions
+        let terminal = if self.finals.contains(target) && !region {
+            " @final"
+        } else {
+            ""
+        };
+        Ok(Some(format!(" = {}{terminal}", identifier(target))))
+    }
+
+    /// Translates the entry and exit handlers and the timeouts of a state.
+    fn handlers(&self, state: Node<'a, '_>, states: &mut Vec<String>) -> Result<(), String> {
+        let id = element_id(state)?;
+        let mut handlers = Vec::new();
+        for (element, handler) in [("onentry", "entry"), ("onexit", "exit")] {
+            let scripts = state
+                .children()
+                .filter(|child| child.has_tag_name(element))
+                .flat_map(|child| child.children())
+                .filter(|child| child.has_tag_name("script"))
+                .map(script)
+                .collect::<Result<Vec<_>, _>>()?;
+            match &scripts[..] {
+                [] => (),
+                [script] => handlers.push(format!("{handler}: {script}")),
+                _ => {
+                    return Err(format!(
+                        "The {} of {} has several scripts, while states have a single {} handler.",
+                        element, id, handler
+                    ))
+                }
+            }
+        }
+
+        // Delayed events without a transition of the state are raised as timeouts
+        let events: HashSet<_> = state
+            .children()
+            .filter(|child| child.has_tag_name("transition"))
+            .filter_map(|transition| transition.attribute("event"))
+            .flat_map(str::split_whitespace)
+            .collect();
+        let timeouts: Vec<_> = delayed_events(state)?
+            .into_iter()
+            .filter(|(event, _)| !events.contains(event))
+            .collect();
+        match &timeouts[..] {
+            [] => (),
+            [(event, duration)] => {
+                handlers.push(format!("timeout: {duration} => {}", identifier(event)))
+            }
+            _ => {
+                return Err(format!(
+                    "{} sends several delayed events, while states have a single timeout.",
+                    id
+                ))
+            }
+        }
+
+        if !handlers.is_empty() {
+            states.push(format!("{} {{ {} }}", identifier(id), handlers.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+/// The state, parallel state and final state elements nested directly in `scope`.
+fn children<'a, 'input>(scope: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    scope.children().filter(|child| {
+        child.has_tag_name("state") || child.has_tag_name("parallel") || child.has_tag_name("final")
+    })
+}
+
+/// The initial state of a scope, given by its `initial` attribute or `<initial>` element, or its
+/// first state otherwise.
+fn initial<'a>(scope: Node<'a, '_>) -> Result<Option<&'a str>, String> {
+    if let Some(initial) = scope.attribute("initial") {
+        return Ok(Some(initial));
+    }
+    if let Some(initial) = scope.children().find(|child| child.has_tag_name("initial")) {
+        return Ok(initial
+            .children()
+            .find(|child| child.has_tag_name("transition"))
+            .and_then(|transition| transition.attribute("target")));
+    }
+    children(scope).next().map(element_id).transpose()
+}
+
+fn element_id<'a>(node: Node<'a, '_>) -> Result<&'a str, String> {
+    node.attribute("id").ok_or_else(|| {
+        format!(
+            "The <{}> at byte {} has no id, which is required to translate it.",
+            node.tag_name().name(),
+            node.range().start
+        )
+    })
+}
+
+/// The events sent with a delay when a state is entered, with the duration of their delay.
+fn delayed_events<'a>(state: Node<'a, '_>) -> Result<Vec<(&'a str, String)>, String> {
+    let mut events = Vec::new();
+    let sends = state
+        .children()
+        .filter(|child| child.has_tag_name("onentry"))
+        .flat_map(|child| child.children())
+        .filter(|child| child.has_tag_name("send"));
+    for send in sends {
+        let event = match send.attribute("event") {
+            Some(event) => event,
+            None => continue,
+        };
+        let duration = match (send.attribute("delay"), send.attribute("delayexpr")) {
+            (Some(delay), _) => duration(delay)?,
+            (None, Some(expression)) => expression.to_string(),
+            (None, None) => continue,
+        };
+        events.push((event, duration));
+    }
+    Ok(events)
+}
+
+/// Translates a delay such as `2s` or `500ms` into a `core::time::Duration`.
+fn duration(delay: &str) -> Result<String, String> {
+    let (value, scale) = if let Some(value) = delay.strip_suffix("ms") {
+        (value, 1.0)
+    } else if let Some(value) = delay.strip_suffix('s') {
+        (value, 1000.0)
+    } else {
+        return Err(format!("The delay {} is not given in s or ms.", delay));
+    };
+    let value: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("The delay {} is not a number of s or ms.", delay))?;
+    let millis = value * scale;
+    if millis % 1000.0 == 0.0 {
+        Ok(format!(
+            "core::time::Duration::from_secs({})",
+            millis as u64 / 1000
+        ))
+    } else {
+        Ok(format!(
+            "core::time::Duration::from_millis({})",
+            millis as u64
+        ))
+    }
+}
+
+/// Translates a script calling a function, as `action()` or `action().await`, into an action.
+fn script(script: Node) -> Result<String, String> {
+    let text = script
+        .text()
+        .unwrap_or_default()
+        .trim()
+        .trim_end_matches(';');
+    let call = |text: &str| {
+        let name = text.trim().strip_suffix("()")?;
+        syn::parse_str::<syn::Ident>(name)
+            .ok()
+            .map(|_| name.to_string())
+    };
+    match text.strip_suffix(".await") {
+        Some(text) => call(text).map(|name| format!("async {name}")),
+        None => call(text),
+    }
+    .ok_or_else(|| {
+        format!(
+            "The script `{}` cannot be translated, only calls of functions such as `action()` are supported.",
+            text
+        )
+    })
+}
+
+/// Translates a condition into a guard expression, where calls of guards such as `guard()` or
+/// `guard().await` become `guard` and `async guard` respectively.
+fn guard(cond: &str) -> String {
+    let mut guard = String::new();
+    let mut rest = cond;
+    while let Some(start) = rest.find(|c: char| c.is_alphabetic() || c == '_') {
+        guard.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let (name, after) = rest.split_at(end);
+        match after.strip_prefix("()") {
+            Some(after) => match after.strip_prefix(".await") {
+                Some(after) => {
+                    guard.push_str(&format!("async {name}"));
+                    rest = after;
+                }
+                None => {
+                    guard.push_str(name);
+                    rest = after;
+                }
+            },
+            None => {
+                guard.push_str(name);
+                rest = after;
+            }
+        }
+    }
+    guard.push_str(rest);
+    guard
+}
+
+/// Names a state or event in UpperCamelCase, from an id such as `power.on` or `power_on`.
+fn identifier(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::translate;
+
+    #[test]
+    fn scxml_translation() {
+        let translation = translate(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <scxml xmlns="http://www.w3.org/2005/07/scxml" version="1.0" name="Device" initial="Off">
+                <state id="Off">
+                    <onentry>
+                        <send event="after.Off" delay="2s"/>
+                    </onentry>
+                    <transition event="power.on" target="On_history"/>
+                    <transition event="after.Off" target="Sleeping"/>
+                </state>
+                <state id="Sleeping">
+                    <transition event="power.on" target="On"/>
+                </state>
+                <state id="On" initial="Idle">
+                    <history id="On_history" type="shallow"/>
+                    <transition event="power.off" target="Off"/>
+                    <state id="Idle">
+                        <onentry>
+                            <script>enter_idle()</script>
+                            <send event="sleep" delay="500ms"/>
+                        </onentry>
+                        <transition event="start" cond="ready() &amp;&amp; !busy().await" target="Busy">
+                            <script>start()</script>
+                        </transition>
+                    </state>
+                    <state id="Busy">
+                        <transition event="tick">
+                            <script>count()</script>
+                        </transition>
+                        <transition event="fail" target="Broken"/>
+                    </state>
+                </state>
+                <final id="Broken"/>
+            </scxml>"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            translation,
+            super::Translation {
+                name: Some("Device".to_string()),
+                transitions: "*Off + PowerOn = On,
+Off + after(core::time::Duration::from_secs(2)) = Sleeping,
+Sleeping + PowerOn = On,
+On(H) {
+*Idle + Start [ ready && !async busy ] / start = Busy,
+Busy + Tick / count,
+Busy + Fail = Broken @final
+},
+On + PowerOff = Off"
+                    .to_string(),
+                states: "Idle { entry: enter_idle, timeout: core::time::Duration::from_millis(500) => Sleep }"
+                    .to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn scxml_errors() {
+        assert!(translate("<statechart/>").is_err());
+        assert!(translate(
+            r#"<scxml initial="A">
+                <state id="A">
+                    <transition event="go" target="B">
+                        <script>x = 1</script>
+                    </transition>
+                </state>
+                <state id="B"/>
+            </scxml>"#
+        )
+        .unwrap_err()
+        .contains("`x = 1`"));
+    }
+}
@@ -0,0 +1,187 @@
+use super::AsyncIdent;
+use syn::ext::IdentExt;
+use syn::{braced, parse, token, Attribute, Ident, Token};
+
+/// The entry and exit handlers, the do-activity, the watchdog, the boxing of the data, the
+/// attributes of the variant and the displayed name of a state, declared in the `states` block as
+/// `State { entry: handler, exit: handler, do: async activity }`.
+#[derive(Debug, Clone)]
+pub struct StateHandlers {
+    pub state: Ident,
+    /// Called whenever the state is entered.
+    pub entry: Option<AsyncIdent>,
+    /// Called whenever the state is exited.
+    pub exit: Option<AsyncIdent>,
+    /// Run while the state is active, and cancelled when it is exited.
+    pub activity: Option<AsyncIdent>,
+    /// The event processed when the state is active for longer than the duration, declared with
+    /// `timeout: duration => Event`.
+    pub watchdog: Option<Watchdog>,
+    /// The pointer type the data of the state is stored behind, declared with `boxed: Box`.
+    pub boxed: Option<syn::Path>,
+    /// The method of the context allocating the pointer to the data, declared with
+    /// `allocate: method` instead of converting the data with `From`.
+    pub allocate: Option<Ident>,
+    /// The attributes of the variant of the state, declared with `attrs: #[attribute]`.
+    pub attrs: Vec<Attribute>,
+    /// The name the state is displayed with, declared with `display: "name"`.
+    pub display: Option<syn::LitStr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    pub duration: syn::Expr,
+    pub event: Ident,
+}
+
+impl parse::Parse for StateHandlers {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let state: Ident = input.parse()?;
+
+        let content;
+        braced!(content in input);
+
+        let mut entry = None;
+        let mut exit = None;
+        let mut activity = None;
+        let mut watchdog = None;
+        let mut boxed = None;
+        let mut allocate = None;
+        let mut attrs = Vec::new();
+        let mut display = None;
+        loop {
+            if content.is_empty() {
+                break;
+            }
+
+            // `do` is a keyword
+            let keyword = Ident::parse_any(&content)?;
+            content.parse::<Token![:]>()?;
+
+            if keyword == "timeout" {
+                if watchdog.is_some() {
+                    return Err(parse::Error::new(
+                        keyword.span(),
+                        format!("The timeout of {} is defined more than once.", state),
+                    ));
+                }
+                let duration = content.parse()?;
+                content.parse::<Token![=>]>()?;
+                watchdog = Some(Watchdog {
+                    duration,
+                    event: content.parse()?,
+                });
+                if content.is_empty() || content.parse::<Token![,]>().is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            if keyword == "attrs" {
+                attrs.extend(Attribute::parse_outer(&content)?);
+                if content.is_empty() || content.parse::<Token![,]>().is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            if keyword == "display" {
+                if display.is_some() {
+                    return Err(parse::Error::new(
+                        keyword.span(),
+                        format!("The displayed name of {} is defined more than once.", state),
+                    ));
+                }
+                display = Some(content.parse()?);
+                if content.is_empty() || content.parse::<Token![,]>().is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            if keyword == "boxed" || keyword == "allocate" {
+                if (keyword == "boxed" && boxed.is_some())
+                    || (keyword == "allocate" && allocate.is_some())
+                {
+                    return Err(parse::Error::new(
+                        keyword.span(),
+                        format!(
+                            "The {} option of {} is defined more than once.",
+                            keyword, state
+                        ),
+                    ));
+                }
+                if keyword == "boxed" {
+                    boxed = Some(content.parse()?);
+                } else {
+                    allocate = Some(content.parse()?);
+                }
+                if content.is_empty() || content.parse::<Token![,]>().is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            let is_async = content.parse::<token::Async>().is_ok();
+            let handler = Some(AsyncIdent {
+                ident: content.parse()?,
+                is_async,
+            });
+
+            let slot = match keyword.to_string().as_str() {
+                "entry" => &mut entry,
+                "exit" => &mut exit,
+                "do" => &mut activity,
+                _ => {
+                    return Err(parse::Error::new(
+                        keyword.span(),
+                        format!(
+                            "Unknown keyword {}. Supported keywords: [\"entry\", \"exit\", \"do\", \"timeout\", \"boxed\", \"allocate\", \"attrs\", \"display\"]",
+                            keyword
+                        ),
+                    ))
+                }
+            };
+            if slot.is_some() {
+                return Err(parse::Error::new(
+                    keyword.span(),
+                    format!(
+                        "The {} handler of {} is defined more than once.",
+                        keyword, state
+                    ),
+                ));
+            }
+            *slot = handler;
+
+            // No comma at end of line, no more handlers
+            if content.is_empty() {
+                break;
+            }
+
+            if content.parse::<Token![,]>().is_err() {
+                break;
+            };
+        }
+
+        if let Some(activity) = &activity {
+            if !activity.is_async {
+                return Err(parse::Error::new(
+                    activity.ident.span(),
+                    "Do-activities need to be async, e.g. `do: async activity`.",
+                ));
+            }
+        }
+
+        Ok(Self {
+            state,
+            entry,
+            exit,
+            activity,
+            watchdog,
+            boxed,
+            allocate,
+            attrs,
+            display,
+        })
+    }
+}
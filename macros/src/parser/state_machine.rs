@@ -1,14 +1,136 @@
-use super::transition::{StateTransition, StateTransitions};
-use syn::{braced, parse, spanned::Spanned, token, Attribute, Ident, Token, Type};
+use super::assertion::Assertion;
+use super::composite_state::{CompositeState, History, ParallelState};
+use super::context_access::ContextAccess;
+use super::event_options::EventOptions;
+use super::lints::{Level, Lints};
+use super::state_handlers::StateHandlers;
+use super::transition::{
+    DeferredEvent, DelegatedEvent, ExpressionGuard, GuardExpression, InlineAction, StateTransition,
+    StateTransitions,
+};
+use super::type_names::TypeNames;
+use crate::parser::AsyncIdent;
+use syn::{
+    braced, bracketed, parenthesized, parse, spanned::Spanned, token, Attribute, Ident, Token, Type,
+};
+
+/// How the states and events are displayed, declared as `display: names` or `display: data`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayMode {
+    /// The states and events are displayed by their names.
+    Names,
+    /// The states and events with data are displayed along with the `Debug` of their data.
+    Data,
+}
+
+impl parse::Parse for DisplayMode {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let mode: Ident = input.parse()?;
+        match mode.to_string().as_str() {
+            "names" => Ok(DisplayMode::Names),
+            "data" => Ok(DisplayMode::Data),
+            _ => Err(parse::Error::new(
+                mode.span(),
+                "Expected one of `names` or `data`.",
+            )),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct StateMachine {
     pub temporary_context_type: Option<Type>,
     pub custom_error: bool,
+    /// `custom_guard_error: true`, where the errors of guards are returned along with the guard.
+    pub custom_guard_error: bool,
+    /// `process_event` returns a `TransitionOutcome` instead of the new state.
+    pub transition_outcome: bool,
+    /// `serde: true`, which derives `Serialize` and `Deserialize` for the states.
+    pub serde: Option<syn::LitBool>,
+    /// The version of the persisted states, which are migrated from older versions.
+    pub schema_version: Option<syn::LitInt>,
+    /// `event_log: true`, which appends the accepted events to the `EventLog` of the context.
+    pub event_log: Option<syn::LitBool>,
+    /// `metrics: true`, which reports transitions and rejected guards to the `Metrics` of the
+    /// context.
+    pub metrics: bool,
+    /// `statistics: true`, which tracks the time spent in every state with the clock.
+    pub statistics: bool,
+    /// `transition_coverage: true`, which records the transitions which have been taken.
+    pub transition_coverage: bool,
+    /// `table_dispatch: true`, which dispatches events through a table of their handlers.
+    pub table_dispatch: bool,
+    /// `hash_dispatch: true`, which looks up the handlers of events in a perfect hash table.
+    pub hash_dispatch: Option<syn::LitBool>,
+    /// `publish_state_id: true`, which keeps the identifier of the current state next to the
+    /// states and publishes it to the context on every transition.
+    pub publish_state_id: bool,
+    /// `state_id_mirror: true`, which stores the identifier of the current state in the
+    /// `AtomicStateId` of the context on every transition.
+    pub state_id_mirror: bool,
+    /// `send_futures: true`, which requires the futures of the async methods of the context to be
+    /// `Send`.
+    pub send_futures: bool,
+    /// `rejected_events: true`, which keeps the rejected events to return them from
+    /// `try_process_event`.
+    pub rejected_events: Option<syn::LitBool>,
+    /// `events_by_ref: true`, where `process_event` borrows the event and the actions borrow the
+    /// data of events.
+    pub events_by_ref: Option<syn::LitBool>,
+    /// `poisoning: true`, which poisons the state machine when a transition is interrupted while
+    /// the states are exited and entered.
+    pub poisoning: bool,
+    /// `arbitrary: true`, which implements `arbitrary::Arbitrary` for the events and generates
+    /// `process_arbitrary` with the `arbitrary` feature.
+    pub arbitrary: bool,
+    /// `typestate: true`, which generates handles typed by the state with the events of the state
+    /// as methods.
+    pub typestate: bool,
+    /// `associated_types: true`, where the clock and the logger are associated types of the
+    /// context along with its error.
+    pub associated_types: Option<syn::LitBool>,
+    /// The number of transitions kept in the transition history.
+    pub transition_history: Option<syn::LitInt>,
     pub transitions: Vec<StateTransition>,
+    pub deferred_events: Vec<DeferredEvent>,
+    pub delegated_events: Vec<DelegatedEvent>,
+    /// The inline actions of all transitions, named `inline_action_N` in the order of declaration.
+    pub inline_actions: Vec<InlineAction>,
+    /// The expression guards of all transitions, named `expression_guard_N` in the order of
+    /// declaration.
+    pub expression_guards: Vec<ExpressionGuard>,
+    pub defer_capacity: Option<syn::LitInt>,
+    /// The capacity of the queue of events posted by actions, which enables posting events.
+    pub post_capacity: Option<syn::LitInt>,
+    pub queue_capacity: Option<syn::LitInt>,
+    /// The state entered when an action fails, with the error as its possible data, declared as
+    /// `on_action_error: Faulted(Error)`.
+    pub fault_state: Option<(Ident, Option<Type>)>,
+    pub composite_states: Vec<CompositeState>,
+    pub parallel_states: Vec<ParallelState>,
+    pub state_handlers: Vec<StateHandlers>,
+    pub event_options: Vec<EventOptions>,
     pub name: Option<Ident>,
     pub states_attr: Vec<Attribute>,
     pub events_attr: Vec<Attribute>,
+    /// `display: names` or `display: data`, which implements `Display` for the states and events.
+    pub display: Option<DisplayMode>,
+    /// `events_type: path::Enum`, an enum defined outside of the state machine whose variants are
+    /// the events.
+    pub events_type: Option<syn::Path>,
+    /// The SCXML document the transitions have been imported from with `from_scxml`.
+    pub scxml_path: Option<String>,
+    /// The levels of the lints, declared in the `lints` block.
+    pub lints: Lints,
+    /// How the guards and actions borrow the context, declared in the `context_access` block.
+    pub context_access: ContextAccess,
+    /// The names and visibilities of the generated types, declared in the `types` block.
+    pub type_names: TypeNames,
+    /// The reachability properties, declared in the `assertions` block.
+    pub assertions: Vec<Assertion>,
+    /// The predicates of the context which have to hold in every state, declared in the
+    /// `invariants` block.
+    pub invariants: Vec<Ident>,
 }
 
 impl StateMachine {
@@ -16,30 +138,356 @@ impl StateMachine {
         StateMachine {
             temporary_context_type: None,
             custom_error: false,
+            custom_guard_error: false,
+            transition_outcome: false,
+            serde: None,
+            schema_version: None,
+            event_log: None,
+            metrics: false,
+            statistics: false,
+            transition_coverage: false,
+            table_dispatch: false,
+            hash_dispatch: None,
+            publish_state_id: false,
+            state_id_mirror: false,
+            send_futures: false,
+            rejected_events: None,
+            events_by_ref: None,
+            poisoning: false,
+            arbitrary: false,
+            typestate: false,
+            associated_types: None,
+            transition_history: None,
             transitions: Vec::new(),
+            deferred_events: Vec::new(),
+            delegated_events: Vec::new(),
+            inline_actions: Vec::new(),
+            expression_guards: Vec::new(),
+            defer_capacity: None,
+            post_capacity: None,
+            queue_capacity: None,
+            fault_state: None,
+            composite_states: Vec::new(),
+            parallel_states: Vec::new(),
+            state_handlers: Vec::new(),
+            event_options: Vec::new(),
             name: None,
             states_attr: Vec::new(),
             events_attr: Vec::new(),
+            events_type: None,
+            display: None,
+            scxml_path: None,
+            lints: Lints::default(),
+            context_access: ContextAccess::default(),
+            type_names: TypeNames::default(),
+            assertions: Vec::new(),
+            invariants: Vec::new(),
+        }
+    }
+
+    pub fn add_transitions(&mut self, mut transitions: StateTransitions, parent: Option<&Ident>) {
+        if transitions.defer {
+            for in_state in transitions.in_states {
+                for event in &transitions.events {
+                    self.deferred_events.push(DeferredEvent {
+                        in_state: in_state.clone(),
+                        event: event.clone(),
+                        parent: parent.cloned(),
+                    });
+                }
+            }
+            return;
+        }
+
+        if transitions.delegate {
+            for in_state in transitions.in_states {
+                for event in &transitions.events {
+                    self.delegated_events.push(DelegatedEvent {
+                        in_state: in_state.clone(),
+                        event: event.clone(),
+                    });
+                }
+            }
+            return;
+        }
+
+        // Name the inline actions, which are shared by all input states and events
+        for (position, mut inline_action) in core::mem::take(&mut transitions.inline_actions) {
+            let ident = Ident::new(
+                &format!("inline_action_{}", self.inline_actions.len()),
+                inline_action.ident.span(),
+            );
+            let action = match transitions.preceding_actions.get_mut(position) {
+                Some(action) => action,
+                None => transitions.action.as_mut().unwrap(),
+            };
+            action.ident = ident.clone();
+            inline_action.ident = ident;
+            self.inline_actions.push(inline_action);
+        }
+        if let Some(mut expression_guard) = transitions.expression_guard.take() {
+            let ident = Ident::new(
+                &format!("expression_guard_{}", self.expression_guards.len()),
+                expression_guard.ident.span(),
+            );
+            transitions.guard = Some(GuardExpression::Guard(AsyncIdent {
+                ident: ident.clone(),
+                is_async: false,
+            }));
+            expression_guard.ident = ident;
+            self.expression_guards.push(expression_guard);
+        }
+
+        for in_state in &transitions.in_states {
+            for (index, event) in transitions.events.iter().enumerate() {
+                // The starting state is only declared once
+                let mut in_state = in_state.clone();
+                in_state.start &= index == 0;
+                let transition = StateTransition {
+                    in_state,
+                    event: event.clone(),
+                    guard: transitions.guard.clone(),
+                    fallback: transitions.fallback,
+                    action: transitions.action.clone(),
+                    preceding_actions: transitions.preceding_actions.clone(),
+                    out_state: transitions.out_state.clone(),
+                    excluded: transitions.excluded.clone(),
+                    priority: transitions.priority,
+                    retry: transitions.retry.clone(),
+                    parent: parent.cloned(),
+                    pattern: transitions.in_states.len() > 1,
+                    source_composite: None,
+                };
+                self.transitions.push(transition);
+            }
         }
     }
 
-    pub fn add_transitions(&mut self, transitions: StateTransitions) {
-        for in_state in transitions.in_states {
-            let transition = StateTransition {
-                in_state,
-                event: transitions.event.clone(),
-                guard: transitions.guard.clone(),
-                action: transitions.action.clone(),
-                out_state: transitions.out_state.clone(),
+    /// Parses a list of transitions, where `parent` is the composite state or region whose block
+    /// is being parsed. Blocks of the form `Parent { ... }` declare the sub-states of `Parent`, and
+    /// lists of the form `Parent [ Region1 { ... }, Region2 { ... } ]` declare the regions of
+    /// `Parent`.
+    pub(super) fn parse_transitions(
+        &mut self,
+        content: parse::ParseStream,
+        parent: Option<&Ident>,
+    ) -> parse::Result<()> {
+        loop {
+            if content.is_empty() {
+                break;
+            }
+
+            let is_composite = (content.peek(Ident) && content.peek2(token::Brace))
+                || (content.peek(Token![*]) && content.peek2(Ident) && content.peek3(token::Brace))
+                || is_history_block(content);
+            let is_parallel = is_parallel_block(content).unwrap_or(false);
+
+            if is_composite {
+                let start = content.parse::<Token![*]>().is_ok();
+                let ident: Ident = content.parse()?;
+                self.check_unique(&ident)?;
+
+                // Possible history, `H` for shallow and `H*` for deep history
+                let history = if content.peek(token::Paren) {
+                    let history_content;
+                    parenthesized!(history_content in content);
+                    let keyword: Ident = history_content.parse()?;
+                    let deep = history_content.parse::<Token![*]>().is_ok();
+                    if keyword != "H" || !history_content.is_empty() {
+                        return Err(parse::Error::new(
+                            keyword.span(),
+                            "Composite states cannot have data associated with it, use (H) or (H*) for history.",
+                        ));
+                    }
+                    Some(if deep {
+                        History::Deep
+                    } else {
+                        History::Shallow
+                    })
+                } else {
+                    None
+                };
+
+                let block;
+                braced!(block in content);
+
+                let index = self.composite_states.len();
+                self.composite_states.push(CompositeState {
+                    ident: ident.clone(),
+                    parent: parent.cloned(),
+                    start,
+                    initial: None,
+                    children: Vec::new(),
+                    history,
+                });
+                let initial = self.parse_block(&block, &ident)?;
+                self.composite_states[index].initial = Some(initial);
+            } else if is_parallel {
+                let start = content.parse::<Token![*]>().is_ok();
+                let ident: Ident = content.parse()?;
+                self.check_unique(&ident)?;
+
+                let block;
+                bracketed!(block in content);
+
+                let mut regions: Vec<CompositeState> = Vec::new();
+                loop {
+                    if block.is_empty() {
+                        break;
+                    }
+
+                    let region: Ident = block.parse()?;
+                    self.check_unique(&region)?;
+                    if regions.iter().any(|r| r.ident == region) {
+                        return Err(parse::Error::new(
+                            region.span(),
+                            format!("Region {} is declared more than once.", region),
+                        ));
+                    }
+
+                    let region_block;
+                    braced!(region_block in block);
+
+                    let composites_before = self.composite_states.len();
+                    let parallels_before = self.parallel_states.len();
+                    let initial = self.parse_block(&region_block, &region)?;
+                    if self.composite_states.len() != composites_before
+                        || self.parallel_states.len() != parallels_before
+                    {
+                        return Err(parse::Error::new(
+                            region.span(),
+                            "Regions cannot contain composite or parallel states.",
+                        ));
+                    }
+
+                    regions.push(CompositeState {
+                        ident: region,
+                        parent: Some(ident.clone()),
+                        start: false,
+                        initial: Some(initial),
+                        children: Vec::new(),
+                        history: None,
+                    });
+
+                    if block.is_empty() {
+                        break;
+                    }
+
+                    if block.parse::<Token![,]>().is_err() {
+                        break;
+                    };
+                }
+
+                if regions.is_empty() {
+                    return Err(parse::Error::new(
+                        ident.span(),
+                        format!("Parallel state {} has no regions.", ident),
+                    ));
+                }
+
+                // Possible state entered when all regions are done
+                let completion = if content.parse::<Token![=]>().is_ok() {
+                    Some(content.parse()?)
+                } else {
+                    None
+                };
+
+                self.parallel_states.push(ParallelState {
+                    ident,
+                    parent: parent.cloned(),
+                    start,
+                    regions,
+                    completion,
+                });
+            } else {
+                let transitions: StateTransitions = content.parse()?;
+                self.add_transitions(transitions, parent);
+            }
+
+            // No comma at end of line, no more transitions
+            if content.is_empty() {
+                break;
+            }
+
+            if content.parse::<Token![,]>().is_err() {
+                break;
             };
-            self.transitions.push(transition);
         }
+
+        Ok(())
+    }
+
+    // helper function making sure composite states, parallel states and regions are unique
+    fn check_unique(&self, ident: &Ident) -> parse::Result<()> {
+        let declared = self.composite_states.iter().any(|c| &c.ident == ident)
+            || self
+                .parallel_states
+                .iter()
+                .any(|p| &p.ident == ident || p.regions.iter().any(|r| &r.ident == ident));
+
+        if declared {
+            return Err(parse::Error::new(
+                ident.span(),
+                format!("State {} is declared more than once.", ident),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parses the transitions of the block of a composite state or region, and returns its
+    /// initial sub-state.
+    fn parse_block(&mut self, block: parse::ParseStream, ident: &Ident) -> parse::Result<Ident> {
+        let transitions_before = self.transitions.len();
+        let composites_before = self.composite_states.len();
+        let parallels_before = self.parallel_states.len();
+        self.parse_transitions(block, Some(ident))?;
+
+        let mut initial = self.transitions[transitions_before..]
+            .iter()
+            .filter(|t| t.in_state.start && t.parent.as_ref() == Some(ident))
+            .map(|t| t.in_state.ident.clone())
+            .chain(
+                self.composite_states[composites_before..]
+                    .iter()
+                    .filter(|c| c.start && c.parent.as_ref() == Some(ident))
+                    .map(|c| c.ident.clone()),
+            )
+            .chain(
+                self.parallel_states[parallels_before..]
+                    .iter()
+                    .filter(|p| p.start && p.parent.as_ref() == Some(ident))
+                    .map(|p| p.ident.clone()),
+            );
+
+        let initial_state = initial.next().ok_or_else(|| {
+            parse::Error::new(
+                ident.span(),
+                format!(
+                    "No initial sub-state defined for {}, indicate it with a *.",
+                    ident
+                ),
+            )
+        })?;
+
+        if let Some(duplicate) = initial.next() {
+            return Err(parse::Error::new(
+                duplicate.span(),
+                format!(
+                    "More than one initial sub-state defined for {} (indicated with *), remove duplicates.",
+                    ident
+                ),
+            ));
+        }
+
+        Ok(initial_state)
     }
 }
 
 impl parse::Parse for StateMachine {
     fn parse(input: parse::ParseStream) -> parse::Result<Self> {
         let mut statemachine = StateMachine::new();
+        #[cfg(feature = "scxml")]
+        let mut scxml = Vec::new();
 
         loop {
             // If the last line ends with a comma this is true
@@ -53,24 +501,79 @@ impl parse::Parse for StateMachine {
                     if input.peek(token::Brace) {
                         let content;
                         braced!(content in input);
-                        loop {
-                            if content.is_empty() {
-                                break;
-                            }
-
-                            let transitions: StateTransitions = content.parse()?;
-                            statemachine.add_transitions(transitions);
-
-                            // No comma at end of line, no more transitions
-                            if content.is_empty() {
-                                break;
-                            }
-
-                            if content.parse::<Token![,]>().is_err() {
-                                break;
-                            };
-                        }
+                        statemachine.parse_transitions(&content, None)?;
+                    }
+                }
+                "states" => {
+                    input.parse::<Token![:]>()?;
+                    let content;
+                    braced!(content in input);
+                    let handlers =
+                        content.parse_terminated::<_, Token![,]>(StateHandlers::parse)?;
+                    statemachine.state_handlers.extend(handlers);
+                }
+                "events" => {
+                    input.parse::<Token![:]>()?;
+                    let content;
+                    braced!(content in input);
+                    let options = content.parse_terminated::<_, Token![,]>(EventOptions::parse)?;
+                    statemachine.event_options.extend(options);
+                }
+                "defer_capacity" => {
+                    input.parse::<Token![:]>()?;
+                    let defer_capacity: syn::LitInt = input.parse()?;
+                    if defer_capacity.base10_parse::<usize>()? == 0 {
+                        return Err(parse::Error::new(
+                            defer_capacity.span(),
+                            "The capacity of the deferred events queue must be at least 1.",
+                        ));
                     }
+                    statemachine.defer_capacity = Some(defer_capacity);
+                }
+                "transition_history" => {
+                    input.parse::<Token![:]>()?;
+                    let transition_history: syn::LitInt = input.parse()?;
+                    if transition_history.base10_parse::<usize>()? == 0 {
+                        return Err(parse::Error::new(
+                            transition_history.span(),
+                            "The capacity of the transition history must be at least 1.",
+                        ));
+                    }
+                    statemachine.transition_history = Some(transition_history);
+                }
+                "post_capacity" => {
+                    input.parse::<Token![:]>()?;
+                    let post_capacity: syn::LitInt = input.parse()?;
+                    if post_capacity.base10_parse::<usize>()? == 0 {
+                        return Err(parse::Error::new(
+                            post_capacity.span(),
+                            "The capacity of the posted events queue must be at least 1.",
+                        ));
+                    }
+                    statemachine.post_capacity = Some(post_capacity);
+                }
+                "queue_capacity" => {
+                    input.parse::<Token![:]>()?;
+                    let queue_capacity: syn::LitInt = input.parse()?;
+                    if queue_capacity.base10_parse::<usize>()? == 0 {
+                        return Err(parse::Error::new(
+                            queue_capacity.span(),
+                            "The capacity of the event queue must be at least 1.",
+                        ));
+                    }
+                    statemachine.queue_capacity = Some(queue_capacity);
+                }
+                "on_action_error" => {
+                    input.parse::<Token![:]>()?;
+                    let fault_state: Ident = input.parse()?;
+                    let data_type = if input.peek(token::Paren) {
+                        let content;
+                        parenthesized!(content in input);
+                        Some(content.parse::<Type>()?)
+                    } else {
+                        None
+                    };
+                    statemachine.fault_state = Some((fault_state, data_type));
                 }
                 "custom_error" => {
                     input.parse::<Token![:]>()?;
@@ -79,6 +582,118 @@ impl parse::Parse for StateMachine {
                         statemachine.custom_error = true
                     }
                 }
+                "custom_guard_error" => {
+                    input.parse::<Token![:]>()?;
+                    let custom_guard_error: syn::LitBool = input.parse()?;
+                    statemachine.custom_guard_error = custom_guard_error.value;
+                }
+                "transition_outcome" => {
+                    input.parse::<Token![:]>()?;
+                    let transition_outcome: syn::LitBool = input.parse()?;
+                    statemachine.transition_outcome = transition_outcome.value;
+                }
+                "serde" => {
+                    input.parse::<Token![:]>()?;
+                    let serde: syn::LitBool = input.parse()?;
+                    if serde.value {
+                        statemachine.serde = Some(serde);
+                    }
+                }
+                "schema_version" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.schema_version = Some(input.parse()?);
+                }
+                "event_log" => {
+                    input.parse::<Token![:]>()?;
+                    let event_log: syn::LitBool = input.parse()?;
+                    if event_log.value {
+                        statemachine.event_log = Some(event_log);
+                    }
+                }
+                "metrics" => {
+                    input.parse::<Token![:]>()?;
+                    let metrics: syn::LitBool = input.parse()?;
+                    statemachine.metrics = metrics.value;
+                }
+                "statistics" => {
+                    input.parse::<Token![:]>()?;
+                    let statistics: syn::LitBool = input.parse()?;
+                    statemachine.statistics = statistics.value;
+                }
+                "transition_coverage" => {
+                    input.parse::<Token![:]>()?;
+                    let transition_coverage: syn::LitBool = input.parse()?;
+                    statemachine.transition_coverage = transition_coverage.value;
+                }
+                "table_dispatch" => {
+                    input.parse::<Token![:]>()?;
+                    let table_dispatch: syn::LitBool = input.parse()?;
+                    statemachine.table_dispatch = table_dispatch.value;
+                }
+                "hash_dispatch" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.hash_dispatch = Some(input.parse()?);
+                }
+                "publish_state_id" => {
+                    input.parse::<Token![:]>()?;
+                    let publish_state_id: syn::LitBool = input.parse()?;
+                    statemachine.publish_state_id = publish_state_id.value;
+                }
+                "state_id_mirror" => {
+                    input.parse::<Token![:]>()?;
+                    let state_id_mirror: syn::LitBool = input.parse()?;
+                    statemachine.state_id_mirror = state_id_mirror.value;
+                }
+                "send_futures" => {
+                    input.parse::<Token![:]>()?;
+                    let send_futures: syn::LitBool = input.parse()?;
+                    statemachine.send_futures = send_futures.value;
+                }
+                "rejected_events" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.rejected_events = Some(input.parse()?);
+                }
+                "events_by_ref" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.events_by_ref = Some(input.parse()?);
+                }
+                "poisoning" => {
+                    input.parse::<Token![:]>()?;
+                    let poisoning: syn::LitBool = input.parse()?;
+                    statemachine.poisoning = poisoning.value;
+                }
+                "arbitrary" => {
+                    input.parse::<Token![:]>()?;
+                    let arbitrary: syn::LitBool = input.parse()?;
+                    if arbitrary.value && cfg!(not(feature = "arbitrary")) {
+                        return Err(parse::Error::new(
+                            arbitrary.span(),
+                            "Generating events requires the `arbitrary` feature of smlang.",
+                        ));
+                    }
+                    statemachine.arbitrary = arbitrary.value;
+                }
+                "typestate" => {
+                    input.parse::<Token![:]>()?;
+                    let typestate: syn::LitBool = input.parse()?;
+                    statemachine.typestate = typestate.value;
+                }
+                "associated_types" => {
+                    input.parse::<Token![:]>()?;
+                    let associated_types: syn::LitBool = input.parse()?;
+                    // The error of the context is associated as with `custom_error`
+                    if associated_types.value {
+                        statemachine.custom_error = true;
+                    }
+                    statemachine.associated_types = Some(associated_types);
+                }
+                "strict_events" => {
+                    input.parse::<Token![:]>()?;
+                    let strict_events: syn::LitBool = input.parse()?;
+                    if strict_events.value {
+                        statemachine.lints.unhandled_events = Level::Deny;
+                    }
+                }
                 "temporary_context" => {
                     input.parse::<Token![:]>()?;
                     let temporary_context_type: Type = input.parse()?;
@@ -106,6 +721,43 @@ impl parse::Parse for StateMachine {
                     input.parse::<Token![:]>()?;
                     statemachine.name = Some(input.parse::<Ident>()?);
                 }
+                "lints" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.lints.parse_levels(input)?;
+                }
+                "context_access" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.context_access.parse_borrows(input)?;
+                }
+                "types" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.type_names.parse_names(input)?;
+                }
+                "assertions" => {
+                    input.parse::<Token![:]>()?;
+                    let content;
+                    braced!(content in input);
+                    let assertions = content.parse_terminated::<_, Token![,]>(Assertion::parse)?;
+                    statemachine.assertions.extend(assertions);
+                }
+                "invariants" => {
+                    input.parse::<Token![:]>()?;
+                    let content;
+                    braced!(content in input);
+                    let invariants = content.parse_terminated::<_, Token![,]>(Ident::parse)?;
+                    statemachine.invariants.extend(invariants);
+                }
+                "from_scxml" => {
+                    input.parse::<Token![:]>()?;
+                    let path: syn::LitStr = input.parse()?;
+                    #[cfg(feature = "scxml")]
+                    scxml.push(path);
+                    #[cfg(not(feature = "scxml"))]
+                    return Err(parse::Error::new(
+                        path.span(),
+                        "Importing SCXML documents requires the `scxml` feature of smlang.",
+                    ));
+                }
 
                 "states_attr" => {
                     input.parse::<Token![:]>()?;
@@ -117,16 +769,61 @@ impl parse::Parse for StateMachine {
                     statemachine.events_attr = Attribute::parse_outer(input)?;
                 }
 
+                "display" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.display = Some(input.parse()?);
+                }
+
+                "events_type" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.events_type = Some(input.parse()?);
+                }
+
                 keyword => {
                     return Err(parse::Error::new(
                         input.span(),
                         format!(
                             "Unknown keyword {}. Support keywords: [\"name\", \
                                 \"transitions\", \
+                                \"from_scxml\", \
+                                \"states\", \
+                                \"events\", \
                                 \"temporary_context\", \
                                 \"custom_error\", \
+                                \"custom_guard_error\", \
+                                \"transition_outcome\", \
+                                \"serde\", \
+                                \"schema_version\", \
+                                \"event_log\", \
+                                \"metrics\", \
+                                \"statistics\", \
+                                \"transition_coverage\", \
+                                \"table_dispatch\", \
+                                \"hash_dispatch\", \
+                                \"publish_state_id\", \
+                                \"state_id_mirror\", \
+                                \"send_futures\", \
+                                \"rejected_events\", \
+                                \"events_by_ref\", \
+                                \"poisoning\", \
+                                \"arbitrary\", \
+                                \"typestate\", \
+                                \"associated_types\", \
+                                \"types\", \
+                                \"strict_events\", \
+                                \"defer_capacity\", \
+                                \"post_capacity\", \
+                                \"transition_history\", \
+                                \"queue_capacity\", \
+                                \"on_action_error\", \
+                                \"lints\", \
+                                \"context_access\", \
+                                \"assertions\", \
+                                \"invariants\", \
                                 \"states_attr\", \
-                                \"events_attr\"
+                                \"events_attr\", \
+                                \"events_type\", \
+                                \"display\"
                                 ]",
                             keyword
                         ),
@@ -144,6 +841,37 @@ impl parse::Parse for StateMachine {
             };
         }
 
+        // The documents are imported once the name of the state machine is known
+        #[cfg(feature = "scxml")]
+        for path in scxml {
+            statemachine.import_scxml(&path)?;
+        }
+
         Ok(statemachine)
     }
 }
+
+/// Returns `true` if the input starts with the block of a composite state with history, of the
+/// form `Composite(H) { ... }`.
+fn is_history_block(input: parse::ParseStream) -> bool {
+    let fork = input.fork();
+    let _ = fork.parse::<Token![*]>();
+    fork.parse::<Ident>().is_ok()
+        && fork.peek(token::Paren)
+        && fork.parse::<proc_macro2::TokenTree>().is_ok()
+        && fork.peek(token::Brace)
+}
+
+/// Returns `true` if the input starts with the list of regions of a parallel state, of the form
+/// `Parallel [ Region { ... }, ... ]`, rather than a guarded completion transition.
+fn is_parallel_block(input: parse::ParseStream) -> parse::Result<bool> {
+    let fork = input.fork();
+    let _ = fork.parse::<Token![*]>();
+    fork.parse::<Ident>()?;
+    if !fork.peek(token::Bracket) {
+        return Ok(false);
+    }
+    let content;
+    bracketed!(content in fork);
+    Ok(content.peek(Ident) && content.peek2(token::Brace))
+}
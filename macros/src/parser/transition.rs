@@ -5,24 +5,164 @@ use super::AsyncIdent;
 use proc_macro2::TokenStream;
 use quote::quote;
 use std::fmt;
-use syn::{bracketed, parse, token, Ident, Token};
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    braced, bracketed, parenthesized, parse, punctuated::Punctuated, spanned::Spanned, token,
+    Ident, LitInt, Pat, Token,
+};
 
 #[derive(Debug, Clone)]
 pub struct StateTransition {
     pub in_state: InputState,
     pub event: Event,
     pub guard: Option<GuardExpression>,
+    /// The transition is taken when all guarded transitions of the state and event fail, declared
+    /// with `[ else ]`.
+    pub fallback: bool,
     pub action: Option<AsyncIdent>,
+    /// The actions run in order before `action`, when several actions are chained with
+    /// `/ action1, action2`.
+    pub preceding_actions: Vec<AsyncIdent>,
     pub out_state: OutputState,
+    /// The states a wildcard transition does not apply to, declared with `except [State1, State2]`.
+    pub excluded: Vec<Ident>,
+    /// The transitions of a state and event with a higher priority are tried first, where the
+    /// priority defaults to 0 and is declared with `priority(N)`.
+    pub priority: Option<i32>,
+    pub retry: Option<Retry>,
+    /// The composite state in whose block this transition is declared.
+    pub parent: Option<Ident>,
+    /// The transition is declared for several input states, as `State1 | State2 + Event`.
+    pub pattern: bool,
+    /// The composite state the transition is declared for, once it has been expanded to the
+    /// sub-states of the composite state.
+    pub source_composite: Option<Ident>,
+}
+
+/// The retries of the guards and actions of a transition which fail, declared as `retry(N)` or
+/// `retry(N, backoff)` to call `backoff` before every retry.
+#[derive(Debug, Clone)]
+pub struct Retry {
+    pub retries: usize,
+    pub backoff: Option<AsyncIdent>,
+}
+
+/// An event which is deferred in a state, declared as `State + Event defer`.
+#[derive(Debug, Clone)]
+pub struct DeferredEvent {
+    pub in_state: InputState,
+    pub event: Event,
+    /// The composite state in whose block the deferral is declared.
+    pub parent: Option<Ident>,
+}
+
+/// An event which is delegated to the sub-machine held as the data of a state, declared as
+/// `State(SubMachine) + Event(SubEvents) delegate`.
+#[derive(Debug, Clone)]
+pub struct DelegatedEvent {
+    pub in_state: InputState,
+    pub event: Event,
+}
+
+/// An action declared inline as a closure, `/ |ctx, data| { ... }`, which is hoisted into a
+/// default method of the context named `ident`.
+#[derive(Debug, Clone)]
+pub struct InlineAction {
+    pub ident: Ident,
+    /// The parameters of the closure, which are bound to the context followed by the arguments of
+    /// the action.
+    pub inputs: Vec<Pat>,
+    pub body: syn::Block,
+}
+
+/// A guard declared as an expression over the data of the state and event,
+/// `[ count > 3 && event.kind == Kind::Hot ]`, which is hoisted into a default method of the
+/// context named `ident`.
+#[derive(Debug, Clone)]
+pub struct ExpressionGuard {
+    pub ident: Ident,
+    /// The expression, where the fields of the state data have been resolved.
+    pub expr: syn::Expr,
+}
+
+impl parse::Parse for ExpressionGuard {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let mut expr: syn::Expr = input.parse()?;
+        StateFields.visit_expr_mut(&mut expr);
+        Ok(Self {
+            ident: Ident::new("expression_guard", expr.span()),
+            expr,
+        })
+    }
+}
+
+/// Resolves the identifiers in an expression guard which are not `state` or `event` to fields of
+/// the state data. Closures and the functions of calls are kept as they are.
+struct StateFields;
+
+impl VisitMut for StateFields {
+    fn visit_expr_mut(&mut self, expr: &mut syn::Expr) {
+        match expr {
+            syn::Expr::Path(path) if path.qself.is_none() => {
+                if let Some(ident) = path.path.get_ident() {
+                    let name = ident.to_string();
+                    if name != "state"
+                        && name != "event"
+                        && name.starts_with(|c: char| c.is_lowercase() || c == '_')
+                    {
+                        *expr = syn::parse_quote! { state.#ident };
+                    }
+                }
+            }
+            syn::Expr::Call(call) => {
+                for arg in &mut call.args {
+                    self.visit_expr_mut(arg);
+                }
+            }
+            syn::Expr::Closure(_) => {}
+            _ => visit_mut::visit_expr_mut(self, expr),
+        }
+    }
+}
+
+/// Returns `true` if the content of the guard brackets is made of named guards, combined with `!`,
+/// `||` and `&&`, rather than an expression over the data of the state and event.
+fn is_guard_expression(input: parse::ParseStream) -> bool {
+    let fork = input.fork();
+    if parse_or(&fork).is_ok() && fork.is_empty() {
+        return true;
+    }
+
+    // A guard with a timeout which is not async is reported by the guard expression
+    let fork = input.fork();
+    fork.parse::<Ident>().is_ok()
+        && fork.parse::<Token![.]>().is_ok()
+        && fork.parse::<Ident>().is_ok_and(|ident| ident == "timeout")
 }
 
 #[derive(Debug)]
 pub struct StateTransitions {
     pub in_states: Vec<InputState>,
-    pub event: Event,
+    /// The events triggering the transitions, where several events are declared with
+    /// `+ (Event1 | Event2)`.
+    pub events: Vec<Event>,
     pub guard: Option<GuardExpression>,
+    pub fallback: bool,
     pub action: Option<AsyncIdent>,
+    pub preceding_actions: Vec<AsyncIdent>,
+    /// The inline actions with their position in the chain of actions, which are named when the
+    /// transitions are added to the state machine.
+    pub inline_actions: Vec<(usize, InlineAction)>,
+    pub expression_guard: Option<ExpressionGuard>,
     pub out_state: OutputState,
+    pub excluded: Vec<Ident>,
+    pub priority: Option<i32>,
+    pub retry: Option<Retry>,
+    /// The event is deferred in the input states instead of triggering a transition.
+    pub defer: bool,
+    /// The event is delegated to the sub-machines of the input states instead of triggering a
+    /// transition.
+    pub delegate: bool,
 }
 
 impl parse::Parse for StateTransitions {
@@ -48,44 +188,327 @@ impl parse::Parse for StateTransitions {
                 }
             }
         }
-        // Event
-        let event: Event = input.parse()?;
+        // Events, transitions without an event are completion transitions
+        let events = if input.peek(Token![+]) {
+            Event::parse_alternatives(input)?
+        } else {
+            vec![Event::completion(input.span())]
+        };
+
+        // Possible deferral or delegation of the event, which can't be combined with a transition
+        let keyword = if input.peek(Ident) {
+            input.fork().parse::<Ident>()?.to_string()
+        } else {
+            String::new()
+        };
+        if keyword == "defer" || keyword == "delegate" {
+            let keyword: Ident = input.parse()?;
+            if input.peek(Token![=]) {
+                return Err(parse::Error::new(
+                    keyword.span(),
+                    if keyword == "defer" {
+                        "Deferred events cannot have a target state."
+                    } else {
+                        "Delegated events cannot have a target state."
+                    },
+                ));
+            }
+            return Ok(Self {
+                in_states,
+                events,
+                guard: None,
+                fallback: false,
+                action: None,
+                preceding_actions: Vec::new(),
+                inline_actions: Vec::new(),
+                expression_guard: None,
+                out_state: input.parse()?,
+                excluded: Vec::new(),
+                priority: None,
+                retry: None,
+                defer: keyword == "defer",
+                delegate: keyword == "delegate",
+            });
+        }
 
-        // Possible guard
+        // Possible guard, or `else` for a fallback transition
+        let mut fallback = false;
+        let mut expression_guard = None;
         let guard = if input.peek(token::Bracket) {
             let content;
             bracketed!(content in input);
-            Some(GuardExpression::parse(&content)?)
+            if content.parse::<Token![else]>().is_ok() {
+                if !content.is_empty() {
+                    return Err(parse::Error::new(
+                        content.span(),
+                        "`else` cannot be combined with guards.",
+                    ));
+                }
+                fallback = true;
+                None
+            } else if is_guard_expression(&content) {
+                Some(GuardExpression::parse(&content)?)
+            } else {
+                // An expression over the data of the state and event, named once the transition
+                // is added to the state machine
+                let guard = ExpressionGuard::parse(&content)?;
+                let guard_ident = guard.ident.clone();
+                expression_guard = Some(guard);
+                Some(GuardExpression::Guard(AsyncIdent {
+                    ident: guard_ident,
+                    is_async: false,
+                }))
+            }
         } else {
             None
         };
 
-        // Possible action
+        // Possible actions, where chained actions are run in order
+        let mut preceding_actions = Vec::new();
+        let mut inline_actions = Vec::new();
         let action = if input.parse::<Token![/]>().is_ok() {
-            let is_async = input.parse::<token::Async>().is_ok();
-            let action: Ident = input.parse()?;
-            Some(AsyncIdent {
-                ident: action,
-                is_async,
-            })
+            let mut action = parse_action(input, &mut inline_actions, 0)?;
+            while is_chained_action(input) {
+                input.parse::<Token![,]>()?;
+                let next = parse_action(input, &mut inline_actions, preceding_actions.len() + 1)?;
+                preceding_actions.push(core::mem::replace(&mut action, next));
+            }
+            Some(action)
         } else {
             None
         };
 
         let out_state: OutputState = input.parse()?;
+        if events[0].is_completion() && out_state.keep_state {
+            return Err(parse::Error::new(
+                input.span(),
+                "Completion transitions need a target state.",
+            ));
+        }
+
+        // Possible states excluded from a wildcard
+        let mut excluded = Vec::new();
+        if input.peek(Ident) && input.fork().parse::<Ident>()? == "except" {
+            let except: Ident = input.parse()?;
+            if !in_states[0].wildcard {
+                return Err(parse::Error::new(
+                    except.span(),
+                    "Only wildcard transitions can exclude states.",
+                ));
+            }
+            let content;
+            bracketed!(content in input);
+            excluded.extend(Punctuated::<Ident, Token![,]>::parse_terminated(&content)?);
+        }
+
+        // Possible priority, declared as `priority(N)`
+        let mut priority = None;
+        if input.peek(Ident)
+            && input.peek2(token::Paren)
+            && input.fork().parse::<Ident>()? == "priority"
+        {
+            let ident: Ident = input.parse()?;
+            if fallback {
+                return Err(parse::Error::new(
+                    ident.span(),
+                    "Fallback transitions are always tried last, so they cannot have a priority.",
+                ));
+            }
+            let content;
+            parenthesized!(content in input);
+            let negative = content.parse::<Token![-]>().is_ok();
+            let value: i32 = content.parse::<LitInt>()?.base10_parse()?;
+            priority = Some(if negative { -value } else { value });
+        }
+
+        // Possible retries of failing guards and actions, declared as `retry(N)` with an optional
+        // backoff called before every retry
+        let mut retry = None;
+        if input.peek(Ident)
+            && input.peek2(token::Paren)
+            && input.fork().parse::<Ident>()? == "retry"
+        {
+            let ident: Ident = input.parse()?;
+            if guard.is_none() && action.is_none() {
+                return Err(parse::Error::new(
+                    ident.span(),
+                    "Only transitions with guards or actions can be retried.",
+                ));
+            }
+            let content;
+            parenthesized!(content in input);
+            let retries = content.parse::<LitInt>()?.base10_parse()?;
+            let backoff = if content.parse::<Token![,]>().is_ok() {
+                let is_async = content.parse::<token::Async>().is_ok();
+                Some(AsyncIdent {
+                    ident: content.parse()?,
+                    is_async,
+                })
+            } else {
+                None
+            };
+            retry = Some(Retry { retries, backoff });
+        }
 
         Ok(Self {
             in_states,
-            event,
+            events,
             guard,
+            fallback,
             action,
+            preceding_actions,
+            inline_actions,
+            expression_guard,
             out_state,
+            excluded,
+            priority,
+            retry,
+            defer: false,
+            delegate: false,
         })
     }
 }
+
+/// Parses an action at `position` in the chain of actions, which is either the name of a method of
+/// the context or an inline closure with a block as body, `|ctx, data| { ... }`.
+fn parse_action(
+    input: parse::ParseStream,
+    inline_actions: &mut Vec<(usize, InlineAction)>,
+    position: usize,
+) -> syn::Result<AsyncIdent> {
+    let is_async = input.parse::<token::Async>().is_ok();
+    if !(input.peek(Token![|]) || input.peek(Token![||])) {
+        return Ok(AsyncIdent {
+            ident: input.parse()?,
+            is_async,
+        });
+    }
+
+    let mut inputs = Vec::new();
+    if input.parse::<Token![||]>().is_err() {
+        input.parse::<Token![|]>()?;
+        loop {
+            if input.peek(Token![|]) {
+                break;
+            }
+            let pat: Pat = input.parse()?;
+            let pat = if input.peek(Token![:]) {
+                Pat::Type(syn::PatType {
+                    attrs: Vec::new(),
+                    pat: Box::new(pat),
+                    colon_token: input.parse()?,
+                    ty: Box::new(input.parse()?),
+                })
+            } else {
+                pat
+            };
+            inputs.push(pat);
+            if input.parse::<Token![,]>().is_err() {
+                break;
+            }
+        }
+        input.parse::<Token![|]>()?;
+    }
+
+    // The body needs to be a block, as the target state follows the action
+    if !input.peek(token::Brace) {
+        return Err(parse::Error::new(
+            input.span(),
+            "The body of an inline action needs to be a block, e.g. `|ctx| { Ok(()) }`.",
+        ));
+    }
+    let content;
+    let brace_token = braced!(content in input);
+    let body = syn::Block {
+        brace_token,
+        stmts: content.call(syn::Block::parse_within)?,
+    };
+
+    // Named once the transition is added to the state machine
+    let ident = Ident::new("inline_action", brace_token.span);
+    inline_actions.push((
+        position,
+        InlineAction {
+            ident: ident.clone(),
+            inputs,
+            body,
+        },
+    ));
+    Ok(AsyncIdent { ident, is_async })
+}
+
+/// Returns `true` if the input continues with another chained action rather than the next
+/// transition. Actions start with a lowercase letter and are followed by the target state, another
+/// action or the end of the transition, unless they are inline closures.
+fn is_chained_action(input: parse::ParseStream) -> bool {
+    let fork = input.fork();
+    if fork.parse::<Token![,]>().is_err() {
+        return false;
+    }
+    let _ = fork.parse::<token::Async>();
+    if fork.peek(Token![|]) || fork.peek(Token![||]) {
+        return true;
+    }
+    match fork.parse::<Ident>() {
+        Ok(ident) => {
+            ident
+                .to_string()
+                .starts_with(|c: char| c.is_lowercase() || c == '_')
+                && (fork.is_empty() || fork.peek(Token![=]) || fork.peek(Token![,]))
+        }
+        Err(_) => false,
+    }
+}
+/// The timeout of an async guard, declared with `async guard.timeout(100ms)`, where a guard which
+/// does not resolve in time fails, or with `async guard.timeout(100ms)?` returns an error.
+#[derive(Debug, Clone)]
+pub struct GuardTimeout {
+    pub duration: syn::Expr,
+    pub is_error: bool,
+}
+
+impl parse::Parse for GuardTimeout {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![.]>()?;
+        let keyword: Ident = input.parse()?;
+        if keyword != "timeout" {
+            return Err(parse::Error::new(
+                keyword.span(),
+                "Only a timeout can be given for a guard, e.g. `async guard.timeout(100ms)`.",
+            ));
+        }
+        let content;
+        parenthesized!(content in input);
+        let duration = match content.fork().parse::<LitInt>() {
+            // A literal with a unit suffix, e.g. `100ms`
+            Ok(lit) if !lit.suffix().is_empty() => {
+                content.parse::<LitInt>()?;
+                let value: u64 = lit.base10_parse()?;
+                let unit = match lit.suffix() {
+                    "s" => quote! { from_secs },
+                    "ms" => quote! { from_millis },
+                    "us" => quote! { from_micros },
+                    "ns" => quote! { from_nanos },
+                    _ => {
+                        return Err(parse::Error::new(
+                            lit.span(),
+                            "The unit of a timeout needs to be one of `s`, `ms`, `us` or `ns`.",
+                        ))
+                    }
+                };
+                syn::parse_quote! { core::time::Duration::#unit(#value) }
+            }
+            _ => content.parse()?,
+        };
+        let is_error = input.parse::<Option<Token![?]>>()?.is_some();
+        Ok(Self { duration, is_error })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum GuardExpression {
     Guard(AsyncIdent),
+    Timeout(AsyncIdent, Box<GuardTimeout>),
     Not(Box<GuardExpression>),
     Group(Box<GuardExpression>),
     And(Box<GuardExpression>, Box<GuardExpression>),
@@ -95,6 +518,10 @@ impl fmt::Display for GuardExpression {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             GuardExpression::Guard(async_ident) => write!(f, "{}", async_ident),
+            GuardExpression::Timeout(async_ident, timeout) if timeout.is_error => {
+                write!(f, "{}.timeout()?", async_ident)
+            }
+            GuardExpression::Timeout(async_ident, _) => write!(f, "{}.timeout()", async_ident),
             GuardExpression::Not(expr) => write!(f, "!{}", expr),
             GuardExpression::Group(expr) => write!(f, "({})", expr),
             GuardExpression::And(lhs, rhs) => {
@@ -109,10 +536,13 @@ impl fmt::Display for GuardExpression {
 impl GuardExpression {
     pub fn to_token_stream<F>(&self, visit: &mut F) -> TokenStream
     where
-        F: FnMut(&AsyncIdent) -> TokenStream,
+        F: FnMut(&AsyncIdent, Option<&GuardTimeout>) -> TokenStream,
     {
         match self {
-            GuardExpression::Guard(async_ident) => async_ident.to_token_stream(visit),
+            GuardExpression::Guard(async_ident) => visit(async_ident, None),
+            GuardExpression::Timeout(async_ident, timeout) => {
+                visit(async_ident, Some(timeout.as_ref()))
+            }
             GuardExpression::Not(expr) => {
                 let expr_tokens = expr.to_token_stream(visit);
                 quote! { !#expr_tokens }
@@ -133,6 +563,20 @@ impl GuardExpression {
             }
         }
     }
+
+    /// Returns the timeouts of the async guards of the expression.
+    pub fn timeouts(&self) -> Vec<&GuardTimeout> {
+        match self {
+            GuardExpression::Guard(_) => Vec::new(),
+            GuardExpression::Timeout(_, timeout) => vec![timeout.as_ref()],
+            GuardExpression::Not(expr) | GuardExpression::Group(expr) => expr.timeouts(),
+            GuardExpression::And(lhs, rhs) | GuardExpression::Or(lhs, rhs) => {
+                let mut timeouts = lhs.timeouts();
+                timeouts.extend(rhs.timeouts());
+                timeouts
+            }
+        }
+    }
 }
 
 pub fn visit_guards<F>(expr: &GuardExpression, mut visit_guard: F) -> Result<(), parse::Error>
@@ -142,7 +586,7 @@ where
     let mut stack = vec![expr];
     while let Some(node) = stack.pop() {
         match node {
-            GuardExpression::Guard(guard) => {
+            GuardExpression::Guard(guard) | GuardExpression::Timeout(guard, _) => {
                 visit_guard(guard)?;
             }
             GuardExpression::Not(inner) | GuardExpression::Group(inner) => {
@@ -203,13 +647,23 @@ fn parse_primary(input: parse::ParseStream) -> syn::Result<GuardExpression> {
     if input.peek(Token![async]) {
         let _async: Token![async] = input.parse()?;
         let ident: Ident = input.parse()?;
-        return Ok(GuardExpression::Guard(AsyncIdent {
+        let guard = AsyncIdent {
             ident,
             is_async: true,
-        }));
+        };
+        if input.peek(Token![.]) {
+            return Ok(GuardExpression::Timeout(guard, Box::new(input.parse()?)));
+        }
+        return Ok(GuardExpression::Guard(guard));
     }
 
     let ident: Ident = input.parse()?;
+    if input.peek(Token![.]) {
+        return Err(parse::Error::new(
+            ident.span(),
+            "Only async guards can have a timeout, e.g. `async guard.timeout(100ms)`.",
+        ));
+    }
     Ok(GuardExpression::Guard(AsyncIdent {
         ident,
         is_async: false,
@@ -218,7 +672,8 @@ fn parse_primary(input: parse::ParseStream) -> syn::Result<GuardExpression> {
 
 #[cfg(test)]
 mod test {
-    use crate::parser::transition::GuardExpression;
+    use crate::parser::transition::{ExpressionGuard, GuardExpression};
+    use quote::ToTokens;
     use syn::parse_str;
 
     #[test]
@@ -243,6 +698,11 @@ mod test {
                 "a && ( !b && c ) || d && e",
                 "a() && (!b() && c()) || d() && e()",
             ),
+            ("async a.timeout(100ms)", "a().await.timeout()"),
+            (
+                "!async a.timeout(Duration::from_secs(1))? && b",
+                "!a().await.timeout()? && b()",
+            ),
         ] {
             let guard_expression: GuardExpression = parse_str(guard_expression_str)?;
             assert_eq!(guard_expression.to_string(), expected);
@@ -250,4 +710,25 @@ mod test {
         }
         Ok(())
     }
+
+    #[test]
+    fn expression_guard_fields() -> Result<(), syn::Error> {
+        for (expression, expected) in [
+            ("count > 3", "state . count > 3"),
+            (
+                "count > 3 && event.kind == Kind::Hot",
+                "state . count > 3 && event . kind == Kind :: Hot",
+            ),
+            ("*state == MAX", "* state == MAX"),
+            ("is_even(count)", "is_even (state . count)"),
+            (
+                "items.iter().any(|i| *i > limit)",
+                "state . items . iter () . any (| i | * i > limit)",
+            ),
+        ] {
+            let guard: ExpressionGuard = parse_str(expression)?;
+            assert_eq!(guard.expr.to_token_stream().to_string(), expected);
+        }
+        Ok(())
+    }
 }
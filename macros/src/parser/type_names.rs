@@ -0,0 +1,88 @@
+use syn::{braced, parse, Ident, Token, Visibility};
+
+/// A generated type renamed in the `types` block, e.g. `states: pub(crate) DoorState`.
+#[derive(Debug, Clone)]
+pub struct TypeName {
+    /// The visibility of the type, `pub` when none is given.
+    pub vis: Visibility,
+    pub ident: Ident,
+}
+
+impl parse::Parse for TypeName {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let vis = match input.parse()? {
+            Visibility::Inherited => syn::parse_quote! { pub },
+            vis => vis,
+        };
+        Ok(TypeName {
+            vis,
+            ident: input.parse()?,
+        })
+    }
+}
+
+/// The names and visibilities of the main generated types, declared in the `types` block as
+/// `types: { states: DoorState, state_machine: pub(crate) Door }`.
+#[derive(Debug, Clone, Default)]
+pub struct TypeNames {
+    /// The enum of the states, `States` by default.
+    pub states: Option<TypeName>,
+    /// The enum of the events, `Events` by default.
+    pub events: Option<TypeName>,
+    /// The state machine, `StateMachine` by default.
+    pub state_machine: Option<TypeName>,
+    /// The trait of the context, `StateMachineContext` by default.
+    pub context: Option<TypeName>,
+}
+
+impl TypeNames {
+    /// Parses the braced names of the `types` block.
+    pub fn parse_names(&mut self, input: parse::ParseStream) -> syn::Result<()> {
+        let content;
+        braced!(content in input);
+
+        loop {
+            if content.is_empty() {
+                break;
+            }
+
+            let kind: Ident = content.parse()?;
+            content.parse::<Token![:]>()?;
+            let name: TypeName = content.parse()?;
+            let slot = match kind.to_string().as_str() {
+                "states" => &mut self.states,
+                "events" => &mut self.events,
+                "state_machine" => &mut self.state_machine,
+                "context" => &mut self.context,
+                _ => {
+                    return Err(parse::Error::new(
+                        kind.span(),
+                        format!(
+                            "Unknown type {}. Supported types: [\"states\", \"events\", \
+                                \"state_machine\", \"context\"]",
+                            kind
+                        ),
+                    ))
+                }
+            };
+            if slot.is_some() {
+                return Err(parse::Error::new(
+                    kind.span(),
+                    format!("The name of the {} type is defined more than once.", kind),
+                ));
+            }
+            *slot = Some(name);
+
+            // No comma at end of line, no more types
+            if content.is_empty() {
+                break;
+            }
+
+            if content.parse::<Token![,]>().is_err() {
+                break;
+            };
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+//! A perfect hash of the keys of the handlers of `hash_dispatch`, built with hash and displace:
+//! the keys are split into buckets, and the keys of every bucket are displaced together until
+//! they hash to free slots.
+
+/// The hash of a key, which is the finalizer of MurmurHash3. The generated lookup uses the same
+/// function.
+fn mix(mut key: u32) -> u32 {
+    key ^= key >> 16;
+    key = key.wrapping_mul(0x85eb_ca6b);
+    key ^= key >> 13;
+    key = key.wrapping_mul(0xc2b2_ae35);
+    key ^= key >> 16;
+    key
+}
+
+/// A perfect hash of a set of keys, where the key `k` is in slot
+/// `mix(k ^ displacements[mix(k) % displacements.len()]) % slots.len()`.
+#[derive(Debug)]
+pub struct PerfectHash {
+    /// The displacement of every bucket.
+    pub displacements: Vec<u32>,
+    /// The index of the key in every slot.
+    pub slots: Vec<Option<usize>>,
+}
+
+impl PerfectHash {
+    /// Builds a perfect hash of distinct keys, with a slot per key.
+    pub fn new(keys: &[u32]) -> Self {
+        let bucket_count = keys.len().div_ceil(4).max(1);
+        let mut slot_count = keys.len().max(1);
+        loop {
+            if let Some(hash) = Self::displace(keys, bucket_count, slot_count) {
+                return hash;
+            }
+            // No displacement of a bucket is left, which needs a free slot more
+            slot_count += 1;
+        }
+    }
+
+    fn displace(keys: &[u32], bucket_count: usize, slot_count: usize) -> Option<Self> {
+        let mut buckets = vec![Vec::new(); bucket_count];
+        for (index, key) in keys.iter().enumerate() {
+            buckets[mix(*key) as usize % bucket_count].push(index);
+        }
+        // The largest buckets are placed first, while most slots are free
+        let mut order: Vec<_> = (0..bucket_count).collect();
+        order.sort_by_key(|bucket| core::cmp::Reverse(buckets[*bucket].len()));
+
+        let mut displacements = vec![0; bucket_count];
+        let mut slots = vec![None; slot_count];
+        for bucket in order {
+            let indices = &buckets[bucket];
+            if indices.is_empty() {
+                continue;
+            }
+            let displacement = (0..=u16::MAX as u32).find(|displacement| {
+                let mut taken: Vec<_> = indices
+                    .iter()
+                    .map(|index| mix(keys[*index] ^ displacement) as usize % slot_count)
+                    .collect();
+                if taken.iter().any(|slot| slots[*slot].is_some()) {
+                    return false;
+                }
+                taken.sort_unstable();
+                taken.dedup();
+                taken.len() == indices.len()
+            })?;
+            for index in indices {
+                slots[mix(keys[*index] ^ displacement) as usize % slot_count] = Some(*index);
+            }
+            displacements[bucket] = displacement;
+        }
+        Some(PerfectHash {
+            displacements,
+            slots,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distinct_slots() {
+        // The keys of a sparse table of 30 states and 30 events
+        let keys: Vec<u32> = (0..900)
+            .filter(|key| key % 7 == 0 || key % 11 == 3)
+            .collect();
+        let hash = PerfectHash::new(&keys);
+
+        for (index, key) in keys.iter().enumerate() {
+            let displacement = hash.displacements[mix(*key) as usize % hash.displacements.len()];
+            let slot = mix(key ^ displacement) as usize % hash.slots.len();
+            assert_eq!(hash.slots[slot], Some(index));
+        }
+        assert!(hash.slots.len() < keys.len() + keys.len() / 10);
+    }
+}
@@ -0,0 +1,403 @@
+//! Exports a state machine as an SCXML document, which can be consumed by external statechart
+//! tools and model checkers.
+
+use crate::parser::event::{ANY_EVENT, COMPLETION_EVENT, TIMEOUT_EVENT};
+use crate::parser::*;
+use std::collections::HashMap;
+
+/// Generates a string containing an SCXML document of the state machine.
+///
+/// Guards are exported as the `cond` of their transitions and actions and state handlers as
+/// `<script>` elements. Timed transitions are triggered by delayed `after.<State>` events sent when
+/// their state is entered, and choices are exported as transient states.
+pub fn generate_scxml(sm: &ParsedStateMachine) -> String {
+    let document = Document::new(sm);
+    let initial = document.top_level(&sm.starting_state.to_string());
+
+    let mut lines = vec![r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string()];
+    let name = match &sm.name {
+        Some(name) => format!(r#" name="{name}""#),
+        None => String::new(),
+    };
+    lines.push(format!(
+        r#"<scxml xmlns="http://www.w3.org/2005/07/scxml" version="1.0"{name} initial="{initial}">"#
+    ));
+    document.children(None, 1, &mut lines);
+    lines.push("</scxml>".to_string());
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// The states of a state machine, with the composite state, parallel state or region each state
+/// is nested in.
+struct Document<'a> {
+    sm: &'a ParsedStateMachine,
+    parents: HashMap<String, String>,
+}
+
+impl<'a> Document<'a> {
+    fn new(sm: &'a ParsedStateMachine) -> Self {
+        let mut parents = HashMap::new();
+        for (state, parent) in sm.state_parents.iter().chain(sm.region_parents.iter()) {
+            parents.insert(state.clone(), parent.to_string());
+        }
+        for parallel in sm.parallel_states.values() {
+            for region in &parallel.regions {
+                parents.insert(region.ident.to_string(), parallel.ident.to_string());
+            }
+        }
+        Self { sm, parents }
+    }
+
+    /// The outermost state `state` is nested in, or `state` itself.
+    fn top_level(&self, state: &str) -> String {
+        let mut state = state;
+        while let Some(parent) = self.parents.get(state) {
+            state = parent;
+        }
+        state.to_string()
+    }
+
+    /// Adds the elements of the states nested in `scope`, or the top-level ones, to the document.
+    fn children(&self, scope: Option<&str>, depth: usize, lines: &mut Vec<String>) {
+        let mut states: Vec<_> = self
+            .sm
+            .states
+            .keys()
+            .chain(self.sm.region_states.keys())
+            .chain(self.sm.composite_states.keys())
+            .chain(self.sm.parallel_states.keys())
+            .filter(|state| self.parents.get(*state).map(String::as_str) == scope)
+            .collect();
+        states.sort();
+        states.dedup();
+        for state in states {
+            self.state(state, depth, lines);
+        }
+    }
+
+    /// Adds the element of a state to the document, with its sub-states or regions.
+    fn state(&self, state: &str, depth: usize, lines: &mut Vec<String>) {
+        let sm = self.sm;
+        let indent = "    ".repeat(depth);
+        if let Some(composite) = sm.composite_states.get(state) {
+            let initial = composite.initial.as_ref().unwrap();
+            lines.push(format!(
+                r#"{indent}<state id="{state}" initial="{initial}">"#
+            ));
+            if let Some(history) = composite.history {
+                let kind = match history {
+                    composite_state::History::Shallow => "shallow",
+                    composite_state::History::Deep => "deep",
+                };
+                lines.push(format!(
+                    r#"{indent}    <history id="{state}_history" type="{kind}">"#
+                ));
+                lines.push(format!(
+                    r#"{indent}        <transition target="{initial}"/>"#
+                ));
+                lines.push(format!("{indent}    </history>"));
+            }
+            self.handlers(state, depth + 1, lines);
+            self.children(Some(state), depth + 1, lines);
+            lines.push(format!("{indent}</state>"));
+        } else if let Some(parallel) = sm.parallel_states.get(state) {
+            lines.push(format!(r#"{indent}<parallel id="{state}">"#));
+            self.handlers(state, depth + 1, lines);
+            for region in &parallel.regions {
+                let initial = region.initial.as_ref().unwrap();
+                lines.push(format!(
+                    r#"{indent}    <state id="{}" initial="{initial}">"#,
+                    region.ident
+                ));
+                self.children(Some(&region.ident.to_string()), depth + 2, lines);
+                lines.push(format!("{indent}    </state>"));
+            }
+            // Parallel states are left once all regions have reached a final sub-state
+            if let Some(completion) = &parallel.completion {
+                lines.push(format!(
+                    r#"{indent}    <transition event="done.state.{state}" target="{}"/>"#,
+                    self.target(completion, None)
+                ));
+            }
+            lines.push(format!("{indent}</parallel>"));
+        } else if sm.terminal_states.contains_key(state) || self.is_region_final(state) {
+            lines.push(format!(r#"{indent}<final id="{state}"/>"#));
+        } else {
+            lines.push(format!(r#"{indent}<state id="{state}">"#));
+            self.handlers(state, depth + 1, lines);
+            let choices = self.transitions(state, depth + 1, lines);
+            lines.push(format!("{indent}</state>"));
+            lines.extend(choices);
+        }
+    }
+
+    /// Returns `true` if `state` is a final sub-state of a region, which completes the region.
+    fn is_region_final(&self, state: &str) -> bool {
+        self.sm.region_parents.contains_key(state)
+            && self.sm.states_events_mapping[state].is_empty()
+            && !self.sm.composite_states.contains_key(state)
+    }
+
+    /// Adds the entry and exit handlers of a state to the document, along with sending and
+    /// cancelling the delayed events of its timed transitions and timeout.
+    fn handlers(&self, state: &str, depth: usize, lines: &mut Vec<String>) {
+        let indent = "    ".repeat(depth);
+        let handlers = self.sm.state_handlers.get(state);
+        let mut delayed = Vec::new();
+        if let Some(duration) = self.sm.timeouts.get(state) {
+            let event = format!("{TIMEOUT_EVENT}.{state}");
+            delayed.push((event.clone(), event, duration));
+        }
+        if let Some(watchdog) = handlers.and_then(|handlers| handlers.watchdog.as_ref()) {
+            let id = format!("timeout.{state}");
+            delayed.push((id, watchdog.event.to_string(), &watchdog.duration));
+        }
+
+        let entry = handlers.and_then(|handlers| handlers.entry.as_ref());
+        if entry.is_some() || !delayed.is_empty() {
+            lines.push(format!("{indent}<onentry>"));
+            if let Some(entry) = entry {
+                lines.push(format!(
+                    "{indent}    <script>{}</script>",
+                    escape(&entry.to_string())
+                ));
+            }
+            for (id, event, duration) in &delayed {
+                lines.push(format!(
+                    r#"{indent}    <send id="{id}" event="{event}" {}/>"#,
+                    delay(duration)
+                ));
+            }
+            lines.push(format!("{indent}</onentry>"));
+        }
+
+        let exit = handlers.and_then(|handlers| handlers.exit.as_ref());
+        if exit.is_some() || !delayed.is_empty() {
+            lines.push(format!("{indent}<onexit>"));
+            for (id, _, _) in &delayed {
+                lines.push(format!(r#"{indent}    <cancel sendid="{id}"/>"#));
+            }
+            if let Some(exit) = exit {
+                lines.push(format!(
+                    "{indent}    <script>{}</script>",
+                    escape(&exit.to_string())
+                ));
+            }
+            lines.push(format!("{indent}</onexit>"));
+        }
+    }
+
+    /// Adds the transitions of a state to the document, in the order they are tried, and returns
+    /// the transient states of its choices which are its siblings.
+    fn transitions(&self, state: &str, depth: usize, lines: &mut Vec<String>) -> Vec<String> {
+        let indent = "    ".repeat(depth);
+        let mut mappings: Vec<_> = self.sm.states_events_mapping[state].values().collect();
+        mappings.sort_by_key(|mapping| mapping.event.to_string());
+
+        let mut choices = Vec::new();
+        for mapping in mappings {
+            let event = match mapping.event.to_string().as_str() {
+                COMPLETION_EVENT => String::new(),
+                ANY_EVENT => r#" event="*""#.to_string(),
+                TIMEOUT_EVENT => format!(r#" event="{TIMEOUT_EVENT}.{state}""#),
+                event => format!(r#" event="{event}""#),
+            };
+            for (index, transition) in mapping.transitions.iter().enumerate() {
+                let cond = transition
+                    .guard
+                    .as_ref()
+                    .map(|guard| format!(r#" cond="{}""#, escape(&guard.to_string())))
+                    .unwrap_or_default();
+                let target = match &transition.choice {
+                    Some(choice) => {
+                        let choice_state = format!("{state}_{}_choice{index}", mapping.event);
+                        self.choice(&choice_state, choice, depth - 1, &mut choices);
+                        format!(r#" target="{choice_state}""#)
+                    }
+                    // Transitions without a target state neither exit nor enter the state
+                    None if transition.keep_state => r#" type="internal""#.to_string(),
+                    None => format!(
+                        r#" target="{}""#,
+                        self.target(&transition.out_state, transition.out_composite.as_ref())
+                    ),
+                };
+                let actions: Vec<_> = transition
+                    .preceding_actions
+                    .iter()
+                    .chain(transition.action.iter())
+                    .collect();
+                if actions.is_empty() {
+                    lines.push(format!("{indent}<transition{event}{cond}{target}/>"));
+                } else {
+                    lines.push(format!("{indent}<transition{event}{cond}{target}>"));
+                    for action in actions {
+                        lines.push(format!(
+                            "{indent}    <script>{}</script>",
+                            escape(&action.to_string())
+                        ));
+                    }
+                    lines.push(format!("{indent}</transition>"));
+                }
+            }
+        }
+        choices
+    }
+
+    /// Adds the transient state of a choice, which is left immediately for the first branch whose
+    /// guard passes.
+    fn choice(&self, state: &str, choice: &choice::Choice, depth: usize, lines: &mut Vec<String>) {
+        let indent = "    ".repeat(depth);
+        lines.push(format!(r#"{indent}<state id="{state}">"#));
+        for branch in &choice.branches {
+            let cond = branch
+                .guard
+                .as_ref()
+                .map(|guard| format!(r#" cond="{}""#, escape(&guard.to_string())))
+                .unwrap_or_default();
+            let target = self.target(&branch.out_state.ident, branch.out_state.composite.as_ref());
+            match &branch.action {
+                Some(action) => {
+                    lines.push(format!(
+                        r#"{indent}    <transition{cond} target="{target}">"#
+                    ));
+                    lines.push(format!(
+                        "{indent}        <script>{}</script>",
+                        escape(&action.to_string())
+                    ));
+                    lines.push(format!("{indent}    </transition>"));
+                }
+                None => lines.push(format!(
+                    r#"{indent}    <transition{cond} target="{target}"/>"#
+                )),
+            }
+        }
+        lines.push(format!("{indent}</state>"));
+    }
+
+    /// The target of a transition, which is the history of a targeted composite state with
+    /// history and the composite state itself otherwise.
+    fn target(&self, state: &syn::Ident, composite: Option<&syn::Ident>) -> String {
+        let state = composite.unwrap_or(state).to_string();
+        match self.sm.composite_states.get(&state) {
+            Some(composite) if composite.history.is_some() => format!("{state}_history"),
+            _ => state,
+        }
+    }
+}
+
+/// The delay of a delayed event, as a literal duration for `Duration::from_secs` and
+/// `Duration::from_millis` of an integer and as an expression otherwise.
+fn delay(duration: &syn::Expr) -> String {
+    if let syn::Expr::Call(call) = duration {
+        if let (syn::Expr::Path(function), [syn::Expr::Lit(literal)]) =
+            (&*call.func, &call.args.iter().collect::<Vec<_>>()[..])
+        {
+            let unit = match function.path.segments.last() {
+                Some(segment) if segment.ident == "from_secs" => Some("s"),
+                Some(segment) if segment.ident == "from_millis" => Some("ms"),
+                _ => None,
+            };
+            if let (Some(unit), syn::Lit::Int(value)) = (unit, &literal.lit) {
+                return format!(r#"delay="{}{unit}""#, value.base10_digits());
+            }
+        }
+    }
+    let expression = quote::quote!(#duration).to_string();
+    format!(r#"delayexpr="{}""#, escape(&expression))
+}
+
+/// Escapes the characters of a string which cannot be used in XML attributes and text.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::{state_machine::StateMachine, ParsedStateMachine};
+
+    fn parse(sm: &str) -> Result<ParsedStateMachine, syn::Error> {
+        ParsedStateMachine::new(syn::parse_str::<StateMachine>(sm)?)
+    }
+
+    #[test]
+    fn scxml_document() -> Result<(), syn::Error> {
+        let sm = parse(
+            "name: Device,
+            transitions: {
+                *Off + PowerOn = On,
+                Off + after(core::time::Duration::from_secs(2)) = Sleeping,
+                Sleeping + PowerOn = On,
+                On + PowerOff = Off,
+                On(H) {
+                    *Idle + Start [ ready && !busy ] / start = Busy,
+                    Busy + Done / measure = choice(u32) {
+                        [ in_range ] = Idle,
+                        = Failed,
+                    },
+                    Busy + Tick / count,
+                },
+                Failed + Discard = Broken @final,
+            },
+            states: {
+                Idle { entry: enter_idle },
+            }",
+        )?;
+
+        assert_eq!(
+            super::generate_scxml(&sm),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<scxml xmlns="http://www.w3.org/2005/07/scxml" version="1.0" name="Device" initial="Off">
+    <final id="Broken"/>
+    <state id="Failed">
+        <transition event="Discard" target="Broken"/>
+    </state>
+    <state id="Off">
+        <onentry>
+            <send id="after.Off" event="after.Off" delay="2s"/>
+        </onentry>
+        <onexit>
+            <cancel sendid="after.Off"/>
+        </onexit>
+        <transition event="PowerOn" target="On_history"/>
+        <transition event="after.Off" target="Sleeping"/>
+    </state>
+    <state id="On" initial="Idle">
+        <history id="On_history" type="shallow">
+            <transition target="Idle"/>
+        </history>
+        <state id="Busy">
+            <transition event="Done" target="Busy_Done_choice0">
+                <script>measure()</script>
+            </transition>
+            <transition event="PowerOff" target="Off"/>
+            <transition event="Tick" type="internal">
+                <script>count()</script>
+            </transition>
+        </state>
+        <state id="Busy_Done_choice0">
+            <transition cond="in_range()" target="Idle"/>
+            <transition target="Failed"/>
+        </state>
+        <state id="Idle">
+            <onentry>
+                <script>enter_idle()</script>
+            </onentry>
+            <transition event="PowerOff" target="Off"/>
+            <transition event="Start" cond="ready() &amp;&amp; !busy()" target="Busy">
+                <script>start()</script>
+            </transition>
+        </state>
+    </state>
+    <state id="Sleeping">
+        <transition event="PowerOn" target="On_history"/>
+    </state>
+</scxml>
+"#
+        );
+        Ok(())
+    }
+}
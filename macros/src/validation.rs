@@ -1,8 +1,11 @@
-use crate::parser::transition::visit_guards;
+use crate::parser::event::ANY_EVENT;
+use crate::parser::lints::Level;
+use crate::parser::transition::{visit_guards, GuardExpression};
 use crate::parser::{AsyncIdent, ParsedStateMachine};
-use proc_macro2::Span;
-use std::collections::HashMap;
-use syn::parse;
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use std::collections::{HashMap, HashSet, VecDeque};
+use syn::{parse, Ident};
 
 /// A basic representation an action call signature.
 #[derive(PartialEq, Clone)]
@@ -53,6 +56,14 @@ impl FunctionSignature {
     }
 }
 
+/// Returns the entries of `map` sorted by name, so that diagnostics don't depend on the order of
+/// the hash map.
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(name, _)| name.as_str());
+    entries
+}
+
 // Verify action and guard function signatures.
 fn validate_action_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
     // Collect all of the action call signatures.
@@ -60,23 +71,44 @@ fn validate_action_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Erro
 
     let all_transitions = &sm.states_events_mapping;
 
-    for (in_state_name, from_transitions) in all_transitions.iter() {
+    for (in_state_name, from_transitions) in sorted(all_transitions) {
         let in_state_data = sm.state_data.data_types.get(in_state_name);
 
-        for (out_state_name, event_mapping) in from_transitions.iter() {
-            let out_state_data = sm.state_data.data_types.get(out_state_name);
-
-            // Get the data associated with this event.
+        for (_, event_mapping) in sorted(from_transitions) {
+            // Get the data associated with this event, which is borrowed in regions.
             let event_data = sm
                 .event_data
                 .data_types
-                .get(&event_mapping.event.to_string());
+                .get(&event_mapping.event.to_string())
+                .map(|data_type| match data_type {
+                    syn::Type::Reference(_) => data_type.clone(),
+                    _ if sm.region_states.contains_key(in_state_name) => {
+                        syn::parse_quote! { &#data_type }
+                    }
+                    _ => data_type.clone(),
+                });
+            let event_data = event_data.as_ref();
             for transition in &event_mapping.transitions {
+                // Actions of internal transitions can borrow the data of the state mutably.
+                let mutable_state_data: Option<syn::Type> = in_state_data
+                    .filter(|_| sm.updates_state_data(in_state_name, transition))
+                    .map(|data_type| syn::parse_quote! { &mut #data_type });
+                let in_state_data = mutable_state_data.as_ref().or(in_state_data);
                 if let Some(AsyncIdent {
                     ident: action,
                     is_async,
                 }) = &transition.action
                 {
+                    // Transitions keeping the state don't provide new data for it, and actions
+                    // of choices provide the data of the choice.
+                    let out_state_data = match &transition.choice {
+                        Some(choice) => choice.data_type.as_ref(),
+                        None => sm
+                            .state_data
+                            .data_types
+                            .get(&transition.out_state.to_string())
+                            .filter(|_| !transition.keep_state),
+                    };
                     let signature = FunctionSignature::new(
                         in_state_data,
                         event_data,
@@ -93,11 +125,137 @@ fn validate_action_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Erro
                     // action.
                     if actions.get(&action.to_string()).unwrap() != &signature {
                         return Err(parse::Error::new(
-                            Span::call_site(),
+                            action.span(),
                             format!("Action `{}` can only be reused when all input states, events, and output states have the same data", action),
                         ));
                     }
                 }
+
+                // Chained actions before the last one borrow the event data and provide no data.
+                for action in &transition.preceding_actions {
+                    let event_data = event_data.map(|data_type| match data_type {
+                        syn::Type::Reference(_) => data_type.clone(),
+                        _ => syn::parse_quote! { &#data_type },
+                    });
+                    let signature = FunctionSignature::new(
+                        in_state_data,
+                        event_data.as_ref(),
+                        None,
+                        action.is_async,
+                    );
+
+                    actions
+                        .entry(action.ident.to_string())
+                        .or_insert_with(|| signature.clone());
+
+                    if actions.get(&action.ident.to_string()).unwrap() != &signature {
+                        return Err(parse::Error::new(
+                            action.ident.span(),
+                            format!("Action `{}` can only be reused when all input states, events, and output states have the same data", action.ident),
+                        ));
+                    }
+                }
+
+                // Backoffs of retried transitions take the number of the retry.
+                if let Some(backoff) = transition.retry.as_ref().and_then(|r| r.backoff.as_ref()) {
+                    let attempt: syn::Type = syn::parse_quote! { usize };
+                    let signature =
+                        FunctionSignature::new(None, Some(&attempt), None, backoff.is_async);
+
+                    actions
+                        .entry(backoff.ident.to_string())
+                        .or_insert_with(|| signature.clone());
+
+                    if actions.get(&backoff.ident.to_string()).unwrap() != &signature {
+                        return Err(parse::Error::new(
+                            backoff.ident.span(),
+                            format!("Backoff `{}` can only be reused when it is async everywhere or nowhere, and is not used as an action", backoff.ident),
+                        ));
+                    }
+                }
+
+                // Inline actions take the context followed by the arguments of the action.
+                for action in transition
+                    .preceding_actions
+                    .iter()
+                    .chain(&transition.action)
+                {
+                    if let Some(inline_action) = sm.inline_actions.get(&action.ident.to_string()) {
+                        let mut parameters = vec!["the context"];
+                        if sm.temporary_context_type.is_some() {
+                            parameters.push("the temporary context");
+                        }
+                        if in_state_data.is_some() {
+                            parameters.push("the state data");
+                        }
+                        if event_data.is_some() {
+                            parameters.push("the event data");
+                        }
+                        if inline_action.inputs.len() != parameters.len() {
+                            return Err(parse::Error::new(
+                                inline_action.ident.span(),
+                                format!(
+                                    "This inline action needs {} parameters: {}.",
+                                    parameters.len(),
+                                    parameters.join(", ")
+                                ),
+                            ));
+                        }
+                    }
+                }
+
+                // Actions of the branches of a choice take the data of the choice.
+                for branch in transition.choice.iter().flat_map(|c| c.branches.iter()) {
+                    if let Some(action) = &branch.action {
+                        let choice_data = transition.choice.as_ref().unwrap().data_type.as_ref();
+                        let out_state_data = sm
+                            .state_data
+                            .data_types
+                            .get(&branch.out_state.ident.to_string());
+                        let signature = FunctionSignature::new(
+                            None,
+                            choice_data,
+                            out_state_data,
+                            action.is_async,
+                        );
+
+                        actions
+                            .entry(action.ident.to_string())
+                            .or_insert_with(|| signature.clone());
+
+                        if actions.get(&action.ident.to_string()).unwrap() != &signature {
+                            return Err(parse::Error::new(
+                                action.ident.span(),
+                                format!("Action `{}` can only be reused when all input states, events, and output states have the same data", action.ident),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Entry and exit handlers are called like actions which only take the data of their state.
+    let mut handlers: Vec<_> = sm.state_handlers.iter().collect();
+    handlers.sort_by_key(|(state, _)| state.as_str());
+    for (state, state_handlers) in handlers {
+        let state_data = sm.state_data.data_types.get(state);
+        for handler in state_handlers
+            .entry
+            .iter()
+            .chain(state_handlers.exit.iter())
+        {
+            let signature = FunctionSignature::new(state_data, None, None, handler.is_async);
+
+            actions
+                .entry(handler.ident.to_string())
+                .or_insert_with(|| signature.clone());
+
+            if actions.get(&handler.ident.to_string()).unwrap() != &signature {
+                return Err(parse::Error::new(
+                    handler.ident.span(),
+                    format!("Entry and exit handler `{}` can only be reused when all states and actions it is used for have the same data", handler.ident),
+                ));
             }
         }
     }
@@ -111,17 +269,32 @@ fn validate_guard_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Error
 
     let all_transitions = &sm.states_events_mapping;
 
-    for (in_state_name, from_transitions) in all_transitions.iter() {
+    for (in_state_name, from_transitions) in sorted(all_transitions) {
         let in_state_data = sm.state_data.data_types.get(in_state_name);
 
-        for (_out_state_name, event_mapping) in from_transitions.iter() {
+        for (_out_state_name, event_mapping) in sorted(from_transitions) {
             // Get the data associated with this event.
             let event_data = sm
                 .event_data
                 .data_types
                 .get(&event_mapping.event.to_string());
             for transition in &event_mapping.transitions {
-                if let Some(guard_expression) = &transition.guard {
+                // Guards of the branches of a choice take the data of the choice.
+                let choice_guards = transition.choice.iter().flat_map(|choice| {
+                    choice.branches.iter().filter_map(move |branch| {
+                        branch
+                            .guard
+                            .as_ref()
+                            .map(|guard| (guard, None, choice.data_type.as_ref()))
+                    })
+                });
+                let guards_data = transition
+                    .guard
+                    .as_ref()
+                    .map(|guard| (guard, in_state_data, event_data))
+                    .into_iter()
+                    .chain(choice_guards);
+                for (guard_expression, in_state_data, event_data) in guards_data {
                     let res = visit_guards(guard_expression, |guard| {
                         let signature =
                             FunctionSignature::new_guard(in_state_data, event_data, guard.is_async);
@@ -135,7 +308,7 @@ fn validate_guard_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Error
                         // guard.
                         if guards.get(&guard.ident.to_string()).unwrap() != &signature {
                             return Err(parse::Error::new(
-                                Span::call_site(),
+                                guard.ident.span(),
                                 format!("Guard `{}` can only be reused when all input states and events have the same data", guard.ident),
                             ));
                         }
@@ -149,19 +322,163 @@ fn validate_guard_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Error
 
     Ok(())
 }
+// Verify that the borrows of the context are declared for guards and actions, and that guards with
+// a timeout don't borrow the context mutably, as the clock is borrowed from it while they run.
+fn validate_context_access(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+    let mut names = HashSet::new();
+    for (_, from_transitions) in sorted(&sm.states_events_mapping) {
+        for (_, event_mapping) in sorted(from_transitions) {
+            for transition in &event_mapping.transitions {
+                let actions = transition
+                    .action
+                    .iter()
+                    .chain(&transition.preceding_actions)
+                    .chain(transition.retry.as_ref().and_then(|r| r.backoff.as_ref()));
+                let branches = transition.choice.iter().flat_map(|c| &c.branches);
+                names.extend(actions.map(|action| action.ident.to_string()));
+                names.extend(
+                    branches
+                        .clone()
+                        .filter_map(|branch| branch.action.as_ref())
+                        .map(|action| action.ident.to_string()),
+                );
+
+                let guards = transition
+                    .guard
+                    .iter()
+                    .chain(branches.filter_map(|branch| branch.guard.as_ref()));
+                for guard_expression in guards {
+                    let mut timed_guards = Vec::new();
+                    visit_guards(guard_expression, |guard| {
+                        names.insert(guard.ident.to_string());
+                        Ok(())
+                    })?;
+                    collect_timed_guards(guard_expression, &mut timed_guards);
+                    for guard in timed_guards {
+                        if sm.context_access.is_mutable_guard(&guard.to_string()) {
+                            return Err(parse::Error::new(
+                                guard.span(),
+                                format!(
+                                    "Guard `{}` has a timeout, so it cannot borrow the context \
+                                     mutably.",
+                                    guard
+                                ),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for (name, (ident, _)) in sorted(&sm.context_access.overrides) {
+        if !names.contains(name) {
+            return Err(parse::Error::new(
+                ident.span(),
+                format!("`{}` is neither a guard nor an action.", name),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Verify that no state is named so that its constructor `new_in_<state>` is `new_in_state`.
+fn validate_state_constructors(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+    for (name, ident) in sorted(&sm.states) {
+        let named =
+            !sm.composite_states.contains_key(name) && !sm.region_parents.contains_key(name);
+        if named && string_morph::to_snake_case(name) == "state" {
+            return Err(parse::Error::new(
+                ident.span(),
+                format!(
+                    "The constructor of state `{}` would be `new_in_state`, which starts the \
+                     state machine in any state.",
+                    name
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Collects the guards with a timeout of a guard expression.
+fn collect_timed_guards<'a>(expr: &'a GuardExpression, timed_guards: &mut Vec<&'a Ident>) {
+    match expr {
+        GuardExpression::Timeout(guard, _) => timed_guards.push(&guard.ident),
+        GuardExpression::Guard(_) => {}
+        GuardExpression::Not(inner) | GuardExpression::Group(inner) => {
+            collect_timed_guards(inner, timed_guards)
+        }
+        GuardExpression::And(left, right) | GuardExpression::Or(left, right) => {
+            collect_timed_guards(left, timed_guards);
+            collect_timed_guards(right, timed_guards);
+        }
+    }
+}
+
 fn validate_unreachable_transitions(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
     let all_transitions = &sm.states_events_mapping;
-    for (in_state, event_mappings) in all_transitions {
-        for (event, event_mapping) in event_mappings {
+    for (in_state, event_mappings) in sorted(all_transitions) {
+        // Events handled explicitly take precedence over a catch-all transition, which is
+        // unreachable when all events are handled explicitly
+        if event_mappings.contains_key(ANY_EVENT) {
+            let deferred = sm.deferred_events.get(in_state);
+            let delegated = sm.sub_machines.get(in_state);
+            let unhandled = sm.events.values().any(|event| {
+                !event_mappings.contains_key(&event.to_string())
+                    && !deferred.is_some_and(|deferred| deferred.contains(event))
+                    && !delegated.is_some_and(|delegated| delegated.contains(event))
+            });
+            if !unhandled {
+                return Err(parse::Error::new(
+                    event_mappings[ANY_EVENT].transitions[0].span,
+                    format!("{} + _: catch-all transition is unreachable because all events are handled explicitly", in_state),
+                ));
+            }
+        }
+        for (event, event_mapping) in sorted(event_mappings) {
             // more than single transition for (in_state,event)
             if event_mapping.transitions.len() > 1 {
+                // Transitions are sorted by their priorities, which are reported when any of them
+                // is explicit
+                let prioritized = event_mapping
+                    .transitions
+                    .iter()
+                    .any(|t| t.priority.is_some());
+                let mut unguarded_priority = None;
                 let mut unguarded_count = 0;
+                let mut fallback_count = 0;
                 for t in &event_mapping.transitions {
-                    if let Some(g) = &t.guard {
+                    let priority = t.priority.unwrap_or_default();
+                    if t.fallback {
+                        // fallback, which is kept after all other transitions
+                        fallback_count += 1;
+                        if unguarded_count > 0 {
+                            return Err(parse::Error::new(
+                                t.span,
+                                format!("{} + {}: [else] : fallback transition is unreachable because there is an unguarded transition, which handles all cases",
+                                        in_state, event),
+                            ));
+                        }
+                        if fallback_count > 1 {
+                            return Err(parse::Error::new(
+                                t.span,
+                                format!("{} + {}: [else] : only one fallback transition can be specified for a state and event combination", in_state, event),
+                            ));
+                        }
+                    } else if let Some(g) = &t.guard {
+                        if let (true, Some(unguarded_priority)) = (prioritized, unguarded_priority)
+                        {
+                            return Err(parse::Error::new(
+                                t.span,
+                                format!("{} + {}: [{}] : guarded transition with priority {} is unreachable because an unguarded transition with priority {}, which is tried first, handles all cases",
+                                        in_state, event, g, priority, unguarded_priority),
+                            ));
+                        }
                         if unguarded_count > 0 {
                             // Guarded transition AFTER an unguarded one
                             return Err(parse::Error::new(
-                                Span::call_site(),
+                                t.span,
                                 format!("{} + {}: [{}] : guarded transition is unreachable because it follows an unguarded transition, which handles all cases",
                                         in_state, event, g),
                             ));
@@ -169,9 +486,22 @@ fn validate_unreachable_transitions(sm: &ParsedStateMachine) -> Result<(), parse
                     } else {
                         // unguarded
                         unguarded_count += 1;
+                        match unguarded_priority {
+                            Some(unguarded_priority)
+                                if prioritized && unguarded_priority != priority =>
+                            {
+                                return Err(parse::Error::new(
+                                    t.span,
+                                    format!("{} + {}: unguarded transition with priority {} is unreachable because an unguarded transition with priority {}, which is tried first, handles all cases",
+                                            in_state, event, priority, unguarded_priority),
+                                ));
+                            }
+                            None => unguarded_priority = Some(priority),
+                            _ => {}
+                        }
                         if unguarded_count > 1 {
                             return Err(parse::Error::new(
-                                Span::call_site(),
+                                t.span,
                                 format!("{} + {}: State and event combination specified multiple times, remove duplicates.", in_state, event),
                             ));
                         }
@@ -183,10 +513,614 @@ fn validate_unreachable_transitions(sm: &ParsedStateMachine) -> Result<(), parse
     Ok(())
 }
 
-/// Validate coherency of the state machine.
-pub fn validate(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+/// Returns the names of the states which can be reached from the starting state.
+pub fn reachable_states(sm: &ParsedStateMachine) -> HashSet<String> {
+    let mut from = vec![sm.starting_state.clone()];
+    // The fault state is entered when any action fails
+    from.extend(sm.fault_state.iter().map(|fault| sm.resolve_initial(fault)));
+    explore(sm, &from, &[]).into_keys().collect()
+}
+
+/// Returns the parallel state a region belongs to.
+fn parallel_of<'a>(sm: &'a ParsedStateMachine, region: &Ident) -> Option<&'a Ident> {
+    sm.parallel_states
+        .values()
+        .find(|parallel| parallel.regions.iter().any(|r| r.ident == *region))
+        .map(|parallel| &parallel.ident)
+}
+
+/// Returns whether `state` is `outer` or one of its sub-states, including those of its regions.
+fn contains(sm: &ParsedStateMachine, outer: &Ident, state: &str) -> bool {
+    let outer_name = outer.to_string();
+    state == outer_name
+        || sm.is_descendant(state, &outer_name)
+        || sm
+            .region_parents
+            .get(state)
+            .is_some_and(|region| region == outer || parallel_of(sm, region) == Some(outer))
+}
+
+/// Searches the states which can be reached from the states `from` without passing the states
+/// `blocked`, assuming that every guard can pass. Every reached state is returned along with the
+/// state it has first been reached from, which are the steps of the shortest paths.
+fn explore(
+    sm: &ParsedStateMachine,
+    from: &[Ident],
+    blocked: &[Ident],
+) -> HashMap<String, Option<String>> {
+    let mut reached = HashMap::new();
+    // The states to visit, along with their predecessor and whether they are entered, as the
+    // parallel state of a region is active without entering its regions again
+    let mut pending: VecDeque<(Ident, Option<String>, bool)> = from
+        .iter()
+        .map(|state| (state.clone(), None, true))
+        .collect();
+    let mut visited = HashSet::new();
+    while let Some((state, predecessor, entered)) = pending.pop_front() {
+        let name = state.to_string();
+        if blocked.iter().any(|b| contains(sm, b, &name))
+            || !visited.insert((name.clone(), entered))
+        {
+            continue;
+        }
+        reached.entry(name.clone()).or_insert(predecessor);
+        let next = Some(name.clone());
+
+        // Composite states are active along with their sub-states, and parallel states along
+        // with the sub-states of their regions
+        pending.extend(
+            sm.ancestors(&name)
+                .into_iter()
+                .chain(
+                    sm.region_parents
+                        .get(&name)
+                        .and_then(|r| parallel_of(sm, r)),
+                )
+                .map(|ancestor| (ancestor.clone(), next.clone(), false)),
+        );
+
+        // Entering a parallel state enters the initial sub-states of all its regions
+        if let Some(parallel) = sm.parallel_states.get(&name) {
+            if entered {
+                pending.extend(
+                    parallel
+                        .regions
+                        .iter()
+                        .filter_map(|r| r.initial.clone())
+                        .map(|initial| (initial, next.clone(), true)),
+                );
+            }
+            pending.extend(
+                parallel
+                    .completion
+                    .iter()
+                    .map(|c| (sm.resolve_initial(c), next.clone(), true)),
+            );
+        }
+
+        let mappings = sm.states_events_mapping.get(&name);
+        for transition in mappings
+            .into_iter()
+            .flat_map(|mappings| mappings.values())
+            .flat_map(|mapping| mapping.transitions.iter())
+        {
+            match &transition.choice {
+                Some(choice) => pending.extend(choice.branches.iter().map(|branch| {
+                    (
+                        sm.resolve_initial(&branch.out_state.ident),
+                        next.clone(),
+                        true,
+                    )
+                })),
+                None if !transition.keep_state => pending.push_back((
+                    sm.resolve_initial(&transition.out_state),
+                    next.clone(),
+                    true,
+                )),
+                None => {}
+            }
+
+            // Failing actions outside of regions enter the fault state
+            let has_actions = transition.action.is_some()
+                || !transition.preceding_actions.is_empty()
+                || transition
+                    .choice
+                    .iter()
+                    .any(|choice| choice.branches.iter().any(|branch| branch.action.is_some()));
+            if has_actions && !sm.region_parents.contains_key(&name) {
+                pending.extend(
+                    sm.fault_state
+                        .iter()
+                        .map(|fault| (sm.resolve_initial(fault), next.clone(), true)),
+                );
+            }
+        }
+    }
+    reached
+}
+
+/// Returns the states which cannot be reached from the starting state, sorted by name.
+fn unreachable_states(sm: &ParsedStateMachine) -> Vec<&Ident> {
+    let reached = reachable_states(sm);
+    let mut unreachable: Vec<&Ident> = sm
+        .states
+        .values()
+        .chain(sm.region_states.values())
+        .chain(
+            sm.composite_states
+                .values()
+                .map(|composite| &composite.ident),
+        )
+        .chain(sm.parallel_states.values().map(|parallel| &parallel.ident))
+        .filter(|state| !reached.contains(&state.to_string()))
+        .collect();
+    unreachable.sort_by_key(|state| state.to_string());
+    unreachable.dedup_by_key(|state| state.to_string());
+    unreachable
+}
+
+/// Checks the reachability properties declared in the `assertions` block, assuming that every
+/// guard can pass.
+fn validate_assertions(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+    for assertion in &sm.assertions {
+        for state in core::iter::once(&assertion.target)
+            .chain(&assertion.source)
+            .chain(&assertion.without)
+        {
+            let name = state.to_string();
+            if !sm.states.contains_key(&name)
+                && !sm.region_states.contains_key(&name)
+                && !sm.composite_states.contains_key(&name)
+                && !sm.parallel_states.contains_key(&name)
+            {
+                return Err(parse::Error::new(
+                    state.span(),
+                    format!("Unknown state {} in assertion.", state),
+                ));
+            }
+        }
+
+        let target = assertion.target.to_string();
+        let sources = match &assertion.source {
+            Some(source) => vec![source],
+            // Every state reachable from the starting state, besides the target itself
+            None => {
+                let reachable = reachable_states(sm);
+                let mut sources: Vec<&Ident> = sm
+                    .states
+                    .values()
+                    .chain(sm.region_states.values())
+                    .filter(|state| {
+                        let name = state.to_string();
+                        reachable.contains(&name)
+                            && !sm.composite_states.contains_key(&name)
+                            && !sm.parallel_states.contains_key(&name)
+                            && !contains(sm, &assertion.target, &name)
+                    })
+                    .collect();
+                sources.sort_by_key(|state| state.to_string());
+                sources.dedup_by_key(|state| state.to_string());
+                sources
+            }
+        };
+        for source in sources {
+            let reached = explore(sm, &[sm.resolve_initial(source)], &assertion.without);
+            match (assertion.reachable, reached.contains_key(&target)) {
+                (true, false) => {
+                    return Err(parse::Error::new(
+                        assertion.target.span(),
+                        format!(
+                            "Assertion failed: state {} is unreachable from {}.",
+                            target, source
+                        ),
+                    ));
+                }
+                (false, true) => {
+                    let mut path = vec![target.clone()];
+                    while let Some(Some(previous)) = reached.get(path.last().unwrap()) {
+                        path.push(previous.clone());
+                    }
+                    path.reverse();
+                    let passing = if assertion.without.is_empty() {
+                        String::new()
+                    } else {
+                        let without: Vec<String> =
+                            assertion.without.iter().map(|s| s.to_string()).collect();
+                        format!(" without passing {}", without.join(", "))
+                    };
+                    return Err(parse::Error::new(
+                        assertion.target.span(),
+                        format!(
+                            "Assertion failed: state {} is reachable from {}{}, along {}.",
+                            target,
+                            source,
+                            passing,
+                            path.join(" -> ")
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A finding of a lint, which is reported according to the level of the lint.
+struct Finding {
+    span: Span,
+    /// The name of the lint, which names the deprecated constant raising its warnings.
+    lint: &'static str,
+    message: String,
+}
+
+/// Reports every state which cannot be reached from the starting state.
+fn unreachable_state_findings(sm: &ParsedStateMachine) -> Vec<Finding> {
+    unreachable_states(sm)
+        .into_iter()
+        .map(|state| Finding {
+            span: state.span(),
+            lint: "unreachable_states",
+            message: format!(
+                "State {} is unreachable from the starting state {}",
+                state, sm.starting_state
+            ),
+        })
+        .collect()
+}
+
+/// Reports every event which is not handled by a state which can be reached from the starting
+/// state, where all states are considered reachable when unreachable states are not denied.
+fn unused_event_findings(sm: &ParsedStateMachine) -> Vec<Finding> {
+    let reached = reachable_states(sm);
+    let reachable =
+        |state: &String| sm.lints.unreachable_states != Level::Deny || reached.contains(state);
+
+    let mut used = HashSet::new();
+    for (state, event_mappings) in &sm.states_events_mapping {
+        if !reachable(state) {
+            continue;
+        }
+        // A catch-all transition handles all events
+        if event_mappings.contains_key(ANY_EVENT) {
+            return Vec::new();
+        }
+        used.extend(event_mappings.keys().cloned());
+    }
+    for (state, delegated) in &sm.sub_machines {
+        if reachable(state) {
+            used.extend(delegated.iter().map(|event| event.to_string()));
+        }
+    }
+
+    let mut unused: Vec<&Ident> = sm
+        .events
+        .iter()
+        .filter(|(name, _)| !used.contains(*name) && !sm.unused_events.contains(*name))
+        .map(|(_, event)| event)
+        .collect();
+    unused.sort_by_key(|event| event.to_string());
+    unused
+        .into_iter()
+        .map(|event| Finding {
+            span: event.span(),
+            lint: "unused_events",
+            message: format!(
+                "Event {} is not handled by any state reachable from the starting state {}, remove it or declare it with `{} {{ allow_unused: true }}` in `events`",
+                event, sm.starting_state, event
+            ),
+        })
+        .collect()
+}
+
+/// Reports every state which neither handles, defers, delegates nor ignores an event, where
+/// parallel states handle the events of all their regions.
+fn unhandled_event_findings(sm: &ParsedStateMachine) -> Vec<Finding> {
+    let mut states: Vec<&Ident> = sm
+        .states
+        .values()
+        .filter(|state| !sm.terminal_states.contains_key(&state.to_string()))
+        .chain(sm.parallel_states.values().map(|parallel| &parallel.ident))
+        .collect();
+    states.sort_by_key(|state| state.to_string());
+
+    states
+        .into_iter()
+        .filter_map(|state| {
+            let name = state.to_string();
+            let mut handling = vec![name.clone()];
+            if let Some(parallel) = sm.parallel_states.get(&name) {
+                handling.extend(
+                    sm.region_parents
+                        .iter()
+                        .filter(|(_, region)| parallel.regions.iter().any(|r| r.ident == **region))
+                        .map(|(sub_state, _)| sub_state.clone()),
+                );
+            }
+
+            let handles = |event: &Ident| {
+                handling.iter().any(|state| {
+                    sm.states_events_mapping.get(state).is_some_and(|mappings| {
+                        mappings.contains_key(ANY_EVENT)
+                            || mappings.contains_key(&event.to_string())
+                    }) || sm
+                        .deferred_events
+                        .get(state)
+                        .is_some_and(|deferred| deferred.contains(event))
+                        || sm
+                            .sub_machines
+                            .get(state)
+                            .is_some_and(|delegated| delegated.contains(event))
+                })
+            };
+            let mut unhandled: Vec<String> = sm
+                .events
+                .values()
+                .filter(|event| !handles(event))
+                .map(|event| event.to_string())
+                .collect();
+            unhandled.sort();
+
+            (!unhandled.is_empty()).then(|| Finding {
+                span: state.span(),
+                lint: "unhandled_events",
+                message: format!(
+                    "State {} does not handle {}, add transitions or `ignore` declarations such as `{} + {} = ignore`",
+                    state,
+                    unhandled.join(", "),
+                    state,
+                    unhandled[0]
+                ),
+            })
+        })
+        .collect()
+}
+
+/// Evaluates a guard expression, where the guards are assigned the bits of `assignment` in the
+/// order of `guards`.
+fn evaluate(expr: &GuardExpression, guards: &[String], assignment: usize) -> bool {
+    match expr {
+        GuardExpression::Guard(guard) | GuardExpression::Timeout(guard, _) => {
+            let index = guards.iter().position(|g| guard.ident == g);
+            assignment & (1 << index.unwrap()) != 0
+        }
+        GuardExpression::Not(expr) => !evaluate(expr, guards, assignment),
+        GuardExpression::Group(expr) => evaluate(expr, guards, assignment),
+        GuardExpression::And(lhs, rhs) => {
+            evaluate(lhs, guards, assignment) && evaluate(rhs, guards, assignment)
+        }
+        GuardExpression::Or(lhs, rhs) => {
+            evaluate(lhs, guards, assignment) || evaluate(rhs, guards, assignment)
+        }
+    }
+}
+
+/// The largest number of distinct guards whose combinations are analyzed.
+const MAX_ANALYZED_GUARDS: usize = 12;
+
+/// Reports the guard expressions which always or never pass, and those which only pass when one of
+/// the guard expressions tried before them passes, where `guards` are in the order they are tried.
+fn lint_guard_expressions(
+    transition: &str,
+    guards: &[&GuardExpression],
+    findings: &mut Vec<Finding>,
+) {
+    for (index, guard) in guards.iter().enumerate() {
+        let mut names = Vec::new();
+        for expr in &guards[..=index] {
+            let _ = visit_guards(expr, |g| {
+                names.push(g.ident.to_string());
+                Ok(())
+            });
+        }
+        names.sort();
+        names.dedup();
+        if names.len() > MAX_ANALYZED_GUARDS {
+            continue;
+        }
+
+        // The guards are visited from right to left, so the span is the one of the first guard
+        let mut span = None;
+        let _ = visit_guards(guard, |g| {
+            span = Some(g.ident.span());
+            Ok(())
+        });
+        let span = span.unwrap();
+
+        let passing: Vec<usize> = (0..1 << names.len())
+            .filter(|&assignment| evaluate(guard, &names, assignment))
+            .collect();
+        if passing.is_empty() {
+            findings.push(Finding {
+                span,
+                lint: "contradictory_guards",
+                message: format!("Guard `{}` of {} never passes", guard, transition),
+            });
+        } else if passing.len() == 1 << names.len() {
+            findings.push(Finding {
+                span,
+                lint: "tautological_guards",
+                message: format!("Guard `{}` of {} always passes", guard, transition),
+            });
+        } else if index > 0
+            && passing.iter().all(|&assignment| {
+                guards[..index]
+                    .iter()
+                    .any(|earlier| evaluate(earlier, &names, assignment))
+            })
+        {
+            findings.push(Finding {
+                span,
+                lint: "shadowed_guards",
+                message: format!(
+                    "Guard `{}` of {} only passes when a guard tried before it passes",
+                    guard, transition
+                ),
+            });
+        }
+    }
+}
+
+/// Reports the guards of transitions and choices which always or never pass, or are shadowed by
+/// the guards tried before them.
+fn guard_findings(sm: &ParsedStateMachine) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (in_state, event_mappings) in sorted(&sm.states_events_mapping) {
+        for (_, event_mapping) in sorted(event_mappings) {
+            let transition = format!("{} + {}", in_state, event_mapping.event);
+            let guards: Vec<&GuardExpression> = event_mapping
+                .transitions
+                .iter()
+                .filter_map(|t| t.guard.as_ref())
+                .collect();
+            lint_guard_expressions(&transition, &guards, &mut findings);
+
+            for choice in event_mapping
+                .transitions
+                .iter()
+                .filter_map(|t| t.choice.as_ref())
+            {
+                let guards: Vec<&GuardExpression> = choice
+                    .branches
+                    .iter()
+                    .filter_map(|branch| branch.guard.as_ref())
+                    .collect();
+                lint_guard_expressions(
+                    &format!("the choice of {}", transition),
+                    &guards,
+                    &mut findings,
+                );
+            }
+        }
+    }
+    findings
+}
+
+/// Reports the states and events handled by the transitions of several patterns, whose overlap
+/// is not resolved by a transition declared for the state alone or by a wildcard, which only
+/// applies to the states not handling the event otherwise.
+fn overlapping_transition_findings(sm: &ParsedStateMachine) -> Vec<Finding> {
+    let event_name = |event: &str| if event == ANY_EVENT { "_" } else { event }.to_string();
+
+    let mut findings = Vec::new();
+    for (in_state, event_mappings) in sorted(&sm.states_events_mapping) {
+        for (event, event_mapping) in sorted(event_mappings) {
+            let mut patterns = event_mapping.transitions.iter().filter(|t| t.pattern);
+            if let (Some(_), Some(transition)) = (patterns.next(), patterns.next()) {
+                findings.push(Finding {
+                    span: transition.span,
+                    lint: "overlapping_transitions",
+                    message: format!(
+                        "Transitions of several patterns handle {} + {}, which are tried in the order of their priorities and declaration",
+                        in_state,
+                        event_name(event)
+                    ),
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// Validate coherency of the state machine, returning the code raising the warnings of the lints.
+pub fn validate(sm: &ParsedStateMachine) -> Result<TokenStream, parse::Error> {
     validate_action_signatures(sm)?;
     validate_guard_signatures(sm)?;
+    validate_context_access(sm)?;
+    validate_state_constructors(sm)?;
     validate_unreachable_transitions(sm)?;
-    Ok(())
+    validate_assertions(sm)?;
+
+    let findings = unreachable_state_findings(sm)
+        .into_iter()
+        .chain(unused_event_findings(sm))
+        .chain(unhandled_event_findings(sm))
+        .chain(guard_findings(sm))
+        .chain(overlapping_transition_findings(sm));
+    let mut errors: Option<parse::Error> = None;
+    let mut warnings = TokenStream::new();
+    for finding in findings {
+        match sm.lints.level(finding.lint) {
+            Level::Deny => {
+                let error = parse::Error::new(
+                    finding.span,
+                    format!("{} (lint `{}`).", finding.message, finding.lint),
+                );
+                match &mut errors {
+                    Some(errors) => errors.combine(error),
+                    None => errors = Some(error),
+                }
+            }
+            Level::Warn => {
+                // Proc macros cannot raise warnings on stable, so the finding is reported by using
+                // a deprecated constant at its span
+                let note = format!("{} (lint `{}`)", finding.message, finding.lint);
+                let constant = Ident::new(finding.lint, finding.span);
+                warnings.extend(quote! {
+                    const _: () = {
+                        #[deprecated(note = #note)]
+                        #[allow(non_upper_case_globals)]
+                        const #constant: () = ();
+                        #constant
+                    };
+                });
+            }
+            Level::Allow => {}
+        }
+    }
+
+    match errors {
+        Some(errors) => Err(errors),
+        None => Ok(warnings),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::{state_machine::StateMachine, ParsedStateMachine};
+
+    #[test]
+    fn guard_warnings() -> Result<(), syn::Error> {
+        let sm = ParsedStateMachine::new(syn::parse_str::<StateMachine>(
+            "transitions: {
+                *Idle + Start [ ready || !ready ] = Running,
+                Idle + Stop [ ready && !ready ] = Stopped,
+                Running + Stop [ ready ] = Idle,
+                Running + Stop [ ready && forced ] = Stopped,
+                Running + Stop [ forced ] = Stopped,
+                Running + Check / measure = choice(u32) {
+                    [ high || low ] = Idle,
+                    [ low ] = Stopped,
+                    = Running,
+                },
+            }",
+        )?)?;
+
+        let notes: Vec<_> = super::guard_findings(&sm)
+            .into_iter()
+            .map(|finding| (finding.lint, finding.message))
+            .collect();
+        assert_eq!(
+            notes,
+            [
+                (
+                    "tautological_guards",
+                    "Guard `ready() || !ready()` of Idle + Start always passes".to_string()
+                ),
+                (
+                    "contradictory_guards",
+                    "Guard `ready() && !ready()` of Idle + Stop never passes".to_string()
+                ),
+                (
+                    "shadowed_guards",
+                    "Guard `low()` of the choice of Running + Check only passes when a guard tried before it passes".to_string()
+                ),
+                (
+                    "shadowed_guards",
+                    "Guard `ready() && forced()` of Running + Stop only passes when a guard tried before it passes".to_string()
+                ),
+            ]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,553 @@
+//! Exports a state machine as an xstate v5 JSON machine definition, which can be visualized and
+//! simulated in the browser.
+
+use crate::parser::event::{ANY_EVENT, COMPLETION_EVENT, TIMEOUT_EVENT};
+use crate::parser::transition::GuardExpression;
+use crate::parser::*;
+use std::collections::HashMap;
+
+/// Generates a string containing the xstate machine definition of the state machine.
+///
+/// Transitions target the ids of their states, guards and actions are referenced by name, and
+/// choices are exported as transient states with `always` transitions.
+pub fn generate_xstate(sm: &ParsedStateMachine) -> String {
+    let machine = Machine::new(sm);
+    let mut definition = vec![
+        ("id".to_string(), Json::from(machine.id.as_str())),
+        (
+            "initial".to_string(),
+            Json::from(machine.top_level(&sm.starting_state.to_string())),
+        ),
+    ];
+    definition.push(("states".to_string(), machine.children(None)));
+
+    let mut json = String::new();
+    Json::Object(definition).write(0, &mut json);
+    json.push('\n');
+    json
+}
+
+/// A JSON value, written with the keys of objects in order.
+enum Json {
+    Object(Vec<(String, Json)>),
+    Array(Vec<Json>),
+    String(String),
+    Bool(bool),
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl Json {
+    fn write(&self, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth + 1);
+        match self {
+            Json::Object(fields) if fields.is_empty() => out.push_str("{}"),
+            Json::Object(fields) => {
+                out.push_str("{\n");
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    out.push_str(&format!("{indent}{}: ", quote(key)));
+                    value.write(depth + 1, out);
+                    out.push_str(if index + 1 < fields.len() {
+                        ",\n"
+                    } else {
+                        "\n"
+                    });
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push('}');
+            }
+            Json::Array(values) => {
+                out.push_str("[\n");
+                for (index, value) in values.iter().enumerate() {
+                    out.push_str(&indent);
+                    value.write(depth + 1, out);
+                    out.push_str(if index + 1 < values.len() {
+                        ",\n"
+                    } else {
+                        "\n"
+                    });
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push(']');
+            }
+            Json::String(value) => out.push_str(&quote(value)),
+            Json::Bool(value) => out.push_str(&value.to_string()),
+        }
+    }
+}
+
+/// Quotes a JSON string, escaping quotes and backslashes.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The states of a state machine, with the composite state, parallel state or region each state
+/// is nested in.
+struct Machine<'a> {
+    sm: &'a ParsedStateMachine,
+    id: String,
+    parents: HashMap<String, String>,
+}
+
+impl<'a> Machine<'a> {
+    fn new(sm: &'a ParsedStateMachine) -> Self {
+        let mut parents = HashMap::new();
+        for (state, parent) in sm.state_parents.iter().chain(sm.region_parents.iter()) {
+            parents.insert(state.clone(), parent.to_string());
+        }
+        for parallel in sm.parallel_states.values() {
+            for region in &parallel.regions {
+                parents.insert(region.ident.to_string(), parallel.ident.to_string());
+            }
+        }
+        let id = match &sm.name {
+            Some(name) => name.to_string(),
+            None => "StateMachine".to_string(),
+        };
+        Self { sm, id, parents }
+    }
+
+    /// The outermost state `state` is nested in, or `state` itself.
+    fn top_level(&self, state: &str) -> String {
+        let mut state = state;
+        while let Some(parent) = self.parents.get(state) {
+            state = parent;
+        }
+        state.to_string()
+    }
+
+    /// The id of a state, which is its path from the machine.
+    fn state_id(&self, state: &str) -> String {
+        let mut path = vec![state];
+        while let Some(parent) = self.parents.get(*path.last().unwrap()) {
+            path.push(parent);
+        }
+        path.push(&self.id);
+        path.reverse();
+        format!("#{}", path.join("."))
+    }
+
+    /// The state nodes nested in `scope`, or the top-level ones.
+    fn children(&self, scope: Option<&str>) -> Json {
+        let sm = self.sm;
+        let mut states: Vec<_> = sm
+            .states
+            .keys()
+            .chain(sm.region_states.keys())
+            .chain(sm.composite_states.keys())
+            .chain(sm.parallel_states.keys())
+            .filter(|state| self.parents.get(*state).map(String::as_str) == scope)
+            .collect();
+        states.sort();
+        states.dedup();
+
+        let mut nodes = Vec::new();
+        if let Some(composite) = scope.and_then(|scope| sm.composite_states.get(scope)) {
+            if let Some(history) = composite.history {
+                let kind = match history {
+                    composite_state::History::Shallow => "shallow",
+                    composite_state::History::Deep => "deep",
+                };
+                nodes.push((
+                    format!("{}_history", composite.ident),
+                    Json::Object(vec![
+                        ("type".to_string(), Json::from("history")),
+                        ("history".to_string(), Json::from(kind)),
+                    ]),
+                ));
+            }
+        }
+        for state in states {
+            let mut choices = Vec::new();
+            nodes.push((state.clone(), self.state(state, &mut choices)));
+            nodes.extend(choices);
+        }
+        Json::Object(nodes)
+    }
+
+    /// The state node of a state, where the transient states of its choices are added to
+    /// `choices`.
+    fn state(&self, state: &str, choices: &mut Vec<(String, Json)>) -> Json {
+        let sm = self.sm;
+        let mut node = Vec::new();
+        if let Some(composite) = sm.composite_states.get(state) {
+            let initial = composite.initial.as_ref().unwrap().to_string();
+            node.push(("initial".to_string(), Json::from(initial)));
+            node.push(("states".to_string(), self.children(Some(state))));
+        } else if let Some(parallel) = sm.parallel_states.get(state) {
+            node.push(("type".to_string(), Json::from("parallel")));
+            let regions = parallel
+                .regions
+                .iter()
+                .map(|region| {
+                    let name = region.ident.to_string();
+                    let initial = region.initial.as_ref().unwrap().to_string();
+                    let states = self.children(Some(&name));
+                    (
+                        name,
+                        Json::Object(vec![
+                            ("initial".to_string(), Json::from(initial)),
+                            ("states".to_string(), states),
+                        ]),
+                    )
+                })
+                .collect();
+            node.push(("states".to_string(), Json::Object(regions)));
+            // Parallel states are left once all regions have reached a final sub-state
+            if let Some(completion) = &parallel.completion {
+                node.push((
+                    "onDone".to_string(),
+                    Json::Object(vec![(
+                        "target".to_string(),
+                        Json::from(self.target(completion, None)),
+                    )]),
+                ));
+            }
+        } else if sm.terminal_states.contains_key(state) || self.is_region_final(state) {
+            node.push(("type".to_string(), Json::from("final")));
+        }
+
+        if let Some(handlers) = sm.state_handlers.get(state) {
+            if let Some(entry) = &handlers.entry {
+                node.push((
+                    "entry".to_string(),
+                    Json::Array(vec![Json::from(entry.ident.to_string())]),
+                ));
+            }
+            if let Some(exit) = &handlers.exit {
+                node.push((
+                    "exit".to_string(),
+                    Json::Array(vec![Json::from(exit.ident.to_string())]),
+                ));
+            }
+            if let Some(activity) = &handlers.activity {
+                node.push((
+                    "invoke".to_string(),
+                    Json::Object(vec![(
+                        "src".to_string(),
+                        Json::from(activity.ident.to_string()),
+                    )]),
+                ));
+            }
+        }
+
+        if let Some(mappings) = sm.states_events_mapping.get(state) {
+            let mut mappings: Vec<_> = mappings.values().collect();
+            mappings.sort_by_key(|mapping| mapping.event.to_string());
+            let mut on = Vec::new();
+            let mut always = Vec::new();
+            let mut after = Vec::new();
+            for mapping in mappings {
+                let transitions: Vec<_> = mapping
+                    .transitions
+                    .iter()
+                    .enumerate()
+                    .map(|(index, transition)| {
+                        self.transition(state, &mapping.event, index, transition, choices)
+                    })
+                    .collect();
+                match mapping.event.to_string().as_str() {
+                    COMPLETION_EVENT => always.extend(transitions),
+                    ANY_EVENT => on.push(("*".to_string(), transitions)),
+                    TIMEOUT_EVENT => after.push((delay(&sm.timeouts[state]), transitions)),
+                    event => on.push((event.to_string(), transitions)),
+                }
+            }
+            let transitions = |transitions: Vec<(String, Vec<Json>)>| {
+                Json::Object(
+                    transitions
+                        .into_iter()
+                        .map(|(event, transitions)| (event, one_or_many(transitions)))
+                        .collect(),
+                )
+            };
+            if !on.is_empty() {
+                node.push(("on".to_string(), transitions(on)));
+            }
+            if !always.is_empty() {
+                node.push(("always".to_string(), one_or_many(always)));
+            }
+            if !after.is_empty() {
+                node.push(("after".to_string(), transitions(after)));
+            }
+        }
+        Json::Object(node)
+    }
+
+    /// Returns `true` if `state` is a final sub-state of a region, which completes the region.
+    fn is_region_final(&self, state: &str) -> bool {
+        self.sm.region_parents.contains_key(state)
+            && self.sm.states_events_mapping[state].is_empty()
+            && !self.sm.composite_states.contains_key(state)
+    }
+
+    /// The transition config of a transition, where choices target a transient state which is
+    /// added to `choices`.
+    fn transition(
+        &self,
+        state: &str,
+        event: &syn::Ident,
+        index: usize,
+        transition: &event::Transition,
+        choices: &mut Vec<(String, Json)>,
+    ) -> Json {
+        let mut config = Vec::new();
+        match &transition.choice {
+            Some(choice) => {
+                let choice_state = format!("{state}_{event}_choice{index}");
+                let branches = choice
+                    .branches
+                    .iter()
+                    .map(|branch| {
+                        let mut config = vec![(
+                            "target".to_string(),
+                            Json::from(self.target(
+                                &branch.out_state.ident,
+                                branch.out_state.composite.as_ref(),
+                            )),
+                        )];
+                        if let Some(guard) = &branch.guard {
+                            config.push(("guard".to_string(), Json::from(guard_name(guard))));
+                        }
+                        if let Some(action) = &branch.action {
+                            config.push((
+                                "actions".to_string(),
+                                Json::Array(vec![Json::from(action.ident.to_string())]),
+                            ));
+                        }
+                        Json::Object(config)
+                    })
+                    .collect();
+                choices.push((
+                    choice_state.clone(),
+                    Json::Object(vec![("always".to_string(), Json::Array(branches))]),
+                ));
+                let mut path = self.state_id(state);
+                path.truncate(path.len() - state.len());
+                config.push(("target".to_string(), Json::from(path + &choice_state)));
+            }
+            // Transitions without a target state neither exit nor enter the state
+            None if transition.keep_state => (),
+            None => {
+                let target = self.target(&transition.out_state, transition.out_composite.as_ref());
+                config.push(("target".to_string(), Json::from(target)));
+                let external_self_transition = transition.source_composite.is_some()
+                    && transition.source_composite == transition.out_composite;
+                if transition.out_state == state || external_self_transition {
+                    config.push(("reenter".to_string(), Json::Bool(true)));
+                }
+            }
+        }
+        if let Some(guard) = &transition.guard {
+            config.push(("guard".to_string(), Json::from(guard_name(guard))));
+        }
+        let actions: Vec<_> = transition
+            .preceding_actions
+            .iter()
+            .chain(transition.action.iter())
+            .map(|action| Json::from(action.ident.to_string()))
+            .collect();
+        if !actions.is_empty() {
+            config.push(("actions".to_string(), Json::Array(actions)));
+        }
+        Json::Object(config)
+    }
+
+    /// The id targeted by a transition, which is the history of a targeted composite state with
+    /// history and the composite state itself otherwise.
+    fn target(&self, state: &syn::Ident, composite: Option<&syn::Ident>) -> String {
+        let state = composite.unwrap_or(state).to_string();
+        match self.sm.composite_states.get(&state) {
+            Some(composite) if composite.history.is_some() => {
+                format!("{}.{state}_history", self.state_id(&state))
+            }
+            _ => self.state_id(&state),
+        }
+    }
+}
+
+/// A single transition, or the transitions tried in order.
+fn one_or_many(mut transitions: Vec<Json>) -> Json {
+    if transitions.len() == 1 {
+        transitions.remove(0)
+    } else {
+        Json::Array(transitions)
+    }
+}
+
+/// The name of a guard, which is the expression of the names of the guards for guards combining
+/// several guards, e.g. `ready && !busy`.
+fn guard_name(guard: &GuardExpression) -> String {
+    match guard {
+        GuardExpression::Guard(guard) | GuardExpression::Timeout(guard, _) => {
+            guard.ident.to_string()
+        }
+        GuardExpression::Not(guard) => format!("!{}", guard_name(guard)),
+        GuardExpression::Group(guard) => format!("({})", guard_name(guard)),
+        GuardExpression::And(lhs, rhs) => format!("{} && {}", guard_name(lhs), guard_name(rhs)),
+        GuardExpression::Or(lhs, rhs) => format!("{} || {}", guard_name(lhs), guard_name(rhs)),
+    }
+}
+
+/// The delay of a timed transition in milliseconds for `Duration::from_secs` and
+/// `Duration::from_millis` of an integer, and the name of the delay given by the expression
+/// otherwise.
+fn delay(duration: &syn::Expr) -> String {
+    if let syn::Expr::Call(call) = duration {
+        if let (syn::Expr::Path(function), [syn::Expr::Lit(literal)]) =
+            (&*call.func, &call.args.iter().collect::<Vec<_>>()[..])
+        {
+            let scale = match function.path.segments.last() {
+                Some(segment) if segment.ident == "from_secs" => Some(1000),
+                Some(segment) if segment.ident == "from_millis" => Some(1),
+                _ => None,
+            };
+            if let (Some(scale), syn::Lit::Int(value)) = (scale, &literal.lit) {
+                if let Ok(value) = value.base10_parse::<u64>() {
+                    return (value * scale).to_string();
+                }
+            }
+        }
+    }
+    quote::quote!(#duration).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::{state_machine::StateMachine, ParsedStateMachine};
+
+    fn parse(sm: &str) -> Result<ParsedStateMachine, syn::Error> {
+        ParsedStateMachine::new(syn::parse_str::<StateMachine>(sm)?)
+    }
+
+    #[test]
+    fn xstate_machine() -> Result<(), syn::Error> {
+        let sm = parse(
+            "name: Device,
+            transitions: {
+                *Off + PowerOn = On,
+                Off + after(core::time::Duration::from_secs(2)) = Sleeping,
+                Sleeping + PowerOn = On,
+                On + PowerOff = Off,
+                On(H) {
+                    *Idle + Start [ ready && !busy ] / start = Busy,
+                    Busy + Done / measure = choice(u32) {
+                        [ in_range ] = Idle,
+                        = Failed,
+                    },
+                    Busy + Tick / count,
+                },
+                Failed + Discard = Broken @final,
+            },
+            states: {
+                Idle { entry: enter_idle },
+            }",
+        )?;
+
+        assert_eq!(
+            super::generate_xstate(&sm),
+            r##"{
+  "id": "Device",
+  "initial": "Off",
+  "states": {
+    "Broken": {
+      "type": "final"
+    },
+    "Failed": {
+      "on": {
+        "Discard": {
+          "target": "#Device.Broken"
+        }
+      }
+    },
+    "Off": {
+      "on": {
+        "PowerOn": {
+          "target": "#Device.On.On_history"
+        }
+      },
+      "after": {
+        "2000": {
+          "target": "#Device.Sleeping"
+        }
+      }
+    },
+    "On": {
+      "initial": "Idle",
+      "states": {
+        "On_history": {
+          "type": "history",
+          "history": "shallow"
+        },
+        "Busy": {
+          "on": {
+            "Done": {
+              "target": "#Device.On.Busy_Done_choice0",
+              "actions": [
+                "measure"
+              ]
+            },
+            "PowerOff": {
+              "target": "#Device.Off"
+            },
+            "Tick": {
+              "actions": [
+                "count"
+              ]
+            }
+          }
+        },
+        "Busy_Done_choice0": {
+          "always": [
+            {
+              "target": "#Device.On.Idle",
+              "guard": "in_range"
+            },
+            {
+              "target": "#Device.Failed"
+            }
+          ]
+        },
+        "Idle": {
+          "entry": [
+            "enter_idle"
+          ],
+          "on": {
+            "PowerOff": {
+              "target": "#Device.Off"
+            },
+            "Start": {
+              "target": "#Device.On.Busy",
+              "guard": "ready && !busy",
+              "actions": [
+                "start"
+              ]
+            }
+          }
+        }
+      }
+    },
+    "Sleeping": {
+      "on": {
+        "PowerOn": {
+          "target": "#Device.On.On_history"
+        }
+      }
+    }
+  }
+}
+"##
+        );
+        Ok(())
+    }
+}
@@ -81,4 +81,531 @@
 //! ```
 #![no_std]
 
-pub use smlang_macros::statemachine;
+#[cfg(feature = "shared")]
+extern crate alloc;
+
+use core::cell::Cell;
+use core::fmt;
+use core::future::Future;
+use core::time::Duration;
+
+pub use smlang_macros::{statemachine, statemachines};
+
+/// Used by the code generated with the `tracing` feature.
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+pub use tracing;
+
+/// Used by the `SharedStateMachine` generated with the `shared` feature.
+#[cfg(feature = "shared")]
+#[doc(hidden)]
+pub use async_lock;
+
+/// Used by the code generated with the `arbitrary` feature.
+#[cfg(feature = "arbitrary")]
+#[doc(hidden)]
+pub use arbitrary;
+
+/// Used by the actors and state sinks generated with the `tokio` feature.
+#[cfg(feature = "tokio")]
+#[doc(hidden)]
+pub use tokio;
+
+/// Used by the actors generated with the `embassy` feature.
+#[cfg(feature = "embassy")]
+#[doc(hidden)]
+pub use embassy_sync;
+
+/// Used by the streams and sinks generated with the `futures` feature.
+#[cfg(feature = "futures")]
+#[doc(hidden)]
+pub use futures;
+
+/// Used by the event queues generated with the `critical-section` feature.
+#[cfg(feature = "critical-section")]
+#[doc(hidden)]
+pub use critical_section;
+
+/// Used by the `SharedStateMachine` generated with the `shared` feature.
+#[cfg(feature = "shared")]
+#[doc(hidden)]
+pub use alloc::sync::Arc;
+
+/// The clock used by state machines with timed transitions, declared as
+/// `State + after(duration) = NextState`, or with timeouts of async guards, declared as
+/// `[ async guard.timeout(duration) ]`.
+///
+/// The context of such a state machine needs to implement this trait, which allows using the
+/// timers of any runtime or embedded HAL. Every time-related feature of the state machine goes
+/// through it, so tests can use a [`MockClock`] instead of real sleeps.
+pub trait Clock {
+    /// Returns the current time, measured from an arbitrary but fixed point in time.
+    fn now(&self) -> Duration;
+
+    /// Completes once [`Clock::now`] has reached `deadline`.
+    fn sleep_until(&self, deadline: Duration) -> impl Future<Output = ()>;
+}
+
+/// The states, events and transitions of a state machine, which is generated as the
+/// `MACHINE_DESCRIPTION` constant, prefixed with the name of the state machine, e.g.
+/// `SAMPLE_MACHINE_DESCRIPTION` for a state machine named `Sample`.
+///
+/// The entries are sorted by name, so the description can drive diagnostics without keeping a
+/// separate table of the state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineDescription {
+    /// The names of all states, including composite states and the sub-states of regions.
+    pub states: &'static [&'static str],
+    /// The names of all events.
+    pub events: &'static [&'static str],
+    /// The transitions, in the order they are tried for every state and event.
+    pub transitions: &'static [TransitionDescription],
+}
+
+/// A transition of a [`MachineDescription`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionDescription {
+    /// The state the transition is taken from.
+    pub from: &'static str,
+    /// The event taking the transition, which is `_` for catch-all transitions, `after` for timed
+    /// transitions and empty for completion transitions.
+    pub event: &'static str,
+    /// The guard expression of the transition, if any.
+    pub guard: Option<&'static str>,
+    /// The action of the transition, if any, where chained actions are separated by commas.
+    pub action: Option<&'static str>,
+    /// The state the transition is taken to, which is `from` for internal transitions.
+    pub to: &'static str,
+}
+
+impl fmt::Display for TransitionDescription {
+    /// Formats the transition as in the DSL, e.g. `Idle + Start [ready] / start = Running`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.from)?;
+        if !self.event.is_empty() {
+            write!(f, " + {}", self.event)?;
+        }
+        if let Some(guard) = self.guard {
+            write!(f, " [{}]", guard)?;
+        }
+        if let Some(action) = self.action {
+            write!(f, " / {}", action)?;
+        }
+        write!(f, " = {}", self.to)
+    }
+}
+
+/// The transitions taken by a state machine declared with `transition_coverage: true`, returned
+/// by the generated `coverage` method. Every transition of the [`MachineDescription`] is tracked
+/// by its index, where every branch of a choice counts as a transition of its own.
+///
+/// Its `Display` implementation lists the transitions which haven't been taken yet.
+#[derive(Debug, Clone)]
+pub struct TransitionCoverage<const N: usize> {
+    transitions: &'static [TransitionDescription],
+    taken: [bool; N],
+}
+
+impl<const N: usize> TransitionCoverage<N> {
+    /// Creates the coverage of the described transitions, none of which has been taken.
+    pub const fn new(transitions: &'static [TransitionDescription]) -> Self {
+        Self {
+            transitions,
+            taken: [false; N],
+        }
+    }
+
+    /// Records that the transition with the index has been taken.
+    pub fn record(&mut self, index: usize) {
+        self.taken[index] = true;
+    }
+
+    /// Returns `true` if the transition with the index has been taken.
+    pub fn is_taken(&self, index: usize) -> bool {
+        self.taken[index]
+    }
+
+    /// Returns the transitions which have been taken.
+    pub fn taken(&self) -> impl Iterator<Item = &TransitionDescription> {
+        self.transitions
+            .iter()
+            .zip(self.taken)
+            .filter_map(|(transition, taken)| taken.then_some(transition))
+    }
+
+    /// Returns the transitions which haven't been taken.
+    pub fn untaken(&self) -> impl Iterator<Item = &TransitionDescription> {
+        self.transitions
+            .iter()
+            .zip(self.taken)
+            .filter_map(|(transition, taken)| (!taken).then_some(transition))
+    }
+
+    /// Returns the percentage of the transitions which have been taken, which is 100 for state
+    /// machines without transitions.
+    pub fn percent(&self) -> f32 {
+        if N == 0 {
+            return 100.0;
+        }
+        let taken = self.taken.iter().filter(|taken| **taken).count();
+        taken as f32 * 100.0 / N as f32
+    }
+
+    /// Panics with the transitions which haven't been taken if less than `percent` of the
+    /// transitions have been taken, e.g. at the end of a test.
+    #[track_caller]
+    pub fn assert_at_least(&self, percent: f32) {
+        if self.percent() < percent {
+            panic!("transition coverage is below {}%: {}", percent, self);
+        }
+    }
+
+    /// Forgets all taken transitions.
+    pub fn clear(&mut self) {
+        self.taken = [false; N];
+    }
+}
+
+impl<const N: usize> fmt::Display for TransitionCoverage<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let taken = self.taken().count();
+        write!(
+            f,
+            "{}/{} transitions taken ({:.1}%)",
+            taken,
+            N,
+            self.percent()
+        )?;
+        for transition in self.untaken() {
+            write!(f, "\n  untaken: {}", transition)?;
+        }
+        Ok(())
+    }
+}
+
+/// A transition recorded in a [`TransitionHistory`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionRecord {
+    /// The state the transition was taken from.
+    pub from: &'static str,
+    /// The event taking the transition, which is `after` for timed transitions and empty for
+    /// completion transitions.
+    pub event: &'static str,
+    /// The state the transition was taken to.
+    pub to: &'static str,
+    /// The time of the [`Clock`] when the transition was taken.
+    pub timestamp: Duration,
+}
+
+/// The last `N` transitions of a state machine declared with `transition_history: N`, where the
+/// oldest transition is overwritten once the history is full.
+#[derive(Debug, Clone)]
+pub struct TransitionHistory<const N: usize> {
+    records: [Option<TransitionRecord>; N],
+    /// The index of the slot of the next record.
+    next: usize,
+}
+
+impl<const N: usize> TransitionHistory<N> {
+    /// Creates an empty history.
+    pub const fn new() -> Self {
+        Self {
+            records: [None; N],
+            next: 0,
+        }
+    }
+
+    /// Records a transition, overwriting the oldest one if the history is full.
+    pub fn push(&mut self, record: TransitionRecord) {
+        self.records[self.next] = Some(record);
+        self.next = (self.next + 1) % N;
+    }
+
+    /// Returns the number of recorded transitions.
+    pub fn len(&self) -> usize {
+        self.records
+            .iter()
+            .filter(|record| record.is_some())
+            .count()
+    }
+
+    /// Returns `true` if no transitions have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.records[0].is_none()
+    }
+
+    /// Returns the most recent transition.
+    pub fn last(&self) -> Option<&TransitionRecord> {
+        self.records[(self.next + N - 1) % N].as_ref()
+    }
+
+    /// Returns the recorded transitions, from the oldest to the most recent.
+    pub fn iter(&self) -> impl Iterator<Item = &TransitionRecord> {
+        let (newer, older) = self.records.split_at(self.next);
+        older.iter().chain(newer).flatten()
+    }
+
+    /// Removes all recorded transitions.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl<const N: usize> Default for TransitionHistory<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The time spent in a state, returned for every state by the generated `statistics` method when
+/// `statistics: true` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateStatistics {
+    /// The name of the state.
+    pub state: &'static str,
+    /// The number of times the state has been left.
+    pub visits: u32,
+    /// The cumulative time spent in the state, including the current visit.
+    pub total: Duration,
+    /// The time spent in the state during its last completed visit.
+    pub last: Duration,
+}
+
+impl StateStatistics {
+    /// Creates the statistics of a state which hasn't been visited yet.
+    pub const fn new(state: &'static str) -> Self {
+        Self {
+            state,
+            visits: 0,
+            total: Duration::ZERO,
+            last: Duration::ZERO,
+        }
+    }
+}
+
+/// A log of the events accepted by a state machine declared with `event_log: true`, implemented
+/// by its context. The state of the machine can be rebuilt by replaying the logged events with the
+/// generated `replay` function.
+///
+/// The events are cloned for the log, so they need to implement `Clone`, e.g. with
+/// `events_attr: #[derive(Clone)]`.
+pub trait EventLog<E> {
+    /// Appends an event which has been accepted by the state machine.
+    fn append(&mut self, event: &E);
+}
+
+/// Counters of a state machine declared with `metrics: true`, implemented by its context, e.g. to
+/// export the transitions to Prometheus or to keep statistics on embedded targets. Both callbacks
+/// are no-ops by default.
+pub trait Metrics {
+    /// Called after every transition, with the names of the states it left and entered and of the
+    /// event which caused it.
+    fn transition_taken(&mut self, from: &'static str, event: &'static str, to: &'static str) {
+        let _ = (from, event, to);
+    }
+
+    /// Called whenever the guard expression of a transition of the state fails for the event.
+    fn guard_rejected(&mut self, state: &'static str, event: &'static str, guard: &'static str) {
+        let _ = (state, event, guard);
+    }
+}
+
+/// An observer of the states of a `SharedStateMachine` generated with the `shared` feature, which
+/// publishes the identifier of its new state whenever the state changes, e.g. to await the changes
+/// in a UI or a supervisor. With the `tokio` feature, it is implemented by the
+/// `tokio::sync::watch::Sender` of the identifiers.
+pub trait StateSink<Id> {
+    /// Called with the identifier of the new state while the state machine is locked, so the
+    /// states are published in the order they are entered.
+    fn publish(&self, id: Id);
+}
+
+/// Publishes nothing, for shared state machines without observers.
+impl<Id> StateSink<Id> for () {
+    fn publish(&self, _: Id) {}
+}
+
+/// The logger of a state machine declared with `associated_types: true`, which is the associated
+/// `Logger` of its context, e.g. to mock the logging in tests. All callbacks are no-ops by default.
+pub trait Logger<S, E> {
+    /// Called at the beginning of `process_event()`.
+    fn process_event(&self, state: &S, event: &E) {
+        let _ = (state, event);
+    }
+
+    /// Called after executing a guard.
+    fn guard(&self, guard: &'static str, result: bool) {
+        let _ = (guard, result);
+    }
+
+    /// Called after executing an action.
+    fn action(&self, action: &'static str) {
+        let _ = action;
+    }
+
+    /// Called when transitioning to a new state.
+    fn transition(&self, old_state: &S, new_state: &S) {
+        let _ = (old_state, new_state);
+    }
+}
+
+/// Logs nothing, for contexts without a logger.
+impl<S, E> Logger<S, E> for () {}
+
+/// The outcome of processing an event, returned by the generated `process_event` method when
+/// `transition_outcome: true` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionOutcome {
+    /// The state the event was processed in.
+    pub from: &'static str,
+    /// The state after processing the event, which is `from` if the state was kept.
+    pub to: &'static str,
+    /// The processed event.
+    pub event: &'static str,
+    /// Whether an action has been run.
+    pub action_taken: bool,
+}
+
+/// An event processed by the generated `simulate` method, along with the states before and after
+/// processing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulationStep {
+    /// The state the event was processed in.
+    pub from: &'static str,
+    /// The processed event.
+    pub event: &'static str,
+    /// The state after processing the event, which is `from` if the event has been rejected or
+    /// the state was kept.
+    pub to: &'static str,
+    /// Whether the event has been accepted, instead of being rejected with an error.
+    pub accepted: bool,
+}
+
+/// What processing an event would do, as reported by the generated `peek_event` method without
+/// running any actions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeekResult {
+    /// The transition to the state would be taken, which is the current state for internal
+    /// transitions and the parallel state for transitions of its regions.
+    Transition(&'static str),
+    /// The transition to a choice would be taken, whose branch depends on the data provided by
+    /// the action.
+    Choice,
+    /// The event would be deferred until another event has been processed.
+    Deferred,
+    /// The event would be delegated to the sub-machine of the current state.
+    Delegated,
+}
+
+/// The error of the generated `FromStr` of the events with `display`, for strings which are not
+/// the name of an event without data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseEventError;
+
+impl fmt::Display for ParseEventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unknown event")
+    }
+}
+
+/// The error of the generated `try_process_event` with `rejected_events`, along with the rejected
+/// event and the state which rejected it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected<E, Err> {
+    /// The rejected event, unless its data has been passed to an action before the error.
+    pub event: Option<E>,
+    /// The name of the current state.
+    pub state: &'static str,
+    /// The error which `process_event` returned.
+    pub error: Err,
+}
+
+/// The state machine of a typestate handle, whose state is only known at runtime once an event has
+/// been rejected, or has led to another state than the one of the event's method, e.g. through a
+/// completion transition.
+pub struct Untyped<M, Err> {
+    /// The state machine.
+    pub machine: M,
+    /// The error which `process_event` returned, if the event was rejected.
+    pub error: Option<Err>,
+}
+
+impl<M, Err: fmt::Debug> fmt::Debug for Untyped<M, Err> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Untyped")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [`Clock`] for deterministic tests, whose time only moves when it is set or advanced, or when
+/// it is slept on.
+///
+/// ```
+/// use core::time::Duration;
+/// use smlang::{Clock, MockClock};
+///
+/// let clock = MockClock::new();
+/// clock.advance(Duration::from_secs(2));
+/// assert_eq!(clock.now(), Duration::from_secs(2));
+/// ```
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: Cell<Duration>,
+}
+
+impl MockClock {
+    /// Creates a clock at time zero.
+    pub const fn new() -> Self {
+        Self {
+            now: Cell::new(Duration::ZERO),
+        }
+    }
+
+    /// Sets the current time.
+    pub fn set(&self, now: Duration) {
+        self.now.set(now);
+    }
+
+    /// Moves the current time forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    /// Completes immediately, moving the current time forward to `deadline` if it is later.
+    fn sleep_until(&self, deadline: Duration) -> impl Future<Output = ()> {
+        if self.now.get() < deadline {
+            self.now.set(deadline);
+        }
+        core::future::ready(())
+    }
+}
+
+/// Implements [`Clock`] with the time driver of `embassy_time`, which the crate using it depends
+/// on, for the `run_actor` generated with the `embassy` feature.
+///
+/// ```ignore
+/// impl smlang::Clock for Context {
+///     smlang::embassy_clock!();
+/// }
+/// ```
+#[cfg(feature = "embassy")]
+#[macro_export]
+macro_rules! embassy_clock {
+    () => {
+        fn now(&self) -> core::time::Duration {
+            core::time::Duration::from_micros(embassy_time::Instant::now().as_micros())
+        }
+
+        async fn sleep_until(&self, deadline: core::time::Duration) {
+            let deadline = embassy_time::Instant::from_micros(deadline.as_micros() as u64);
+            embassy_time::Timer::at(deadline).await
+        }
+    };
+}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1(u32) / action1, action2 = State2,
+        State2 + Event2(u32) / action1 = State1, //~ Action `action1` can only be reused when all input states, events, and output states have the same data
+    }
+}
+
+fn main() {}
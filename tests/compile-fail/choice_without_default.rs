@@ -0,0 +1,14 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 / action1 = choice(u32) { //~ The last branch of a choice cannot have a guard, as it is taken when all other guards fail.
+            [ guard1 ] = State2,
+            [ guard2 ] = State3,
+        },
+    }
+}
+
+fn main() {}
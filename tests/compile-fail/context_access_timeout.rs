@@ -0,0 +1,14 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    context_access: {
+        guards: &mut self,
+    },
+    transitions: {
+        *Idle + Connect [ async is_reachable.timeout(100ms) ] = Connected,
+    },
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2,
+        State1 + Event1 defer, //~ State State1 both handles and defers Event1.
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2,
+        State2 + Event2(u32) delegate,
+    }
+}
+
+fn main() {}
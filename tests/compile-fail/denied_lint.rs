@@ -0,0 +1,15 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    lints: {
+        contradictory_guards: deny,
+    },
+    transitions: {
+        *Idle + Start [ready && !ready] = Running,
+        Running + Stop = Idle,
+    }
+}
+
+fn main() {}
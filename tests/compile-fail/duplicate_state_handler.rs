@@ -0,0 +1,16 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 / action = State2(u32),
+        State2(u32) + Event2 = State1,
+    },
+    states: {
+        State1 { entry: handler },
+        State2 { entry: handler }, //~ Entry and exit handler `handler` can only be reused when all states and actions it is used for have the same data
+    },
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + (Event1(u32) | Event2(u8)) = State2,
+    }
+}
+
+fn main() {}
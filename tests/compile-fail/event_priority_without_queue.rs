@@ -0,0 +1,14 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2,
+    },
+    events: {
+        Event1 { priority: 1 },
+    }
+}
+
+fn main() {}
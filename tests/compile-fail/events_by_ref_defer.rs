@@ -0,0 +1,14 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    events_by_ref: true,
+    transitions: {
+        *Idle + Start = Running,
+        Idle + Upload(Vec<u8>) defer,
+        Running + Upload(Vec<u8>) = Idle,
+    },
+}
+
+fn main() {}
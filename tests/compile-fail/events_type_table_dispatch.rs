@@ -0,0 +1,20 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+mod protocol {
+    pub enum Message {
+        Connect,
+        Ping,
+    }
+}
+
+statemachine! {
+    events_type: protocol::Message,
+    table_dispatch: true,
+    transitions: {
+        *Idle + Connect = Connected,
+    },
+}
+
+fn main() {}
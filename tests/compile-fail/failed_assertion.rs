@@ -0,0 +1,18 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Locked + Unlock = Unlocked,
+        Locked + Service = Maintenance,
+        Unlocked + Coin = Dispensing,
+        Maintenance + Test = Dispensing,
+        Dispensing + Done = Locked,
+    },
+    assertions: {
+        Dispensing unreachable from Locked without Unlocked,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 / action1 = State2,
+    },
+    on_action_error: Faulted(u32),
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2 @final,
+        State2 + Event1 = State1,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    table_dispatch: true,
+    hash_dispatch: true,
+    transitions: {
+        *Idle + Start = Running,
+        Running + Stop = Idle,
+    },
+}
+
+fn main() {}
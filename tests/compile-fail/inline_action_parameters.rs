@@ -0,0 +1,11 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1(u32) / |ctx| { Ok(()) } = State2,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,17 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = Composite1,
+        Composite1(H) {
+            *SubState1 + Event2 = Composite2,
+            Composite2(H*) { //~ Composite2 has history, so it cannot be nested in Composite1 which has history as well.
+                *SubState2 + Event3 = SubState3,
+            },
+        },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Start = Operating,
+        Operating { //~ No initial sub-state defined for Operating, indicate it with a *.
+            Warmup + Ready = Running,
+        },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    lints: {
+        overlapping_transitions: deny,
+    },
+    transitions: {
+        *Idle + Start [ready] = Running,
+        Idle | Running + Start [forced] = Idle,
+        Running + Stop [ready] = Idle,
+        _ + Stop = Idle,
+        Idle | Running + Reset [forced] = Idle,
+        Running | Idle + Reset = Running,
+    }
+}
+
+fn main() {}
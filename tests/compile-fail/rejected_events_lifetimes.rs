@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    rejected_events: true,
+    transitions: {
+        *Idle + Send(&'a [u8]) = Sending,
+    },
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2 retry(3),
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    schema_version: 2,
+    transitions: {
+        *State1 + Event1 = State2,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    serde: true,
+    transitions: {
+        *State1 + Event1 / action = State2(&'a u32),
+    }
+}
+
+fn main() {}
@@ -0,0 +1,18 @@
+extern crate smlang;
+
+use smlang::statemachines;
+
+statemachines! {
+    Door {
+        transitions: {
+            *Closed + Open(u8) = Opened,
+        },
+    },
+    Alarm {
+        transitions: {
+            *Disarmed + Open(u16) = Ringing,
+        },
+    },
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2,
+        State2 + Expired(u32) = State1,
+    },
+    states: {
+        State2 { timeout: core::time::Duration::from_secs(1) => Expired },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    strict_events: true,
+    transitions: {
+        *Idle + Start = Running,
+        Idle + Pause = ignore,
+        Running + Stop = Idle,
+        Running + Pause = Paused,
+        Paused + (Start | Stop) = Running,
+    }
+}
+
+fn main() {}
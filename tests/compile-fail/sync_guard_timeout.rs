@@ -0,0 +1,11 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 [ guard.timeout(100ms) ] = State2,
+    }
+}
+
+fn main() {}
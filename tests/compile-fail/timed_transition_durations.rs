@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + after(core::time::Duration::from_secs(1)) [ guard1 ] = State2,
+        State1 + after(core::time::Duration::from_secs(2)) = State3, //~ The timed transitions of State1 must have the same duration.
+    }
+}
+
+fn main() {}
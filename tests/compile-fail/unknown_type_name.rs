@@ -0,0 +1,16 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    types: {
+        states: DoorState,
+        transitions: DoorTransitions,
+    },
+    transitions: {
+        *Closed + Open = Opened,
+        Opened + Close = Closed,
+    },
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Start = Running,
+        Running + Stop = Idle,
+        Running + Fail = Broken,
+    },
+    assertions: {
+        Idle reachable from _,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2,
+        State2 + Event1 = State1,
+        State1 + _ = State2,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 [ guard ] = State2,
+        State1 + Event1 = State1,
+        State1 + Event1 [ else ] = State2,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2 priority(2),
+        State1 + Event1 [ guard ] = State1,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    lints: {
+        unreachable_states: deny,
+    },
+    transitions: {
+        *Idle + Start = Running,
+        Running + Stop = Idle,
+        Orphan + Start = Running,
+    }
+}
+
+fn main() {}
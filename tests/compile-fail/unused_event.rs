@@ -0,0 +1,16 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    lints: {
+        unused_events: deny,
+    },
+    transitions: {
+        *Idle + Start = Running,
+        Running + Stop = Idle,
+        Running + Pause defer,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2,
+        _ + Event2 = State1 except [State3],
+    }
+}
+
+fn main() {}
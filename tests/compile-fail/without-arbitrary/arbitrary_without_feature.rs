@@ -0,0 +1,15 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Start(u8) = Running,
+        Running + Stop = Idle,
+    },
+    events: {
+        Start { arbitrary: level },
+    },
+}
+
+fn main() {}
@@ -0,0 +1,24 @@
+extern crate smlang;
+
+use smlang::statemachine;
+use std::rc::Rc;
+
+statemachine! {
+    transitions: {
+        *Idle + Start / async start = Running,
+    },
+    send_futures: true,
+}
+
+struct Context;
+
+impl StateMachineContext for Context {
+    async fn start(&mut self) -> Result<(), ()> {
+        let counter = Rc::new(0);
+        core::future::ready(()).await;
+        drop(counter);
+        Ok(())
+    }
+}
+
+fn main() {}
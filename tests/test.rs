@@ -15,6 +15,21 @@ mod internal_macros {
             assert_eq!($expected_count, $sm.context().count);
         }};
     }
+    /// Implements `smlang::Clock` for a context holding an `smlang::MockClock` as `clock`.
+    #[macro_export]
+    macro_rules! impl_mock_clock {
+        ($context:ty) => {
+            impl smlang::Clock for $context {
+                fn now(&self) -> core::time::Duration {
+                    smlang::Clock::now(&self.clock)
+                }
+
+                async fn sleep_until(&self, deadline: core::time::Duration) {
+                    smlang::Clock::sleep_until(&self.clock, deadline).await
+                }
+            }
+        };
+    }
     #[macro_export]
     macro_rules! assert_transition_ok {
         ($sm:expr, $event:expr, $expected_action:expr, $expected_result:pat) => {{
@@ -42,6 +57,12 @@ mod internal_macros {
 fn compile_fail_tests() {
     let t = trybuild::TestCases::new();
     t.compile_fail("tests/compile-fail/*.rs");
+    // Only fails to compile without the feature
+    #[cfg(not(feature = "arbitrary"))]
+    t.compile_fail("tests/compile-fail/without-arbitrary/*.rs");
+    // Names the traits of `core` by their full paths with the `Future` of the `futures` crate
+    #[cfg(not(feature = "futures"))]
+    t.compile_fail("tests/compile-fail/without-futures/*.rs");
 }
 #[test]
 fn wildcard_after_input_state() {
@@ -65,6 +86,8 @@ fn wildcard_after_input_state() {
 }
 
 #[test]
+// State4 and State5 are unreachable, which is reported with a warning
+#[allow(deprecated)]
 fn multiple_lifetimes() {
     pub struct X;
     pub struct Y;
@@ -199,6 +222,144 @@ fn async_guards_and_actions() {
     });
 }
 
+#[test]
+fn wildcard_precedence() {
+    statemachine! {
+        lints: {
+            overlapping_transitions: deny,
+        },
+        transitions: {
+            *Idle + Start [ready] = Running,
+            Running + Stop = Idle,
+            // The guarded transition of Idle takes precedence over the wildcard, even when its
+            // guard fails
+            _ + Start = Fault except [Fault],
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    struct Context {
+        ready: bool,
+    }
+
+    impl StateMachineContext for Context {
+        fn ready(&self) -> Result<bool, ()> {
+            Ok(self.ready)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context { ready: false });
+    assert_eq!(
+        sm.process_event(Events::Start),
+        Err(Error::TransitionsFailed)
+    );
+    assert!(matches!(sm.state(), &States::Idle));
+
+    sm.context_mut().ready = true;
+    sm.process_event(Events::Start).unwrap();
+    sm.process_event(Events::Stop).unwrap();
+    sm.process_event(Events::Start).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Start),
+        Ok(&States::Fault)
+    ));
+
+    // Excluded states don't handle the event of the wildcard
+    assert_eq!(sm.process_event(Events::Start), Err(Error::InvalidEvent));
+}
+
+#[test]
+fn send_futures() {
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) [async is_ready] / async start = Running(u32),
+            Running(u32) + Stop / async stop = Idle,
+        },
+        send_futures: true,
+    }
+
+    struct Context {
+        lock: smol::lock::Mutex<u32>,
+    }
+
+    impl StateMachineContext for Context {
+        async fn is_ready(&self, _speed: &u32) -> Result<bool, ()> {
+            Ok(*self.lock.lock().await == 0)
+        }
+
+        async fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            *self.lock.lock().await = speed;
+            Ok(speed)
+        }
+
+        async fn stop(&mut self, _speed: &u32) -> Result<(), ()> {
+            *self.lock.lock().await = 0;
+            Ok(())
+        }
+    }
+
+    // The futures processing the events are `Send` for any context, so they can be spawned
+    async fn process<T: StateMachineContext + Send + 'static>(
+        mut sm: StateMachine<T>,
+    ) -> StateMachine<T> {
+        smol::spawn(async move {
+            sm.process_event(Events::Start(3)).await.unwrap();
+            sm
+        })
+        .await
+    }
+
+    let context = Context {
+        lock: smol::lock::Mutex::new(0),
+    };
+    let mut sm = smol::block_on(process(StateMachine::new(context)));
+    assert!(matches!(sm.state(), &States::Running(3)));
+    assert!(smol::block_on(sm.process_event(Events::Stop)).is_ok());
+}
+
+#[test]
+fn static_state_machine() {
+    statemachine! {
+        transitions: {
+            *Idle + Start / start = Running,
+            Running + Pause defer,
+            Running + Stop = Idle,
+            Idle + Pause = Paused,
+        },
+        transition_history: 2,
+        statistics: true,
+        transition_coverage: true,
+        publish_state_id: true,
+    }
+
+    struct Context {
+        clock: smlang::MockClock,
+        starts: u32,
+    }
+    impl_mock_clock!(Context);
+    impl StateMachineContext for Context {
+        fn start(&mut self) -> Result<(), ()> {
+            self.starts += 1;
+            Ok(())
+        }
+    }
+
+    // The state machine is built at compile time, without a lazily initialized cell
+    static SM: std::sync::Mutex<StateMachine<Context>> =
+        std::sync::Mutex::new(StateMachine::new(Context {
+            clock: smlang::MockClock::new(),
+            starts: 0,
+        }));
+
+    let mut sm = SM.lock().unwrap();
+    assert!(matches!(sm.state(), &States::Idle));
+    assert!(sm.process_event(Events::Start).is_ok());
+    assert!(sm.process_event(Events::Pause).is_ok());
+    assert!(sm.process_event(Events::Stop).is_ok());
+    assert!(matches!(sm.state(), &States::Paused));
+    assert_eq!(sm.context().starts, 1);
+}
+
 #[test]
 fn guard_expressions() {
     #[derive(PartialEq, Display)]
@@ -517,3 +678,5128 @@ fn test_specify_attrs() {
     assert_transition!(sm, Events::tostate2, States::State2, 0);
     assert_transition!(sm, Events::tostate3, States::State3, 1);
 }
+
+/// The messages of a protocol, defined outside of the state machine.
+mod protocol {
+    #[derive(Debug)]
+    pub enum Message<'a> {
+        Connect,
+        Payload(&'a [u8]),
+        Ping,
+        Disconnect,
+    }
+}
+
+#[test]
+fn external_events() {
+    use protocol::Message;
+
+    statemachine! {
+        events_type: protocol::Message,
+        transitions: {
+            *Idle + Connect = Connected,
+            Connected + Payload(&'a [u8]) [ is_valid ] / count = Connected,
+            Connected + Disconnect = Closing,
+            Closing + _ = Idle,
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        received: usize,
+    }
+    impl StateMachineContext for Context {
+        fn is_valid(&self, payload: &[u8]) -> Result<bool, ()> {
+            Ok(!payload.is_empty())
+        }
+        fn count(&mut self, payload: &[u8]) -> Result<(), ()> {
+            self.received += payload.len();
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    // Messages which are not events of the state machine are rejected
+    assert!(matches!(
+        sm.process_event(Message::Ping),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(
+        sm.process_event(Message::Connect),
+        Ok(&States::Connected)
+    ));
+    let payload = [1, 2, 3];
+    assert!(sm.process_event(Message::Payload(&payload)).is_ok());
+    assert!(matches!(
+        sm.process_event(Message::Payload(&[])),
+        Err(Error::TransitionsFailed)
+    ));
+    assert_eq!(sm.context().received, 3);
+    assert!(sm.process_event(Events::Disconnect).is_ok());
+    // ...while a catch-all transition takes them
+    assert!(matches!(sm.process_event(Message::Ping), Ok(&States::Idle)));
+}
+
+#[test]
+fn shared_events() {
+    smlang::statemachines! {
+        events_type: Message,
+        events_attr: #[derive(Debug, Clone, Copy)],
+        Door {
+            transitions: {
+                *Closed + Open = Opened,
+                Opened + Close = Closed,
+            },
+        },
+        Alarm {
+            transitions: {
+                *Disarmed + Arm(u8) [ is_valid ] = Armed,
+                Armed + Open = Ringing,
+                Ringing + Disarm = Disarmed,
+            },
+        },
+    }
+
+    struct Context;
+    impl DoorStateMachineContext for Context {}
+    impl AlarmStateMachineContext for Context {
+        fn is_valid(&self, code: &u8) -> Result<bool, ()> {
+            Ok(*code == 42)
+        }
+    }
+
+    let mut door = DoorStateMachine::new(Context);
+    let mut alarm = AlarmStateMachine::new(Context);
+    // Every message is fanned out to both state machines, which reject the events of the other
+    let messages = [Message::Arm(42), Message::Open, Message::Disarm];
+    let results: Vec<_> = messages
+        .iter()
+        .map(|message| {
+            (
+                door.process_event(*message).is_ok(),
+                alarm.process_event(*message).is_ok(),
+            )
+        })
+        .collect();
+    assert_eq!(results, [(false, true), (true, true), (false, true)]);
+    assert!(matches!(door.state(), &DoorStates::Opened));
+    assert!(matches!(alarm.state(), &AlarmStates::Disarmed));
+    assert!(matches!(
+        door.process_event(DoorEvents::Arm(0)),
+        Err(DoorError::InvalidEvent)
+    ));
+    assert!(door.process_event(Message::Close).is_ok());
+}
+
+#[test]
+fn display_and_from_str() {
+    use std::str::FromStr;
+
+    statemachine! {
+        display: data,
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Stop = Idle,
+        },
+        states: {
+            Idle { display: "idle" },
+        },
+    }
+
+    statemachine! {
+        name: Plain,
+        display: names,
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Stop = Idle,
+        },
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            Ok(speed)
+        }
+    }
+    impl PlainStateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            Ok(speed)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert_eq!(sm.state().to_string(), "idle");
+    assert_eq!(Events::Start(3).to_string(), "Start(3)");
+    sm.process_event(Events::Start(3)).unwrap();
+    assert_eq!(sm.state().to_string(), "Running(3)");
+
+    let mut plain = PlainStateMachine::new(Context);
+    assert_eq!(plain.state().to_string(), "Idle");
+    assert_eq!(PlainEvents::Start(3).to_string(), "Start");
+    plain.process_event(PlainEvents::Start(3)).unwrap();
+    assert_eq!(plain.state().to_string(), "Running");
+
+    // Only the events without data are parsed from their names
+    assert!(matches!("Stop".parse(), Ok(Events::Stop)));
+    assert!(matches!(
+        Events::from_str("Start"),
+        Err(smlang::ParseEventError)
+    ));
+    assert!(matches!(
+        Events::from_str("Pause"),
+        Err(smlang::ParseEventError)
+    ));
+    assert!(sm.process_event("Stop".parse().unwrap()).is_ok());
+}
+
+#[test]
+fn enumerated_variants() {
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Stop = Idle,
+            Idle + Maintain = Maintenance,
+            Maintenance {
+                *Inspecting + Stop = Idle,
+            },
+            Idle + Halt = Halted,
+        },
+    }
+
+    assert_eq!(States::VARIANT_COUNT, 4);
+    assert_eq!(States::NAMES, ["Halted", "Idle", "Maintenance", "Running"]);
+    let states: Vec<_> = States::all().collect();
+    assert!(matches!(states[..], [States::Halted, States::Idle]));
+
+    assert_eq!(Events::VARIANT_COUNT, 4);
+    assert_eq!(Events::NAMES, ["Halt", "Maintain", "Start", "Stop"]);
+    // Every event without data is rejected in the halted state
+    struct Context;
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            Ok(speed)
+        }
+    }
+    let mut sm = StateMachine::new_with_state(Context, States::Halted);
+    assert_eq!(Events::all().count(), 3);
+    for event in Events::all() {
+        assert!(matches!(sm.process_event(event), Err(Error::InvalidEvent)));
+    }
+}
+
+#[test]
+fn state_accessors() {
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Stop = Idle,
+            Idle + Maintain = Maintenance,
+            Maintenance {
+                *Inspecting + Repair = Repairing,
+            },
+        },
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            Ok(speed)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert!(sm.state().is_idle());
+    assert_eq!(sm.state().as_running(), None);
+    sm.process_event(Events::Start(3)).unwrap();
+    assert!(!sm.state().is_idle() && sm.state().is_running());
+    assert_eq!(sm.state().as_running(), Some(&3));
+    assert_eq!(States::Running(4).into_running(), Some(4));
+    assert_eq!(States::Idle.into_running(), None);
+
+    // The enums of the sub-states have their own predicates
+    sm.process_event(Events::Stop).unwrap();
+    sm.process_event(Events::Maintain).unwrap();
+    assert!(sm.state().is_maintenance());
+    assert!(matches!(sm.state().as_maintenance(), Some(sub_state) if sub_state.is_inspecting()));
+    sm.process_event(Events::Repair).unwrap();
+    assert!(matches!(sm.state().as_maintenance(), Some(sub_state) if sub_state.is_repairing()));
+}
+
+#[test]
+fn rejected_events() {
+    statemachine! {
+        rejected_events: true,
+        states_attr: #[derive(Debug)],
+        events_attr: #[derive(Debug)],
+        transitions: {
+            *Idle + Send(u32) [is_small] / send = Sending,
+            Idle + Check(u32) [is_valid] = Idle,
+            Sending + Ack = Idle,
+        },
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn is_small(&self, size: &u32) -> Result<bool, ()> {
+            Ok(*size < 10)
+        }
+        fn is_valid(&self, crc: &u32) -> Result<bool, ()> {
+            if *crc == 0 {
+                Err(())
+            } else {
+                Ok(false)
+            }
+        }
+        fn send(&mut self, _: u32) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+
+    // The event is returned when no transition is taken
+    let rejected = sm.try_process_event(Events::Ack).unwrap_err();
+    assert!(matches!(rejected.event, Some(Events::Ack)));
+    assert!(matches!(rejected.error, Error::InvalidEvent));
+    assert_eq!(rejected.state, "Idle");
+
+    let rejected = sm.try_process_event(Events::Send(12)).unwrap_err();
+    assert!(matches!(rejected.event, Some(Events::Send(12))));
+    assert!(matches!(rejected.error, Error::TransitionsFailed));
+
+    // Along with the data of the event when a guard returns an error
+    let rejected = sm.try_process_event(Events::Check(0)).unwrap_err();
+    assert!(matches!(rejected.event, Some(Events::Check(0))));
+    assert!(matches!(rejected.error, Error::GuardFailed(())));
+
+    assert!(sm.try_process_event(Events::Send(3)).unwrap().is_sending());
+    let rejected = sm.try_process_event(Events::Check(1)).unwrap_err();
+    assert!(matches!(rejected.event, Some(Events::Check(1))));
+    assert_eq!(rejected.state, "Sending");
+}
+
+#[test]
+fn events_by_ref() {
+    pub struct Payload(Vec<u8>);
+
+    statemachine! {
+        events_by_ref: true,
+        transitions: {
+            *Idle + Upload(Payload) [fits] / store = Stored(usize),
+            Stored(usize) + Rename(&'a str) / rename = Stored(usize),
+            Stored(usize) + Upload(Payload) / replace = Stored(usize),
+        },
+    }
+
+    struct Context {
+        names: Vec<String>,
+    }
+    impl StateMachineContext for Context {
+        fn fits(&self, payload: &Payload) -> Result<bool, ()> {
+            Ok(payload.0.len() <= 4)
+        }
+        fn store(&mut self, payload: &Payload) -> Result<usize, ()> {
+            Ok(payload.0.len())
+        }
+        fn replace(&mut self, _: &usize, payload: &Payload) -> Result<usize, ()> {
+            Ok(payload.0.len())
+        }
+        fn rename(&mut self, size: &usize, name: &str) -> Result<usize, ()> {
+            self.names.push(name.to_string());
+            Ok(*size)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context { names: Vec::new() });
+
+    // The caller keeps the events, whether they are accepted or rejected
+    let large = Events::Upload(Payload(vec![0; 8]));
+    assert!(matches!(
+        sm.process_event(&large),
+        Err(Error::TransitionsFailed)
+    ));
+    let small = Events::Upload(Payload(vec![1, 2, 3]));
+    assert!(matches!(sm.process_event(&small), Ok(&States::Stored(3))));
+    assert!(matches!(&small, Events::Upload(payload) if payload.0.len() == 3));
+
+    let name = String::from("data.bin");
+    sm.process_event(&Events::Rename(&name)).unwrap();
+    assert_eq!(sm.context().names, ["data.bin"]);
+    assert!(matches!(sm.process_event(&small), Ok(&States::Stored(3))));
+}
+
+#[test]
+fn custom_guard_error() {
+    statemachine! {
+        custom_error: true,
+        custom_guard_error: true,
+        states_attr: #[derive(Debug)],
+        transitions: {
+            *Idle + Connect [is_online && has_credentials] = Connected,
+            // The guards of a choice name their errors too
+            Connected + Send / measure = choice(u32) {
+                [fits_window] = Connected,
+                = Idle,
+            },
+        },
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum LinkError {
+        Offline,
+        NoCredentials,
+        NoWindow,
+    }
+
+    struct Context {
+        online: bool,
+    }
+    impl StateMachineContext for Context {
+        type Error = LinkError;
+        fn is_online(&self) -> Result<bool, LinkError> {
+            if self.online {
+                Ok(true)
+            } else {
+                Err(LinkError::Offline)
+            }
+        }
+        fn has_credentials(&self) -> Result<bool, LinkError> {
+            Err(LinkError::NoCredentials)
+        }
+        fn measure(&mut self) -> Result<u32, LinkError> {
+            Ok(3)
+        }
+        fn fits_window(&self, _: &u32) -> Result<bool, LinkError> {
+            Err(LinkError::NoWindow)
+        }
+    }
+
+    // Either guard of the expression can fail
+    let mut sm = StateMachine::new(Context { online: false });
+    assert_eq!(
+        sm.process_event(Events::Connect).unwrap_err(),
+        Error::GuardFailed(Guards::IsOnline, LinkError::Offline)
+    );
+    sm.context_mut().online = true;
+    let error = sm.process_event(Events::Connect).unwrap_err();
+    assert_eq!(
+        error,
+        Error::GuardFailed(Guards::HasCredentials, LinkError::NoCredentials)
+    );
+    assert!(matches!(error, Error::GuardFailed(guard, _) if guard.name() == "has_credentials"));
+
+    let mut sm = StateMachine::new_with_state(Context { online: true }, States::Connected);
+    assert_eq!(
+        sm.process_event(Events::Send).unwrap_err(),
+        Error::GuardFailed(Guards::FitsWindow, LinkError::NoWindow)
+    );
+}
+
+#[test]
+fn renamed_types() {
+    statemachine! {
+        name: Door,
+        types: {
+            states: DoorState,
+            events: pub(crate) DoorEvent,
+            state_machine: Door,
+            context: DoorContext,
+        },
+        transitions: {
+            *Closed + Open = Opened,
+            Opened + Close = Closed,
+            Closed + Lock = Locked,
+            Locked {
+                *Latched + Bolt = Bolted,
+            },
+        },
+    }
+
+    statemachine! {
+        name: Light,
+        types: {
+            states: LightState,
+            events: LightEvent,
+            state_machine: pub(crate) Light,
+            context: LightContext,
+        },
+        transitions: {
+            *Off + Toggle = On,
+            On + Toggle = Off,
+        },
+    }
+
+    struct Context;
+    impl DoorContext for Context {}
+    impl LightContext for Context {}
+
+    // Both state machines live in the same scope under their own names
+    let mut door = Door::new(Context);
+    let mut light = Light::new(Context);
+    assert!(matches!(
+        door.process_event(DoorEvent::Open),
+        Ok(&DoorState::Opened)
+    ));
+    assert!(matches!(
+        light.process_event(LightEvent::Toggle),
+        Ok(&LightState::On)
+    ));
+    assert!(door.process_event(DoorEvent::Close).is_ok());
+    assert!(door.process_event(DoorEvent::Lock).is_ok());
+    assert!(matches!(
+        door.process_event(DoorEvent::Bolt),
+        Ok(&DoorState::Locked(DoorLockedStates::Bolted))
+    ));
+    assert!(matches!(
+        door.process_event(DoorEvent::Open),
+        Err(DoorError::InvalidEvent)
+    ));
+}
+
+#[test]
+fn variant_attrs() {
+    use serde::Serialize;
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Stop = Idle,
+        },
+        states_attr: #[derive(Debug, Hash, Serialize)],
+        events_attr: #[derive(Debug, Hash, Serialize)] #[serde(rename_all = "snake_case")],
+        states: {
+            Idle { attrs: #[serde(rename = "idle")] },
+            Running { attrs: #[serde(rename = "running")] #[doc = "Running at a speed."] },
+        },
+        events: {
+            Start { attrs: #[serde(rename = "go")] },
+        },
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            Ok(speed)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert_eq!(serde_json::to_string(sm.state()).unwrap(), "\"idle\"");
+    assert_eq!(
+        serde_json::to_string(&Events::Start(3)).unwrap(),
+        r#"{"go":3}"#
+    );
+    assert_eq!(serde_json::to_string(&Events::Stop).unwrap(), "\"stop\"");
+
+    sm.process_event(Events::Start(3)).unwrap();
+    assert_eq!(
+        serde_json::to_string(sm.state()).unwrap(),
+        r#"{"running":3}"#
+    );
+
+    // `Hash` is consistent with `PartialEq`, which only compares the variants
+    fn hash(value: &impl core::hash::Hash) -> u64 {
+        use core::hash::Hasher;
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+    assert_eq!(hash(&States::Running(1)), hash(&States::Running(2)));
+    assert_ne!(hash(&States::Running(1)), hash(&States::Idle));
+    assert_eq!(hash(&Events::Start(1)), hash(&Events::Start(2)));
+}
+
+#[test]
+fn hierarchical_states() {
+    statemachine! {
+        transitions: {
+            *Idle + Start = Operating,
+            Operating + Stop = Idle,
+            Operating {
+                *Warmup + Ready = Running,
+                Running + Fast [too_hot] / cool = Cooling(u32),
+                Running + Pause = Paused,
+                Paused {
+                    *Holding + Pause = Idle,
+                    Holding + Resume = Running,
+                },
+                Cooling(u32) + Ready / cooled = Running,
+                Cooling(u32) + Fast / cooled = Running,
+            },
+            Operating + Fast / count = _,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        hot: bool,
+        fast: u32,
+        log: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn too_hot(&self) -> Result<bool, ()> {
+            Ok(self.hot)
+        }
+
+        fn count(&mut self) -> Result<(), ()> {
+            self.fast += 1;
+            Ok(())
+        }
+
+        fn cool(&mut self) -> Result<u32, ()> {
+            Ok(10)
+        }
+
+        fn cooled(&mut self, _state_data: &u32) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn on_entry_operating(&mut self) {
+            self.log.push("enter operating");
+        }
+
+        fn on_exit_operating(&mut self) {
+            self.log.push("exit operating");
+        }
+
+        fn on_entry_paused(&mut self) {
+            self.log.push("enter paused");
+        }
+
+        fn on_exit_paused(&mut self) {
+            self.log.push("exit paused");
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert_eq!(sm.state(), &States::Idle);
+
+    // Entering a composite state enters its initial sub-state
+    sm.process_event(Events::Start).unwrap();
+    assert_eq!(sm.state(), &States::Operating(OperatingStates::Warmup));
+    assert_eq!(sm.context().log, ["enter operating"]);
+
+    // Sub-states do not compare equal to their siblings
+    assert_ne!(sm.state(), &States::Operating(OperatingStates::Running));
+
+    // Unhandled events bubble up to the composite state
+    sm.process_event(Events::Fast).unwrap();
+    assert_eq!(sm.state(), &States::Operating(OperatingStates::Warmup));
+    assert_eq!(sm.context().fast, 1);
+
+    // Events bubble up when the guards of the sub-state fail
+    sm.process_event(Events::Ready).unwrap();
+    sm.process_event(Events::Fast).unwrap();
+    assert_eq!(sm.state(), &States::Operating(OperatingStates::Running));
+    assert_eq!(sm.context().fast, 2);
+
+    sm.context_mut().hot = true;
+    sm.process_event(Events::Fast).unwrap();
+    assert!(matches!(
+        sm.state(),
+        &States::Operating(OperatingStates::Cooling(_))
+    ));
+    assert_eq!(sm.context().fast, 2);
+    sm.process_event(Events::Ready).unwrap();
+
+    // Nested composite states
+    sm.process_event(Events::Pause).unwrap();
+    assert_eq!(
+        sm.state(),
+        &States::Operating(OperatingStates::Paused(PausedStates::Holding))
+    );
+    sm.process_event(Events::Resume).unwrap();
+    assert_eq!(sm.state(), &States::Operating(OperatingStates::Running));
+
+    // Leaving the composite states exits all of them, innermost first
+    sm.process_event(Events::Pause).unwrap();
+    sm.context_mut().log.clear();
+    sm.process_event(Events::Stop).unwrap();
+    assert_eq!(sm.state(), &States::Idle);
+    assert_eq!(sm.context().log, ["exit paused", "exit operating"]);
+}
+
+#[test]
+fn composite_self_transitions() {
+    statemachine! {
+        transitions: {
+            *Idle + Start = Operating,
+            Operating + Reset = Operating,
+            Operating {
+                *Warmup + Ready / measure = Running(u32),
+                Running(u32) + Pause = Paused,
+                Paused {
+                    *Holding + Ready = Waiting,
+                },
+            },
+        },
+        states: {
+            Operating { entry: power_on, exit: power_off },
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        log: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn measure(&mut self) -> Result<u32, ()> {
+            Ok(7)
+        }
+
+        fn power_on(&mut self) -> Result<(), ()> {
+            self.log.push("power on");
+            Ok(())
+        }
+
+        fn power_off(&mut self) -> Result<(), ()> {
+            self.log.push("power off");
+            Ok(())
+        }
+
+        fn on_entry_operating(&mut self) {
+            self.log.push("enter operating");
+        }
+
+        fn on_exit_operating(&mut self) {
+            self.log.push("exit operating");
+        }
+
+        fn on_entry_warmup(&mut self) {
+            self.log.push("enter warmup");
+        }
+
+        fn on_exit_warmup(&mut self) {
+            self.log.push("exit warmup");
+        }
+
+        fn on_exit_paused(&mut self) {
+            self.log.push("exit paused");
+        }
+
+        fn on_exit_waiting(&mut self) {
+            self.log.push("exit waiting");
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Start).unwrap();
+    assert_eq!(sm.state(), &States::Operating(OperatingStates::Warmup));
+
+    // A self-transition of a composite state from its initial sub-state exits and enters the
+    // composite state and the sub-state again
+    sm.context_mut().log.clear();
+    sm.process_event(Events::Reset).unwrap();
+    assert_eq!(sm.state(), &States::Operating(OperatingStates::Warmup));
+    assert_eq!(
+        sm.context().log,
+        [
+            "exit warmup",
+            "exit operating",
+            "power off",
+            "enter operating",
+            "power on",
+            "enter warmup",
+        ]
+    );
+
+    // From a nested sub-state, all sub-states are exited and the initial sub-state is entered
+    sm.process_event(Events::Ready).unwrap();
+    assert!(matches!(
+        sm.state(),
+        &States::Operating(OperatingStates::Running(7))
+    ));
+    sm.process_event(Events::Pause).unwrap();
+    sm.process_event(Events::Ready).unwrap();
+    assert_eq!(
+        sm.state(),
+        &States::Operating(OperatingStates::Paused(PausedStates::Waiting))
+    );
+    sm.context_mut().log.clear();
+    sm.process_event(Events::Reset).unwrap();
+    assert_eq!(sm.state(), &States::Operating(OperatingStates::Warmup));
+    assert_eq!(
+        sm.context().log,
+        [
+            "exit waiting",
+            "exit paused",
+            "exit operating",
+            "power off",
+            "enter operating",
+            "power on",
+            "enter warmup",
+        ]
+    );
+}
+
+#[test]
+fn parallel_regions() {
+    statemachine! {
+        transitions: {
+            *Idle + Start = Active,
+            Active [
+                Heater {
+                    *Heating + Warm = Holding,
+                    Holding + Cold = Heating,
+                    Holding + Done = HeaterOff,
+                },
+                Stirrer {
+                    *Stirring + Speed(u32) [fast_enough] / settle = Settled(u32),
+                    Settled(u32) + Done = StirrerOff,
+                },
+            ] = Finished,
+            Active + Abort = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        log: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn fast_enough(&self, speed: &u32) -> Result<bool, ()> {
+            Ok(*speed > 10)
+        }
+
+        fn settle(&mut self, speed: &u32) -> Result<u32, ()> {
+            Ok(*speed)
+        }
+
+        fn on_entry_active(&mut self) {
+            self.log.push("enter active");
+        }
+
+        fn on_exit_active(&mut self) {
+            self.log.push("exit active");
+        }
+
+        fn on_entry_heating(&mut self) {
+            self.log.push("enter heating");
+        }
+
+        fn on_exit_heating(&mut self) {
+            self.log.push("exit heating");
+        }
+
+        fn on_entry_stirring(&mut self) {
+            self.log.push("enter stirring");
+        }
+
+        fn on_exit_stirring(&mut self) {
+            self.log.push("exit stirring");
+        }
+
+        fn on_exit_holding(&mut self) {
+            self.log.push("exit holding");
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // Entering a parallel state enters the initial sub-state of every region
+    sm.process_event(Events::Start).unwrap();
+    assert_eq!(
+        sm.state(),
+        &States::Active(ActiveStates {
+            heater: HeaterStates::Heating,
+            stirrer: StirrerStates::Stirring,
+        })
+    );
+    assert_eq!(
+        sm.context().log,
+        ["enter active", "enter heating", "enter stirring"]
+    );
+
+    // Regions change their sub-states independently
+    sm.process_event(Events::Warm).unwrap();
+    assert_eq!(
+        sm.process_event(Events::Speed(5)),
+        Err(Error::TransitionsFailed)
+    );
+    assert_eq!(
+        sm.state(),
+        &States::Active(ActiveStates {
+            heater: HeaterStates::Holding,
+            stirrer: StirrerStates::Stirring,
+        })
+    );
+    sm.process_event(Events::Speed(20)).unwrap();
+    assert!(matches!(
+        sm.state(),
+        &States::Active(ActiveStates {
+            heater: HeaterStates::Holding,
+            stirrer: StirrerStates::Settled(20),
+        })
+    ));
+
+    sm.process_event(Events::Cold).unwrap();
+    assert!(matches!(
+        sm.state(),
+        &States::Active(ActiveStates {
+            heater: HeaterStates::Heating,
+            ..
+        })
+    ));
+    sm.process_event(Events::Warm).unwrap();
+
+    // Events handled by no region are handled by the parallel state
+    sm.context_mut().log.clear();
+    sm.process_event(Events::Abort).unwrap();
+    assert_eq!(sm.state(), &States::Idle);
+    assert_eq!(sm.context().log, ["exit holding", "exit active"]);
+
+    // The parallel state is left once all regions reach a final sub-state
+    sm.process_event(Events::Start).unwrap();
+    sm.process_event(Events::Warm).unwrap();
+    sm.process_event(Events::Speed(20)).unwrap();
+    sm.process_event(Events::Done).unwrap();
+    assert_eq!(sm.state(), &States::Finished);
+    assert_eq!(sm.process_event(Events::Warm), Err(Error::InvalidEvent));
+}
+
+#[test]
+fn failed_actions_roll_back() {
+    statemachine! {
+        transitions: {
+            *Counting(u32) + Add(u32) / add = Counting(u32),
+            Counting(u32) + Start / async start = Active,
+            Active [
+                Pump {
+                    *PumpIdle + Run / run_pump = Pumping,
+                },
+                Valve {
+                    *Closed + Run / async open_valve = Open,
+                },
+            ] = Drained,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        fail: bool,
+        log: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn add(&mut self, count: &u32, amount: u32) -> Result<u32, ()> {
+            if self.fail {
+                Err(())
+            } else {
+                Ok(count + amount)
+            }
+        }
+
+        async fn start(&mut self, _: &u32) -> Result<(), ()> {
+            if self.fail {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn run_pump(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+
+        async fn open_valve(&mut self) -> Result<(), ()> {
+            if self.fail {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn on_exit_counting(&mut self) {
+            self.log.push("exit counting");
+        }
+
+        fn on_exit_pump_idle(&mut self) {
+            self.log.push("exit pump idle");
+        }
+
+        fn on_entry_pumping(&mut self) {
+            self.log.push("enter pumping");
+        }
+    }
+
+    smol::block_on(async {
+        let mut sm = StateMachine::new(
+            Context {
+                fail: true,
+                ..Context::default()
+            },
+            5,
+        );
+
+        // A failed action leaves the state machine in the source state with its data
+        assert_eq!(
+            sm.process_event(Events::Add(2)).await,
+            Err(Error::ActionFailed(()))
+        );
+        assert_eq!(sm.state(), &States::Counting(5));
+        assert_eq!(
+            sm.process_event(Events::Start).await,
+            Err(Error::ActionFailed(()))
+        );
+        assert_eq!(sm.state(), &States::Counting(5));
+        assert!(sm.context().log.is_empty());
+
+        // A failed action of one region cancels the transitions of every region
+        sm.context_mut().fail = false;
+        sm.process_event(Events::Start).await.unwrap();
+        sm.context_mut().fail = true;
+        assert_eq!(
+            sm.process_event(Events::Run).await,
+            Err(Error::ActionFailed(()))
+        );
+        assert_eq!(
+            sm.state(),
+            &States::Active(ActiveStates {
+                pump: PumpStates::PumpIdle,
+                valve: ValveStates::Closed,
+            })
+        );
+        assert_eq!(sm.context().log, ["exit counting"]);
+
+        // Once the actions of both regions succeed, the regions complete the parallel state
+        sm.context_mut().fail = false;
+        sm.process_event(Events::Run).await.unwrap();
+        assert_eq!(sm.state(), &States::Drained);
+        assert_eq!(
+            sm.context().log,
+            ["exit counting", "exit pump idle", "enter pumping"]
+        );
+    });
+}
+
+#[test]
+fn entry_and_exit_handlers() {
+    statemachine! {
+        transitions: {
+            *Idle + Start / start = Running(u32),
+            Running(u32) + Stop = Idle,
+            Running(u32) + Fail = Faulted,
+            Faulted + Reset = Idle,
+        },
+        states: {
+            Running { entry: spin_up, exit: spin_down },
+            Faulted { entry: alarm },
+            Idle { exit: alarm },
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        log: Vec<String>,
+    }
+
+    impl StateMachineContext for Context {
+        fn start(&mut self) -> Result<u32, ()> {
+            Ok(42)
+        }
+
+        fn spin_up(&mut self, speed: &u32) -> Result<(), ()> {
+            self.log.push(format!("spin up to {}", speed));
+            Ok(())
+        }
+
+        fn spin_down(&mut self, speed: &u32) -> Result<(), ()> {
+            self.log.push(format!("spin down from {}", speed));
+            Ok(())
+        }
+
+        fn alarm(&mut self) -> Result<(), ()> {
+            self.log.push("alarm".to_string());
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // The handlers run around the action of the transition
+    sm.process_event(Events::Start).unwrap();
+    assert_eq!(sm.context().log, ["alarm", "spin up to 42"]);
+
+    // The handlers run regardless of the transition leaving or entering the state
+    sm.context_mut().log.clear();
+    sm.process_event(Events::Fail).unwrap();
+    assert_eq!(sm.context().log, ["spin down from 42", "alarm"]);
+
+    sm.context_mut().log.clear();
+    sm.process_event(Events::Reset).unwrap();
+    sm.process_event(Events::Start).unwrap();
+    sm.process_event(Events::Stop).unwrap();
+    assert_eq!(
+        sm.context().log,
+        ["alarm", "spin up to 42", "spin down from 42"]
+    );
+}
+
+#[test]
+fn failed_entry_and_exit_handlers() {
+    statemachine! {
+        publish_state_id: true,
+        transitions: {
+            *Idle + Heat / heat = Heating(u32),
+            Heating(u32) + Cool = Idle,
+        },
+        states: {
+            Heating { entry: power_on, exit: power_off },
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        broken: bool,
+        callbacks: core::cell::RefCell<Vec<String>>,
+        log: Vec<String>,
+        published: Vec<StateId>,
+    }
+
+    impl StateMachineContext for Context {
+        fn heat(&mut self) -> Result<u32, ()> {
+            Ok(1)
+        }
+
+        fn power_on(&mut self, _level: &u32) -> Result<(), ()> {
+            if self.broken {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn power_off(&mut self, _level: &u32) -> Result<(), ()> {
+            if self.broken {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn transition_callback(&self, old_state: &States, new_state: &States) {
+            self.callbacks
+                .borrow_mut()
+                .push(format!("{:?} -> {:?}", old_state, new_state));
+        }
+
+        fn publish_state_id(&mut self, id: StateId) {
+            self.published.push(id);
+        }
+
+        fn on_transition(&mut self, old_state: &'static str, event: &'static str, _: &States) {
+            self.log.push(format!("{} + {}", old_state, event));
+        }
+    }
+
+    // A failed entry handler is returned once the transition has been completed
+    let mut sm = StateMachine::new(Context {
+        broken: true,
+        ..Context::default()
+    });
+    assert_eq!(sm.process_event(Events::Heat), Err(Error::ActionFailed(())));
+    assert!(matches!(sm.state(), States::Heating(1)));
+    assert_eq!(*sm.context().callbacks.borrow(), ["Idle -> Heating(1)"]);
+    assert_eq!(sm.state_id(), StateId::Heating);
+    assert_eq!(sm.context().published, [StateId::Heating]);
+    assert_eq!(sm.context().log, ["Idle + Heat"]);
+
+    // A failed exit handler cancels the transition before the state is left
+    assert_eq!(sm.process_event(Events::Cool), Err(Error::ActionFailed(())));
+    assert!(matches!(sm.state(), States::Heating(1)));
+    assert_eq!(*sm.context().callbacks.borrow(), ["Idle -> Heating(1)"]);
+    assert_eq!(sm.context().published, [StateId::Heating]);
+    assert_eq!(sm.context().log, ["Idle + Heat"]);
+
+    sm.context_mut().broken = false;
+    sm.process_event(Events::Cool).unwrap();
+    assert_eq!(sm.context().published, [StateId::Heating, StateId::Idle]);
+
+    statemachine! {
+        name: Oven,
+        custom_error: true,
+        on_action_error: Faulted(u8),
+        transitions: {
+            *Idle + Heat = Heating,
+            Faulted(u8) + Reset = Idle,
+        },
+        states: {
+            Heating { entry: power_on },
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct OvenContext {
+        log: Vec<String>,
+    }
+
+    impl OvenStateMachineContext for OvenContext {
+        type Error = u8;
+
+        fn power_on(&mut self) -> Result<(), u8> {
+            Err(7)
+        }
+
+        fn on_transition(
+            &mut self,
+            old_state: &'static str,
+            event: &'static str,
+            new_state: &OvenStates,
+        ) {
+            self.log
+                .push(format!("{} + {} = {:?}", old_state, event, new_state));
+        }
+    }
+
+    // With a fault state, a failed entry handler enters the fault state from the state entered
+    let mut sm = OvenStateMachine::new(OvenContext::default());
+    assert!(matches!(
+        sm.process_event(OvenEvents::Heat),
+        Ok(OvenStates::Faulted(7))
+    ));
+    assert_eq!(
+        sm.context().log,
+        ["Idle + Heat = Heating", "Heating + Heat = Faulted(7)"]
+    );
+    sm.process_event(OvenEvents::Reset).unwrap();
+    assert_eq!(sm.state(), &OvenStates::Idle);
+
+    statemachine! {
+        name: Boiler,
+        poisoning: true,
+        transitions: {
+            *Idle + Heat = Heating,
+            Heating + Cool = Idle,
+        },
+        states: {
+            Heating { entry: power_on },
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    struct BoilerContext;
+
+    impl BoilerStateMachineContext for BoilerContext {
+        fn power_on(&mut self) -> Result<(), ()> {
+            Err(())
+        }
+    }
+
+    // With poisoning, a failed entry handler poisons the state machine
+    let mut sm = BoilerStateMachine::new(BoilerContext);
+    assert_eq!(
+        sm.process_event(BoilerEvents::Heat),
+        Err(BoilerError::ActionFailed(()))
+    );
+    assert!(sm.is_poisoned());
+    assert_eq!(sm.state(), &BoilerStates::Heating);
+    assert_eq!(
+        sm.process_event(BoilerEvents::Cool),
+        Err(BoilerError::Poisoned)
+    );
+}
+
+#[test]
+fn internal_transitions_keep_state_data() {
+    statemachine! {
+        transitions: {
+            *Idle + Start / start = Running(u32),
+            Running(u32) + Tick [is_fast] / log_tick,
+            Running(u32) + Restart / restart = Running(u32),
+        },
+        states: {
+            Running { entry: enter_running },
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        starts: u32,
+        entries: u32,
+        ticks: Vec<u32>,
+    }
+
+    impl StateMachineContext for Context {
+        fn is_fast(&self, speed: &u32) -> Result<bool, ()> {
+            Ok(*speed > 1)
+        }
+
+        fn start(&mut self) -> Result<u32, ()> {
+            self.starts += 1;
+            Ok(self.starts)
+        }
+
+        fn restart(&mut self, _speed: &u32) -> Result<u32, ()> {
+            self.start()
+        }
+
+        fn log_tick(&mut self, speed: &mut u32) -> Result<(), ()> {
+            self.ticks.push(*speed);
+            *speed += 1;
+            Ok(())
+        }
+
+        fn enter_running(&mut self, _speed: &u32) -> Result<(), ()> {
+            self.entries += 1;
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Start).unwrap();
+    assert_eq!(
+        sm.process_event(Events::Tick),
+        Err(Error::TransitionsFailed)
+    );
+
+    // A self-transition replaces the state data
+    sm.process_event(Events::Restart).unwrap();
+    assert!(matches!(sm.state(), &States::Running(2)));
+
+    // An internal transition runs its action, which updates the data of the state it stays in
+    sm.process_event(Events::Tick).unwrap();
+    sm.process_event(Events::Tick).unwrap();
+    assert!(matches!(sm.state(), &States::Running(4)));
+    assert_eq!(sm.context().ticks, [2, 3]);
+    assert_eq!(sm.context().entries, 1);
+}
+
+#[test]
+fn deferred_events() {
+    statemachine! {
+        transitions: {
+            *Idle + Request(u32) / accept = Busy(u32),
+            Busy(u32) + Request(u32) defer,
+            Busy(u32) + Done = Idle,
+            _ + Cancel defer,
+        },
+        // Cancel is only ever deferred
+        events: {
+            Cancel { allow_unused: true },
+        },
+        defer_capacity: 2,
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        accepted: Vec<u32>,
+    }
+
+    impl StateMachineContext for Context {
+        fn accept(&mut self, request: u32) -> Result<u32, ()> {
+            self.accepted.push(request);
+            Ok(request)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Request(1)).unwrap();
+
+    // Requests are deferred while busy
+    sm.process_event(Events::Request(2)).unwrap();
+    sm.process_event(Events::Request(3)).unwrap();
+    assert!(matches!(sm.state(), &States::Busy(1)));
+    assert_eq!(sm.deferred_events(), 2);
+    assert_eq!(
+        sm.process_event(Events::Request(4)),
+        Err(Error::DeferredEventsFull)
+    );
+
+    // Deferred events are re-delivered in order after the next state change, and deferred again
+    // by the new state
+    sm.process_event(Events::Done).unwrap();
+    assert!(matches!(sm.state(), &States::Busy(2)));
+    assert_eq!(sm.context().accepted, [1, 2]);
+    assert_eq!(sm.deferred_events(), 1);
+
+    sm.process_event(Events::Done).unwrap();
+    assert!(matches!(sm.state(), &States::Busy(3)));
+    assert_eq!(sm.deferred_events(), 0);
+
+    // Wildcards defer the event in all states
+    sm.process_event(Events::Cancel).unwrap();
+    sm.process_event(Events::Done).unwrap();
+    assert_eq!(sm.deferred_events(), 1);
+}
+
+#[test]
+fn timed_transitions() {
+    use core::time::Duration;
+    use smlang::Clock;
+
+    statemachine! {
+        transitions: {
+            *Idle + Start = Heating,
+            Heating + after(Duration::from_secs(10)) [ is_hot ] / report = Holding,
+            Holding + after(Duration::from_secs(5)) = Idle,
+            Holding + Poke,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        clock: smlang::MockClock,
+        hot: bool,
+        reports: usize,
+    }
+
+    impl_mock_clock!(Context);
+
+    impl StateMachineContext for Context {
+        fn is_hot(&self) -> Result<bool, ()> {
+            Ok(self.hot)
+        }
+
+        fn report(&mut self) -> Result<(), ()> {
+            self.reports += 1;
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert_eq!(sm.deadline(), None);
+    assert_eq!(sm.process_timeout(), Err(Error::InvalidEvent));
+
+    // The timer starts when the state is entered
+    sm.context().clock.set(Duration::from_secs(3));
+    sm.process_event(Events::Start).unwrap();
+    assert_eq!(sm.deadline(), Some(Duration::from_secs(13)));
+
+    // Guards of timed transitions are evaluated once they are taken
+    smol::block_on(sm.wait_timeout());
+    assert_eq!(sm.context().clock.now(), Duration::from_secs(13));
+    assert_eq!(sm.process_timeout(), Err(Error::TransitionsFailed));
+
+    sm.context_mut().hot = true;
+    assert!(matches!(sm.process_timeout(), Ok(&States::Holding)));
+    assert_eq!(sm.context().reports, 1);
+    assert_eq!(sm.deadline(), Some(Duration::from_secs(18)));
+
+    // Internal transitions don't restart the timer, restarting it is explicit
+    sm.context().clock.set(Duration::from_secs(16));
+    sm.process_event(Events::Poke).unwrap();
+    assert_eq!(sm.deadline(), Some(Duration::from_secs(18)));
+    sm.restart_timer();
+    assert_eq!(sm.deadline(), Some(Duration::from_secs(21)));
+
+    smol::block_on(sm.wait_timeout());
+    assert!(matches!(sm.process_timeout(), Ok(&States::Idle)));
+}
+
+#[test]
+fn choice_pseudo_states() {
+    statemachine! {
+        transitions: {
+            *Idle + Measure(u32) / measure = choice(u32) {
+                [ is_high ] / alarm = High(u32),
+                [ is_low ] = Low,
+                = Normal,
+            },
+            High(u32) | Low | Normal + Reset = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        offset: u32,
+        alarms: usize,
+    }
+
+    impl StateMachineContext for Context {
+        fn measure(&mut self, raw: u32) -> Result<u32, ()> {
+            Ok(raw + self.offset)
+        }
+
+        fn is_high(&self, value: &u32) -> Result<bool, ()> {
+            Ok(*value > 100)
+        }
+
+        fn is_low(&self, value: &u32) -> Result<bool, ()> {
+            Ok(*value < 10)
+        }
+
+        fn alarm(&mut self, value: u32) -> Result<u32, ()> {
+            self.alarms += 1;
+            Ok(value)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context {
+        offset: 5,
+        ..Default::default()
+    });
+
+    // The guards of the branches are evaluated on the result of the action
+    assert!(matches!(
+        sm.process_event(Events::Measure(100)),
+        Ok(&States::High(105))
+    ));
+    assert_eq!(sm.context().alarms, 1);
+
+    sm.process_event(Events::Reset).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Measure(1)),
+        Ok(&States::Low)
+    ));
+
+    // The last branch is taken when no guard passes
+    sm.process_event(Events::Reset).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Measure(50)),
+        Ok(&States::Normal)
+    ));
+    assert_eq!(sm.context().alarms, 1);
+}
+
+#[test]
+fn history_states() {
+    statemachine! {
+        transitions: {
+            *Off + PowerOn = Washing,
+            Washing + PowerOff = Off,
+            Washing(H*) {
+                *Filling + Filled = Cycle,
+                Cycle {
+                    *Soaking + Soaked / spin = Spinning(u32),
+                },
+            },
+            Off + Dry = Drying,
+            Drying + PowerOff = Off,
+            Drying(H) {
+                *Heating + Hot = Tumbling,
+                Tumbling {
+                    *Slow + Faster = Fast,
+                },
+            },
+        },
+        states_attr: #[derive(Debug, Clone)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        entered_spinning: usize,
+    }
+
+    impl StateMachineContext for Context {
+        fn spin(&mut self) -> Result<u32, ()> {
+            Ok(1200)
+        }
+
+        fn on_entry_spinning(&mut self) {
+            self.entered_spinning += 1;
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(sm.history().washing.is_none());
+
+    // Deep history resumes the nested sub-state along with its data
+    sm.process_event(Events::PowerOn).unwrap();
+    sm.process_event(Events::Filled).unwrap();
+    sm.process_event(Events::Soaked).unwrap();
+    sm.process_event(Events::PowerOff).unwrap();
+    assert!(matches!(
+        sm.history().washing,
+        Some(WashingStates::Cycle(CycleStates::Spinning(1200)))
+    ));
+    sm.process_event(Events::PowerOn).unwrap();
+    assert!(matches!(
+        sm.state(),
+        States::Washing(WashingStates::Cycle(CycleStates::Spinning(1200)))
+    ));
+    assert_eq!(sm.context().entered_spinning, 2);
+
+    // Shallow history enters nested composite states anew
+    sm.process_event(Events::PowerOff).unwrap();
+    sm.process_event(Events::Dry).unwrap();
+    sm.process_event(Events::Hot).unwrap();
+    sm.process_event(Events::Faster).unwrap();
+    sm.process_event(Events::PowerOff).unwrap();
+    sm.process_event(Events::Dry).unwrap();
+    assert!(matches!(
+        sm.state(),
+        States::Drying(DryingStates::Tumbling(TumblingStates::Slow))
+    ));
+
+    // The history can be persisted and restored
+    let history = sm.history().clone();
+    let mut sm = StateMachine::new(Context::default());
+    sm.set_history(history);
+    sm.process_event(Events::PowerOn).unwrap();
+    assert!(matches!(
+        sm.state(),
+        States::Washing(WashingStates::Cycle(CycleStates::Spinning(1200)))
+    ));
+}
+
+#[test]
+fn completion_transitions() {
+    statemachine! {
+        transitions: {
+            *Idle + Insert(u32) / insert = Checking(u32),
+            Checking(u32) [ is_enough ] / vend = Vending,
+            Checking(u32) = Idle,
+            Vending = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        vended: usize,
+    }
+
+    impl StateMachineContext for Context {
+        fn insert(&mut self, coins: u32) -> Result<u32, ()> {
+            Ok(coins)
+        }
+
+        fn is_enough(&self, coins: &u32) -> Result<bool, ()> {
+            Ok(*coins >= 2)
+        }
+
+        fn vend(&mut self, _coins: &u32) -> Result<(), ()> {
+            self.vended += 1;
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // Completion transitions run to completion within `process_event`
+    assert!(matches!(
+        sm.process_event(Events::Insert(1)),
+        Ok(&States::Idle)
+    ));
+    assert_eq!(sm.context().vended, 0);
+
+    assert!(matches!(
+        sm.process_event(Events::Insert(2)),
+        Ok(&States::Idle)
+    ));
+    assert_eq!(sm.context().vended, 1);
+}
+
+#[test]
+fn chained_actions() {
+    statemachine! {
+        transitions: {
+            *Idle + Order(u32) / validate, log, async reserve = Reserved(u32),
+            Reserved(u32) + Cancel / log_cancel, release = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        log: Vec<String>,
+    }
+
+    impl StateMachineContext for Context {
+        fn validate(&mut self, order: &u32) -> Result<(), ()> {
+            self.log.push(format!("validate {}", order));
+            Ok(())
+        }
+
+        fn log(&mut self, order: &u32) -> Result<(), ()> {
+            self.log.push(format!("log {}", order));
+            Ok(())
+        }
+
+        async fn reserve(&mut self, order: u32) -> Result<u32, ()> {
+            self.log.push(format!("reserve {}", order));
+            Ok(order)
+        }
+
+        fn log_cancel(&mut self, reserved: &u32) -> Result<(), ()> {
+            self.log.push(format!("cancel {}", reserved));
+            Ok(())
+        }
+
+        fn release(&mut self, reserved: &u32) -> Result<(), ()> {
+            self.log.push(format!("release {}", reserved));
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    smol::block_on(async {
+        // Actions run in order, and the last one provides the data of the next state
+        assert!(matches!(
+            sm.process_event(Events::Order(7)).await,
+            Ok(&States::Reserved(7))
+        ));
+        sm.process_event(Events::Cancel).await.unwrap();
+    });
+    assert_eq!(
+        sm.context().log,
+        ["validate 7", "log 7", "reserve 7", "cancel 7", "release 7"]
+    );
+}
+
+#[test]
+fn fallback_transitions() {
+    statemachine! {
+        transitions: {
+            // The fallback is taken last, wherever it is declared
+            *Idle + Login(u32) [ else ] / deny = Denied,
+            Idle + Login(u32) [ is_admin ] / admin = Admin(u32),
+            Idle + Login(u32) [ is_user ] = User,
+            Denied | User | Admin(u32) + Logout = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        denied: Vec<u32>,
+    }
+
+    impl StateMachineContext for Context {
+        fn is_admin(&self, id: &u32) -> Result<bool, ()> {
+            Ok(*id == 0)
+        }
+
+        fn is_user(&self, id: &u32) -> Result<bool, ()> {
+            Ok(*id < 10)
+        }
+
+        fn admin(&mut self, id: u32) -> Result<u32, ()> {
+            Ok(id)
+        }
+
+        fn deny(&mut self, id: u32) -> Result<(), ()> {
+            self.denied.push(id);
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(
+        sm.process_event(Events::Login(0)),
+        Ok(&States::Admin(0))
+    ));
+    sm.process_event(Events::Logout).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Login(3)),
+        Ok(&States::User)
+    ));
+    sm.process_event(Events::Logout).unwrap();
+
+    // All guards fail, so the fallback is taken instead of returning `GuardFailed`
+    assert!(matches!(
+        sm.process_event(Events::Login(42)),
+        Ok(&States::Denied)
+    ));
+    assert_eq!(sm.context().denied, [42]);
+}
+
+#[test]
+fn catch_all_transitions() {
+    statemachine! {
+        transitions: {
+            *Idle + Start / start = Running(u32),
+            Running(u32) + Tick(u32) / tick = Running(u32),
+            Running(u32) + Stop = Idle,
+            // Any other event is a fault while running
+            Running(u32) + _ / fault = Fault,
+            Fault + Reset = Idle,
+            Fault + _ [ is_critical ] = Shutdown,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        faults: Vec<u32>,
+        critical: bool,
+    }
+
+    impl StateMachineContext for Context {
+        fn start(&mut self) -> Result<u32, ()> {
+            Ok(0)
+        }
+
+        fn tick(&mut self, ticks: &u32, tick: u32) -> Result<u32, ()> {
+            Ok(ticks + tick)
+        }
+
+        fn fault(&mut self, ticks: &u32) -> Result<(), ()> {
+            self.faults.push(*ticks);
+            Ok(())
+        }
+
+        fn is_critical(&self) -> Result<bool, ()> {
+            Ok(self.critical)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Start).ok();
+    assert!(matches!(sm.state(), &States::Running(_)));
+
+    // Events handled explicitly take precedence over the catch-all transition
+    assert!(matches!(
+        sm.process_event(Events::Tick(3)),
+        Ok(&States::Running(3))
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Start),
+        Ok(&States::Fault)
+    ));
+    assert_eq!(sm.context().faults, [3]);
+
+    // The catch-all transition can be guarded
+    assert!(matches!(
+        sm.process_event(Events::Stop),
+        Err(Error::TransitionsFailed)
+    ));
+    sm.context_mut().critical = true;
+    assert!(matches!(
+        sm.process_event(Events::Stop),
+        Ok(&States::Shutdown)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Reset),
+        Err(Error::InvalidEvent)
+    ));
+}
+
+#[test]
+fn wildcard_exclusions() {
+    statemachine! {
+        transitions: {
+            *Idle + Start = Running,
+            Running + Pause = Paused,
+            Paused + Start = Running,
+            Running + Stop = Shutdown,
+            Maintenance {
+                *Checking + Check = Repairing,
+            },
+            Idle + Service = Maintenance,
+            // Neither the shutdown nor the maintenance states are reset
+            _ + Reset = Idle except [Shutdown, Maintenance],
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Start).unwrap();
+    sm.process_event(Events::Pause).unwrap();
+    assert!(matches!(sm.process_event(Events::Reset), Ok(&States::Idle)));
+
+    sm.process_event(Events::Service).unwrap();
+    sm.process_event(Events::Check).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Reset),
+        Err(Error::InvalidEvent)
+    ));
+
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Start).unwrap();
+    sm.process_event(Events::Stop).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Reset),
+        Err(Error::InvalidEvent)
+    ));
+}
+
+#[test]
+fn event_alternatives() {
+    statemachine! {
+        transitions: {
+            *Idle + (Coin(u32) | Token(u32)) / insert = Paid(u32),
+            Paid(u32) + (Cancel | Timeout) / refund = Idle,
+            Paid(u32) + (Coin(u32) | Token(u32)) defer,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        refunded: u32,
+    }
+
+    impl StateMachineContext for Context {
+        fn insert(&mut self, value: u32) -> Result<u32, ()> {
+            Ok(value)
+        }
+
+        fn refund(&mut self, paid: &u32) -> Result<(), ()> {
+            self.refunded += paid;
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(
+        sm.process_event(Events::Coin(2)),
+        Ok(&States::Paid(2))
+    ));
+    sm.process_event(Events::Token(5)).unwrap();
+    assert_eq!(sm.deferred_events(), 1);
+
+    // The deferred token is re-delivered once the coin has been refunded
+    assert!(matches!(
+        sm.process_event(Events::Timeout),
+        Ok(&States::Paid(5))
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Cancel),
+        Ok(&States::Idle)
+    ));
+    assert_eq!(sm.context().refunded, 7);
+}
+
+#[test]
+fn transition_priorities() {
+    statemachine! {
+        transitions: {
+            *Idle + Reading(u32) [ is_valid ] = Normal,
+            // Overheating is checked first, even though the reading is also valid
+            Idle + Reading(u32) [ is_hot ] = Alarm priority(1),
+            Idle + Reading(u32) [ is_cold ] = Heating priority(-1),
+            Normal | Alarm | Heating + Reset = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn is_valid(&self, reading: &u32) -> Result<bool, ()> {
+            Ok(*reading < 200)
+        }
+
+        fn is_hot(&self, reading: &u32) -> Result<bool, ()> {
+            Ok(*reading > 100)
+        }
+
+        fn is_cold(&self, reading: &u32) -> Result<bool, ()> {
+            Ok(*reading < 10)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert!(matches!(
+        sm.process_event(Events::Reading(150)),
+        Ok(&States::Alarm)
+    ));
+    sm.process_event(Events::Reset).unwrap();
+
+    // Transitions with the same priority are tried in the order they are declared
+    assert!(matches!(
+        sm.process_event(Events::Reading(5)),
+        Ok(&States::Normal)
+    ));
+}
+
+#[test]
+fn sub_machines() {
+    statemachine! {
+        name: Handshake,
+        transitions: {
+            *Hello + Ack(u32) [ is_valid ] = Established,
+        },
+    }
+
+    statemachine! {
+        name: Connection,
+        transitions: {
+            *Idle + Connect / start_handshake = Connecting(HandshakeStateMachine<HandshakeContext>),
+            Connecting(HandshakeStateMachine<HandshakeContext>) + Handshake(HandshakeEvents) delegate,
+            // Taken once the handshake has reached its final state
+            Connecting(HandshakeStateMachine<HandshakeContext>) = Connected,
+            Connecting(HandshakeStateMachine<HandshakeContext>) + Abort = Idle,
+        },
+    }
+
+    struct HandshakeContext;
+    impl HandshakeStateMachineContext for HandshakeContext {
+        fn is_valid(&self, key: &u32) -> Result<bool, ()> {
+            Ok(*key == 42)
+        }
+    }
+
+    struct Context;
+    impl ConnectionStateMachineContext for Context {
+        fn start_handshake(&mut self) -> Result<HandshakeStateMachine<HandshakeContext>, ()> {
+            Ok(HandshakeStateMachine::new(HandshakeContext))
+        }
+    }
+
+    let mut sm = ConnectionStateMachine::new(Context);
+    sm.process_event(ConnectionEvents::Connect).unwrap();
+    assert!(matches!(
+        sm.process_event(ConnectionEvents::Handshake(HandshakeEvents::Ack(1))),
+        Err(ConnectionError::TransitionsFailed)
+    ));
+
+    // The transitions of the state still apply while the sub-machine is running
+    assert!(matches!(
+        sm.process_event(ConnectionEvents::Abort),
+        Ok(&ConnectionStates::Idle)
+    ));
+
+    sm.process_event(ConnectionEvents::Connect).unwrap();
+    assert!(matches!(
+        sm.process_event(ConnectionEvents::Handshake(HandshakeEvents::Ack(42))),
+        Ok(&ConnectionStates::Connected)
+    ));
+    assert!(sm.is_finished());
+}
+
+#[test]
+fn final_states() {
+    statemachine! {
+        transitions: {
+            *Running + Cancel = Cancelled @final,
+            Running + Finish(u32) / store = Done(u32) @final,
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        completions: usize,
+    }
+    impl StateMachineContext for Context {
+        fn store(&mut self, result: u32) -> Result<u32, ()> {
+            Ok(result)
+        }
+
+        fn on_completion(&mut self, final_state: &States) {
+            assert!(matches!(final_state, States::Done(3)));
+            self.completions += 1;
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(!sm.is_finished());
+    assert!(matches!(
+        sm.process_event(Events::Finish(3)),
+        Ok(&States::Done(3))
+    ));
+    assert_eq!(sm.context().completions, 1);
+    assert!(sm.is_finished());
+
+    // Events are no longer processed once a final state has been entered
+    assert!(matches!(
+        sm.process_event(Events::Cancel),
+        Err(Error::MachineFinished)
+    ));
+    assert_eq!(sm.context().completions, 1);
+}
+
+#[test]
+fn inline_actions() {
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / |_, count| { Ok(count * 2) } = Counting(u32),
+            Counting(u32) + Tick / |_, count: &u32| { Ok(*count + 1) } = Counting(u32),
+            // Inline actions can call the methods of the context, and be chained
+            Counting(u32) + Stop / |_, _| { Ok(()) }, |ctx, count| { ctx.record(*count) } = Idle,
+            Idle + Record(u32) / record,
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        recorded: Option<u32>,
+    }
+    impl StateMachineContext for Context {
+        fn record(&mut self, count: u32) -> Result<(), ()> {
+            self.recorded = Some(count);
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(
+        sm.process_event(Events::Start(2)),
+        Ok(&States::Counting(4))
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Tick),
+        Ok(&States::Counting(5))
+    ));
+    assert!(matches!(sm.process_event(Events::Stop), Ok(&States::Idle)));
+    assert_eq!(sm.context().recorded, Some(5));
+}
+
+#[test]
+fn expression_guards() {
+    #[derive(Debug, PartialEq)]
+    pub enum Kind {
+        Hot,
+        Cold,
+    }
+
+    pub struct Reading {
+        kind: Kind,
+        value: u32,
+    }
+
+    pub struct Counter {
+        count: u32,
+    }
+
+    statemachine! {
+        transitions: {
+            *Idle + Start / start = Monitoring(Counter),
+            // Fields of the state data are referenced directly, and the event data as `event`
+            Monitoring(Counter) + Measure(Reading) [ count > 1 && event.kind == Kind::Hot ] = Alarm,
+            Monitoring(Counter) + Measure(Reading) / increment = Monitoring(Counter),
+            Alarm + Reset(u32) [ *event == 0 ] = Idle,
+        },
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn start(&mut self) -> Result<Counter, ()> {
+            Ok(Counter { count: 0 })
+        }
+
+        fn increment(&mut self, counter: &Counter, reading: Reading) -> Result<Counter, ()> {
+            Ok(Counter {
+                count: counter.count + reading.value,
+            })
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Start).unwrap();
+    let hot = || Reading {
+        kind: Kind::Hot,
+        value: 1,
+    };
+    assert!(matches!(
+        sm.process_event(Events::Measure(hot())),
+        Ok(&States::Monitoring(Counter { count: 1 }))
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Measure(Reading {
+            kind: Kind::Cold,
+            value: 1,
+        })),
+        Ok(&States::Monitoring(Counter { count: 2 }))
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Measure(hot())),
+        Ok(&States::Alarm)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Reset(1)),
+        Err(Error::TransitionsFailed)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Reset(0)),
+        Ok(&States::Idle)
+    ));
+}
+
+#[test]
+fn failed_actions() {
+    #[derive(Debug, PartialEq)]
+    pub enum PaymentError {
+        Declined,
+    }
+
+    statemachine! {
+        transitions: {
+            *Cart(u32) + Pay(u32) / charge = Paid(u32),
+        },
+        custom_error: true,
+    }
+
+    #[derive(Default)]
+    struct Context {
+        exits: usize,
+    }
+    impl StateMachineContext for Context {
+        type Error = PaymentError;
+
+        fn charge(&mut self, total: &u32, amount: u32) -> Result<u32, PaymentError> {
+            if amount < *total {
+                return Err(PaymentError::Declined);
+            }
+            Ok(amount)
+        }
+
+        fn on_exit_cart(&mut self) {
+            self.exits += 1;
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default(), 10);
+
+    // The failed action cancels the transition, so the state is not exited and keeps its data
+    assert!(matches!(
+        sm.process_event(Events::Pay(5)),
+        Err(Error::ActionFailed(PaymentError::Declined))
+    ));
+    assert!(matches!(sm.state(), &States::Cart(10)));
+    assert_eq!(sm.context().exits, 0);
+
+    assert!(matches!(
+        sm.process_event(Events::Pay(10)),
+        Ok(&States::Paid(10))
+    ));
+    assert_eq!(sm.context().exits, 1);
+}
+
+#[test]
+fn fault_state() {
+    #[derive(Debug, PartialEq)]
+    pub enum DeviceError {
+        Timeout,
+    }
+
+    statemachine! {
+        transitions: {
+            *Idle + Start / power_on = Running,
+            Running + Measure(u32) / measure = Running,
+            Faulted(DeviceError) + Reset = Idle,
+        },
+        custom_error: true,
+        on_action_error: Faulted(DeviceError),
+    }
+
+    #[derive(Default)]
+    struct Context {
+        exits: usize,
+    }
+    impl StateMachineContext for Context {
+        type Error = DeviceError;
+
+        fn power_on(&mut self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn measure(&mut self, value: u32) -> Result<(), DeviceError> {
+            if value == 0 {
+                return Err(DeviceError::Timeout);
+            }
+            Ok(())
+        }
+
+        fn on_exit_running(&mut self) {
+            self.exits += 1;
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Start).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Measure(1)),
+        Ok(&States::Running)
+    ));
+
+    // The failed action leaves the state for the fault state, holding the error
+    assert!(matches!(
+        sm.process_event(Events::Measure(0)),
+        Ok(&States::Faulted(DeviceError::Timeout))
+    ));
+    assert_eq!(sm.context().exits, 1);
+    assert!(matches!(sm.process_event(Events::Reset), Ok(&States::Idle)));
+}
+
+#[test]
+fn retries() {
+    pub struct Request(u32);
+
+    statemachine! {
+        transitions: {
+            *Idle + Send(Request) [ link_up ] / transmit = Sent(u32) retry(2, backoff),
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        link_failures: core::cell::Cell<usize>,
+        transmit_failures: usize,
+        backoffs: Vec<usize>,
+    }
+    impl StateMachineContext for Context {
+        fn link_up(&self, _request: &Request) -> Result<bool, ()> {
+            if self.link_failures.get() > 0 {
+                self.link_failures.set(self.link_failures.get() - 1);
+                return Err(());
+            }
+            Ok(true)
+        }
+
+        fn transmit(&mut self, request: &Request) -> Result<u32, ()> {
+            if self.transmit_failures > 0 {
+                self.transmit_failures -= 1;
+                return Err(());
+            }
+            Ok(request.0)
+        }
+
+        fn backoff(&mut self, attempt: usize) {
+            self.backoffs.push(attempt);
+        }
+    }
+
+    // The guard and the action are retried while they fail
+    let mut sm = StateMachine::new(Context {
+        link_failures: 1.into(),
+        transmit_failures: 1,
+        ..Default::default()
+    });
+    assert!(matches!(
+        sm.process_event(Events::Send(Request(7))),
+        Ok(&States::Sent(7))
+    ));
+    assert_eq!(sm.context().backoffs, [1, 1]);
+
+    // The failure is returned once the retries are used up
+    let mut sm = StateMachine::new(Context {
+        transmit_failures: 3,
+        ..Default::default()
+    });
+    assert!(matches!(
+        sm.process_event(Events::Send(Request(7))),
+        Err(Error::ActionFailed(()))
+    ));
+    assert_eq!(sm.context().backoffs, [1, 2]);
+    assert!(matches!(sm.state(), &States::Idle));
+}
+
+#[test]
+fn non_clone_data() {
+    // The data of the events and states is moved, so it doesn't need to implement `Clone`
+    pub struct Buffer(Vec<u8>);
+
+    statemachine! {
+        transitions: {
+            *Idle + Load(Buffer) [ not_empty ] / load = Loaded(Buffer),
+            Loaded(Buffer) + Load(Buffer) defer,
+            Loaded(Buffer) + Append(Buffer) [ fits ] / append,
+            Loaded(Buffer) + Flush / flush = Idle,
+            Idle + Open = Session,
+            Session + Close = Idle,
+            Session(H) {
+                *Empty + Load(Buffer) / load = Holding(Buffer),
+            },
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        flushed: Vec<usize>,
+        actions: core::cell::RefCell<Vec<&'static str>>,
+    }
+
+    impl StateMachineContext for Context {
+        fn not_empty(&self, buffer: &Buffer) -> Result<bool, ()> {
+            Ok(!buffer.0.is_empty())
+        }
+
+        fn fits(&self, loaded: &Buffer, buffer: &Buffer) -> Result<bool, ()> {
+            Ok(loaded.0.len() + buffer.0.len() <= 4)
+        }
+
+        fn load(&mut self, buffer: Buffer) -> Result<Buffer, ()> {
+            Ok(buffer)
+        }
+
+        fn append(&mut self, loaded: &mut Buffer, buffer: Buffer) -> Result<(), ()> {
+            loaded.0.extend(buffer.0);
+            Ok(())
+        }
+
+        fn flush(&mut self, loaded: &Buffer) -> Result<(), ()> {
+            self.flushed.push(loaded.0.len());
+            Ok(())
+        }
+
+        fn log_action(&self, action: &'static str) {
+            self.actions.borrow_mut().push(action);
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Load(Buffer(vec![1, 2]))).unwrap();
+
+    // Deferred events are moved into the queue and out of it when they are re-delivered
+    sm.process_event(Events::Load(Buffer(vec![3]))).unwrap();
+    sm.process_event(Events::Append(Buffer(vec![4]))).unwrap();
+    assert!(matches!(sm.state(), States::Loaded(Buffer(data)) if *data == [1, 2, 4]));
+    sm.process_event(Events::Flush).unwrap();
+    assert!(matches!(sm.state(), States::Loaded(Buffer(data)) if *data == [3]));
+    sm.process_event(Events::Flush).unwrap();
+    assert_eq!(sm.context().flushed, [3, 1]);
+
+    // The history takes the sub-state along with its data, and gives it back on re-entry
+    sm.process_event(Events::Open).unwrap();
+    sm.process_event(Events::Load(Buffer(vec![5]))).unwrap();
+    sm.process_event(Events::Close).unwrap();
+    assert!(matches!(
+        &sm.history().session,
+        Some(SessionStates::Holding(Buffer(data))) if *data == [5]
+    ));
+    sm.process_event(Events::Open).unwrap();
+    assert!(matches!(
+        sm.state(),
+        States::Session(SessionStates::Holding(Buffer(data))) if *data == [5]
+    ));
+    assert!(sm.history().session.is_none());
+
+    assert_eq!(
+        *sm.context().actions.borrow(),
+        ["load", "append", "flush", "load", "flush", "load"]
+    );
+}
+
+#[test]
+fn posted_events() {
+    statemachine! {
+        transitions: {
+            *Idle + Load(u32) / load = Loaded(u32),
+            Loaded(u32) + Validate [ is_valid ] = Ready,
+            Loaded(u32) + Validate = Idle,
+            Ready + Start / start = Running,
+        },
+        post_capacity: 2,
+    }
+
+    #[derive(Default)]
+    struct Context {
+        posted: PostedEvents,
+    }
+    impl StateMachineContext for Context {
+        fn posted_events(&mut self) -> &mut PostedEvents {
+            &mut self.posted
+        }
+
+        fn load(&mut self, value: u32) -> Result<u32, ()> {
+            // Validated and started before `process_event` returns
+            assert!(self.post(Events::Validate).is_ok());
+            assert!(self.post(Events::Start).is_ok());
+            assert!(self.post(Events::Start).is_err());
+            Ok(value)
+        }
+
+        fn is_valid(&self, value: &u32) -> Result<bool, ()> {
+            Ok(*value > 0)
+        }
+
+        fn start(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(
+        sm.process_event(Events::Load(1)),
+        Ok(&States::Running)
+    ));
+    assert!(sm.context().posted.is_empty());
+
+    // The error of a posted event is returned
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(
+        sm.process_event(Events::Load(0)),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(sm.state(), &States::Idle));
+}
+
+#[test]
+fn queued_events() {
+    statemachine! {
+        transitions: {
+            *Idle + Load(u32) / load = Loaded(u32),
+            Loaded(u32) + Validate [ is_valid ] = Ready,
+            Ready + Start / start = Running,
+        },
+        queue_capacity: 2,
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn load(&mut self, value: u32) -> Result<u32, ()> {
+            Ok(value)
+        }
+
+        fn is_valid(&self, value: &u32) -> Result<bool, ()> {
+            Ok(*value > 0)
+        }
+
+        fn start(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    let mut queue = EventQueue::new();
+    let (mut producer, mut consumer) = queue.split();
+    let mut sm = StateMachine::new(Context);
+
+    // Events are only processed by `process_queued`
+    assert!(producer.push(Events::Load(1)).is_ok());
+    assert!(producer.push(Events::Validate).is_ok());
+    assert!(producer.is_full());
+    assert!(producer.push(Events::Start).is_err());
+    assert!(matches!(sm.state(), &States::Idle));
+    assert!(matches!(
+        sm.process_queued(&mut consumer),
+        Ok(&States::Ready)
+    ));
+    assert!(consumer.is_empty());
+
+    // Events can be pushed from another thread
+    std::thread::scope(|scope| {
+        scope.spawn(move || while producer.push(Events::Start).is_err() {});
+    });
+    assert!(matches!(
+        sm.process_queued(&mut consumer),
+        Ok(&States::Running)
+    ));
+
+    // The error of an event is returned, while the events after it stay queued
+    let (mut producer, mut consumer) = queue.split();
+    assert!(producer.push(Events::Load(1)).is_ok());
+    assert!(producer.push(Events::Start).is_ok());
+    assert!(matches!(
+        sm.process_queued(&mut consumer),
+        Err(Error::InvalidEvent)
+    ));
+    assert_eq!(queue.len(), 1);
+}
+
+#[test]
+fn queued_event_priorities() {
+    statemachine! {
+        transitions: {
+            *Idle + Move(u32) = Moving,
+            Moving + Move(u32) = Moving,
+            _ + EmergencyStop = Stopped,
+            Stopped + Reset = Idle,
+        },
+        events: {
+            EmergencyStop { priority: 2 },
+            Reset { priority: 1 },
+        },
+        queue_capacity: 2,
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {}
+
+    let mut queue = EventQueue::new();
+    let (mut producer, mut consumer) = queue.split();
+    let mut sm = StateMachine::new(Context);
+
+    // Every priority has its own capacity
+    assert!(producer.push(Events::Move(1)).is_ok());
+    assert!(producer.push(Events::Move(2)).is_ok());
+    assert!(producer.push(Events::Move(3)).is_err());
+    assert!(producer.push(Events::Reset).is_ok());
+    assert!(producer.push(Events::EmergencyStop).is_ok());
+    assert!(!producer.is_full());
+
+    // The emergency stop preempts the other events, followed by the reset
+    assert!(matches!(
+        sm.process_queued(&mut consumer),
+        Ok(&States::Moving)
+    ));
+    assert!(consumer.is_empty());
+
+    // The moves pushed before the emergency stop are only processed after it
+    let mut sm = StateMachine::new(Context);
+    assert!(producer.push(Events::Move(1)).is_ok());
+    assert!(producer.push(Events::Move(2)).is_ok());
+    assert!(producer.push(Events::EmergencyStop).is_ok());
+    assert!(matches!(
+        sm.process_queued(&mut consumer),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(sm.state(), &States::Stopped));
+    assert_eq!(queue.len(), 1);
+}
+
+// Interrupt handlers are simulated by threads, which take the critical section of the `std`
+// implementation of `critical-section`
+#[cfg(feature = "critical-section")]
+#[test]
+fn interrupt_safe_queued_events() {
+    statemachine! {
+        transitions: {
+            *Counting + Tick / count,
+            Counting + Stop = Stopped,
+        },
+        queue_capacity: 4,
+    }
+
+    struct Context(u32);
+    impl StateMachineContext for Context {
+        fn count(&mut self) -> Result<(), ()> {
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    static QUEUE: EventQueue = EventQueue::new();
+    let mut consumer = QUEUE.take_consumer().unwrap();
+    assert!(QUEUE.take_consumer().is_none());
+    let mut sm = StateMachine::new(Context(0));
+
+    // Any number of handlers push to the queue while the main task drains it
+    std::thread::scope(|scope| {
+        for _ in 0..3 {
+            scope.spawn(|| {
+                for _ in 0..10 {
+                    while QUEUE.push(Events::Tick).is_err() {}
+                }
+            });
+        }
+        while sm.context().0 < 30 {
+            assert!(sm.process_queued(&mut consumer).is_ok());
+        }
+    });
+    assert!(consumer.is_empty());
+
+    assert!(QUEUE.push(Events::Stop).is_ok());
+    assert!(matches!(
+        sm.process_queued(&mut consumer),
+        Ok(&States::Stopped)
+    ));
+}
+
+#[test]
+fn do_activities() {
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / start = Working(u32),
+            Working(u32) + Done = Idle,
+            Working(u32) + Cancel = Idle,
+            Idle + Wait = Waiting,
+            Waiting + Cancel = Idle,
+        },
+        states: {
+            Working { do: async work },
+            Waiting { do: async wait },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        steps: u32,
+        waits: u32,
+    }
+    impl StateMachineContext for Context {
+        fn start(&mut self, steps: u32) -> Result<u32, ()> {
+            Ok(steps)
+        }
+
+        async fn work(&mut self, steps: &u32) -> Events {
+            while self.steps < *steps {
+                self.steps += 1;
+                smol::future::yield_now().await;
+            }
+            Events::Done
+        }
+
+        async fn wait(&mut self) -> Events {
+            self.waits += 1;
+            smol::future::pending().await
+        }
+    }
+
+    smol::block_on(async {
+        let mut sm = StateMachine::new(Context::default());
+
+        // Without an activity, the next event is processed
+        let start = async { Events::Start(3) };
+        assert!(matches!(
+            sm.process_next_event(start).await,
+            Ok(&States::Working(3))
+        ));
+
+        // The event of the activity is processed when it completes first
+        let done = sm.process_next_event(smol::future::pending()).await;
+        assert!(matches!(done, Ok(&States::Idle)));
+        assert_eq!(sm.context().steps, 3);
+
+        // The activity is cancelled when the event is ready first
+        sm.process_event(Events::Wait).unwrap();
+        let cancel = async {
+            smol::future::yield_now().await;
+            Events::Cancel
+        };
+        assert!(matches!(
+            sm.process_next_event(cancel).await,
+            Ok(&States::Idle)
+        ));
+        assert_eq!(sm.context().waits, 1);
+    });
+}
+
+#[test]
+fn cancellable_actions() {
+    statemachine! {
+        transitions: {
+            *Idle + Download(u32) / async download = Done(u32),
+            Done(u32) + Reset = Idle,
+        },
+        states: {
+            Idle { exit: left_idle },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        exits: u32,
+    }
+    impl StateMachineContext for Context {
+        async fn download(&mut self, size: u32) -> Result<u32, ()> {
+            if size > 10 {
+                smol::future::pending::<()>().await;
+            }
+            smol::future::yield_now().await;
+            Ok(size)
+        }
+
+        fn left_idle(&mut self) -> Result<(), ()> {
+            self.exits += 1;
+            Ok(())
+        }
+    }
+
+    smol::block_on(async {
+        let mut sm = StateMachine::new(Context::default());
+
+        // A long-running action is cancelled, and the state is kept
+        let cancel = smol::future::yield_now();
+        assert!(matches!(
+            sm.process_event_cancellable(Events::Download(100), cancel)
+                .await,
+            Err(Error::Cancelled)
+        ));
+        assert!(matches!(sm.state(), &States::Idle));
+        assert_eq!(sm.context().exits, 0);
+
+        // An action which completes first takes the transition
+        let cancel = smol::future::pending();
+        assert!(matches!(
+            sm.process_event_cancellable(Events::Download(1), cancel)
+                .await,
+            Ok(&States::Done(1))
+        ));
+        assert_eq!(sm.context().exits, 1);
+
+        // Actions can't be cancelled through `process_event`
+        sm.process_event(Events::Reset).await.unwrap();
+        assert!(matches!(
+            sm.process_event(Events::Download(2)).await,
+            Ok(&States::Done(2))
+        ));
+    });
+}
+
+#[test]
+fn guard_timeouts() {
+    use smlang::Clock;
+
+    statemachine! {
+        transitions: {
+            *Idle + Check(u32) [ async is_ready.timeout(10ms) ] = Ready,
+            Idle + Check(u32) = Waiting,
+            Waiting + Check(u32) [ async is_ready.timeout(core::time::Duration::from_millis(10))? ] = Ready,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        clock: smlang::MockClock,
+    }
+    impl_mock_clock!(Context);
+    impl StateMachineContext for Context {
+        async fn is_ready(&self, delay: &u32) -> Result<bool, ()> {
+            if *delay > 0 {
+                smol::future::pending::<()>().await;
+            }
+            Ok(true)
+        }
+    }
+
+    smol::block_on(async {
+        // A guard which resolves in time passes
+        let mut sm = StateMachine::new(Context::default());
+        assert!(matches!(
+            sm.process_event(Events::Check(0)).await,
+            Ok(&States::Ready)
+        ));
+
+        // A guard which times out fails, once the time of the clock has passed
+        let mut sm = StateMachine::new(Context::default());
+        assert!(matches!(
+            sm.process_event(Events::Check(1000)).await,
+            Ok(&States::Waiting)
+        ));
+        assert_eq!(
+            sm.context().clock.now(),
+            core::time::Duration::from_millis(10)
+        );
+        assert_eq!(sm.time_in_state(), core::time::Duration::ZERO);
+
+        // Or returns an error
+        assert!(matches!(
+            sm.process_event(Events::Check(1000)).await,
+            Err(Error::GuardTimedOut)
+        ));
+        assert!(matches!(sm.state(), &States::Waiting));
+    });
+}
+
+#[test]
+fn mock_clock() {
+    use core::time::Duration;
+    use smlang::{Clock, MockClock};
+
+    statemachine! {
+        transitions: {
+            *Idle + Start = Heating,
+            Heating + after(Duration::from_secs(10)) = Holding,
+            Holding + Poke,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        clock: MockClock,
+    }
+    impl_mock_clock!(Context);
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.context().clock.set(Duration::from_secs(5));
+    assert_eq!(sm.time_in_state(), Duration::ZERO);
+
+    // The time in a state is measured from its entry
+    sm.process_event(Events::Start).unwrap();
+    sm.context().clock.advance(Duration::from_secs(4));
+    assert_eq!(sm.time_in_state(), Duration::from_secs(4));
+
+    // Waiting for a timed transition moves the clock to its deadline without sleeping
+    smol::block_on(sm.wait_timeout());
+    assert_eq!(sm.context().clock.now(), Duration::from_secs(15));
+    assert_eq!(sm.time_in_state(), Duration::from_secs(10));
+    assert!(matches!(sm.process_timeout(), Ok(&States::Holding)));
+    assert_eq!(sm.time_in_state(), Duration::ZERO);
+
+    // Internal transitions don't leave the state
+    sm.context().clock.advance(Duration::from_secs(1));
+    sm.process_event(Events::Poke).unwrap();
+    assert_eq!(sm.time_in_state(), Duration::from_secs(1));
+}
+
+#[test]
+fn starting_state_timer() {
+    use core::time::Duration;
+    use smlang::{Clock, MockClock};
+
+    statemachine! {
+        transitions: {
+            *Heating + after(Duration::from_secs(10)) = Holding,
+            Heating + Poll,
+            _ + Alarm = Idle,
+        },
+        states: {
+            Heating { timeout: Duration::from_secs(30) => Alarm },
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        clock: MockClock,
+    }
+    impl_mock_clock!(Context);
+    impl StateMachineContext for Context {}
+
+    let secs = Duration::from_secs;
+    let context = Context::default();
+    context.clock.set(secs(100));
+
+    // Until the first event is processed, the timer of the starting state reads zero and its
+    // deadlines are measured from the current time
+    let mut sm = StateMachine::new(context);
+    assert_eq!(sm.time_in_state(), Duration::ZERO);
+    assert_eq!(sm.deadline(), Some(secs(110)));
+    assert_eq!(sm.watchdog_deadline(), Some(secs(130)));
+
+    // The first event starts the timer, rather than the clock starting at zero
+    sm.context().clock.advance(secs(3));
+    sm.process_event(Events::Poll).unwrap();
+    sm.context().clock.advance(secs(4));
+    assert_eq!(sm.time_in_state(), secs(4));
+    assert_eq!(sm.deadline(), Some(secs(113)));
+    assert_eq!(sm.watchdog_deadline(), Some(secs(133)));
+    smol::block_on(sm.wait_timeout());
+    assert_eq!(sm.context().clock.now(), secs(113));
+    assert!(matches!(sm.process_timeout(), Ok(&States::Holding)));
+
+    // The timer can be started when the state machine is created as well
+    let context = Context::default();
+    context.clock.set(secs(200));
+    let mut sm = StateMachine::new(context);
+    sm.restart_timer();
+    sm.context().clock.advance(secs(5));
+    assert_eq!(sm.time_in_state(), secs(5));
+    assert_eq!(sm.deadline(), Some(secs(210)));
+    smol::block_on(sm.wait_watchdog());
+    assert!(matches!(sm.process_watchdog(), Ok(&States::Idle)));
+}
+
+#[test]
+fn state_timeouts() {
+    use core::time::Duration;
+    use smlang::MockClock;
+
+    statemachine! {
+        transitions: {
+            *Idle + Connect = Connecting,
+            Connecting + Connected = Online,
+            Connecting + Poll,
+            _ + Watchdog / reset = Idle,
+        },
+        states: {
+            Connecting { timeout: Duration::from_secs(5) => Watchdog },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        clock: MockClock,
+        resets: usize,
+    }
+    impl_mock_clock!(Context);
+    impl StateMachineContext for Context {
+        fn reset(&mut self) -> Result<(), ()> {
+            self.resets += 1;
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert_eq!(sm.watchdog_deadline(), None);
+    assert!(matches!(sm.process_watchdog(), Err(Error::InvalidEvent)));
+
+    // The timeout starts when the state is entered, and is kept by internal transitions
+    sm.context().clock.set(Duration::from_secs(2));
+    sm.process_event(Events::Connect).unwrap();
+    sm.context().clock.advance(Duration::from_secs(1));
+    sm.process_event(Events::Poll).unwrap();
+    assert_eq!(sm.watchdog_deadline(), Some(Duration::from_secs(7)));
+
+    // The event of the timeout is processed like any other event
+    smol::block_on(sm.wait_watchdog());
+    assert!(matches!(sm.process_watchdog(), Ok(&States::Idle)));
+    assert_eq!(sm.context().resets, 1);
+
+    // Leaving the state in time disarms the timeout
+    sm.process_event(Events::Connect).unwrap();
+    sm.process_event(Events::Connected).unwrap();
+    assert_eq!(sm.watchdog_deadline(), None);
+}
+
+#[test]
+fn boxed_states() {
+    use core::ops::Deref;
+
+    #[derive(Debug)]
+    pub struct Frame {
+        bytes: [u8; 256],
+        len: usize,
+    }
+
+    /// A handle to a slot of the pool of the context
+    #[derive(Debug)]
+    pub struct Pooled<T>(Box<T>);
+    impl<T> Deref for Pooled<T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.0
+        }
+    }
+
+    statemachine! {
+        transitions: {
+            *Idle + Receive(u8) / start = Receiving(Frame),
+            Receiving(Frame) + Receive(u8) [ has_room ] / append = Receiving(Frame),
+            Receiving(Frame) + End / finish = Complete(Frame),
+            Complete(Frame) + Reset = Idle,
+        },
+        states: {
+            Receiving { boxed: Box },
+            Complete { boxed: Pooled, allocate: allocate_frame },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        free_slots: usize,
+    }
+    impl StateMachineContext for Context {
+        fn start(&mut self, byte: u8) -> Result<Frame, ()> {
+            let mut frame = Frame {
+                bytes: [0; 256],
+                len: 1,
+            };
+            frame.bytes[0] = byte;
+            Ok(frame)
+        }
+
+        fn has_room(&self, frame: &Frame, _: &u8) -> Result<bool, ()> {
+            Ok(frame.len < frame.bytes.len())
+        }
+
+        fn append(&mut self, frame: &Frame, byte: u8) -> Result<Frame, ()> {
+            let mut bytes = frame.bytes;
+            bytes[frame.len] = byte;
+            Ok(Frame {
+                bytes,
+                len: frame.len + 1,
+            })
+        }
+
+        fn finish(&mut self, frame: &Frame) -> Result<Frame, ()> {
+            Ok(Frame { ..*frame })
+        }
+
+        fn allocate_frame(&mut self, frame: Frame) -> Result<Pooled<Frame>, ()> {
+            if self.free_slots == 0 {
+                return Err(());
+            }
+            self.free_slots -= 1;
+            Ok(Pooled(Box::new(frame)))
+        }
+    }
+
+    // The frames don't inflate the states
+    assert_eq!(
+        core::mem::size_of::<States>(),
+        core::mem::size_of::<Box<Frame>>() * 2
+    );
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Receive(1)).unwrap();
+    sm.process_event(Events::Receive(2)).unwrap();
+    assert!(matches!(sm.state(), States::Receiving(frame) if frame.len == 2));
+
+    // A failed allocation cancels the transition
+    assert!(matches!(
+        sm.process_event(Events::End),
+        Err(Error::ActionFailed(()))
+    ));
+    assert!(matches!(sm.state(), States::Receiving(_)));
+
+    sm.context_mut().free_slots = 1;
+    sm.process_event(Events::End).unwrap();
+    assert!(matches!(sm.state(), States::Complete(frame) if frame.bytes[..frame.len] == [1, 2]));
+    assert_eq!(sm.context().free_slots, 0);
+    assert!(matches!(sm.process_event(Events::Reset), Ok(&States::Idle)));
+}
+
+#[test]
+fn machine_description() {
+    use smlang::TransitionDescription;
+
+    statemachine! {
+        name: Door,
+        transitions: {
+            *Closed + Open [ is_unlocked ] / open = Opened,
+            Opened + Close / warn, close = Closed,
+            Opened + Knock,
+            _ + Reset = Closed,
+        }
+    }
+
+    assert_eq!(DOOR_MACHINE_DESCRIPTION.states, &["Closed", "Opened"]);
+    assert_eq!(
+        DOOR_MACHINE_DESCRIPTION.events,
+        &["Close", "Knock", "Open", "Reset"]
+    );
+    assert_eq!(
+        DOOR_MACHINE_DESCRIPTION.transitions,
+        &[
+            TransitionDescription {
+                from: "Closed",
+                event: "Open",
+                guard: Some("is_unlocked()"),
+                action: Some("open"),
+                to: "Opened",
+            },
+            TransitionDescription {
+                from: "Closed",
+                event: "Reset",
+                guard: None,
+                action: None,
+                to: "Closed",
+            },
+            TransitionDescription {
+                from: "Opened",
+                event: "Close",
+                guard: None,
+                action: Some("warn, close"),
+                to: "Closed",
+            },
+            TransitionDescription {
+                from: "Opened",
+                event: "Knock",
+                guard: None,
+                action: None,
+                to: "Opened",
+            },
+            TransitionDescription {
+                from: "Opened",
+                event: "Reset",
+                guard: None,
+                action: None,
+                to: "Closed",
+            },
+        ]
+    );
+}
+
+#[test]
+fn machine_json() {
+    statemachine! {
+        name: Door,
+        transitions: {
+            *Closed + Open [ is_unlocked ] / open = Opened,
+            Opened + Close / warn, close = Closed,
+        }
+    }
+
+    let json: serde_json::Value = serde_json::from_str(DOOR_MACHINE_JSON).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "name": "Door",
+            "initial": "Closed",
+            "states": ["Closed", "Opened"],
+            "events": ["Close", "Open"],
+            "transitions": [
+                {
+                    "from": "Closed",
+                    "event": "Open",
+                    "guard": "is_unlocked()",
+                    "action": "open",
+                    "to": "Opened",
+                },
+                {
+                    "from": "Opened",
+                    "event": "Close",
+                    "guard": null,
+                    "action": "warn, close",
+                    "to": "Closed",
+                },
+            ],
+        })
+    );
+}
+
+#[test]
+fn state_ids() {
+    use core::convert::TryFrom;
+
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Pause = Paused,
+            Paused {
+                *Waiting + Resume = Resumed,
+            },
+        },
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            Ok(speed)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert_eq!(sm.state_id(), StateId::Idle);
+    sm.process_event(Events::Start(3)).unwrap();
+    sm.process_event(Events::Pause).unwrap();
+
+    // The sub-states of composite states have identifiers of their own
+    assert_eq!(sm.state_id(), StateId::Waiting);
+    sm.process_event(Events::Resume).unwrap();
+    assert_eq!(sm.state_id(), StateId::Resumed);
+
+    // The identifiers are numbered in the order of the names of the states
+    let ids: Vec<u8> = [
+        StateId::Idle,
+        StateId::Resumed,
+        StateId::Running,
+        StateId::Waiting,
+    ]
+    .iter()
+    .copied()
+    .map(u8::from)
+    .collect();
+    assert_eq!(ids, [0, 1, 2, 3]);
+    assert_eq!(StateId::try_from(2), Ok(StateId::Running));
+    assert_eq!(StateId::try_from(4), Err(4));
+}
+
+#[test]
+fn published_state_ids() {
+    use core::convert::TryFrom;
+    use core::sync::atomic::{AtomicU8, Ordering};
+
+    statemachine! {
+        publish_state_id: true,
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Stop = Idle,
+        },
+    }
+
+    // The state as seen by another core
+    static STATE_ID: AtomicU8 = AtomicU8::new(0);
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            Ok(speed)
+        }
+
+        fn publish_state_id(&mut self, id: StateId) {
+            STATE_ID.store(id.into(), Ordering::Release);
+        }
+    }
+    let published = || StateId::try_from(STATE_ID.load(Ordering::Acquire)).unwrap();
+
+    let mut sm = StateMachine::new(Context);
+    assert_eq!(sm.state_id(), StateId::Idle);
+    sm.process_event(Events::Start(3)).unwrap();
+    assert_eq!(sm.state_id(), StateId::Running);
+    assert_eq!(published(), StateId::Running);
+
+    sm.process_event(Events::Stop).unwrap();
+    assert_eq!(published(), StateId::Idle);
+
+    // The identifier of an initial state is kept, but not published
+    let sm = StateMachine::new_with_state(Context, States::Running(1));
+    assert_eq!(sm.state_id(), StateId::Running);
+    assert_eq!(published(), StateId::Idle);
+}
+
+#[test]
+fn state_id_mirror() {
+    statemachine! {
+        state_id_mirror: true,
+        transitions: {
+            *Idle + Start = Running,
+            Running + Stop = Idle,
+        },
+    }
+
+    static MIRROR: AtomicStateId = AtomicStateId::new(StateId::Idle);
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn state_id_mirror(&self) -> &AtomicStateId {
+            &MIRROR
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    std::thread::scope(|scope| {
+        // The observer reads the state without locking the state machine
+        let observer = scope.spawn(|| {
+            while MIRROR.load() != StateId::Running {
+                std::hint::spin_loop();
+            }
+        });
+        sm.process_event(Events::Start).unwrap();
+        observer.join().unwrap();
+    });
+
+    sm.process_event(Events::Stop).unwrap();
+    assert_eq!(MIRROR.load(), StateId::Idle);
+    assert_eq!(sm.state_id(), StateId::Idle);
+}
+
+#[cfg(feature = "shared")]
+#[test]
+fn shared_state_machine() {
+    statemachine! {
+        transitions: {
+            *Idle + Fill(u32) / async fill = Filled(u32),
+            Filled(u32) + Fill(u32) / async fill_more = Filled(u32),
+            Filled(u32) + Drain = Idle,
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        fills: usize,
+    }
+    impl StateMachineContext for Context {
+        async fn fill(&mut self, amount: u32) -> Result<u32, ()> {
+            smol::future::yield_now().await;
+            self.fills += 1;
+            Ok(amount)
+        }
+
+        async fn fill_more(&mut self, level: &u32, amount: u32) -> Result<u32, ()> {
+            smol::future::yield_now().await;
+            self.fills += 1;
+            Ok(level + amount)
+        }
+    }
+
+    let sm = SharedStateMachine::new(StateMachine::new(Context::default()));
+    smol::block_on(async {
+        // The tasks process their events one after the other, although the actions yield
+        let tasks: Vec<_> = (1..=4)
+            .map(|amount| {
+                let sm = sm.clone();
+                smol::spawn(async move { sm.process_event(Events::Fill(amount)).await })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await, Ok(StateId::Filled));
+        }
+
+        let guard = sm.lock().await;
+        assert!(matches!(guard.state(), States::Filled(10)));
+        assert_eq!(guard.context().fills, 4);
+        drop(guard);
+
+        assert_eq!(sm.process_event(Events::Drain).await, Ok(StateId::Idle));
+        assert_eq!(sm.state().await, StateId::Idle);
+    });
+}
+
+#[cfg(feature = "shared")]
+#[test]
+fn shared_state_sink() {
+    use std::sync::Mutex;
+
+    statemachine! {
+        transitions: {
+            *Idle + Start = Running,
+            Running + Poll,
+            Running + Stop = Idle,
+        },
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {}
+
+    #[derive(Default)]
+    struct Sink(Mutex<Vec<StateId>>);
+    impl smlang::StateSink<StateId> for &Sink {
+        fn publish(&self, id: StateId) {
+            self.0.lock().unwrap().push(id);
+        }
+    }
+
+    let sink = Sink::default();
+    let sm = SharedStateMachine::with_sink(StateMachine::new(Context), &sink);
+    smol::block_on(async {
+        sm.process_event(Events::Start).await.unwrap();
+        sm.process_event(Events::Poll).await.unwrap();
+        assert!(sm.process_event(Events::Start).await.is_err());
+        sm.process_event(Events::Stop).await.unwrap();
+    });
+
+    // Only the changes of the state are published
+    assert_eq!(*sink.0.lock().unwrap(), [StateId::Running, StateId::Idle]);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn tokio_actor() {
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Poll,
+            Running(u32) + Stop = Idle,
+        },
+        events_attr: #[derive(Debug)],
+    }
+
+    #[derive(Default)]
+    struct Context {
+        starts: usize,
+    }
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            self.starts += 1;
+            Ok(speed)
+        }
+    }
+
+    let (actor, sender) = StateMachine::new(Context::default()).into_actor(2);
+    let mut states = sender.subscribe();
+    assert_eq!(sender.state(), StateId::Idle);
+
+    smol::block_on(async {
+        let actor = smol::spawn(actor.run());
+
+        sender.send(Events::Start(3)).await.unwrap();
+        states.changed().await.unwrap();
+        assert_eq!(*states.borrow_and_update(), StateId::Running);
+
+        // Rejected events and those which don't change the state are not broadcast
+        sender.send(Events::Start(4)).await.unwrap();
+        sender.send(Events::Poll).await.unwrap();
+        sender.send(Events::Stop).await.unwrap();
+        states.changed().await.unwrap();
+        assert_eq!(*states.borrow_and_update(), StateId::Idle);
+        assert_eq!(sender.state(), StateId::Idle);
+
+        // The actor stops once every sender has been dropped
+        let other = sender.clone();
+        drop(sender);
+        other.send(Events::Start(5)).await.unwrap();
+        drop(other);
+        let sm = actor.await;
+        assert!(matches!(sm.state(), States::Running(5)));
+        assert_eq!(sm.context().starts, 2);
+    });
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn event_streams_and_sinks() {
+    use smlang::futures::{stream, SinkExt, StreamExt};
+
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / async start = Running(u32),
+            Running(u32) + Poll,
+            Running(u32) + Stop = Idle,
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        starts: usize,
+    }
+    impl StateMachineContext for Context {
+        async fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            // The processing of the event is pending once, and is polled again by the sink
+            smol::future::yield_now().await;
+            self.starts += 1;
+            Ok(speed)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    smol::block_on(async {
+        let mut sink = core::pin::pin!(sm.sink());
+        sink.send(Events::Start(3)).await.unwrap();
+        sink.send(Events::Poll).await.unwrap();
+        assert!(matches!(
+            sink.send(Events::Start(4)).await,
+            Err(Error::InvalidEvent)
+        ));
+
+        // The sink still processes events after a rejected one
+        let mut events = stream::iter([Events::Stop, Events::Start(5)]).map(Ok);
+        sink.send_all(&mut events).await.unwrap();
+    });
+    assert!(matches!(sm.state(), States::Running(5)));
+    assert_eq!(sm.context().starts, 2);
+
+    // The events of a stream are pending before they are ready
+    let pending = |events: Vec<Events>| {
+        stream::iter(events).then(|event| async {
+            smol::future::yield_now().await;
+            event
+        })
+    };
+    let mut sm = StateMachine::new(Context::default());
+    smol::block_on(async {
+        let events = pending(vec![Events::Start(1), Events::Poll, Events::Stop]);
+        assert!(sm.process_stream(events).await.is_ok());
+        assert!(matches!(sm.state(), States::Idle));
+
+        // The events following a rejected event are not processed
+        let events = pending(vec![Events::Stop, Events::Start(2)]);
+        assert!(matches!(
+            sm.process_stream(events).await,
+            Err(Error::InvalidEvent)
+        ));
+        assert!(matches!(sm.state(), States::Idle));
+    });
+    assert_eq!(sm.context().starts, 1);
+}
+
+#[cfg(feature = "embassy")]
+#[test]
+fn embassy_actor() {
+    use smlang::embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use smlang::embassy_sync::channel::Channel;
+    use smlang::embassy_sync::watch::Watch;
+
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Poll,
+            Running(u32) + Stop = Idle,
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        starts: usize,
+    }
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            self.starts += 1;
+            Ok(speed)
+        }
+    }
+
+    let events = Channel::<NoopRawMutex, Events, 2>::new();
+    let states = Watch::<NoopRawMutex, StateId, 1>::new();
+    let mut receiver = states.receiver().unwrap();
+    let mut sm = StateMachine::new(Context::default());
+
+    let driver = async {
+        // The current state is sent once the actor starts
+        assert_eq!(receiver.changed().await, StateId::Idle);
+
+        events.send(Events::Start(3)).await;
+        assert_eq!(receiver.changed().await, StateId::Running);
+
+        // Rejected events and those which don't change the state are not sent
+        events.send(Events::Start(4)).await;
+        events.send(Events::Poll).await;
+        events.send(Events::Stop).await;
+        assert_eq!(receiver.changed().await, StateId::Idle);
+    };
+    smol::block_on(smol::future::or(
+        async {
+            sm.run_actor(events.receiver(), states.sender()).await;
+        },
+        driver,
+    ));
+    assert!(matches!(sm.state(), States::Idle));
+    assert_eq!(sm.context().starts, 1);
+}
+
+#[test]
+fn valid_events() {
+    statemachine! {
+        transitions: {
+            *Idle + Start = Running,
+            Idle + Pause defer,
+            Running {
+                *Fast + Slow = Slow,
+                Slow + _ = Fast,
+            },
+            Running + Pause = Paused,
+            Paused [
+                Display {
+                    *Blinking + Dim = Dark,
+                    Dark + Brighten = Blinking,
+                },
+                Alarm {
+                    *Ringing + Snooze(u32) = Silent,
+                },
+            ],
+            Paused + Start = Running,
+        }
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context);
+    assert_eq!(sm.valid_events().collect::<Vec<_>>(), ["Pause", "Start"]);
+    assert!(!sm.is_valid_event("Slow"));
+
+    // Sub-states accept the events of their composite states
+    sm.process_event(Events::Start).unwrap();
+    assert_eq!(sm.valid_events().collect::<Vec<_>>(), ["Pause", "Slow"]);
+
+    // Any event is accepted with a wildcard event
+    sm.process_event(Events::Slow).unwrap();
+    assert_eq!(sm.valid_events().count(), 6);
+    sm.process_event(Events::Start).unwrap();
+
+    // Parallel states accept the events of the current sub-state of every region
+    sm.process_event(Events::Pause).unwrap();
+    assert_eq!(
+        sm.valid_events().collect::<Vec<_>>(),
+        ["Dim", "Snooze", "Start"]
+    );
+}
+
+#[test]
+fn peek_event() {
+    use smlang::PeekResult;
+
+    statemachine! {
+        transitions: {
+            *Locked + Unlock(u32) [ valid_code ] / unlock = Unlocked,
+            Locked + Knock / knock,
+            Unlocked + Lock / lock = Locked,
+            Unlocked + Open / open = choice {
+                [ is_blocked ] = Unlocked,
+                = Opened,
+            },
+        },
+        states_attr: #[derive(Debug)],
+        events_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        log: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn valid_code(&self, code: &u32) -> Result<bool, ()> {
+            Ok(*code == 1234)
+        }
+
+        fn unlock(&mut self, _: u32) -> Result<(), ()> {
+            self.log.push("unlock");
+            Ok(())
+        }
+
+        fn knock(&mut self) -> Result<(), ()> {
+            self.log.push("knock");
+            Ok(())
+        }
+
+        fn lock(&mut self) -> Result<(), ()> {
+            self.log.push("lock");
+            Ok(())
+        }
+
+        fn open(&mut self) -> Result<(), ()> {
+            self.log.push("open");
+            Ok(())
+        }
+
+        fn is_blocked(&self) -> Result<bool, ()> {
+            Ok(false)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // The guards are evaluated, but no actions are run and the state is kept
+    assert_eq!(
+        sm.peek_event(&Events::Unlock(1234)),
+        Ok(PeekResult::Transition("Unlocked"))
+    );
+    assert_eq!(
+        sm.peek_event(&Events::Unlock(1)),
+        Err(Error::TransitionsFailed)
+    );
+    assert_eq!(
+        sm.peek_event(&Events::Knock),
+        Ok(PeekResult::Transition("Locked"))
+    );
+    assert_eq!(sm.peek_event(&Events::Lock), Err(Error::InvalidEvent));
+    assert_eq!(sm.state(), &States::Locked);
+    assert!(sm.context().log.is_empty());
+
+    sm.process_event(Events::Unlock(1234)).unwrap();
+    assert_eq!(sm.peek_event(&Events::Open), Ok(PeekResult::Choice));
+}
+
+#[test]
+fn transition_outcomes() {
+    use smlang::TransitionOutcome;
+
+    statemachine! {
+        transition_outcome: true,
+        transitions: {
+            *Idle + Start / start = Running,
+            Running + Tick,
+            Running + Stop [ can_stop ] = Idle,
+            Running + Pause = Paused,
+            Paused [
+                Display {
+                    *Blinking + Dim / dim = Dark,
+                },
+            ],
+        }
+    }
+
+    #[derive(Default)]
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn start(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn dim(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn can_stop(&self) -> Result<bool, ()> {
+            Ok(false)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert_eq!(
+        sm.process_event(Events::Start),
+        Ok(TransitionOutcome {
+            from: "Idle",
+            to: "Running",
+            event: "Start",
+            action_taken: true,
+        })
+    );
+    assert_eq!(
+        sm.process_event(Events::Tick),
+        Ok(TransitionOutcome {
+            from: "Running",
+            to: "Running",
+            event: "Tick",
+            action_taken: false,
+        })
+    );
+    assert_eq!(
+        sm.process_event(Events::Stop),
+        Err(Error::TransitionsFailed)
+    );
+
+    // Regions are named by their parallel state
+    sm.process_event(Events::Pause).unwrap();
+    assert_eq!(
+        sm.process_event(Events::Dim),
+        Ok(TransitionOutcome {
+            from: "Paused",
+            to: "Paused",
+            event: "Dim",
+            action_taken: true,
+        })
+    );
+}
+
+#[test]
+fn serde_states() {
+    statemachine! {
+        serde: true,
+        transitions: {
+            *Idle + Start(u32) / start = Running(u32),
+            Running(u32) + Pause = Paused,
+            Paused(H) {
+                *Holding + Alarm = Ringing,
+            },
+            Paused + Resume = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn start(&mut self, speed: u32) -> Result<u32, ()> {
+            Ok(speed)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Start(3)).unwrap();
+    let json = serde_json::to_string(sm.state()).unwrap();
+    assert_eq!(json, r#"{"Running":3}"#);
+    let sm = StateMachine::restore(Context, serde_json::from_str(&json).unwrap());
+    assert!(matches!(sm.state(), States::Running(3)));
+
+    // The sub-states of composite states and the history are persisted as well
+    let mut sm = sm;
+    sm.process_event(Events::Pause).unwrap();
+    sm.process_event(Events::Alarm).unwrap();
+    let state = serde_json::to_string(sm.state()).unwrap();
+    let history = serde_json::to_string(sm.history()).unwrap();
+    let mut sm = StateMachine::restore(Context, serde_json::from_str(&state).unwrap());
+    sm.set_history(serde_json::from_str(&history).unwrap());
+    assert_eq!(sm.state(), &States::Paused(PausedStates::Ringing));
+    sm.process_event(Events::Resume).unwrap();
+    sm.process_event(Events::Start(1)).unwrap();
+    sm.process_event(Events::Pause).unwrap();
+    assert_eq!(sm.state(), &States::Paused(PausedStates::Ringing));
+
+    // States which aren't declared fail to deserialize
+    assert!(serde_json::from_str::<States>(r#""Stopped""#).is_err());
+}
+
+#[test]
+fn schema_migrations() {
+    use serde::Deserialize;
+
+    statemachine! {
+        name: Pump,
+        serde: true,
+        schema_version: 2,
+        transitions: {
+            *Idle + Start(u32) / start = Pumping(u32),
+            Pumping(u32) + Stop = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    // In version 1, the pumping state had no flow rate
+    #[derive(Deserialize)]
+    enum PumpStatesV1 {
+        Idle,
+        Pumping,
+    }
+
+    struct Context;
+
+    impl PumpStateMachineContext for Context {
+        fn start(&mut self, flow: u32) -> Result<u32, ()> {
+            Ok(flow)
+        }
+
+        fn migrate<'de, D: serde::Deserializer<'de>>(
+            &mut self,
+            version: u32,
+            state: D,
+        ) -> Result<PumpStates, D::Error> {
+            assert_eq!(version, 1);
+            Ok(match PumpStatesV1::deserialize(state)? {
+                PumpStatesV1::Idle => PumpStates::Idle,
+                PumpStatesV1::Pumping => PumpStates::Pumping(10),
+            })
+        }
+    }
+
+    assert_eq!(PUMP_SCHEMA_VERSION, 2);
+    let state = serde_json::json!({ "Pumping": 5 });
+    let sm = PumpStateMachine::restore_versioned(Context, 2, state).unwrap();
+    assert!(matches!(sm.state(), PumpStates::Pumping(5)));
+
+    let state = serde_json::json!("Pumping");
+    let sm = PumpStateMachine::restore_versioned(Context, 1, state).unwrap();
+    assert!(matches!(sm.state(), PumpStates::Pumping(10)));
+
+    let state = serde_json::json!("Idle");
+    assert!(PumpStateMachine::restore_versioned(Context, 3, state).is_err());
+}
+
+#[test]
+fn event_log() {
+    statemachine! {
+        event_log: true,
+        transitions: {
+            *Idle + Insert(u32) / insert = Paid(u32),
+            Paid(u32) + Insert(u32) / add = Paid(u32),
+            Paid(u32) + Vend [ enough ] = Idle,
+        },
+        states_attr: #[derive(Debug)],
+        events_attr: #[derive(Debug, Clone)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        log: Vec<Events>,
+    }
+
+    impl smlang::EventLog<Events> for Context {
+        fn append(&mut self, event: &Events) {
+            self.log.push(event.clone());
+        }
+    }
+
+    impl StateMachineContext for Context {
+        fn insert(&mut self, coin: u32) -> Result<u32, ()> {
+            Ok(coin)
+        }
+
+        fn add(&mut self, paid: &u32, coin: u32) -> Result<u32, ()> {
+            Ok(paid + coin)
+        }
+
+        fn enough(&self, paid: &u32) -> Result<bool, ()> {
+            Ok(*paid >= 3)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Insert(1)).unwrap();
+    sm.process_event(Events::Insert(1)).unwrap();
+
+    // Events which aren't accepted are not logged
+    assert!(sm.process_event(Events::Vend).is_err());
+    assert_eq!(sm.context().log, [Events::Insert(1), Events::Insert(1)]);
+
+    // Replaying the log rebuilds the state, without logging the events again
+    let log = core::mem::take(&mut sm.context_mut().log);
+    let sm = StateMachine::replay(Context::default(), log).unwrap();
+    assert!(matches!(sm.state(), States::Paid(2)));
+    assert!(sm.context().log.is_empty());
+}
+
+#[test]
+fn simulate() {
+    statemachine! {
+        transitions: {
+            *Idle + Insert(u32) / insert = Paid(u32),
+            Paid(u32) + Insert(u32) / add = Paid(u32),
+            Paid(u32) + Vend [ enough ] = Idle,
+        },
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn insert(&mut self, coin: u32) -> Result<u32, ()> {
+            Ok(coin)
+        }
+
+        fn add(&mut self, paid: &u32, coin: u32) -> Result<u32, ()> {
+            Ok(paid + coin)
+        }
+
+        fn enough(&self, paid: &u32) -> Result<bool, ()> {
+            Ok(*paid >= 3)
+        }
+    }
+
+    let events = [
+        Events::Insert(1),
+        Events::Vend,
+        Events::Insert(2),
+        Events::Vend,
+    ];
+    let (sm, steps): (_, Vec<_>) = StateMachine::simulate(Context, events);
+    assert!(matches!(sm.state(), States::Idle));
+
+    // Rejected events are part of the simulation
+    let step = |from, event, to, accepted| smlang::SimulationStep {
+        from,
+        event,
+        to,
+        accepted,
+    };
+    assert_eq!(
+        steps,
+        [
+            step("Idle", "Insert", "Paid", true),
+            step("Paid", "Vend", "Paid", false),
+            step("Paid", "Insert", "Paid", true),
+            step("Paid", "Vend", "Idle", true),
+        ]
+    );
+}
+
+#[test]
+fn transition_history() {
+    use core::time::Duration;
+    use smlang::MockClock;
+
+    statemachine! {
+        transition_history: 2,
+        transitions: {
+            *Idle + Start = Running,
+            Running {
+                *Fast + Slow = Slow,
+            },
+            _ + Reset = Idle,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        clock: MockClock,
+    }
+
+    impl_mock_clock!(Context);
+
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(sm.transition_history().is_empty());
+
+    sm.context().clock.advance(Duration::from_secs(1));
+    sm.process_event(Events::Start).unwrap();
+    let record = sm.transition_history().last().unwrap();
+    assert_eq!(
+        (record.from, record.event, record.to),
+        ("Idle", "Start", "Fast")
+    );
+    assert_eq!(record.timestamp, Duration::from_secs(1));
+
+    // Only the last transitions are kept
+    sm.process_event(Events::Slow).unwrap();
+    sm.process_event(Events::Reset).unwrap();
+    let records: Vec<_> = sm
+        .transition_history()
+        .iter()
+        .map(|record| (record.from, record.event, record.to))
+        .collect();
+    assert_eq!(
+        records,
+        [("Fast", "Slow", "Slow"), ("Slow", "Reset", "Idle")]
+    );
+}
+
+#[test]
+fn transition_coverage() {
+    statemachine! {
+        transition_coverage: true,
+        transitions: {
+            *Idle + Start [ready] = Running,
+            Idle + Start = Idle,
+            Running + Measure / measure = choice(u32) {
+                [ in_range ] = Idle,
+                = Measuring,
+            },
+            Measuring [
+                Left {
+                    *LeftBusy + Done = LeftDone,
+                },
+                Right {
+                    *RightBusy + Done = RightDone,
+                },
+            ] = Idle,
+        }
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn ready(&self) -> Result<bool, ()> {
+            Ok(true)
+        }
+        fn measure(&mut self) -> Result<u32, ()> {
+            Ok(7)
+        }
+        fn in_range(&self, value: &u32) -> Result<bool, ()> {
+            Ok(*value < 5)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert_eq!(sm.coverage().percent(), 0.0);
+
+    // Every branch of a choice and every transition of a region is covered by its own
+    sm.process_event(Events::Start).unwrap();
+    sm.process_event(Events::Measure).unwrap();
+    sm.process_event(Events::Done).unwrap();
+    assert!(matches!(sm.state(), &States::Idle));
+    assert_eq!(sm.coverage().taken().count(), 4);
+    sm.coverage().assert_at_least(60.0);
+
+    let untaken: Vec<_> = sm.coverage().untaken().map(|t| t.to_string()).collect();
+    assert_eq!(
+        untaken,
+        ["Idle + Start = Idle", "Running + Measure / measure = Idle"]
+    );
+    assert_eq!(
+        sm.coverage().to_string(),
+        "4/6 transitions taken (66.7%)\n  \
+        untaken: Idle + Start = Idle\n  \
+        untaken: Running + Measure / measure = Idle"
+    );
+
+    let result = std::panic::catch_unwind(|| sm.coverage().assert_at_least(100.0));
+    assert!(result.is_err());
+}
+
+#[test]
+fn table_dispatch() {
+    statemachine! {
+        table_dispatch: true,
+        transitions: {
+            *Idle + Insert(u32) / insert = Paid(u32),
+            Paid(u32) + Insert(u32) / add = Paid(u32),
+            Paid(u32) + Vend [ enough ] = Vending,
+            Paid(u32) + _ = Idle,
+            Vending [
+                Dispenser {
+                    *Dispensing + Done = Dispensed,
+                },
+                Display {
+                    *Thanking + Done = Waiting,
+                },
+            ] = Idle,
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {
+        fn insert(&mut self, coin: u32) -> Result<u32, ()> {
+            Ok(coin)
+        }
+        fn add(&mut self, paid: &u32, coin: u32) -> Result<u32, ()> {
+            Ok(paid + coin)
+        }
+        fn enough(&self, paid: &u32) -> Result<bool, ()> {
+            Ok(*paid >= 3)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert_eq!(sm.process_event(Events::Vend), Err(Error::InvalidEvent));
+    sm.process_event(Events::Insert(1)).unwrap();
+    assert_eq!(
+        sm.process_event(Events::Vend),
+        Err(Error::TransitionsFailed)
+    );
+    sm.process_event(Events::Insert(2)).unwrap();
+    assert!(matches!(sm.state(), States::Paid(3)));
+
+    // The regions of parallel states are dispatched to as well
+    sm.process_event(Events::Vend).unwrap();
+    assert!(matches!(sm.state(), States::Vending(_)));
+    sm.process_event(Events::Done).unwrap();
+    assert!(matches!(sm.state(), States::Idle));
+
+    // Events without transitions of their own are handled by the catch-all transition
+    sm.process_event(Events::Insert(1)).unwrap();
+    sm.process_event(Events::Done).unwrap();
+    assert!(matches!(sm.state(), States::Idle));
+}
+
+#[test]
+fn hash_dispatch() {
+    statemachine! {
+        hash_dispatch: true,
+        transitions: {
+            *Locked + Coin(u32) [ enough ] = Unlocked,
+            Locked + Push = Locked,
+            Unlocked + Push / count = Locked,
+            Unlocked + Coin(u32) / refund,
+            Broken + Repair = Locked,
+            Broken + _ = Broken,
+            _ + Kick = Broken except [Broken],
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        passed: u32,
+        refunded: u32,
+    }
+    impl StateMachineContext for Context {
+        fn enough(&self, coin: &u32) -> Result<bool, ()> {
+            Ok(*coin >= 2)
+        }
+        fn count(&mut self) -> Result<(), ()> {
+            self.passed += 1;
+            Ok(())
+        }
+        fn refund(&mut self, coin: u32) -> Result<(), ()> {
+            self.refunded += coin;
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert_eq!(sm.process_event(Events::Repair), Err(Error::InvalidEvent));
+    assert_eq!(
+        sm.process_event(Events::Coin(1)),
+        Err(Error::TransitionsFailed)
+    );
+    sm.process_event(Events::Coin(2)).unwrap();
+    sm.process_event(Events::Coin(3)).unwrap();
+    sm.process_event(Events::Push).unwrap();
+    assert!(matches!(sm.state(), States::Locked));
+    assert_eq!((sm.context().passed, sm.context().refunded), (1, 3));
+
+    // Events without transitions of their own are handled by the catch-all transition
+    sm.process_event(Events::Kick).unwrap();
+    sm.process_event(Events::Coin(2)).unwrap();
+    assert!(matches!(sm.state(), States::Broken));
+    sm.process_event(Events::Repair).unwrap();
+    assert!(matches!(sm.state(), States::Locked));
+}
+
+#[test]
+fn walk_transitions() {
+    statemachine! {
+        transitions: {
+            *Idle + Start(u32) [valid] / start = Running,
+            Running(u32) + Tick / tick,
+            Running(u32) + Measure / measure = choice(u32) {
+                [ in_range ] = Idle,
+                = Paused,
+            },
+            Paused {
+                *Waiting + Resume = Idle,
+            },
+            Paused + _ = Idle,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        in_range: bool,
+    }
+    impl StateMachineContext for Context {
+        fn valid(&self, start: &u32) -> Result<bool, ()> {
+            Ok(*start > 0)
+        }
+        fn start(&mut self, start: u32) -> Result<u32, ()> {
+            Ok(start)
+        }
+        fn tick(&mut self, _state: &mut u32) -> Result<(), ()> {
+            Ok(())
+        }
+        fn measure(&mut self, value: &u32) -> Result<u32, ()> {
+            Ok(*value)
+        }
+        fn in_range(&self, _value: &u32) -> Result<bool, ()> {
+            Ok(self.in_range)
+        }
+    }
+
+    struct Fixtures;
+    impl TransitionFixtures<Context> for Fixtures {
+        // The guards of the choice let the walked branch pass
+        fn context(&mut self, transition: &smlang::TransitionDescription) -> Context {
+            Context {
+                in_range: transition.to == "Idle",
+            }
+        }
+        fn state_running(&mut self) -> u32 {
+            3
+        }
+        fn event_start(&mut self) -> u32 {
+            1
+        }
+    }
+
+    StateMachine::walk_transitions(&mut Fixtures);
+
+    // A transition which doesn't end in its target state fails the walk
+    struct Rejecting;
+    impl TransitionFixtures<Context> for Rejecting {
+        fn context(&mut self, _transition: &smlang::TransitionDescription) -> Context {
+            Context::default()
+        }
+        fn state_running(&mut self) -> u32 {
+            3
+        }
+        fn event_start(&mut self) -> u32 {
+            0
+        }
+    }
+    let result = std::panic::catch_unwind(|| StateMachine::walk_transitions(&mut Rejecting));
+    let message = *result.unwrap_err().downcast::<String>().unwrap();
+    assert_eq!(
+        message,
+        "walking `Idle + Start [valid()] / start = Running` ended in Idle with an error"
+    );
+}
+
+#[test]
+fn on_transition() {
+    statemachine! {
+        transitions: {
+            *Idle + Load(u32) / load = Loaded(u32),
+            Loaded(u32) + Start = Running,
+            Running [ done ] = Idle,
+        },
+        post_capacity: 1,
+    }
+
+    #[derive(Default)]
+    struct Context {
+        posted: PostedEvents,
+        log: Vec<(&'static str, &'static str, &'static str)>,
+    }
+
+    impl StateMachineContext for Context {
+        fn posted_events(&mut self) -> &mut PostedEvents {
+            &mut self.posted
+        }
+
+        fn load(&mut self, value: u32) -> Result<u32, ()> {
+            assert!(self.post(Events::Start).is_ok());
+            Ok(value)
+        }
+
+        fn done(&self) -> Result<bool, ()> {
+            Ok(true)
+        }
+
+        fn on_transition(
+            &mut self,
+            old_state: &'static str,
+            event: &'static str,
+            new_state: &States,
+        ) {
+            let new_state = match new_state {
+                States::Idle => "Idle",
+                States::Loaded(_) => "Loaded",
+                States::Running => "Running",
+            };
+            self.log.push((old_state, event, new_state));
+        }
+    }
+
+    // Transitions of posted and completion events are observed as well
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Load(1)).unwrap();
+    assert_eq!(
+        sm.context().log,
+        [
+            ("Idle", "Load", "Loaded"),
+            ("Loaded", "Start", "Running"),
+            ("Running", "", "Idle"),
+        ]
+    );
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_spans() {
+    use std::fmt::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    statemachine! {
+        transitions: {
+            *Idle + Start [ ready ] / start = Running,
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn ready(&self) -> Result<bool, ()> {
+            Ok(true)
+        }
+
+        fn start(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+            write!(self.0, " {}={:?}", field.name(), value).unwrap();
+        }
+    }
+
+    // Collects the spans and events as their name or message followed by their fields
+    #[derive(Clone, Default)]
+    struct Collector(Arc<Mutex<Vec<String>>>);
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields(span.metadata().name().to_string());
+            span.record(&mut fields);
+            let mut log = self.0.lock().unwrap();
+            log.push(fields.0);
+            Id::from_u64(log.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0
+                .lock()
+                .unwrap()
+                .push(fields.0.trim_start().to_string());
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    let collector = Collector::default();
+    tracing::subscriber::with_default(collector.clone(), || {
+        let mut sm = StateMachine::new(Context);
+        sm.process_event(Events::Start).unwrap();
+    });
+
+    assert_eq!(
+        *collector.0.lock().unwrap(),
+        [
+            "process_event state=\"Idle\" event=\"Start\"",
+            "guard=\"ready()\" passed=true",
+            "action=\"start\"",
+            "message=transition from=\"Idle\" event=\"Start\" to=\"Running\"",
+        ]
+    );
+}
+
+#[cfg(feature = "defmt")]
+#[test]
+fn defmt_format() {
+    statemachine! {
+        transitions: {
+            *Idle + Load(u32) / load = Loaded(u32),
+            Loaded(u32) + Start = Running,
+            Running {
+                *Busy + Done = Idle,
+            },
+        }
+    }
+
+    fn is_format<T: defmt::Format>(_: &T) {}
+
+    // The states and events are formatted by their names, without formatting their data
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn load(&mut self, value: u32) -> Result<u32, ()> {
+            Ok(value)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Load(1)).unwrap();
+    is_format(sm.process_event(Events::Start).unwrap());
+    is_format(&Events::Done);
+}
+
+#[test]
+fn metrics() {
+    statemachine! {
+        transitions: {
+            *Locked + Coin(u32) [ enough ] = Unlocked,
+            Unlocked + Push = Locked,
+            Locked | Unlocked + Reset = Locked,
+        },
+        metrics: true,
+    }
+
+    #[derive(Default)]
+    struct Context {
+        taken: Vec<(&'static str, &'static str, &'static str)>,
+        rejected: Vec<(&'static str, &'static str, &'static str)>,
+    }
+
+    impl StateMachineContext for Context {
+        fn enough(&self, coin: &u32) -> Result<bool, ()> {
+            Ok(*coin >= 50)
+        }
+    }
+
+    impl smlang::Metrics for Context {
+        fn transition_taken(&mut self, from: &'static str, event: &'static str, to: &'static str) {
+            self.taken.push((from, event, to));
+        }
+
+        fn guard_rejected(
+            &mut self,
+            state: &'static str,
+            event: &'static str,
+            guard: &'static str,
+        ) {
+            self.rejected.push((state, event, guard));
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(
+        sm.process_event(Events::Coin(10)),
+        Err(Error::TransitionsFailed)
+    ));
+    sm.process_event(Events::Coin(50)).unwrap();
+    sm.process_event(Events::Push).unwrap();
+    sm.process_event(Events::Reset).unwrap();
+
+    assert_eq!(
+        sm.context().taken,
+        [
+            ("Locked", "Coin", "Unlocked"),
+            ("Unlocked", "Push", "Locked"),
+            ("Locked", "Reset", "Locked"),
+        ]
+    );
+    assert_eq!(sm.context().rejected, [("Locked", "Coin", "enough()")]);
+}
+
+#[test]
+fn state_statistics() {
+    use core::time::Duration;
+    use smlang::{MockClock, StateStatistics};
+
+    statemachine! {
+        statistics: true,
+        transitions: {
+            *Idle + Start = Running,
+            Running + Stop = Idle,
+            Running + Poll,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        clock: MockClock,
+    }
+
+    impl_mock_clock!(Context);
+
+    impl StateMachineContext for Context {}
+
+    let secs = Duration::from_secs;
+    let mut sm = StateMachine::new(Context::default());
+    // The time before the first event counts towards the starting state once its timer is started
+    sm.restart_timer();
+    sm.context().clock.advance(secs(2));
+    sm.process_event(Events::Start).unwrap();
+    sm.context().clock.advance(secs(3));
+    sm.process_event(Events::Stop).unwrap();
+    sm.context().clock.advance(secs(1));
+    sm.process_event(Events::Start).unwrap();
+
+    // Internal transitions keep the state, and the current visit counts towards the total time
+    sm.context().clock.advance(secs(4));
+    sm.process_event(Events::Poll).unwrap();
+    assert_eq!(
+        sm.statistics(),
+        [
+            StateStatistics {
+                state: "Idle",
+                visits: 2,
+                total: secs(3),
+                last: secs(1),
+            },
+            StateStatistics {
+                state: "Running",
+                visits: 1,
+                total: secs(7),
+                last: secs(3),
+            },
+        ]
+    );
+}
+
+#[test]
+fn unreachable_states() {
+    // States entered through composite and parallel states and their completions are reachable
+    statemachine! {
+        name: Reachable,
+        transitions: {
+            *Idle + Start = Active,
+            Active {
+                *Loading + Loaded = Ready,
+            },
+            Active + Split = Both,
+            Both [
+                Left {
+                    *LeftBusy + Done = LeftDone,
+                },
+                Right {
+                    *RightBusy + Done = RightDone,
+                },
+            ] = Finished,
+        }
+    }
+
+    struct Context;
+    impl ReachableStateMachineContext for Context {}
+
+    let mut sm = ReachableStateMachine::new(Context);
+    sm.process_event(ReachableEvents::Start).unwrap();
+    sm.process_event(ReachableEvents::Loaded).unwrap();
+    sm.process_event(ReachableEvents::Split).unwrap();
+    sm.process_event(ReachableEvents::Done).unwrap();
+    assert!(matches!(sm.state(), &ReachableStates::Finished));
+
+    // Unreachable states only raise a warning, and are never constructed
+    #[allow(deprecated, dead_code)]
+    mod warned {
+        use smlang::statemachine;
+
+        statemachine! {
+            lints: {
+                unreachable_states: warn,
+            },
+            transitions: {
+                *Idle + Start = Running,
+                Orphan + Start = Idle,
+            }
+        }
+    }
+
+    struct WarnedContext;
+    impl warned::StateMachineContext for WarnedContext {}
+
+    let mut sm = warned::StateMachine::new(WarnedContext);
+    sm.process_event(warned::Events::Start).unwrap();
+    assert!(matches!(sm.state(), &warned::States::Running));
+}
+
+#[test]
+fn strict_events() {
+    statemachine! {
+        strict_events: true,
+        transitions: {
+            *Idle + Start = Running,
+            Idle + (Stop | Tick) = ignore,
+            Running + Stop = Idle,
+            Running + Start = ignore,
+            // Parallel states handle the events of all their regions
+            Running + Tick = Measuring,
+            Measuring [
+                Left {
+                    *LeftBusy + Start = LeftDone,
+                },
+                Right {
+                    *RightBusy + Tick = RightDone,
+                },
+            ] = Idle,
+            Measuring + Stop = Idle,
+        }
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context);
+
+    // Ignored events are accepted without leaving the state
+    sm.process_event(Events::Stop).unwrap();
+    assert!(matches!(sm.state(), &States::Idle));
+    sm.process_event(Events::Start).unwrap();
+    sm.process_event(Events::Start).unwrap();
+    assert!(matches!(sm.state(), &States::Running));
+
+    sm.process_event(Events::Tick).unwrap();
+    sm.process_event(Events::Start).unwrap();
+    sm.process_event(Events::Tick).unwrap();
+    assert!(matches!(sm.state(), &States::Idle));
+}
+
+#[test]
+fn assertions() {
+    statemachine! {
+        name: Vending,
+        on_action_error: Faulted,
+        transitions: {
+            *Locked + Unlock = Unlocked,
+            Unlocked + Coin / count = Paid,
+            Unlocked + Lock = Locked,
+            Paid + Select = Dispensing,
+            Dispensing + Done = Locked,
+            Faulted + Reset = Locked,
+        },
+        assertions: {
+            // Any state with an action can enter the fault state
+            Faulted reachable from Unlocked,
+            Locked reachable from _,
+            Dispensing unreachable from Locked without Unlocked,
+            Paid unreachable from Dispensing without [Locked, Faulted],
+        }
+    }
+
+    struct Context {
+        jammed: bool,
+    }
+    impl VendingStateMachineContext for Context {
+        fn count(&mut self) -> Result<(), ()> {
+            if self.jammed {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    let mut sm = VendingStateMachine::new(Context { jammed: false });
+    sm.process_event(VendingEvents::Unlock).unwrap();
+    sm.process_event(VendingEvents::Coin).unwrap();
+    sm.process_event(VendingEvents::Select).unwrap();
+    assert!(matches!(sm.state(), &VendingStates::Dispensing));
+    sm.process_event(VendingEvents::Done).unwrap();
+    sm.process_event(VendingEvents::Unlock).unwrap();
+    sm.process_event(VendingEvents::Lock).unwrap();
+    assert!(matches!(sm.state(), &VendingStates::Locked));
+
+    sm.context_mut().jammed = true;
+    sm.process_event(VendingEvents::Unlock).unwrap();
+    sm.process_event(VendingEvents::Coin).unwrap();
+    assert!(matches!(sm.state(), &VendingStates::Faulted));
+    sm.process_event(VendingEvents::Reset).unwrap();
+    assert!(matches!(sm.state(), &VendingStates::Locked));
+}
+
+#[test]
+fn invariants() {
+    statemachine! {
+        transitions: {
+            *Empty + Fill / fill = Filled,
+            Filled + Drain / drain = Empty,
+        },
+        invariants: { bounded },
+    }
+
+    struct Context {
+        level: u32,
+    }
+    impl StateMachineContext for Context {
+        fn fill(&mut self) -> Result<(), ()> {
+            self.level += 10;
+            Ok(())
+        }
+        fn drain(&mut self) -> Result<(), ()> {
+            self.level = 0;
+            Ok(())
+        }
+        fn bounded(&self, state: &States) -> bool {
+            match state {
+                States::Empty => self.level == 0,
+                States::Filled => self.level <= 10,
+            }
+        }
+    }
+
+    let mut sm = StateMachine::new(Context { level: 0 });
+    assert!(sm.context().bounded(sm.state()));
+    sm.process_event(Events::Fill).unwrap();
+    assert!(sm.context().bounded(sm.state()));
+    sm.process_event(Events::Drain).unwrap();
+    assert!(sm.context().bounded(sm.state()));
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn arbitrary_events() {
+    use smlang::arbitrary::Unstructured;
+
+    fn level(u: &mut Unstructured) -> smlang::arbitrary::Result<u8> {
+        u.int_in_range(1..=10)
+    }
+
+    statemachine! {
+        transitions: {
+            *Idle + Start(u8) / start = Running(u8),
+            Running(u8) + Stop / stop = Idle,
+        },
+        events: {
+            Start { arbitrary: level },
+        },
+        arbitrary: true,
+        states_attr: #[derive(Debug)],
+    }
+
+    #[derive(Default)]
+    struct Context {
+        levels: Vec<u8>,
+        stops: usize,
+    }
+
+    impl StateMachineContext for Context {
+        fn start(&mut self, level: u8) -> Result<u8, ()> {
+            self.levels.push(level);
+            Ok(level)
+        }
+
+        fn stop(&mut self, _level: &u8) -> Result<(), ()> {
+            self.stops += 1;
+            Ok(())
+        }
+    }
+
+    // Every event is preceded by a byte continuing the events, and followed by its data
+    let data = [1, 0, 3, 1, 1, 1, 0, 7, 1, 0, 5, 1, 1];
+
+    // Replay the events generated from the same bytes, skipping those which are rejected
+    let mut expected = StateMachine::new(Context::default());
+    let mut events = 0;
+    for event in Unstructured::new(&data).arbitrary_iter::<Events>().unwrap() {
+        let _ = expected.process_event(event.unwrap());
+        events += 1;
+    }
+    assert!(events > 1);
+
+    let mut sm = StateMachine::new(Context::default());
+    let state = format!("{:?}", sm.process_arbitrary(&data));
+    assert_eq!(state, format!("{:?}", expected.state()));
+    assert_eq!(sm.context().levels, expected.context().levels);
+    assert_eq!(sm.context().stops, expected.context().stops);
+    assert!(!sm.context().levels.is_empty());
+    assert!(sm
+        .context()
+        .levels
+        .iter()
+        .all(|level| (1..=10).contains(level)));
+
+    // Nothing is processed without data
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(sm.process_arbitrary(&[]), States::Idle));
+    assert!(sm.context().levels.is_empty());
+
+    #[cfg(feature = "fuzz")]
+    StateMachine::fuzz(Context::default(), &data);
+}
+
+#[cfg(feature = "scxml")]
+#[test]
+fn scxml_import() {
+    statemachine! {
+        from_scxml: "tests/scxml/player.scxml",
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        media: bool,
+        started: usize,
+        dimmed: usize,
+    }
+
+    impl PlayerStateMachineContext for Context {
+        fn has_media(&self) -> Result<bool, ()> {
+            Ok(self.media)
+        }
+
+        fn start(&mut self) -> Result<(), ()> {
+            self.started += 1;
+            Ok(())
+        }
+
+        fn dim(&mut self) -> Result<(), ()> {
+            self.dimmed += 1;
+            Ok(())
+        }
+    }
+
+    let mut sm = PlayerStateMachine::new(Context::default());
+    assert!(sm.process_event(PlayerEvents::Play).is_err());
+    sm.context_mut().media = true;
+    sm.process_event(PlayerEvents::Play).unwrap();
+    assert_eq!(sm.context().started, 1);
+
+    // The history of the composite state is resumed from the pause
+    sm.process_event(PlayerEvents::FastForward).unwrap();
+    sm.process_event(PlayerEvents::Pause).unwrap();
+    assert_eq!(sm.context().dimmed, 1);
+    assert!(matches!(
+        sm.process_event(PlayerEvents::Pause),
+        Ok(PlayerStates::Playing(PlayerPlayingStates::Fast))
+    ));
+
+    sm.process_event(PlayerEvents::Stop).unwrap();
+    sm.process_event(PlayerEvents::Eject).unwrap();
+    assert!(matches!(sm.state(), PlayerStates::Ejected));
+}
+
+#[test]
+fn poisoning() {
+    statemachine! {
+        name: Heater,
+        poisoning: true,
+        transitions: {
+            *Idle + Start / start = Heating(u32),
+            Heating(u32) + Stop = Idle,
+        },
+        states: {
+            Heating { entry: power_on },
+        },
+        states_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Context {
+        broken: bool,
+        started: u32,
+    }
+
+    impl HeaterStateMachineContext for Context {
+        fn start(&mut self) -> Result<u32, ()> {
+            self.started += 1;
+            Ok(60)
+        }
+
+        fn power_on(&mut self, _temperature: &u32) -> Result<(), ()> {
+            assert!(!self.broken, "the relay is broken");
+            Ok(())
+        }
+    }
+
+    let mut sm = HeaterStateMachine::new(Context::default());
+    sm.process_event(HeaterEvents::Start).unwrap();
+    sm.process_event(HeaterEvents::Stop).unwrap();
+    assert!(!sm.is_poisoned());
+
+    // The entry handler panics after the idle state has been left
+    sm.context_mut().broken = true;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _ = sm.process_event(HeaterEvents::Start);
+    }));
+    assert!(result.is_err());
+    assert!(sm.is_poisoned());
+    assert_eq!(
+        sm.process_event(HeaterEvents::Stop),
+        Err(HeaterError::Poisoned)
+    );
+    assert_eq!(sm.context().started, 2);
+
+    // Once recovered, the events are processed again
+    sm.context_mut().broken = false;
+    sm.recover_with(HeaterStates::Idle);
+    assert!(!sm.is_poisoned());
+    sm.process_event(HeaterEvents::Start).unwrap();
+    assert!(matches!(sm.state(), HeaterStates::Heating(60)));
+}
+
+#[test]
+fn typestate() {
+    statemachine! {
+        name: Kettle,
+        typestate: true,
+        transitions: {
+            *Empty + Fill(u32) / fill = Filled(u32),
+            Filled(u32) + Boil [is_full] / boil = Boiling(u32),
+            Boiling(u32) + Pour = Empty,
+        },
+    }
+
+    struct Context;
+
+    impl KettleStateMachineContext for Context {
+        fn fill(&mut self, volume: u32) -> Result<u32, ()> {
+            Ok(volume)
+        }
+
+        fn is_full(&self, volume: &u32) -> Result<bool, ()> {
+            Ok(*volume >= 500)
+        }
+
+        fn boil(&mut self, volume: &u32) -> Result<u32, ()> {
+            Ok(*volume)
+        }
+    }
+
+    // Only the events of the state are methods of its handle
+    let kettle: KettleHandle<kettle_typestate::Empty, Context> = KettleHandle::new(Context);
+    let kettle = kettle.fill(750).unwrap();
+    assert_eq!(*kettle.data(), 750);
+    let kettle = kettle.boil().unwrap();
+    let kettle = kettle.pour().unwrap();
+    assert!(matches!(kettle.machine().state(), KettleStates::Empty));
+
+    // A rejected event returns the state machine along with the error
+    let kettle = kettle.fill(100).unwrap();
+    let untyped = match kettle.boil() {
+        Err(untyped) => untyped,
+        Ok(_) => panic!("the kettle is not full"),
+    };
+    assert_eq!(untyped.error, Some(KettleError::TransitionsFailed));
+    let machine = untyped.machine;
+    assert!(matches!(machine.state(), KettleStates::Filled(100)));
+
+    // The handle of the current state can be recovered from the state machine
+    let machine = match KettleHandle::<kettle_typestate::Empty, _>::try_from_machine(machine) {
+        Err(machine) => machine,
+        Ok(_) => panic!("the kettle is filled"),
+    };
+    let kettle = KettleHandle::<kettle_typestate::Filled, _>::try_from_machine(machine).ok();
+    assert!(matches!(kettle.map(|kettle| *kettle.data()), Some(100)));
+}
+
+#[test]
+fn associated_types() {
+    statemachine! {
+        name: Pump,
+        associated_types: true,
+        transitions: {
+            *Idle + Start [is_primed] / start = Running,
+            Running + after(core::time::Duration::from_secs(60)) = Idle,
+        },
+        states_attr: #[derive(Debug)],
+        events_attr: #[derive(Debug)]
+    }
+
+    #[derive(Default)]
+    struct Log(std::cell::RefCell<Vec<String>>);
+
+    impl smlang::Logger<PumpStates, PumpEvents> for Log {
+        fn process_event(&self, state: &PumpStates, event: &PumpEvents) {
+            self.0.borrow_mut().push(format!("{:?} {:?}", state, event));
+        }
+
+        fn transition(&self, old_state: &PumpStates, new_state: &PumpStates) {
+            self.0
+                .borrow_mut()
+                .push(format!("{:?} -> {:?}", old_state, new_state));
+        }
+    }
+
+    // The clock and the logger are injected, without implementing them for the context
+    #[derive(Default)]
+    struct Context {
+        clock: smlang::MockClock,
+        log: Log,
+    }
+
+    impl PumpStateMachineContext for Context {
+        type Error = &'static str;
+        type Clock = smlang::MockClock;
+        type Logger = Log;
+
+        fn clock(&self) -> &smlang::MockClock {
+            &self.clock
+        }
+
+        fn logger(&self) -> &Log {
+            &self.log
+        }
+
+        fn is_primed(&self) -> Result<bool, &'static str> {
+            Ok(true)
+        }
+
+        fn start(&mut self) -> Result<(), &'static str> {
+            Ok(())
+        }
+    }
+
+    let mut sm = PumpStateMachine::new(Context::default());
+    sm.process_event(PumpEvents::Start).unwrap();
+    assert_eq!(
+        *sm.context().log.0.borrow(),
+        ["Idle Start", "Idle -> Running"]
+    );
+
+    sm.context()
+        .clock
+        .advance(core::time::Duration::from_secs(30));
+    assert_eq!(sm.time_in_state(), core::time::Duration::from_secs(30));
+}
+
+#[test]
+fn context_access() {
+    statemachine! {
+        name: Door,
+        context_access: {
+            count_attempt: &mut self,
+            notify: &self,
+        },
+        transitions: {
+            *Locked + Unlock(u32) [count_attempt && is_code] / notify = Unlocked,
+            Unlocked + Lock = Locked,
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        attempts: u32,
+        notified: std::cell::Cell<u32>,
+    }
+
+    impl DoorStateMachineContext for Context {
+        // The attempts are counted by a guard borrowing the context mutably
+        fn count_attempt(&mut self, _code: &u32) -> Result<bool, ()> {
+            self.attempts += 1;
+            Ok(self.attempts <= 2)
+        }
+
+        fn is_code(&self, code: &u32) -> Result<bool, ()> {
+            Ok(*code == 1234)
+        }
+
+        // The action only borrows the context
+        fn notify(&self, _code: u32) -> Result<(), ()> {
+            self.notified.set(self.notified.get() + 1);
+            Ok(())
+        }
+    }
+
+    let mut sm = DoorStateMachine::new(Context::default());
+    assert!(sm.process_event(DoorEvents::Unlock(1)).is_err());
+    sm.process_event(DoorEvents::Unlock(1234)).unwrap();
+    sm.process_event(DoorEvents::Lock).unwrap();
+    assert!(sm.process_event(DoorEvents::Unlock(1234)).is_err());
+    assert_eq!(sm.context().attempts, 3);
+    assert_eq!(sm.context().notified.get(), 1);
+}
+
+#[test]
+fn state_constructors() {
+    statemachine! {
+        name: Boot,
+        transitions: {
+            *Idle + Start / start = Running(u8),
+            Running(u8) + Stop = Idle,
+            Running(u8) + Service = Maintenance,
+            Maintenance {
+                *Inspecting + Done = Repairing,
+            },
+            Maintenance + Stop = Idle,
+        },
+        invariants: { speed_is_limited },
+        states_attr: #[derive(Debug)]
+    }
+
+    struct Context;
+
+    impl BootStateMachineContext for Context {
+        fn start(&mut self) -> Result<u8, ()> {
+            Ok(1)
+        }
+
+        fn speed_is_limited(&self, state: &BootStates) -> bool {
+            !matches!(state, BootStates::Running(speed) if *speed > 100)
+        }
+    }
+
+    // The state read at boot is validated by the invariants
+    let sm = BootStateMachine::new_in_state(Context, BootStates::Running(3)).ok();
+    assert!(matches!(
+        sm.as_ref().map(|sm| sm.state()),
+        Some(BootStates::Running(3))
+    ));
+    let rejected = BootStateMachine::new_in_state(Context, BootStates::Running(120));
+    assert!(matches!(rejected, Err((_, BootStates::Running(120)))));
+
+    // Every state has a constructor, where sub-states are nested in their composite state
+    let sm = BootStateMachine::new_in_repairing(Context);
+    assert!(matches!(
+        sm.state(),
+        BootStates::Maintenance(BootMaintenanceStates::Repairing)
+    ));
+    let mut sm = BootStateMachine::new_in_running(Context, 7);
+    sm.process_event(BootEvents::Stop).unwrap();
+    assert!(matches!(sm.state(), BootStates::Idle));
+
+    // The state machine started in a state processes events like any other
+    let mut sm = BootStateMachine::new_in_idle(Context);
+    sm.process_event(BootEvents::Start).unwrap();
+    sm.process_event(BootEvents::Service).unwrap();
+    sm.process_event(BootEvents::Done).unwrap();
+    assert!(matches!(
+        sm.state(),
+        BootStates::Maintenance(BootMaintenanceStates::Repairing)
+    ));
+}